@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use ezsockets::Bytes;
 use ezsockets::ClientConfig;
 use std::io::BufRead;
 use url::Url;
@@ -14,7 +15,7 @@ impl ezsockets::ClientExt for Client {
         Ok(())
     }
 
-    async fn binary(&mut self, bytes: Vec<u8>) -> Result<(), ezsockets::Error> {
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), ezsockets::Error> {
         tracing::info!("received bytes: {bytes:?}");
         Ok(())
     }
@@ -39,6 +40,9 @@ async fn main() {
     for line in lines {
         let line = line.unwrap();
         tracing::info!("sending {line}");
-        handle.text(line);
+        if let Err(err) = handle.text(line) {
+            tracing::error!("{err}");
+            break;
+        }
     }
 }
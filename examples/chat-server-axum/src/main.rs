@@ -4,6 +4,7 @@ use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::Router;
 use ezsockets::axum::Upgrade;
+use ezsockets::Bytes;
 use ezsockets::Error;
 use ezsockets::Server;
 use ezsockets::Socket;
@@ -28,6 +29,7 @@ struct ChatServer {
 impl ezsockets::ServerExt for ChatServer {
     type Session = ChatSession;
     type Params = ChatMessage;
+    type State = ();
 
     async fn accept(
         &mut self,
@@ -63,7 +65,8 @@ impl ezsockets::ServerExt for ChatServer {
                 let text = format!("from {from}: {text}");
                 for (id, handle) in sessions {
                     tracing::info!("sending {text} to {id}");
-                    handle.text(text.clone());
+                    // The session may have disconnected meanwhile.
+                    let _ = handle.text(text.clone());
                 }
             }
         };
@@ -94,7 +97,7 @@ impl ezsockets::SessionExt for ChatSession {
         Ok(())
     }
 
-    async fn binary(&mut self, _bytes: Vec<u8>) -> Result<(), Error> {
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
         unimplemented!()
     }
 
@@ -141,5 +144,5 @@ async fn websocket_handler(
     Extension(server): Extension<Server<ChatServer>>,
     ezsocket: Upgrade,
 ) -> impl IntoResponse {
-    ezsocket.on_upgrade(server, ())
+    ezsocket.on_upgrade(server, ()).await
 }
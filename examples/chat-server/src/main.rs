@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use ezsockets::Bytes;
 use ezsockets::Error;
 use ezsockets::Server;
 use ezsockets::Socket;
@@ -33,6 +34,7 @@ struct ChatServer {
 impl ezsockets::ServerExt for ChatServer {
     type Params = Message;
     type Session = SessionActor;
+    type State = ();
 
     async fn accept(
         &mut self,
@@ -91,7 +93,8 @@ impl ezsockets::ServerExt for ChatServer {
                         .join(",")
                 );
                 for session in sessions {
-                    session.text(text.clone());
+                    // The session may have disconnected meanwhile.
+                    let _ = session.text(text.clone());
                 }
             }
             Message::Join { id, room } => {
@@ -115,7 +118,7 @@ impl ezsockets::ServerExt for ChatServer {
                     .map(|id| self.sessions.get(id).unwrap());
 
                 for session in sessions {
-                    session.text(format!("User with ID: {id} just joined {room} room"));
+                    let _ = session.text(format!("User with ID: {id} just joined {room} room"));
                 }
             }
         };
@@ -162,7 +165,7 @@ impl ezsockets::SessionExt for SessionActor {
         Ok(())
     }
 
-    async fn binary(&mut self, _bytes: Vec<u8>) -> Result<(), Error> {
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
         unimplemented!()
     }
 
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use ezsockets::Bytes;
 use ezsockets::Error;
 use ezsockets::Server;
 use ezsockets::Socket;
@@ -16,6 +17,7 @@ struct CounterServer {}
 impl ezsockets::ServerExt for CounterServer {
     type Session = CounterSession;
     type Params = ();
+    type State = ();
 
     async fn accept(
         &mut self,
@@ -30,8 +32,11 @@ impl ezsockets::ServerExt for CounterServer {
                     let session = handle.clone();
                     async move {
                         loop {
-                            session.call(Message::Increment);
-                            session.call(Message::Share);
+                            if session.call(Message::Increment).is_err()
+                                || session.call(Message::Share).is_err()
+                            {
+                                break;
+                            }
                             tokio::time::sleep(INTERVAL).await;
                         }
                     }
@@ -93,18 +98,18 @@ impl ezsockets::SessionExt for CounterSession {
     }
 
     async fn text(&mut self, text: String) -> Result<(), Error> {
-        self.handle.text(text);
+        self.handle.text(text)?;
         Ok(())
     }
 
-    async fn binary(&mut self, _bytes: Vec<u8>) -> Result<(), Error> {
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
         unimplemented!()
     }
 
     async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
         match params {
             Message::Increment => self.counter += 1,
-            Message::Share => self.handle.text(format!("counter: {}", self.counter)),
+            Message::Share => self.handle.text(format!("counter: {}", self.counter))?,
         };
         Ok(())
     }
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use ezsockets::Bytes;
 use ezsockets::Error;
 use ezsockets::Server;
 use ezsockets::Socket;
@@ -13,6 +14,7 @@ struct EchoServer {}
 impl ezsockets::ServerExt for EchoServer {
     type Session = EchoSession;
     type Params = ();
+    type State = ();
 
     async fn accept(
         &mut self,
@@ -54,11 +56,11 @@ impl ezsockets::SessionExt for EchoSession {
     }
 
     async fn text(&mut self, text: String) -> Result<(), Error> {
-        self.handle.text(text);
+        self.handle.text(text)?;
         Ok(())
     }
 
-    async fn binary(&mut self, _bytes: Vec<u8>) -> Result<(), Error> {
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
         unimplemented!()
     }
 
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use ezsockets::Bytes;
 use ezsockets::ClientConfig;
 use ezsockets::Error;
 use std::io::BufRead;
@@ -15,7 +16,7 @@ impl ezsockets::ClientExt for Client {
         Ok(())
     }
 
-    async fn binary(&mut self, bytes: Vec<u8>) -> Result<(), Error> {
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Error> {
         tracing::info!("received bytes: {bytes:?}");
         Ok(())
     }
@@ -42,6 +43,9 @@ async fn main() {
     for line in lines {
         let line = line.unwrap();
         tracing::info!("sending {line}");
-        handle.text(line);
+        if let Err(err) = handle.text(line) {
+            tracing::error!("{err}");
+            break;
+        }
     }
 }
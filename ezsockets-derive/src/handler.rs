@@ -0,0 +1,359 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::format_ident;
+use quote::quote;
+use std::collections::HashMap;
+use syn::parse::Parser;
+use syn::ImplItem;
+use syn::ItemImpl;
+use syn::Type;
+
+/// Handler of the implemented trait.
+struct Method {
+    name: &'static str,
+    /// Whether the handler is async.
+    asynchronous: bool,
+    /// Receiver, arguments and return type of the handler.
+    signature: TokenStream2,
+    /// Names of the arguments passed along to the inherent method.
+    arguments: TokenStream2,
+    /// Body of a required handler the impl block doesn't define, `None` for handlers with a default.
+    fallback: Option<TokenStream2>,
+}
+
+fn method(
+    name: &'static str,
+    asynchronous: bool,
+    signature: TokenStream2,
+    arguments: TokenStream2,
+    fallback: Option<TokenStream2>,
+) -> Method {
+    Method {
+        name,
+        asynchronous,
+        signature,
+        arguments,
+        fallback,
+    }
+}
+
+fn client_methods() -> Vec<Method> {
+    let ok = Some(quote! { ::std::result::Result::Ok(()) });
+    vec![
+        method(
+            "text",
+            true,
+            quote! { (&mut self, text: ::std::string::String) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { text },
+            ok.clone(),
+        ),
+        method(
+            "binary",
+            true,
+            quote! { (&mut self, bytes: ::ezsockets::Bytes) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { bytes },
+            ok.clone(),
+        ),
+        method(
+            "text_with_meta",
+            true,
+            quote! { (&mut self, text: ::std::string::String, meta: ::ezsockets::MessageMeta) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { text, meta },
+            None,
+        ),
+        method(
+            "binary_with_meta",
+            true,
+            quote! { (&mut self, bytes: ::ezsockets::Bytes, meta: ::ezsockets::MessageMeta) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { bytes, meta },
+            None,
+        ),
+        method(
+            "call",
+            true,
+            quote! { (&mut self, params: Self::Params) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { params },
+            ok,
+        ),
+        method(
+            "refresh_credentials",
+            true,
+            quote! { (&mut self) -> ::std::result::Result<::std::option::Option<::std::string::String>, ::ezsockets::Error> },
+            quote! {},
+            None,
+        ),
+        method(
+            "reauth_message",
+            false,
+            quote! { (&self, token: &str) -> ::std::option::Option<::ezsockets::Message> },
+            quote! { token },
+            None,
+        ),
+        method(
+            "ping",
+            true,
+            quote! { (&mut self, payload: ::ezsockets::Bytes) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { payload },
+            None,
+        ),
+        method(
+            "pong",
+            true,
+            quote! { (&mut self, payload: ::ezsockets::Bytes) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { payload },
+            None,
+        ),
+    ]
+}
+
+fn session_methods() -> Vec<Method> {
+    let ok = Some(quote! { ::std::result::Result::Ok(()) });
+    vec![
+        method(
+            "id",
+            false,
+            quote! { (&self) -> &Self::ID },
+            quote! {},
+            Some(quote! { &self.id }),
+        ),
+        method(
+            "text",
+            true,
+            quote! { (&mut self, text: ::std::string::String) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { text },
+            ok.clone(),
+        ),
+        method(
+            "binary",
+            true,
+            quote! { (&mut self, bytes: ::ezsockets::Bytes) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { bytes },
+            ok.clone(),
+        ),
+        method(
+            "text_with_meta",
+            true,
+            quote! { (&mut self, text: ::std::string::String, meta: ::ezsockets::MessageMeta) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { text, meta },
+            None,
+        ),
+        method(
+            "binary_with_meta",
+            true,
+            quote! { (&mut self, bytes: ::ezsockets::Bytes, meta: ::ezsockets::MessageMeta) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { bytes, meta },
+            None,
+        ),
+        method(
+            "hello",
+            true,
+            quote! { (&mut self, message: &::ezsockets::Message) -> ::std::result::Result<bool, ::ezsockets::Error> },
+            quote! { message },
+            None,
+        ),
+        method(
+            "data",
+            true,
+            quote! { (&mut self, data: ::ezsockets::Bytes, kind: ::ezsockets::DataKind) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { data, kind },
+            None,
+        ),
+        method(
+            "call",
+            true,
+            quote! { (&mut self, params: Self::Params) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { params },
+            ok,
+        ),
+        method(
+            "congestion",
+            true,
+            quote! { (&mut self, state: ::ezsockets::CongestionState) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { state },
+            None,
+        ),
+        method(
+            "quota_exceeded",
+            true,
+            quote! { (&mut self, usage: ::ezsockets::QuotaUsage) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { usage },
+            None,
+        ),
+        method(
+            "ping",
+            true,
+            quote! { (&mut self, payload: ::ezsockets::Bytes) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { payload },
+            None,
+        ),
+        method(
+            "pong",
+            true,
+            quote! { (&mut self, payload: ::ezsockets::Bytes) -> ::std::result::Result<(), ::ezsockets::Error> },
+            quote! { payload },
+            None,
+        ),
+        method(
+            "on_handler_error",
+            true,
+            quote! { (&mut self, error: &::ezsockets::Error) -> ::ezsockets::ErrorAction },
+            quote! { error },
+            None,
+        ),
+    ]
+}
+
+/// Associated types of the implemented trait, set with the arguments of the attribute.
+struct Associated {
+    name: &'static str,
+    /// Type used if the attribute doesn't set it, `None` if it must be set.
+    default: Option<Type>,
+}
+
+pub(crate) fn client_ext(attribute: TokenStream2, item: ItemImpl) -> syn::Result<TokenStream2> {
+    let associated = [Associated {
+        name: "params",
+        default: Some(syn::parse_quote!(())),
+    }];
+    handler(
+        quote! { ::ezsockets::ClientExt },
+        &associated,
+        client_methods(),
+        attribute,
+        item,
+    )
+}
+
+pub(crate) fn session_ext(attribute: TokenStream2, item: ItemImpl) -> syn::Result<TokenStream2> {
+    let associated = [
+        Associated {
+            name: "id",
+            default: None,
+        },
+        Associated {
+            name: "args",
+            default: Some(syn::parse_quote!(())),
+        },
+        Associated {
+            name: "params",
+            default: Some(syn::parse_quote!(())),
+        },
+    ];
+    handler(
+        quote! { ::ezsockets::SessionExt },
+        &associated,
+        session_methods(),
+        attribute,
+        item,
+    )
+}
+
+/// Implements `trait_path` for the type of `item`, delegating to the inherent methods of `item` named like the handlers.
+fn handler(
+    trait_path: TokenStream2,
+    associated: &[Associated],
+    methods: Vec<Method>,
+    attribute: TokenStream2,
+    item: ItemImpl,
+) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(syn::Error::new_spanned(
+            path,
+            "expected an inherent impl block, without a trait",
+        ));
+    }
+
+    let mut types = HashMap::new();
+    let parser = syn::meta::parser(|meta| {
+        match associated
+            .iter()
+            .find(|associated| meta.path.is_ident(associated.name))
+        {
+            Some(associated) => {
+                types.insert(associated.name, meta.value()?.parse::<Type>()?);
+                Ok(())
+            }
+            None => Err(meta.error("unsupported argument")),
+        }
+    });
+    parser.parse2(attribute)?;
+    let mut types_tokens = Vec::new();
+    for associated in associated {
+        let ty = match types.remove(associated.name).or(associated.default.clone()) {
+            Some(ty) => ty,
+            None => {
+                return Err(syn::Error::new(
+                    proc_macro2::Span::call_site(),
+                    format!("missing `{} = ...` argument", associated.name),
+                ))
+            }
+        };
+        let name = format_ident!("{}", capitalize(associated.name));
+        types_tokens.push(quote! { type #name = #ty; });
+    }
+
+    // Whether each method of the impl block is async.
+    let inherent: HashMap<String, bool> = item
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            ImplItem::Fn(function) => Some((
+                function.sig.ident.to_string(),
+                function.sig.asyncness.is_some(),
+            )),
+            _ => None,
+        })
+        .collect();
+
+    let self_ty = &item.self_ty;
+    let mut handlers = Vec::new();
+    for method in methods {
+        let name = format_ident!("{}", method.name);
+        let signature = &method.signature;
+        let asyncness = method.asynchronous.then(|| quote! { async });
+        let body = match inherent.get(method.name) {
+            Some(true) => {
+                let arguments = &method.arguments;
+                quote! { <#self_ty>::#name(self, #arguments).await }
+            }
+            Some(false) => {
+                let arguments = &method.arguments;
+                quote! { <#self_ty>::#name(self, #arguments) }
+            }
+            None => match &method.fallback {
+                Some(fallback) => {
+                    // Avoids warnings about the unused arguments.
+                    let arguments = &method.arguments;
+                    quote! {
+                        let _ = (#arguments);
+                        #fallback
+                    }
+                }
+                None => continue,
+            },
+        };
+        handlers.push(quote! {
+            #asyncness fn #name #signature {
+                #body
+            }
+        });
+    }
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        #item
+
+        #[::ezsockets::__private::async_trait]
+        impl #impl_generics #trait_path for #self_ty #where_clause {
+            #(#types_tokens)*
+
+            #(#handlers)*
+        }
+    })
+}
+
+fn capitalize(name: &str) -> String {
+    match name {
+        "id" => String::from("ID"),
+        _ => name[..1].to_uppercase() + &name[1..],
+    }
+}
@@ -0,0 +1,218 @@
+//! Derive macros of [ezsockets](https://docs.rs/ezsockets), enabled with its `derive` feature.
+
+mod handler;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::Data;
+use syn::DeriveInput;
+use syn::Fields;
+use syn::LitStr;
+
+/// Implements `ezsockets::protocols::router::WsMessage` for an enum, see its documentation.
+///
+/// Accepts `#[ws(tag = "...")]` on the enum, naming the discriminating field, `type` by default,
+/// and `#[ws(rename = "...")]` on variants, replacing the snake case name of the variant.
+#[proc_macro_derive(WsMessage, attributes(ws))]
+pub fn derive_ws_message(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    ws_message(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `ezsockets::ClientExt` for the type of an inherent impl block, delegating each handler to the method
+/// of the block with the same name, e.g. `async fn text(&mut self, text: String) -> Result<(), Error>`.
+///
+/// `text`, `binary` and `call` do nothing if the block doesn't define them, the other handlers keep their default.
+/// Methods may be async or not. The `Params` type is set with `#[client_ext(params = ...)]`, `()` by default.
+#[proc_macro_attribute]
+pub fn client_ext(attribute: TokenStream, item: TokenStream) -> TokenStream {
+    let item = syn::parse_macro_input!(item as syn::ItemImpl);
+    handler::client_ext(attribute.into(), item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `ezsockets::SessionExt` for the type of an inherent impl block, like [`macro@client_ext`].
+///
+/// The `ID` type must be set with `#[session_ext(id = ...)]`, `Args` and `Params` are set with `args = ...`
+/// and `params = ...`, `()` by default. Without an `id` method, the session is identified by its `id` field.
+#[proc_macro_attribute]
+pub fn session_ext(attribute: TokenStream, item: TokenStream) -> TokenStream {
+    let item = syn::parse_macro_input!(item as syn::ItemImpl);
+    handler::session_ext(attribute.into(), item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn ws_message(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "WsMessage can only be derived for enums",
+            ))
+        }
+    };
+    let tag = attribute(&input.attrs, "tag")?.unwrap_or_else(|| String::from("type"));
+
+    let mut kinds = Vec::new();
+    let mut kind_arms = Vec::new();
+    let mut encode_arms = Vec::new();
+    let mut decode_arms = Vec::new();
+    for variant in &data.variants {
+        let name = &variant.ident;
+        let kind =
+            attribute(&variant.attrs, "rename")?.unwrap_or_else(|| snake_case(&name.to_string()));
+        let fields = match &variant.fields {
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .map(|field| field.ident.clone().unwrap())
+                .collect(),
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(fields) => {
+                return Err(syn::Error::new(
+                    fields.span(),
+                    "WsMessage variants must have named fields, or none",
+                ))
+            }
+        };
+        let keys: Vec<String> = fields.iter().map(ToString::to_string).collect();
+        if keys.contains(&tag) {
+            return Err(syn::Error::new(
+                variant.span(),
+                format!("field `{tag}` conflicts with the tag of the message"),
+            ));
+        }
+
+        kind_arms.push(quote! { Self::#name { .. } => #kind });
+        encode_arms.push(quote! {
+            Self::#name { #(#fields),* } => {
+                let mut object = ::ezsockets::__private::serde_json::Map::new();
+                object.insert(
+                    ::std::string::String::from(#tag),
+                    ::ezsockets::__private::serde_json::Value::from(#kind),
+                );
+                #(
+                    object.insert(
+                        ::std::string::String::from(#keys),
+                        ::ezsockets::__private::serde_json::to_value(#fields)?,
+                    );
+                )*
+                ::ezsockets::__private::serde_json::Value::Object(object)
+            }
+        });
+        decode_arms.push(quote! {
+            #kind => ::std::result::Result::Ok(Self::#name {
+                #(
+                    #fields: ::ezsockets::__private::serde_json::from_value(
+                        object.remove(#keys).unwrap_or_default(),
+                    )
+                    .map_err(|err| {
+                        ::ezsockets::protocols::router::RouteError::Malformed(
+                            ::std::format!("invalid `{}` of {} message: {}", #keys, #kind, err),
+                        )
+                    })?,
+                )*
+            }),
+        });
+        kinds.push(kind);
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::ezsockets::protocols::router::WsMessage for #ident #type_generics #where_clause {
+            const TAG: &'static str = #tag;
+            const KINDS: &'static [&'static str] = &[#(#kinds),*];
+
+            fn kind(&self) -> &'static str {
+                match self {
+                    #(#kind_arms,)*
+                }
+            }
+
+            fn to_json(
+                &self,
+            ) -> ::std::result::Result<
+                ::ezsockets::__private::serde_json::Value,
+                ::ezsockets::__private::serde_json::Error,
+            > {
+                ::std::result::Result::Ok(match self {
+                    #(#encode_arms)*
+                })
+            }
+
+            fn from_json(
+                message: ::ezsockets::__private::serde_json::Value,
+            ) -> ::std::result::Result<Self, ::ezsockets::protocols::router::RouteError> {
+                let mut object = match message {
+                    ::ezsockets::__private::serde_json::Value::Object(object) => object,
+                    _ => {
+                        return ::std::result::Result::Err(
+                            ::ezsockets::protocols::router::RouteError::Malformed(
+                                ::std::string::String::from("expected a JSON object"),
+                            ),
+                        )
+                    }
+                };
+                let kind = match object.remove(#tag) {
+                    ::std::option::Option::Some(
+                        ::ezsockets::__private::serde_json::Value::String(kind),
+                    ) => kind,
+                    _ => {
+                        return ::std::result::Result::Err(
+                            ::ezsockets::protocols::router::RouteError::Unrouted(
+                                ::std::option::Option::None,
+                            ),
+                        )
+                    }
+                };
+                match kind.as_str() {
+                    #(#decode_arms)*
+                    _ => ::std::result::Result::Err(
+                        ::ezsockets::protocols::router::RouteError::Unrouted(
+                            ::std::option::Option::Some(kind),
+                        ),
+                    ),
+                }
+            }
+        }
+    })
+}
+
+/// Value of `#[ws(name = "...")]` among `attributes`.
+fn attribute(attributes: &[syn::Attribute], name: &str) -> syn::Result<Option<String>> {
+    let mut value = None;
+    for attribute in attributes.iter().filter(|attr| attr.path().is_ident("ws")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident(name) {
+                value = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported attribute"))
+            }
+        })?;
+    }
+    Ok(value)
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, char) in name.char_indices() {
+        if char.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(char.to_lowercase());
+        } else {
+            snake.push(char);
+        }
+    }
+    snake
+}
@@ -7,12 +7,18 @@ use crate::Server;
 use crate::ServerExt;
 use crate::SessionExt;
 use crate::Socket;
+use crate::SocketConfig;
+use crate::TraceContext;
+use crate::TRACEPARENT;
 use async_trait::async_trait;
 use axum::extract::ws;
 use axum::extract::ws::rejection::*;
 use axum::extract::ConnectInfo;
 use axum::extract::FromRequest;
 use axum::extract::RequestParts;
+use axum::http::header;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
 use axum::response::Response;
 use std::net::SocketAddr;
 
@@ -27,6 +33,7 @@ use std::net::SocketAddr;
 pub struct Upgrade {
     ws: ws::WebSocketUpgrade,
     address: SocketAddr,
+    trace_context: Option<TraceContext>,
 }
 
 #[async_trait]
@@ -42,9 +49,15 @@ where
             .get::<ConnectInfo<SocketAddr>>()
             .expect("Axum Server must be created with `axum::Router::into_make_service_with_connect_info::<SocketAddr, _>()`")
             .to_owned();
+        let trace_context = req
+            .headers()
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(TraceContext::parse);
         Ok(Self {
             ws: ws::WebSocketUpgrade::from_request(req).await?,
             address,
+            trace_context,
         })
     }
 }
@@ -53,11 +66,11 @@ impl From<ws::Message> for RawMessage {
     fn from(message: ws::Message) -> Self {
         match message {
             ws::Message::Text(text) => RawMessage::Text(text),
-            ws::Message::Binary(binary) => RawMessage::Binary(binary),
+            ws::Message::Binary(binary) => RawMessage::Binary(binary.into()),
             ws::Message::Ping(ping) => RawMessage::Ping(ping),
             ws::Message::Pong(pong) => RawMessage::Pong(pong),
             ws::Message::Close(Some(close)) => RawMessage::Close(Some(CloseFrame {
-                code: CloseCode::try_from(close.code).unwrap(),
+                code: CloseCode::try_from(close.code).unwrap_or(CloseCode::Other(close.code)),
                 reason: close.reason.into(),
             })),
             ws::Message::Close(None) => RawMessage::Close(None),
@@ -69,7 +82,7 @@ impl From<RawMessage> for ws::Message {
     fn from(message: RawMessage) -> Self {
         match message {
             RawMessage::Text(text) => ws::Message::Text(text),
-            RawMessage::Binary(binary) => ws::Message::Binary(binary),
+            RawMessage::Binary(binary) => ws::Message::Binary(binary.into()),
             RawMessage::Ping(ping) => ws::Message::Ping(ping),
             RawMessage::Pong(pong) => ws::Message::Pong(pong),
             RawMessage::Close(Some(close)) => ws::Message::Close(Some(ws::CloseFrame {
@@ -77,6 +90,9 @@ impl From<RawMessage> for ws::Message {
                 reason: close.reason.into(),
             })),
             RawMessage::Close(None) => ws::Message::Close(None),
+            RawMessage::Fragment(_) => {
+                panic!("axum can't write fragments, disable SocketConfig::write_fragments")
+            }
         }
     }
 }
@@ -88,14 +104,51 @@ impl Upgrade {
     /// When using `WebSocketUpgrade`, the response produced by this method
     /// should be returned from the handler. See the [module docs](self) for an
     /// example.
-    pub fn on_upgrade<E: ServerExt + 'static>(
+    ///
+    /// Responds with `503 Service Unavailable` if the connection exceeds [`ServerConfig::accept_limit`](crate::ServerConfig::accept_limit),
+    /// including a `Retry-After` header if [`AcceptLimit::retry_after`](crate::AcceptLimit::retry_after) is set.
+    pub async fn on_upgrade<E: ServerExt + 'static>(
         self,
         server: Server<E>,
         args: <E::Session as SessionExt>::Args,
     ) -> Response {
-        self.ws.on_upgrade(move |socket| async move {
-            let socket = Socket::new(socket, Default::default()); // TODO: Make it really configurable via Extensions
-            server.accept(socket, self.address, args).await;
+        if !server.admit(self.address).await {
+            return unavailable(&server);
+        }
+        let permit = match server.handshake_permit(self.address) {
+            Some(permit) => permit,
+            None => return unavailable(&server),
+        };
+        let mut ws = self.ws;
+        if let Some(max_message_size) = server.config().socket.max_message_size {
+            ws = ws.max_message_size(max_message_size);
+        }
+        ws.on_upgrade(move |socket| async move {
+            drop(permit);
+            let config = SocketConfig {
+                write_fragments: false,
+                ..server.config().socket.clone()
+            };
+            let socket = Socket::new(socket, config)
+                .with_addresses(None, Some(self.address))
+                .with_trace_context(self.trace_context);
+            if let Err(err) = server.accept(socket, self.address, args).await {
+                tracing::warn!(
+                    "could not hand the connection from {} over to the server: {err}",
+                    self.address
+                );
+            }
         })
     }
 }
+
+fn unavailable<E: ServerExt>(server: &Server<E>) -> Response {
+    match server.config().accept_limit.retry_after_header() {
+        Some(retry_after) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, retry_after)],
+        )
+            .into_response(),
+        None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
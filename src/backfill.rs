@@ -0,0 +1,165 @@
+use crate::Message;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::Arc;
+
+pub(crate) type KeyFn = Arc<dyn Fn(&Message) -> Option<String> + Send + Sync>;
+
+/// Messages sent to a session joining a room before the live broadcasts, e.g. the history of the room,
+/// see [`Server::join_with_backfill`](crate::Server::join_with_backfill).
+pub struct Backfill {
+    pub(crate) source: BoxStream<'static, Message>,
+    pub(crate) key: Option<KeyFn>,
+}
+
+impl std::fmt::Debug for Backfill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Backfill").finish_non_exhaustive()
+    }
+}
+
+impl Backfill {
+    pub fn new(source: impl futures::Stream<Item = Message> + Send + 'static) -> Self {
+        Self {
+            source: source.boxed(),
+            key: None,
+        }
+    }
+
+    /// Identifies messages by the key returned by `key`, so that live broadcasts the backfill already sent
+    /// are skipped, e.g. because the history was read after they were broadcast. Messages without a key are never skipped.
+    pub fn dedup_by(
+        mut self,
+        key: impl Fn(&Message) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.key = Some(Arc::new(key));
+        self
+    }
+}
+
+struct Pending {
+    generation: u64,
+    /// Live broadcasts held until the backfill completes.
+    held: Vec<Message>,
+    key: Option<KeyFn>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// Members of rooms being backfilled, tracked by the server actor.
+pub(crate) struct Backfills<ID> {
+    pending: HashMap<ID, HashMap<String, Pending>>,
+    /// Identifies the backfills, so that a backfill completing after the session left and joined again is ignored.
+    generation: u64,
+}
+
+impl<ID> Default for Backfills<ID> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+            generation: 0,
+        }
+    }
+}
+
+impl<ID: Hash + Eq> Backfills<ID> {
+    /// Generation of the next backfill, to pass to [`Backfills::start`] and [`Backfills::finish`].
+    pub(crate) fn next_generation(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
+    }
+
+    pub(crate) fn start(
+        &mut self,
+        room: String,
+        id: ID,
+        generation: u64,
+        key: Option<KeyFn>,
+        task: tokio::task::JoinHandle<()>,
+    ) {
+        let pending = Pending {
+            generation,
+            held: Vec::new(),
+            key,
+            task,
+        };
+        if let Some(previous) = self.pending.entry(id).or_default().insert(room, pending) {
+            previous.task.abort();
+        }
+    }
+
+    /// Holds the message if the member of the room is being backfilled, returns it back otherwise.
+    pub(crate) fn hold(&mut self, room: &str, id: &ID, message: Message) -> Option<Message> {
+        match self
+            .pending
+            .get_mut(id)
+            .and_then(|rooms| rooms.get_mut(room))
+        {
+            Some(pending) => {
+                pending.held.push(message);
+                None
+            }
+            None => Some(message),
+        }
+    }
+
+    /// Completes the backfill, returns the held messages which weren't part of it, identified by `keys`.
+    pub(crate) fn finish(
+        &mut self,
+        room: &str,
+        id: &ID,
+        generation: u64,
+        keys: HashSet<String>,
+    ) -> Vec<Message> {
+        let current = self
+            .pending
+            .get(id)
+            .and_then(|rooms| rooms.get(room))
+            .is_some_and(|pending| pending.generation == generation);
+        if !current {
+            return Vec::new();
+        }
+        let pending = match self.remove(room, id) {
+            Some(pending) => pending,
+            None => return Vec::new(),
+        };
+        match pending.key {
+            Some(key) => pending
+                .held
+                .into_iter()
+                .filter(|message| !key(message).is_some_and(|key| keys.contains(&key)))
+                .collect(),
+            None => pending.held,
+        }
+    }
+
+    /// Stops the backfill, e.g. because the session left the room, dropping the held messages.
+    pub(crate) fn cancel(&mut self, room: &str, id: &ID) {
+        if let Some(pending) = self.remove(room, id) {
+            pending.task.abort();
+        }
+    }
+
+    /// Stops the backfills of a disconnected session.
+    pub(crate) fn cancel_all(&mut self, id: &ID) {
+        for pending in self
+            .pending
+            .remove(id)
+            .into_iter()
+            .flat_map(HashMap::into_values)
+        {
+            pending.task.abort();
+        }
+    }
+
+    fn remove(&mut self, room: &str, id: &ID) -> Option<Pending> {
+        let rooms = self.pending.get_mut(id)?;
+        let pending = rooms.remove(room);
+        if rooms.is_empty() {
+            self.pending.remove(id);
+        }
+        pending
+    }
+}
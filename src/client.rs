@@ -1,23 +1,67 @@
-use crate::socket::Config;
+use crate::stats::HandlerTimingsRecorder;
+use crate::time::random_duration;
+use crate::time::tick;
+use crate::CloseCode;
+use crate::CloseFrame;
 use crate::Error;
+use crate::HandlerTimings;
+use crate::IdempotencyKey;
 use crate::Message;
+use crate::MessageMeta;
+use crate::SendError;
 use crate::Socket;
+use crate::SocketConfig;
+use crate::TlsInfo;
+use crate::TraceContext;
+use crate::TRACEPARENT;
 use async_trait::async_trait;
+use bytes::Bytes;
 use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
+use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tokio::time::Instant;
 use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::MaybeTlsStream;
+use tokio_tungstenite::WebSocketStream;
+use tracing::Instrument;
 use url::Url;
 
+#[cfg(feature = "rustls")]
+use tokio_rustls::rustls;
+
 const DEFAULT_RECONNECT_INTERVAL: Duration = Duration::new(5, 0);
+/// Time without any traffic after which a stale connection can be replaced.
+const QUIET_PERIOD: Duration = Duration::from_secs(1);
+const DEFAULT_RESTART_JITTER: Duration = Duration::from_secs(25);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientConfig {
     url: Url,
     reconnect_interval: Option<Duration>,
     headers: http::HeaderMap<http::HeaderValue>,
+    close_on_drop: bool,
+    socket_config: SocketConfig,
+    endpoint_refresh: Option<Duration>,
+    endpoint_resolver: Option<Arc<dyn EndpointResolver>>,
+    credentials_refresh: Option<Duration>,
+    restart_jitter: Duration,
+    runtime: Option<tokio::runtime::Handle>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    /// Checked against every handshake response, see [`connect_checked`].
+    expectations: Option<ResponseExpectations>,
+    idempotency_key: Option<IdempotencyKey>,
+    #[cfg(feature = "rustls")]
+    tls: Option<Arc<rustls::ClientConfig>>,
 }
 
 impl ClientConfig {
@@ -26,9 +70,138 @@ impl ClientConfig {
             url,
             reconnect_interval: Some(DEFAULT_RECONNECT_INTERVAL),
             headers: http::HeaderMap::new(),
+            close_on_drop: true,
+            socket_config: SocketConfig::default(),
+            endpoint_refresh: None,
+            endpoint_resolver: None,
+            credentials_refresh: None,
+            restart_jitter: DEFAULT_RESTART_JITTER,
+            runtime: None,
+            record: None,
+            replay: None,
+            expectations: None,
+            idempotency_key: None,
+            #[cfg(feature = "rustls")]
+            tls: None,
         }
     }
 
+    /// TLS configuration used for `wss://` URLs, instead of the default one trusting the webpki roots.
+    #[cfg(feature = "rustls")]
+    pub fn tls(mut self, tls: Arc<rustls::ClientConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Authenticates to the server with a client certificate chosen by `resolver` (mutual TLS).
+    ///
+    /// The rest of the configuration set with [`ClientConfig::tls`] is kept, e.g. to trust a private CA,
+    /// the webpki roots are trusted otherwise.
+    ///
+    /// The resolver only has to provide a [`rustls::sign::CertifiedKey`], whose signing key
+    /// may be backed by a hardware token (HSM, TPM, smartcard), so the private key never has to be loaded into memory.
+    #[cfg(feature = "rustls")]
+    pub fn client_cert_resolver(
+        mut self,
+        resolver: Arc<dyn rustls::client::ResolvesClientCert>,
+    ) -> Self {
+        let mut tls = match self.tls.take() {
+            Some(tls) => (*tls).clone(),
+            None => {
+                let mut roots = rustls::RootCertStore::empty();
+                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
+                    |anchor| {
+                        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                            anchor.subject,
+                            anchor.spki,
+                            anchor.name_constraints,
+                        )
+                    },
+                ));
+                rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth()
+            }
+        };
+        tls.client_auth_cert_resolver = resolver;
+        self.tls(Arc::new(tls))
+    }
+
+    /// Maximum random delay added before reconnecting, when the server closes the connection with
+    /// [`CloseCode::Restart`] or [`CloseCode::Again`], so that clients of a draining server don't reconnect all at once.
+    pub fn restart_jitter(mut self, restart_jitter: Duration) -> Self {
+        self.restart_jitter = restart_jitter;
+        self
+    }
+
+    /// Periodically re-resolves the host of the URL, and once the address of the current connection
+    /// is no longer advertised, reconnects to a fresh address as soon as the connection is quiet.
+    pub fn endpoint_refresh(mut self, interval: Duration) -> Self {
+        self.endpoint_refresh = Some(interval);
+        self
+    }
+
+    /// Resolver used by [`ClientConfig::endpoint_refresh`] instead of the system resolver.
+    pub fn endpoint_resolver(mut self, resolver: Arc<dyn EndpointResolver>) -> Self {
+        self.endpoint_resolver = Some(resolver);
+        self
+    }
+
+    /// Periodically asks [`ClientExt::refresh_credentials`] for a new bearer token, which should be shorter than the token lifetime.
+    ///
+    /// The token is sent to the server with [`ClientExt::reauth_message`], or by replacing the connection if there's no such message.
+    /// It's also refreshed before every reconnection attempt, so that reconnecting never uses an expired token.
+    pub fn credentials_refresh(mut self, interval: Duration) -> Self {
+        self.credentials_refresh = Some(interval);
+        self
+    }
+
+    /// Matches the responses of the server to the calls made with [`Client::call_idempotent`].
+    pub fn idempotency_key(mut self, key: IdempotencyKey) -> Self {
+        self.idempotency_key = Some(key);
+        self
+    }
+
+    /// Configuration of the underlying socket, applied on every (re)connection.
+    pub fn socket_config(mut self, socket_config: SocketConfig) -> Self {
+        self.socket_config = socket_config;
+        self
+    }
+
+    /// Spawns the client and socket actors on `runtime` instead of the current runtime.
+    pub fn runtime(mut self, runtime: tokio::runtime::Handle) -> Self {
+        self.socket_config.runtime = Some(runtime.clone());
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Records every frame received from the server, with its timing, to the file at `path`,
+    /// so that the session can be played back later with [`ClientConfig::replay`].
+    ///
+    /// The file is replaced when connecting, and frames keep being appended to it across reconnections.
+    pub fn record(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record = Some(path.into());
+        self
+    }
+
+    /// Plays back the recording at `path`, made with [`ClientConfig::record`], instead of connecting to the server,
+    /// e.g. to develop against a captured production feed offline.
+    ///
+    /// Frames reach the handlers with their original timing, messages sent by the client are discarded,
+    /// and the client stops once the recording ends.
+    pub fn replay(mut self, path: impl Into<PathBuf>) -> Self {
+        self.replay = Some(path.into());
+        self
+    }
+
+    /// Whether the connection should be gracefully closed once the last `Client` handle returned from `connect` is dropped.
+    /// Enabled by default.
+    pub fn close_on_drop(mut self, close_on_drop: bool) -> Self {
+        self.close_on_drop = close_on_drop;
+        self
+    }
+
     pub fn basic(mut self, username: &str, password: &str) -> Self {
         let credentials = base64::encode(format!("{username}:{password}"));
         self.headers.insert(
@@ -38,6 +211,24 @@ impl ClientConfig {
         self
     }
 
+    /// Authenticates with a bearer token, e.g. a JWT, see [`ClientConfig::credentials_refresh`] to keep it fresh.
+    pub fn bearer(mut self, token: &str) -> Self {
+        self.headers.insert(
+            http::header::AUTHORIZATION,
+            http::HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        self
+    }
+
+    /// Propagates `trace_context` to the server through the `traceparent` header of the upgrade request.
+    pub fn trace_context(mut self, trace_context: &TraceContext) -> Self {
+        self.headers.insert(
+            TRACEPARENT,
+            http::HeaderValue::from_str(&trace_context.to_string()).unwrap(),
+        );
+        self
+    }
+
     fn connect_http_request(&self) -> http::Request<()> {
         let mut http_request = http::Request::builder()
             .uri(self.url.as_str())
@@ -59,19 +250,248 @@ impl ClientConfig {
     }
 }
 
+/// Resolves the host of the URL for [`ClientConfig::endpoint_refresh`], e.g. from a service registry rather than DNS.
+#[async_trait]
+pub trait EndpointResolver: std::fmt::Debug + Send + Sync {
+    async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>>;
+}
+
+/// Handshake response expected from the server, see [`connect_checked`].
+#[derive(Debug, Clone, Default)]
+pub struct ResponseExpectations {
+    status: Option<u16>,
+    subprotocol: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl ResponseExpectations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Status code of the response, e.g. to check the error returned by a server refusing the connection.
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Subprotocol selected by the server with the `Sec-WebSocket-Protocol` header.
+    pub fn subprotocol(mut self, subprotocol: impl Into<String>) -> Self {
+        self.subprotocol = Some(subprotocol.into());
+        self
+    }
+
+    /// Header the response must have with the given value.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    fn check(
+        &self,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+    ) -> Result<(), ResponseMismatch> {
+        let mut mismatches = Vec::new();
+        if let Some(expected) = self.status {
+            if status.as_u16() != expected {
+                mismatches.push(Mismatch {
+                    field: String::from("status"),
+                    expected: expected.to_string(),
+                    actual: Some(status.as_u16().to_string()),
+                });
+            }
+        }
+        let subprotocol = self
+            .subprotocol
+            .iter()
+            .map(|subprotocol| ("Sec-WebSocket-Protocol", subprotocol.as_str()));
+        let headers_expected = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+        for (name, expected) in subprotocol.chain(headers_expected) {
+            let actual = headers
+                .get(name)
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+            if actual.as_deref() != Some(expected) {
+                mismatches.push(Mismatch {
+                    field: name.to_string(),
+                    expected: expected.to_string(),
+                    actual,
+                });
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(ResponseMismatch { mismatches })
+        }
+    }
+}
+
+/// Part of the handshake response which doesn't meet the [`ResponseExpectations`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// `status`, or the name of the header.
+    pub field: String,
+    pub expected: String,
+    /// `None` if the header is missing.
+    pub actual: Option<String>,
+}
+
+/// Error the client fails with when the handshake response doesn't meet the [`ResponseExpectations`], see [`connect_checked`].
+#[derive(Debug, Clone)]
+pub struct ResponseMismatch {
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl std::fmt::Display for ResponseMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unexpected handshake response")?;
+        for (i, mismatch) in self.mismatches.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            write!(
+                f,
+                "{separator}{} is {} instead of {}",
+                mismatch.field,
+                mismatch.actual.as_deref().unwrap_or("missing"),
+                mismatch.expected
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ResponseMismatch {}
+
+/// Error the client fails with when the server refuses the upgrade with a response meeting the [`ResponseExpectations`],
+/// e.g. [`ResponseExpectations::status`] set to `403`, see [`connect_checked`].
+///
+/// Unlike the other errors, it means the server behaves as expected, rather than that the connection failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedRefusal {
+    /// Status code of the refusal.
+    pub status: u16,
+}
+
+impl std::fmt::Display for ExpectedRefusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "server refused the upgrade with status {} as expected",
+            self.status
+        )
+    }
+}
+
+impl std::error::Error for ExpectedRefusal {}
+
 #[async_trait]
 pub trait ClientExt: Send {
     type Params: std::fmt::Debug + Send;
 
     async fn text(&mut self, text: String) -> Result<(), Error>;
-    async fn binary(&mut self, bytes: Vec<u8>) -> Result<(), Error>;
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Error>;
+
+    /// Same as `text`, with metadata of the received message. Defaults to calling `text`.
+    async fn text_with_meta(&mut self, text: String, meta: MessageMeta) -> Result<(), Error> {
+        let _ = meta;
+        self.text(text).await
+    }
+
+    /// Same as `binary`, with metadata of the received message. Defaults to calling `binary`.
+    async fn binary_with_meta(&mut self, bytes: Bytes, meta: MessageMeta) -> Result<(), Error> {
+        let _ = meta;
+        self.binary(bytes).await
+    }
     async fn call(&mut self, params: Self::Params) -> Result<(), Error>;
+
+    /// Returns a fresh bearer token, called periodically with [`ClientConfig::credentials_refresh`] and before reconnecting.
+    /// Returning `None` keeps the current credentials.
+    async fn refresh_credentials(&mut self) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    /// Message re-authenticating the current connection with a refreshed `token`, in the format expected by the server.
+    /// Without such a message, which is the default, the connection is replaced by one using the new token.
+    fn reauth_message(&self, token: &str) -> Option<Message> {
+        let _ = token;
+        None
+    }
+
+    /// Called for every Ping frame received when [`SocketConfig::expose_control_frames`] is set,
+    /// after it was answered with a Pong.
+    async fn ping(&mut self, payload: Bytes) -> Result<(), Error> {
+        let _ = payload;
+        Ok(())
+    }
+
+    /// Called for every Pong frame received when [`SocketConfig::expose_control_frames`] is set,
+    /// e.g. to read a timestamp the peer put in its payload.
+    async fn pong(&mut self, payload: Bytes) -> Result<(), Error> {
+        let _ = payload;
+        Ok(())
+    }
+}
+
+/// Closes the connection once dropped, shared between all `Client` handles returned from `connect`.
+#[derive(Debug)]
+struct CloseGuard {
+    socket: mpsc::UnboundedSender<Message>,
+}
+
+impl Drop for CloseGuard {
+    fn drop(&mut self) {
+        tracing::debug!("all client handles dropped, closing connection");
+        let _ = self.socket.send(Message::Close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: String::from("client handle dropped"),
+        })));
+    }
+}
+
+/// Request sent with [`Client::call_idempotent`] which wasn't responded to yet.
+#[derive(Debug)]
+struct PendingCall {
+    key: String,
+    request: Message,
+    respond_to: oneshot::Sender<Message>,
+}
+
+type PendingCalls = Arc<Mutex<Vec<PendingCall>>>;
+
+#[derive(Debug, Default, Clone)]
+struct Addresses {
+    local: Option<SocketAddr>,
+    peer: Option<SocketAddr>,
+    tls: Option<TlsInfo>,
+}
+
+/// State of the connection of a [`Client`].
+#[derive(Debug, Clone)]
+pub enum ConnectionState {
+    /// Establishing the initial connection.
+    Connecting,
+    /// Connected to the server.
+    Connected { since: std::time::Instant },
+    /// Connection was lost, set as soon as it's noticed, `attempt` numbers the next attempt to reconnect, starting at 1.
+    Reconnecting { attempt: u64 },
+    /// Client stopped, either closed by the application, or because connecting failed.
+    Closed { reason: Option<CloseFrame> },
 }
 
 #[derive(Debug)]
 pub struct Client<E: ClientExt> {
     socket: mpsc::UnboundedSender<Message>,
     calls: mpsc::UnboundedSender<E::Params>,
+    guard: Option<Arc<CloseGuard>>,
+    addresses: Arc<Mutex<Addresses>>,
+    timings: Arc<HandlerTimingsRecorder>,
+    reconnects: Arc<AtomicU64>,
+    state: watch::Receiver<ConnectionState>,
+    idempotency_key: Option<IdempotencyKey>,
+    pending_calls: PendingCalls,
 }
 
 impl<E: ClientExt> Clone for Client<E> {
@@ -79,6 +499,13 @@ impl<E: ClientExt> Clone for Client<E> {
         Self {
             socket: self.socket.clone(),
             calls: self.calls.clone(),
+            guard: self.guard.clone(),
+            addresses: self.addresses.clone(),
+            reconnects: self.reconnects.clone(),
+            timings: self.timings.clone(),
+            state: self.state.clone(),
+            idempotency_key: self.idempotency_key.clone(),
+            pending_calls: self.pending_calls.clone(),
         }
     }
 }
@@ -90,29 +517,131 @@ impl<E: ClientExt> From<Client<E>> for mpsc::UnboundedSender<E::Params> {
 }
 
 impl<E: ClientExt> Client<E> {
-    pub fn text(&self, text: String) {
-        self.socket.send(Message::Text(text)).unwrap();
+    /// Local address of the current connection, `None` if not connected yet.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addresses.lock().unwrap().local
+    }
+
+    /// Address of the server for the current connection, `None` if not connected yet.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.addresses.lock().unwrap().peer
+    }
+
+    /// Parameters of the TLS handshake of the current connection, `None` if it isn't encrypted or not connected yet.
+    pub fn tls(&self) -> Option<TlsInfo> {
+        self.addresses.lock().unwrap().tls.clone()
+    }
+
+    /// Current state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        self.state.borrow().clone()
+    }
+
+    /// Receiver notified on every state change of the connection.
+    pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Number of times the client reconnected after losing the connection.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
     }
 
-    pub fn binary(&self, bytes: Vec<u8>) {
-        self.socket.send(Message::Binary(bytes)).unwrap();
+    /// Time spent inside each of the handler callbacks of this client.
+    pub fn handler_timings(&self) -> HandlerTimings {
+        self.timings.snapshot()
     }
 
+    /// Sends a text message, returning it back if the client actor already stopped.
+    pub fn text(&self, text: String) -> Result<(), SendError<Message>> {
+        self.socket
+            .send(Message::Text(text))
+            .map_err(|err| SendError(err.0))
+    }
+
+    /// Sends a binary message, returning it back if the client actor already stopped.
+    pub fn binary(&self, bytes: impl Into<Bytes>) -> Result<(), SendError<Message>> {
+        self.socket
+            .send(Message::Binary(bytes.into()))
+            .map_err(|err| SendError(err.0))
+    }
+
+    /// Sends the request, and sends it again after every reconnection until the server responds to it, returns the response.
+    ///
+    /// The response is the first message received with the idempotency `key` of the request, as extracted by
+    /// [`ClientConfig::idempotency_key`], and isn't passed to the handlers of the client. The request may reach
+    /// the server more than once, which handles it only once with [`Idempotency`](crate::Idempotency).
+    ///
+    /// Returns the request back if [`ClientConfig::idempotency_key`] isn't set,
+    /// or if the client actor stops before the response is received.
+    pub async fn call_idempotent(
+        &self,
+        key: impl Into<String>,
+        request: Message,
+    ) -> Result<Message, SendError<Message>> {
+        if self.idempotency_key.is_none() {
+            return Err(SendError(request));
+        }
+        let key = key.into();
+        let (sender, receiver) = oneshot::channel();
+        self.pending_calls.lock().unwrap().push(PendingCall {
+            key: key.clone(),
+            request: request.clone(),
+            respond_to: sender,
+        });
+        if self.socket.send(request.clone()).is_err() {
+            self.pending_calls
+                .lock()
+                .unwrap()
+                .retain(|call| call.key != key);
+            return Err(SendError(request));
+        }
+        receiver.await.map_err(|_| SendError(request))
+    }
+
+    /// Calls the client actor, the call is dropped if the client actor already stopped.
     pub fn call(&self, message: E::Params) {
-        self.calls.send(message).unwrap();
+        if self.calls.send(message).is_err() {
+            tracing::warn!("client actor stopped, dropping call");
+        }
+    }
+
+    /// Same as `call`, but silently ignores calls made after the client actor has stopped.
+    pub(crate) fn call_if_alive(&self, message: E::Params) {
+        let _ = self.calls.send(message);
+    }
+
+    /// Closes the connection with the given close frame, without reconnecting.
+    pub fn close(&self, frame: Option<CloseFrame>) {
+        let _ = self.socket.send(Message::Close(frame));
+    }
+
+    /// Gracefully closes the connection and waits until the server answered the Close frame and the client actor has stopped.
+    pub async fn shutdown(&self) {
+        self.close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: String::from("client shutdown"),
+        }));
+        self.socket.closed().await;
     }
 
     /// Calls a method on the session, allowing the Session to respond with oneshot::Sender.
     /// This is just for easier construction of the Params which happen to contain oneshot::Sender in it.
+    ///
+    /// Fails if the client actor stopped, or dropped the sender without responding.
     pub async fn call_with<R: std::fmt::Debug>(
         &self,
         f: impl FnOnce(oneshot::Sender<R>) -> E::Params,
-    ) -> R {
+    ) -> Result<R, Error> {
         let (sender, receiver) = oneshot::channel();
         let params = f(sender);
 
-        self.calls.send(params).unwrap();
-        receiver.await.unwrap()
+        self.calls
+            .send(params)
+            .map_err(|_| "client actor stopped")?;
+        Ok(receiver
+            .await
+            .map_err(|_| "client dropped the call without responding")?)
     }
 }
 
@@ -122,39 +651,281 @@ pub async fn connect<E: ClientExt + 'static>(
 ) -> (Client<E>, impl Future<Output = Result<(), Error>>) {
     let (socket_sender, socket_receiver) = mpsc::unbounded_channel();
     let (call_sender, call_receiver) = mpsc::unbounded_channel();
-    let handle = Client {
+    let (state, state_receiver) = watch::channel(ConnectionState::Connecting);
+    let mut handle = Client {
         socket: socket_sender,
         calls: call_sender,
+        guard: None,
+        addresses: Default::default(),
+        timings: Default::default(),
+        reconnects: Default::default(),
+        state: state_receiver,
+        idempotency_key: config.idempotency_key.clone(),
+        pending_calls: Default::default(),
     };
     let client = client_fn(handle.clone());
-    let future = tokio::spawn(async move {
-        let http_request = config.connect_http_request();
-        tracing::info!("connecting to {}...", config.url);
-        let (stream, _) = tokio_tungstenite::connect_async(http_request).await?;
-        let socket = Socket::new(stream, Config::default());
-        tracing::info!("connected to {}", config.url);
-        let mut actor = ClientActor {
-            client,
-            socket_receiver,
-            call_receiver,
-            socket,
-            heartbeat: Instant::now(),
-            config,
-        };
-        actor.run().await?;
-        Ok(())
-    });
-    let future = async move { future.await.unwrap() };
+    if config.close_on_drop {
+        handle.guard = Some(Arc::new(CloseGuard {
+            socket: handle.socket.clone(),
+        }));
+    }
+    let addresses = handle.addresses.clone();
+    let timings = handle.timings.clone();
+    let reconnects = handle.reconnects.clone();
+    let pending_calls = handle.pending_calls.clone();
+    let runtime = config.runtime.clone();
+    let span = tracing::info_span!(
+        "client",
+        url = %config.url,
+        otel.kind = tracing::field::Empty,
+        network.protocol.name = tracing::field::Empty,
+        network.peer.address = tracing::field::Empty,
+        network.peer.port = tracing::field::Empty,
+    );
+    let future = crate::runtime::spawn(
+        "ezsockets::client",
+        runtime.as_ref(),
+        async move {
+            let mut config = config;
+            if let (Some(path), None) = (&config.record, &config.replay) {
+                match crate::replay::record(path) {
+                    Ok(recorder) => config.socket_config.middleware.insert(0, recorder),
+                    Err(err) => {
+                        let _ = state.send(ConnectionState::Closed { reason: None });
+                        pending_calls.lock().unwrap().clear();
+                        return Err(err);
+                    }
+                }
+            }
+            tracing::info!("connecting to {}...", config.url);
+            let socket = match connect_socket(&config, None).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    let _ = state.send(ConnectionState::Closed { reason: None });
+                    pending_calls.lock().unwrap().clear();
+                    return Err(err);
+                }
+            };
+            tracing::info!("connected to {}", config.url);
+            let mut actor = ClientActor {
+                addresses,
+                timings,
+                reconnects,
+                pending_calls,
+                state,
+                client,
+                socket_receiver,
+                call_receiver,
+                socket,
+                heartbeat: Instant::now(),
+                last_activity: Instant::now(),
+                endpoint_refresh: config.endpoint_refresh.map(tokio::time::interval),
+                credentials_refresh: config.credentials_refresh.map(|interval| {
+                    tokio::time::interval_at(tokio::time::Instant::now() + interval, interval)
+                }),
+                stale_endpoint: false,
+                endpoint: None,
+                replacement: None,
+                retiring: None,
+                config,
+            };
+            actor.connected();
+            let result = actor.run().await;
+            if !matches!(*actor.state.borrow(), ConnectionState::Closed { .. }) {
+                let _ = actor.state.send(ConnectionState::Closed { reason: None });
+            }
+            // Fails the pending `call_idempotent` calls.
+            actor.pending_calls.lock().unwrap().clear();
+            result
+        }
+        .instrument(span),
+    );
+    let future = async move { future.await? };
     (handle, future)
 }
 
+/// Same as [`connect`], but fails if the handshake response of the server doesn't meet `expectations`,
+/// with a [`ResponseMismatch`] listing the differences, e.g. to test the contract of a third-party server.
+/// If the server refuses the upgrade as expected, the client fails with [`ExpectedRefusal`] instead.
+///
+/// The response of every reconnection is checked as well.
+pub async fn connect_checked<E: ClientExt + 'static>(
+    client_fn: impl FnOnce(Client<E>) -> E,
+    mut config: ClientConfig,
+    expectations: ResponseExpectations,
+) -> (Client<E>, impl Future<Output = Result<(), Error>>) {
+    config.expectations = Some(expectations);
+    connect(client_fn, config).await
+}
+
+/// Connects to the URL of `config`, at `address` if given instead of an address resolved by the system.
+async fn connect_socket(
+    config: &ClientConfig,
+    address: Option<SocketAddr>,
+) -> Result<Socket, Error> {
+    if let Some(path) = &config.replay {
+        return crate::replay::replay(path.clone(), config.socket_config.clone()).await;
+    }
+    let http_request = config.connect_http_request();
+    let result = match address {
+        Some(address) => connect_to(config, http_request, address).await,
+        #[cfg(feature = "rustls")]
+        None => {
+            tokio_tungstenite::connect_async_tls_with_config(
+                http_request,
+                config.socket_config.websocket_config(),
+                config.tls.clone().map(tokio_tungstenite::Connector::Rustls),
+            )
+            .await
+        }
+        #[cfg(not(feature = "rustls"))]
+        None => {
+            tokio_tungstenite::connect_async_with_config(
+                http_request,
+                config.socket_config.websocket_config(),
+            )
+            .await
+        }
+    };
+    let (stream, response) = match (result, &config.expectations) {
+        (Ok(connected), _) => connected,
+        // The server refused the upgrade, its response may be expected nonetheless.
+        (Err(tungstenite::Error::Http(response)), Some(expectations)) => {
+            expectations.check(response.status(), response.headers())?;
+            return Err(ExpectedRefusal {
+                status: response.status().as_u16(),
+            }
+            .into());
+        }
+        (Err(err), _) => return Err(err.into()),
+    };
+    if let Some(expectations) = &config.expectations {
+        expectations.check(response.status(), response.headers())?;
+    }
+    Ok(new_socket(stream, config))
+}
+
+/// Opens the WebSocket connection of `http_request` over a TCP connection to `address`,
+/// the host of the URL is still used for the `Host` header and the TLS server name.
+async fn connect_to(
+    config: &ClientConfig,
+    http_request: http::Request<()>,
+    address: SocketAddr,
+) -> Result<
+    (
+        WebSocketStream<MaybeTlsStream<TcpStream>>,
+        tungstenite::handshake::client::Response,
+    ),
+    tungstenite::Error,
+> {
+    let stream = TcpStream::connect(address).await?;
+    #[cfg(feature = "rustls")]
+    let result = tokio_tungstenite::client_async_tls_with_config(
+        http_request,
+        stream,
+        config.socket_config.websocket_config(),
+        config.tls.clone().map(tokio_tungstenite::Connector::Rustls),
+    )
+    .await;
+    #[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+    let result = tokio_tungstenite::client_async_tls_with_config(
+        http_request,
+        stream,
+        config.socket_config.websocket_config(),
+        None,
+    )
+    .await;
+    #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+    let result = tokio_tungstenite::client_async_with_config(
+        http_request,
+        MaybeTlsStream::Plain(stream),
+        config.socket_config.websocket_config(),
+    )
+    .await;
+    result
+}
+
+fn new_socket(stream: WebSocketStream<MaybeTlsStream<TcpStream>>, config: &ClientConfig) -> Socket {
+    // The host of the URL is sent as SNI, unless it's an IP address.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    let server_name = config.url.domain().map(String::from);
+    let (tcp, tls) = match stream.get_ref() {
+        MaybeTlsStream::Plain(stream) => (Some(stream), None),
+        #[cfg(feature = "native-tls")]
+        MaybeTlsStream::NativeTls(stream) => {
+            let tls = TlsInfo {
+                server_name,
+                alpn_protocol: None,
+            };
+            (Some(stream.get_ref().get_ref().get_ref()), Some(tls))
+        }
+        #[cfg(feature = "rustls")]
+        MaybeTlsStream::Rustls(stream) => {
+            let tls = TlsInfo {
+                server_name,
+                alpn_protocol: stream.get_ref().1.alpn_protocol().map(Vec::from),
+            };
+            (Some(stream.get_ref().0), Some(tls))
+        }
+        _ => (None, None),
+    };
+    let local_addr = tcp.and_then(|stream| stream.local_addr().ok());
+    let peer_addr = tcp.and_then(|stream| stream.peer_addr().ok());
+    Socket::new(stream, config.socket_config.clone())
+        .with_addresses(local_addr, peer_addr)
+        .with_tls(tls)
+}
+
 struct ClientActor<E: ClientExt> {
+    addresses: Arc<Mutex<Addresses>>,
+    timings: Arc<HandlerTimingsRecorder>,
+    reconnects: Arc<AtomicU64>,
+    pending_calls: PendingCalls,
+    state: watch::Sender<ConnectionState>,
     client: E,
     socket_receiver: mpsc::UnboundedReceiver<Message>,
     call_receiver: mpsc::UnboundedReceiver<E::Params>,
     socket: Socket,
     config: ClientConfig,
     heartbeat: Instant,
+    last_activity: Instant,
+    endpoint_refresh: Option<tokio::time::Interval>,
+    credentials_refresh: Option<tokio::time::Interval>,
+    stale_endpoint: bool,
+    /// Address advertised instead of the stale endpoint, connected to when replacing the connection.
+    endpoint: Option<SocketAddr>,
+    /// Connection being opened in the background to replace the current one.
+    replacement: Option<Replacement>,
+    /// Replaced connection, read until the server completes its close handshake.
+    retiring: Option<Socket>,
+}
+
+struct Replacement {
+    reason: &'static str,
+    socket: oneshot::Receiver<Result<Socket, Error>>,
+}
+
+/// Waits for the replacement connection to be opened, or forever if none is being opened.
+async fn replaced(replacement: &mut Option<Replacement>) -> (&'static str, Result<Socket, Error>) {
+    match replacement {
+        Some(replacement) => {
+            let socket = (&mut replacement.socket)
+                .await
+                .unwrap_or_else(|_| Err("connection task was cancelled".into()));
+            (replacement.reason, socket)
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Receives from the replaced connection, or waits forever if there's none.
+async fn recv_retiring(
+    socket: &mut Option<Socket>,
+) -> Option<Result<(Message, MessageMeta), Error>> {
+    match socket {
+        Some(socket) => socket.stream.recv_with_meta().await,
+        None => std::future::pending().await,
+    }
 }
 
 impl<E: ClientExt> ClientActor<E> {
@@ -162,22 +933,68 @@ impl<E: ClientExt> ClientActor<E> {
         loop {
             tokio::select! {
                 Some(message) = self.socket_receiver.recv() => {
-                    self.socket.send(message.clone()).await;
-                    if let Message::Close(_frame) = message {
+                    self.last_activity = Instant::now();
+                    let _ = self.socket.send(message.clone()).await;
+                    if let Message::Close(frame) = message {
+                        self.socket.stream.closed().await;
+                        let _ = self.state.send(ConnectionState::Closed { reason: frame });
                         return Ok(())
                     }
                 }
                 Some(params) = self.call_receiver.recv() => {
-                    self.client.call(params).await?;
+                    let started_at = Instant::now();
+                    let result = self.client.call(params).await;
+                    self.timings.call.record(started_at.elapsed());
+                    result?;
+                }
+                _ = tick(&mut self.endpoint_refresh) => {
+                    self.refresh_endpoint().await;
+                }
+                _ = tick(&mut self.credentials_refresh) => {
+                    self.reauthenticate().await;
                 }
-                result = self.socket.stream.recv() => {
+                (reason, result) = replaced(&mut self.replacement) => {
+                    self.replacement = None;
+                    self.replaced(reason, result).await;
+                }
+                result = recv_retiring(&mut self.retiring) => {
                     match result {
-                        Some(Ok(message)) => {
-                             match message.to_owned() {
-                                Message::Text(text) => self.client.text(text).await?,
-                                Message::Binary(bytes) => self.client.binary(bytes).await?,
-                                Message::Close(_frame) => {
-                                    self.reconnect().await;
+                        Some(Ok((message @ (Message::Text(_) | Message::Binary(_)), meta))) => {
+                            if !self.respond_to_call(&message) {
+                                self.received(message, meta).await?;
+                            }
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(_)) | None => {
+                            tracing::debug!("replaced connection closed");
+                            self.retiring = None;
+                        }
+                    }
+                }
+                result = self.socket.stream.recv_with_meta() => {
+                    match result {
+                        Some(Ok((message, meta))) => {
+                            self.last_activity = Instant::now();
+                            if self.respond_to_call(&message) {
+                                continue;
+                            }
+                             match message {
+                                Message::Text(_) | Message::Binary(_) => self.received(message, meta).await?,
+                                Message::Ping(payload) => self.client.ping(payload).await?,
+                                Message::Pong(payload) => self.client.pong(payload).await?,
+                                Message::Close(frame) if self.config.replay.is_some() => {
+                                    tracing::info!("replay finished");
+                                    let _ = self.state.send(ConnectionState::Closed { reason: frame });
+                                    return Ok(());
+                                }
+                                Message::Close(frame) => {
+                                    let jitter = match frame {
+                                        Some(CloseFrame { code: CloseCode::Restart | CloseCode::Again, .. }) => {
+                                            random_duration(self.config.restart_jitter)
+                                        }
+                                        _ => Duration::ZERO,
+                                    };
+                                    self.reconnect(jitter).await?;
                                 }
                             };
                         }
@@ -185,7 +1002,7 @@ impl<E: ClientExt> ClientActor<E> {
                             tracing::error!("connection error: {error}");
                         }
                         None => {
-                            self.reconnect().await;
+                            self.reconnect(Duration::ZERO).await?;
                         }
                     };
                 }
@@ -196,24 +1013,238 @@ impl<E: ClientExt> ClientActor<E> {
         Ok(())
     }
 
-    async fn reconnect(&mut self) {
-        let reconnect_interval = self
-            .config
-            .reconnect_interval
-            .expect("reconnect interval should be set for reconnecting");
-        tracing::info!("reconnecting in {}s", reconnect_interval.as_secs());
-        for i in 1.. {
+    /// Hands a received text or binary message to the client.
+    async fn received(&mut self, message: Message, meta: MessageMeta) -> Result<(), Error> {
+        let started_at = Instant::now();
+        match message {
+            Message::Text(text) => {
+                let result = self.client.text_with_meta(text, meta).await;
+                self.timings.text.record(started_at.elapsed());
+                result
+            }
+            Message::Binary(bytes) => {
+                let result = self.client.binary_with_meta(bytes, meta).await;
+                self.timings.binary.record(started_at.elapsed());
+                result
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Updates the addresses and the state after establishing a new connection.
+    fn connected(&self) {
+        *self.addresses.lock().unwrap() = Addresses {
+            local: self.socket.local_addr(),
+            peer: self.socket.peer_addr(),
+            tls: self.socket.tls().cloned(),
+        };
+        let _ = self.state.send(ConnectionState::Connected {
+            since: std::time::Instant::now(),
+        });
+        #[cfg(feature = "otel")]
+        crate::otel::record_connection(
+            &tracing::Span::current(),
+            "client",
+            self.socket.peer_addr(),
+        );
+    }
+
+    /// Checks whether the address of the current connection is still advertised by DNS,
+    /// and replaces the connection if it isn't and the connection is quiet.
+    async fn refresh_endpoint(&mut self) {
+        if !self.stale_endpoint {
+            let (host, port) = match (
+                self.config.url.host_str(),
+                self.config.url.port_or_known_default(),
+            ) {
+                (Some(host), Some(port)) => (host, port),
+                _ => return,
+            };
+            let peer = match self.socket.peer_addr() {
+                Some(peer) => peer,
+                None => {
+                    tracing::debug!("skipping endpoint refresh, the peer address is unknown");
+                    return;
+                }
+            };
+            let addresses = match &self.config.endpoint_resolver {
+                Some(resolver) => resolver.resolve(host, port).await,
+                None => tokio::net::lookup_host((host, port))
+                    .await
+                    .map(|addresses| addresses.collect()),
+            };
+            match addresses {
+                Ok(addresses) => {
+                    self.stale_endpoint =
+                        !addresses.iter().any(|address| address.ip() == peer.ip());
+                    self.endpoint = addresses.first().copied().filter(|_| self.stale_endpoint);
+                }
+                Err(err) => {
+                    tracing::warn!("failed to resolve {host}: {err}");
+                    return;
+                }
+            }
+            if self.stale_endpoint {
+                tracing::info!("{} is no longer advertised for {host}", peer.ip());
+            }
+        }
+        if self.stale_endpoint && self.last_activity.elapsed() >= QUIET_PERIOD {
+            self.replace_connection("endpoint refresh");
+        }
+    }
+
+    /// Starts opening a new connection in the background, to the fresh endpoint if the current one is stale.
+    /// Once it's open, the current connection is gracefully closed with `reason`.
+    fn replace_connection(&mut self, reason: &'static str) {
+        if self.replacement.is_some() {
+            return;
+        }
+        let (sender, receiver) = oneshot::channel();
+        let config = self.config.clone();
+        let address = self.endpoint;
+        tokio::spawn(
+            async move {
+                let _ = sender.send(connect_socket(&config, address).await);
+            }
+            .instrument(tracing::Span::current()),
+        );
+        self.replacement = Some(Replacement {
+            reason,
+            socket: receiver,
+        });
+    }
+
+    /// Switches to the replacement connection, then starts the close handshake of the replaced one,
+    /// which is still read until it completes.
+    async fn replaced(&mut self, reason: &'static str, result: Result<Socket, Error>) {
+        let socket = match result {
+            Ok(socket) => socket,
+            Err(err) => {
+                tracing::warn!("failed to replace connection on {reason}: {err}");
+                return;
+            }
+        };
+        let socket = std::mem::replace(&mut self.socket, socket);
+        let _ = socket
+            .send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: reason.to_string(),
+            })))
+            .await;
+        self.retiring = Some(socket);
+        self.stale_endpoint = false;
+        self.endpoint = None;
+        self.connected();
+        self.heartbeat = Instant::now();
+        self.resend_pending_calls().await;
+        tracing::info!("replaced connection on {reason}");
+    }
+
+    /// Sends again the requests of [`Client::call_idempotent`] which weren't responded to, after a new connection was established.
+    async fn resend_pending_calls(&mut self) {
+        let messages: Vec<Message> = self
+            .pending_calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|call| call.request.clone())
+            .collect();
+        if !messages.is_empty() {
+            tracing::debug!("resending {} pending calls", messages.len());
+        }
+        for message in messages {
+            let _ = self.socket.send(message).await;
+        }
+    }
+
+    /// Hands the received message to the call of [`Client::call_idempotent`] it responds to, returns `false` if there's none.
+    fn respond_to_call(&self, message: &Message) -> bool {
+        let key = match (&self.config.idempotency_key, message) {
+            (Some(key), Message::Text(_) | Message::Binary(_)) => key.get(message),
+            _ => None,
+        };
+        let key = match key {
+            Some(key) => key,
+            None => return false,
+        };
+        let mut pending_calls = self.pending_calls.lock().unwrap();
+        let index = match pending_calls.iter().position(|call| call.key == key) {
+            Some(index) => index,
+            None => return false,
+        };
+        let call = pending_calls.remove(index);
+        let _ = call.respond_to.send(message.clone());
+        true
+    }
+
+    /// Asks for a fresh bearer token and stores it for the next connections, returns it if it changed.
+    async fn refresh_credentials(&mut self) -> Result<Option<String>, Error> {
+        let token = match self.client.refresh_credentials().await? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let value = http::HeaderValue::from_str(&format!("Bearer {token}"))?;
+        self.config
+            .headers
+            .insert(http::header::AUTHORIZATION, value);
+        Ok(Some(token))
+    }
+
+    /// Refreshes the credentials and re-authenticates the current connection with them,
+    /// failures are retried on the next tick.
+    async fn reauthenticate(&mut self) {
+        let token = match self.refresh_credentials().await {
+            Ok(Some(token)) => token,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::warn!("failed to refresh credentials, retrying on the next tick: {err}");
+                return;
+            }
+        };
+        match self.client.reauth_message(&token) {
+            Some(message) => {
+                tracing::debug!("re-authenticating with refreshed credentials");
+                let _ = self.socket.send(message).await;
+            }
+            None => self.replace_connection("credentials refresh"),
+        }
+    }
+
+    /// Reconnects, waiting additional `jitter` before the first attempt.
+    async fn reconnect(&mut self, jitter: Duration) -> Result<(), Error> {
+        let reconnect_interval = match self.config.reconnect_interval {
+            Some(reconnect_interval) => reconnect_interval,
+            None => return Err("connection closed and reconnecting is disabled".into()),
+        };
+        // The replacement would close the connection about to be reopened.
+        self.replacement = None;
+        tracing::info!(
+            "reconnecting in {}s",
+            (reconnect_interval + jitter).as_secs()
+        );
+        let mut i = 1;
+        let _ = self
+            .state
+            .send(ConnectionState::Reconnecting { attempt: i });
+        tokio::time::sleep(jitter).await;
+        loop {
             tokio::time::sleep(reconnect_interval).await;
+            if self.credentials_refresh.is_some() {
+                if let Err(err) = self.refresh_credentials().await {
+                    tracing::warn!("failed to refresh credentials before reconnecting: {err}");
+                }
+            }
             tracing::info!("reconnecting attempt no: {}...", i);
-            let connect_http_request = self.config.connect_http_request();
-            let result = tokio_tungstenite::connect_async(connect_http_request).await;
+            let result = connect_socket(&self.config, None).await;
             match result {
-                Ok((socket, _)) => {
+                Ok(socket) => {
                     tracing::info!("successfully reconnected");
-                    let socket = Socket::new(socket, Config::default());
+                    self.reconnects.fetch_add(1, Ordering::Relaxed);
                     self.socket = socket;
+                    self.connected();
                     self.heartbeat = Instant::now();
-                    return;
+                    self.resend_pending_calls().await;
+                    return Ok(());
                 }
                 Err(err) => {
                     tracing::error!(
@@ -221,6 +1252,10 @@ impl<E: ClientExt> ClientActor<E> {
                         err,
                         reconnect_interval.as_secs()
                     );
+                    i += 1;
+                    let _ = self
+                        .state
+                        .send(ConnectionState::Reconnecting { attempt: i });
                 }
             };
         }
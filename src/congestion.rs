@@ -0,0 +1,136 @@
+use crate::Message;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+type KindFn = Arc<dyn Fn(&Message) -> Option<String> + Send + Sync>;
+type ReduceFn = Arc<dyn Fn(&Message) -> Option<Message> + Send + Sync>;
+
+/// Write readiness of a connection, derived from the outbound queue depth and write progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionState {
+    /// Outbound queue is below the congestion threshold.
+    Ready,
+    /// Outbound queue is above the congestion threshold, but writes are still making progress.
+    Buffering,
+    /// A single write didn't complete within the stall timeout.
+    Stalled,
+}
+
+/// Reduced encodings of outgoing messages, registered per message type, which are sent instead of the full
+/// messages while the connection is [`CongestionState::Stalled`], e.g. a thumbnail instead of an image.
+///
+/// Messages of unregistered types are sent unchanged.
+#[derive(Clone)]
+pub struct ReducedEncodings {
+    kind: KindFn,
+    encodings: HashMap<String, ReduceFn>,
+}
+
+impl std::fmt::Debug for ReducedEncodings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReducedEncodings")
+            .field("kinds", &self.encodings.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReducedEncodings {
+    /// `kind` tells the type of a message, e.g. the `type` field of JSON messages, `None` if it has none.
+    pub fn new(kind: impl Fn(&Message) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            kind: Arc::new(kind),
+            encodings: HashMap::new(),
+        }
+    }
+
+    /// Registers the reduced encoding of the messages of type `kind`, `reduce` returning `None` drops the message.
+    pub fn register(
+        mut self,
+        kind: impl Into<String>,
+        reduce: impl Fn(&Message) -> Option<Message> + Send + Sync + 'static,
+    ) -> Self {
+        self.encodings.insert(kind.into(), Arc::new(reduce));
+        self
+    }
+
+    /// Reduced encoding of `message`, `None` if it's dropped.
+    pub fn reduce(&self, message: Message) -> Option<Message> {
+        let reduce = (self.kind)(&message).and_then(|kind| self.encodings.get(&kind));
+        match reduce {
+            Some(reduce) => reduce(&message),
+            None => Some(message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Congestion {
+    queued: AtomicUsize,
+    stalled: AtomicBool,
+    threshold: usize,
+    state: watch::Sender<CongestionState>,
+    // keeps the channel open, so that the state is updated even if nobody is watching
+    _receiver: watch::Receiver<CongestionState>,
+}
+
+impl Congestion {
+    pub(crate) fn new(threshold: usize) -> Self {
+        let (state, receiver) = watch::channel(CongestionState::Ready);
+        Self {
+            queued: AtomicUsize::new(0),
+            stalled: AtomicBool::new(false),
+            threshold,
+            state,
+            _receiver: receiver,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<CongestionState> {
+        self.state.subscribe()
+    }
+
+    /// Number of queued messages which weren't written yet.
+    pub(crate) fn buffered(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.update();
+    }
+
+    pub(crate) fn written(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.stalled.store(false, Ordering::Relaxed);
+        self.update();
+    }
+
+    /// Queued messages dropped by the send queue without being written.
+    pub(crate) fn discarded(&self, count: usize) {
+        self.queued.fetch_sub(count, Ordering::Relaxed);
+        self.update();
+    }
+
+    pub(crate) fn stalled(&self) {
+        self.stalled.store(true, Ordering::Relaxed);
+        self.update();
+    }
+
+    fn update(&self) {
+        let state = if self.stalled.load(Ordering::Relaxed) {
+            CongestionState::Stalled
+        } else if self.queued.load(Ordering::Relaxed) > self.threshold {
+            CongestionState::Buffering
+        } else {
+            CongestionState::Ready
+        };
+        if *self.state.borrow() != state {
+            tracing::debug!(?state, "congestion state changed");
+            let _ = self.state.send(state);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::Message;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+type KeyFn = Arc<dyn Fn(&Message) -> Option<String> + Send + Sync>;
+
+/// Configuration of the inbound deduplication window.
+///
+/// Messages are identified by the `key` function, messages for which it returns `None` are always delivered.
+/// A message is dropped if a message with the same key was received within the window,
+/// which spans at most `max_entries` keys received during the last `max_age`.
+#[derive(Clone)]
+pub struct Deduplication {
+    key: KeyFn,
+    max_entries: usize,
+    max_age: Duration,
+}
+
+impl std::fmt::Debug for Deduplication {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Deduplication")
+            .field("max_entries", &self.max_entries)
+            .field("max_age", &self.max_age)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Deduplication {
+    pub fn new(
+        key: impl Fn(&Message) -> Option<String> + Send + Sync + 'static,
+        max_entries: usize,
+        max_age: Duration,
+    ) -> Self {
+        Self {
+            key: Arc::new(key),
+            max_entries,
+            max_age,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct DeduplicationWindow {
+    config: Deduplication,
+    keys: HashSet<String>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl DeduplicationWindow {
+    pub(crate) fn new(config: Deduplication) -> Self {
+        Self {
+            config,
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns `true` if the message was already seen within the window, otherwise remembers it.
+    pub(crate) fn is_duplicate(&mut self, message: &Message) -> bool {
+        let key = match (self.config.key)(message) {
+            Some(key) => key,
+            None => return false,
+        };
+        let now = Instant::now();
+        while let Some((received_at, _)) = self.order.front() {
+            if self.order.len() < self.config.max_entries
+                && now.duration_since(*received_at) <= self.config.max_age
+            {
+                break;
+            }
+            let (_, key) = self.order.pop_front().unwrap();
+            self.keys.remove(&key);
+        }
+        if self.keys.contains(&key) {
+            return true;
+        }
+        self.keys.insert(key.clone());
+        self.order.push_back((now, key));
+        false
+    }
+}
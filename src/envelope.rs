@@ -0,0 +1,86 @@
+use crate::Message;
+
+/// Standard shape of application errors reported to clients, so that clients written in any language
+/// can handle them the same way.
+///
+/// Sent as a JSON text message:
+/// `{"type":"error","code":"...","message":"...","details":"...","correlation_id":"..."}`,
+/// where `details` and `correlation_id` are omitted if not set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorEnvelope {
+    /// Stable, machine readable identifier of the error, e.g. `invalid_request`.
+    pub code: String,
+    /// Human readable description of the error.
+    pub message: String,
+    pub details: Option<String>,
+    /// Identifier of the request which failed, as sent by the client, so that it can match the error with it.
+    pub correlation_id: Option<String>,
+}
+
+impl ErrorEnvelope {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+            details: None,
+            correlation_id: None,
+        }
+    }
+
+    pub fn details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+
+    pub fn correlation_id(mut self, correlation_id: impl Into<String>) -> Self {
+        self.correlation_id = Some(correlation_id.into());
+        self
+    }
+
+    /// Serializes the envelope, see [`ErrorEnvelope`] for its shape.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from(r#"{"type":"error","code":"#);
+        push_json_string(&mut json, &self.code);
+        json.push_str(r#","message":"#);
+        push_json_string(&mut json, &self.message);
+        if let Some(details) = &self.details {
+            json.push_str(r#","details":"#);
+            push_json_string(&mut json, details);
+        }
+        if let Some(correlation_id) = &self.correlation_id {
+            json.push_str(r#","correlation_id":"#);
+            push_json_string(&mut json, correlation_id);
+        }
+        json.push('}');
+        json
+    }
+}
+
+impl std::fmt::Display for ErrorEnvelope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl From<ErrorEnvelope> for Message {
+    fn from(envelope: ErrorEnvelope) -> Self {
+        Message::Text(envelope.to_json())
+    }
+}
+
+/// Appends `value` as a quoted JSON string.
+fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str(r#"\""#),
+            '\\' => json.push_str(r"\\"),
+            '\n' => json.push_str(r"\n"),
+            '\r' => json.push_str(r"\r"),
+            '\t' => json.push_str(r"\t"),
+            char if char.is_control() => json.push_str(&format!(r"\u{:04x}", char as u32)),
+            char => json.push(char),
+        }
+    }
+    json.push('"');
+}
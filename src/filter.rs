@@ -0,0 +1,43 @@
+use crate::Message;
+use crate::RawMessage;
+use std::sync::Arc;
+
+/// Outcome of a [`MessageFilter`].
+#[derive(Debug, Clone)]
+pub enum Filter {
+    /// Deliver the message to the handler as usual.
+    Deliver,
+    /// Silently drop the message.
+    Drop,
+    /// Drop the message and send the reply straight back, without involving the handler.
+    Fastpath(Message),
+}
+
+type FilterFn = Arc<dyn Fn(&RawMessage) -> Filter + Send + Sync>;
+
+/// Filter evaluated by the stream actor on every inbound message before it's dispatched to the handler.
+///
+/// Useful for trivial messages, like application level keepalives or echoes, which can be answered or
+/// dropped without a hop through the session or client actor.
+#[derive(Clone)]
+pub struct MessageFilter {
+    filter: FilterFn,
+}
+
+impl std::fmt::Debug for MessageFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MessageFilter").finish_non_exhaustive()
+    }
+}
+
+impl MessageFilter {
+    pub fn new(filter: impl Fn(&RawMessage) -> Filter + Send + Sync + 'static) -> Self {
+        Self {
+            filter: Arc::new(filter),
+        }
+    }
+
+    pub(crate) fn apply(&self, message: &RawMessage) -> Filter {
+        (self.filter)(message)
+    }
+}
@@ -0,0 +1,164 @@
+//! Handoff of live WebSocket connections to another process over a Unix socket, e.g. to upgrade a gateway
+//! without disconnecting its clients. Linux only, enabled with the `handoff` feature.
+//!
+//! The old process sends the file descriptor of each connection, see [`Socket::raw_fd`](crate::Socket::raw_fd),
+//! along with the state of its session serialized by the application, then exits without closing the connections.
+//! The new process receives them with [`recv`], and resumes them with
+//! [`tungstenite::resume`](crate::tungstenite::resume), building the session arguments from the state.
+//!
+//! Messages the old process already read from a connection but didn't handle are lost, so connections
+//! should be handed off while they're quiet, e.g. after asking clients to pause.
+
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+use tokio::net::UnixStream;
+
+/// Connection received from another process, see [`recv`].
+#[derive(Debug)]
+pub struct Handoff {
+    pub stream: TcpStream,
+    /// State of the session, as sent by the other process.
+    pub state: Vec<u8>,
+}
+
+/// Sends a duplicate of the connection `fd` over `channel`, along with the `state` of its session.
+///
+/// The connection stays open in this process until `fd` is closed, which doesn't affect the duplicate.
+pub async fn send(channel: &UnixStream, fd: RawFd, state: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(state.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "state is too large"))?;
+    let mut message = len.to_be_bytes().to_vec();
+    message.extend_from_slice(state);
+    let mut sent = 0;
+    while sent < message.len() {
+        channel.writable().await?;
+        // The descriptor is attached to the first byte only.
+        let fd = (sent == 0).then_some(fd);
+        match channel.try_io(Interest::WRITABLE, || {
+            send_with_fd(channel.as_raw_fd(), &message[sent..], fd)
+        }) {
+            Ok(len) => sent += len,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Receives a connection sent by [`send`] from another process.
+pub async fn recv(channel: &UnixStream) -> io::Result<Handoff> {
+    let mut fd = None;
+    let mut len = [0; 4];
+    recv_exact(channel, &mut len, &mut fd).await?;
+    let mut state = vec![0; u32::from_be_bytes(len) as usize];
+    recv_exact(channel, &mut state, &mut fd).await?;
+    let fd = fd
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no file descriptor received"))?;
+    let stream = std::net::TcpStream::from(fd);
+    stream.set_nonblocking(true)?;
+    Ok(Handoff {
+        stream: TcpStream::from_std(stream)?,
+        state,
+    })
+}
+
+async fn recv_exact(
+    channel: &UnixStream,
+    buffer: &mut [u8],
+    fd: &mut Option<OwnedFd>,
+) -> io::Result<()> {
+    let mut received = 0;
+    while received < buffer.len() {
+        channel.readable().await?;
+        match channel.try_io(Interest::READABLE, || {
+            recv_with_fd(channel.as_raw_fd(), &mut buffer[received..])
+        }) {
+            Ok((0, _)) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok((len, received_fd)) => {
+                received += len;
+                if received_fd.is_some() {
+                    *fd = received_fd;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Room for the control message carrying a single descriptor, aligned like `cmsghdr`.
+type ControlBuffer = [u64; 4];
+
+fn send_with_fd(socket: RawFd, data: &[u8], fd: Option<RawFd>) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control = ControlBuffer::default();
+    // SAFETY: `msghdr` is plain data, and the control message is written within `control`,
+    // which is large and aligned enough for a header followed by a single descriptor.
+    let sent = unsafe {
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        if let Some(fd) = fd {
+            let space = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as usize;
+            debug_assert!(space <= std::mem::size_of_val(&control));
+            message.msg_control = control.as_mut_ptr().cast();
+            message.msg_controllen = space as _;
+            let header = libc::CMSG_FIRSTHDR(&message);
+            (*header).cmsg_level = libc::SOL_SOCKET;
+            (*header).cmsg_type = libc::SCM_RIGHTS;
+            (*header).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(header).cast::<RawFd>(), fd);
+        }
+        libc::sendmsg(socket, &message, libc::MSG_NOSIGNAL)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
+
+fn recv_with_fd(socket: RawFd, buffer: &mut [u8]) -> io::Result<(usize, Option<OwnedFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr().cast(),
+        iov_len: buffer.len(),
+    };
+    let mut control = ControlBuffer::default();
+    // SAFETY: the kernel writes at most `msg_controllen` bytes of control messages into `control`,
+    // and the descriptors it passes are owned by this process from then on.
+    unsafe {
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = std::mem::size_of_val(&control) as _;
+        let received = libc::recvmsg(socket, &mut message, libc::MSG_CMSG_CLOEXEC);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut fd = None;
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let received_fd = std::ptr::read_unaligned(libc::CMSG_DATA(header).cast::<RawFd>());
+                fd = Some(OwnedFd::from_raw_fd(received_fd));
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+        if message.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected control messages",
+            ));
+        }
+        Ok((received as usize, fd))
+    }
+}
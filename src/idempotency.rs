@@ -0,0 +1,131 @@
+use crate::Error;
+use crate::Message;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+type KeyFn = Arc<dyn Fn(&Message) -> Option<String> + Send + Sync>;
+
+/// Extracts the idempotency key carried by requests and their responses, e.g. the `id` field of JSON messages.
+///
+/// A response carries the key of its request, so that clients can match responses to their calls,
+/// and servers can record responses to send them again to retried requests, see [`Idempotency`].
+#[derive(Clone)]
+pub struct IdempotencyKey(KeyFn);
+
+impl std::fmt::Debug for IdempotencyKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdempotencyKey").finish_non_exhaustive()
+    }
+}
+
+impl IdempotencyKey {
+    pub fn new(key: impl Fn(&Message) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(key))
+    }
+
+    /// Key of the message, `None` if it's neither a request nor a response.
+    pub fn get(&self, message: &Message) -> Option<String> {
+        (self.0)(message)
+    }
+}
+
+/// Request as recorded by an [`IdempotencyStore`].
+#[derive(Debug, Clone)]
+pub enum IdempotentRequest {
+    /// Received for the first time.
+    New,
+    /// Received before, but not responded to yet.
+    Pending,
+    /// Received before, and responded to with the message.
+    Completed(Message),
+}
+
+/// Storage of the requests received from an identity, shared by every session of an identity, and possibly by multiple servers.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Records the request `key` of `identity` as pending if it's new, returns how it was recorded before.
+    async fn begin(&self, identity: &str, key: &str) -> Result<IdempotentRequest, Error>;
+
+    /// Records `response` as the response to the pending request `key` of `identity`.
+    async fn complete(&self, identity: &str, key: &str, response: Message) -> Result<(), Error>;
+}
+
+#[derive(Debug)]
+struct RecordedRequest {
+    received_at: Instant,
+    response: Option<Message>,
+}
+
+/// In-memory [`IdempotencyStore`], shared by the sessions of a single process, forgetting requests after `ttl`.
+#[derive(Debug)]
+pub struct MemoryIdempotencyStore {
+    ttl: Duration,
+    requests: Mutex<HashMap<(String, String), RecordedRequest>>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn begin(&self, identity: &str, key: &str) -> Result<IdempotentRequest, Error> {
+        let now = Instant::now();
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|_, request| now.duration_since(request.received_at) < self.ttl);
+        let request = (identity.to_string(), key.to_string());
+        Ok(match requests.get(&request) {
+            Some(RecordedRequest {
+                response: Some(response),
+                ..
+            }) => IdempotentRequest::Completed(response.clone()),
+            Some(RecordedRequest { response: None, .. }) => IdempotentRequest::Pending,
+            None => {
+                let recorded = RecordedRequest {
+                    received_at: now,
+                    response: None,
+                };
+                requests.insert(request, recorded);
+                IdempotentRequest::New
+            }
+        })
+    }
+
+    async fn complete(&self, identity: &str, key: &str, response: Message) -> Result<(), Error> {
+        let request = (identity.to_string(), key.to_string());
+        if let Some(recorded) = self.requests.lock().unwrap().get_mut(&request) {
+            recorded.response = Some(response);
+        }
+        Ok(())
+    }
+}
+
+/// Exactly-once handling of requests which clients retry until they get a response, e.g. after reconnecting.
+///
+/// Requests are identified by `key` among the requests of `identity`. A retried request isn't handed to the session
+/// again: it's dropped while the original one is pending, and answered with the recorded response once it's completed.
+/// The response is recorded when the session sends a message carrying the key of a request it received.
+#[derive(Clone)]
+pub struct Idempotency {
+    /// Identity the requests are recorded for, usually a user or a tenant shared by multiple sessions.
+    pub identity: String,
+    pub key: IdempotencyKey,
+    pub store: Arc<dyn IdempotencyStore>,
+}
+
+impl std::fmt::Debug for Idempotency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Idempotency")
+            .field("identity", &self.identity)
+            .finish_non_exhaustive()
+    }
+}
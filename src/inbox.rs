@@ -0,0 +1,187 @@
+use crate::Error;
+use crate::Message;
+use crate::MessageMeta;
+use futures::task::AtomicWaker;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use tokio::sync::Notify;
+
+/// What happens to a message received while the inbound queue is full,
+/// see [`SocketConfig::recv_capacity`](crate::SocketConfig::recv_capacity).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Stops reading from the connection until there's room, so that the peer is slowed down by TCP backpressure.
+    ///
+    /// Pongs aren't read meanwhile either, the connection times out if the consumer stalls for longer
+    /// than [`SocketConfig::timeout`](crate::SocketConfig::timeout).
+    #[default]
+    Block,
+    /// Drops the oldest queued message to make room.
+    DropOldest,
+    /// Closes the connection with [`CloseCode::Policy`](crate::CloseCode::Policy).
+    Close,
+}
+
+pub(crate) type Item = Result<(Message, MessageMeta), Error>;
+
+/// Reason a message couldn't be queued.
+#[derive(Debug)]
+pub(crate) enum Rejected {
+    /// The [`Inbox`] was dropped.
+    Closed,
+    /// The queue is full and the policy is [`OverflowPolicy::Close`].
+    Full,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<Item>>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    /// Wakes the receiver once a message is queued or the sender is dropped.
+    waker: AtomicWaker,
+    /// Wakes the sender once a message is taken or the receiver is dropped.
+    space: Notify,
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
+}
+
+impl Shared {
+    // A panic while the lock is held can't leave the queue in an inconsistent state.
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Item>> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Queue of the messages received by the stream actor, waiting to be taken by the [`Stream`](crate::Stream).
+pub(crate) fn channel(capacity: Option<usize>, policy: OverflowPolicy) -> (InboxSender, Inbox) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        capacity,
+        policy,
+        waker: AtomicWaker::new(),
+        space: Notify::new(),
+        sender_dropped: AtomicBool::new(false),
+        receiver_dropped: AtomicBool::new(false),
+    });
+    (
+        InboxSender {
+            shared: shared.clone(),
+        },
+        Inbox { shared },
+    )
+}
+
+pub(crate) struct InboxSender {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for InboxSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InboxSender").finish_non_exhaustive()
+    }
+}
+
+impl InboxSender {
+    /// Queues the message, applying the overflow policy if the queue is full.
+    pub(crate) async fn send(&self, item: Item) -> Result<(), Rejected> {
+        loop {
+            {
+                let mut queue = self.shared.lock();
+                if self.shared.receiver_dropped.load(Ordering::Acquire) {
+                    return Err(Rejected::Closed);
+                }
+                let full = self
+                    .shared
+                    .capacity
+                    .is_some_and(|capacity| queue.len() >= capacity);
+                match (full, self.shared.policy) {
+                    (true, OverflowPolicy::Block) => {}
+                    (true, OverflowPolicy::Close) => return Err(Rejected::Full),
+                    (full, _) => {
+                        if full {
+                            tracing::trace!("inbound queue full, dropping the oldest message");
+                            queue.pop_front();
+                        }
+                        queue.push_back(item);
+                        drop(queue);
+                        self.shared.waker.wake();
+                        return Ok(());
+                    }
+                }
+            }
+            self.shared.space.notified().await;
+        }
+    }
+
+    /// Queues the message regardless of the capacity, e.g. the Close frame ending the stream.
+    pub(crate) fn force(&self, item: Item) -> Result<(), Rejected> {
+        let mut queue = self.shared.lock();
+        if self.shared.receiver_dropped.load(Ordering::Acquire) {
+            return Err(Rejected::Closed);
+        }
+        queue.push_back(item);
+        drop(queue);
+        self.shared.waker.wake();
+        Ok(())
+    }
+}
+
+impl Drop for InboxSender {
+    fn drop(&mut self) {
+        self.shared.sender_dropped.store(true, Ordering::Release);
+        self.shared.waker.wake();
+    }
+}
+
+pub(crate) struct Inbox {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for Inbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inbox").finish_non_exhaustive()
+    }
+}
+
+impl Inbox {
+    pub(crate) async fn recv(&self) -> Option<Item> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub(crate) fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<Item>> {
+        if let Some(item) = self.pop() {
+            return Poll::Ready(Some(item));
+        }
+        self.shared.waker.register(cx.waker());
+        // Read before checking the queue again, so that the last messages are taken before the end of the stream.
+        let sender_dropped = self.shared.sender_dropped.load(Ordering::Acquire);
+        match self.pop() {
+            Some(item) => Poll::Ready(Some(item)),
+            None if sender_dropped => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+
+    fn pop(&self) -> Option<Item> {
+        let item = self.shared.lock().pop_front();
+        if item.is_some() {
+            self.shared.space.notify_one();
+        }
+        item
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        {
+            let _queue = self.shared.lock();
+            self.shared.receiver_dropped.store(true, Ordering::Release);
+        }
+        self.shared.space.notify_one();
+    }
+}
@@ -1,12 +1,79 @@
+mod congestion;
+mod dedup;
+mod envelope;
+mod filter;
+mod idempotency;
+mod inbox;
+mod middleware;
+#[cfg(feature = "otel")]
+mod otel;
+pub mod protocols;
+mod queue;
+mod runtime;
+mod shaping;
 mod socket;
+mod stats;
+mod time;
+mod trace;
+mod transfer;
+mod wheel;
+
+pub use bytes::Bytes;
+pub use congestion::CongestionState;
+pub use congestion::ReducedEncodings;
+pub use dedup::Deduplication;
+pub use envelope::ErrorEnvelope;
+pub use filter::Filter;
+pub use filter::MessageFilter;
+pub use idempotency::Idempotency;
+pub use idempotency::IdempotencyKey;
+pub use idempotency::IdempotencyStore;
+pub use idempotency::IdempotentRequest;
+pub use idempotency::MemoryIdempotencyStore;
+pub use inbox::OverflowPolicy;
+pub use middleware::Middleware;
+pub use middleware::SocketMiddleware;
+pub use queue::ConflatingQueue;
+pub use queue::FifoQueue;
+pub use queue::LatestQueue;
+pub use queue::PriorityQueue;
+pub use queue::RingQueue;
+pub use queue::SendQueue;
+pub use queue::SendQueueFactory;
+pub use shaping::BurstPattern;
+pub use shaping::NetworkConditions;
+pub use transfer::Transfer;
+pub use wheel::TimerWheel;
 
 pub use socket::CloseCode;
 pub use socket::CloseFrame;
+pub use socket::CloseFrameBuilder;
+pub use socket::DataKind;
+pub use socket::Fragment;
+pub use socket::IdleTimeout;
+pub use socket::InvalidCloseFrame;
 pub use socket::Message;
+pub use socket::MessageKind;
+pub use socket::MessageMeta;
 pub use socket::RawMessage;
+pub use socket::ReuniteError;
+pub use socket::SendError;
+pub use socket::SendTimeoutError;
 pub use socket::Sink;
 pub use socket::Socket;
+pub use socket::SocketConfig;
+pub use socket::TlsInfo;
+pub use socket::TrySendError;
+pub use socket::UnexpectedFragment;
+pub use socket::MAX_CLOSE_REASON_LEN;
+
 pub use socket::Stream;
+pub use stats::HandlerTimings;
+pub use stats::Histogram;
+pub use stats::SessionUsage;
+pub use stats::SocketStats;
+pub use trace::TraceContext;
+pub use trace::TRACEPARENT;
 
 #[cfg(feature = "axum")]
 pub mod axum;
@@ -14,27 +81,88 @@ pub mod axum;
 #[cfg(feature = "tokio-tungstenite")]
 pub mod tungstenite;
 
+#[cfg(all(feature = "handoff", target_os = "linux"))]
+pub mod handoff;
+
+#[cfg(all(feature = "derive", feature = "client"))]
+pub use ezsockets_derive::client_ext;
+#[cfg(all(feature = "derive", feature = "server"))]
+pub use ezsockets_derive::session_ext;
+#[cfg(feature = "derive")]
+pub use ezsockets_derive::WsMessage;
+
+/// Used by the code generated by the derive macros.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use async_trait::async_trait;
+    pub use serde_json;
+}
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "client")] {
         mod client;
+        mod pool;
+        mod replay;
+        mod shared;
 
         pub use client::connect;
+        pub use client::connect_checked;
         pub use client::ClientConfig;
         pub use client::ClientExt;
+        pub use client::ConnectionState;
+        pub use client::EndpointResolver;
+        pub use client::ExpectedRefusal;
         pub use client::Client;
+        pub use pool::ClientPool;
+        pub use client::Mismatch;
+        pub use client::ResponseExpectations;
+        pub use client::ResponseMismatch;
+
+        pub use shared::SharedClient;
+        pub use shared::SharedConsumer;
     }
 }
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "server")] {
+        mod backfill;
+        mod quota;
+        mod rooms;
+        mod schedule;
         mod server;
         mod session;
+        mod throttle;
 
+        pub use backfill::Backfill;
+
+        pub use quota::MemoryQuotaStore;
+        pub use quota::Quota;
+        pub use quota::QuotaAction;
+        pub use quota::QuotaStore;
+        pub use quota::QuotaUsage;
+
+        pub use rooms::RejectReason;
+
+        pub use schedule::Schedule;
+        pub use schedule::ScheduledBroadcast;
+
+        pub use server::GoAway;
         pub use server::Server;
+        pub use server::ServerConfig;
         pub use server::ServerExt;
+        pub use server::SessionInfo;
 
+        pub use session::ErrorAction;
         pub use session::Session;
+        pub use session::Hello;
+        pub use session::SessionConfig;
+        pub use session::Watchdog;
         pub use session::SessionExt;
+
+
+        pub use throttle::AcceptLimit;
+        pub use throttle::HandshakePermit;
     }
 }
 
@@ -0,0 +1,58 @@
+use crate::RawMessage;
+use std::sync::Arc;
+
+/// Interceptor of the messages of a socket, e.g. for logging, metrics, or transparent encryption,
+/// registered through [`SocketConfig::middleware`](crate::SocketConfig::middleware).
+///
+/// Hooks run inside the socket actors on every message, control frames included, so they should be quick.
+pub trait SocketMiddleware: Send + Sync {
+    /// Called with every message received from the connection, before the socket handles it.
+    /// Returning `None` drops the message.
+    fn inbound(&self, message: RawMessage) -> Option<RawMessage> {
+        Some(message)
+    }
+
+    /// Called with every message right before it's written to the connection.
+    /// Returning `None` drops the message.
+    fn outbound(&self, message: RawMessage) -> Option<RawMessage> {
+        Some(message)
+    }
+}
+
+/// [`SocketMiddleware`] registered on a socket.
+///
+/// Inbound messages go through the middlewares in the order they were registered, outbound messages
+/// in the reverse order, so that the first middleware is the closest to the connection.
+#[derive(Clone)]
+pub struct Middleware {
+    middleware: Arc<dyn SocketMiddleware>,
+}
+
+impl std::fmt::Debug for Middleware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Middleware").finish_non_exhaustive()
+    }
+}
+
+impl Middleware {
+    pub fn new(middleware: impl SocketMiddleware + 'static) -> Self {
+        Self {
+            middleware: Arc::new(middleware),
+        }
+    }
+}
+
+pub(crate) fn inbound(middlewares: &[Middleware], message: RawMessage) -> Option<RawMessage> {
+    middlewares.iter().try_fold(message, |message, middleware| {
+        middleware.middleware.inbound(message)
+    })
+}
+
+pub(crate) fn outbound(middlewares: &[Middleware], message: RawMessage) -> Option<RawMessage> {
+    middlewares
+        .iter()
+        .rev()
+        .try_fold(message, |message, middleware| {
+            middleware.middleware.outbound(message)
+        })
+}
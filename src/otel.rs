@@ -0,0 +1,116 @@
+use crate::RawMessage;
+use opentelemetry::global;
+use opentelemetry::metrics::Counter;
+use opentelemetry::metrics::Histogram;
+use opentelemetry::metrics::Unit;
+use opentelemetry::KeyValue;
+#[cfg(any(feature = "client", feature = "server"))]
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+#[cfg(any(feature = "client", feature = "server"))]
+use tracing::Span;
+
+/// Direction of a message, as the `network.io.direction` attribute.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Receive,
+    Transmit,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Receive => "receive",
+            Self::Transmit => "transmit",
+        }
+    }
+}
+
+struct Instruments {
+    messages: Counter<u64>,
+    message_size: Histogram<u64>,
+    closes: Counter<u64>,
+    #[cfg(feature = "server")]
+    overloads: Counter<u64>,
+}
+
+/// Instruments of the `ezsockets` meter, created from the global meter provider on first use.
+fn instruments() -> &'static Instruments {
+    static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+    INSTRUMENTS.get_or_init(|| {
+        let meter = global::meter("ezsockets");
+        Instruments {
+            messages: meter
+                .u64_counter("websocket.messages")
+                .with_description("Number of data messages sent or received")
+                .with_unit(Unit::new("{message}"))
+                .init(),
+            message_size: meter
+                .u64_histogram("websocket.message.size")
+                .with_description("Size of the payload of data messages")
+                .with_unit(Unit::new("By"))
+                .init(),
+            closes: meter
+                .u64_counter("websocket.closes")
+                .with_description("Number of Close frames sent or received")
+                .with_unit(Unit::new("{frame}"))
+                .init(),
+            #[cfg(feature = "server")]
+            overloads: meter
+                .u64_counter("websocket.server.overloads")
+                .with_description("Number of connections rejected before the handshake because the server is overloaded")
+                .with_unit(Unit::new("{connection}"))
+                .init(),
+        }
+    })
+}
+
+/// Records a message written to or read from the connection.
+pub(crate) fn record_message(message: &RawMessage, direction: Direction) {
+    let (kind, len) = match message {
+        RawMessage::Text(text) => ("text", text.len()),
+        RawMessage::Binary(bytes) => ("binary", bytes.len()),
+        RawMessage::Close(frame) => {
+            let code = frame
+                .as_ref()
+                .map_or(1005, |frame| u16::from(frame.code.clone()));
+            instruments().closes.add(
+                1,
+                &[
+                    KeyValue::new("network.io.direction", direction.as_str()),
+                    KeyValue::new("websocket.close.code", i64::from(code)),
+                ],
+            );
+            return;
+        }
+        RawMessage::Ping(_) | RawMessage::Pong(_) | RawMessage::Fragment(_) => return,
+    };
+    let attributes = [
+        KeyValue::new("network.io.direction", direction.as_str()),
+        KeyValue::new("websocket.message.type", kind),
+    ];
+    instruments().messages.add(1, &attributes);
+    instruments().message_size.record(len as u64, &attributes);
+}
+
+/// Records a connection rejected by the accept limit, `reason` being the limit it exceeded.
+#[cfg(feature = "server")]
+pub(crate) fn record_overload(reason: &'static str) {
+    instruments()
+        .overloads
+        .add(1, &[KeyValue::new("ezsockets.overload.reason", reason)]);
+}
+
+/// Records the network attributes of a connection on its span, declared empty when the span is created.
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) fn record_connection(span: &Span, kind: &'static str, peer_addr: Option<SocketAddr>) {
+    span.record("otel.kind", kind);
+    span.record("network.protocol.name", "websocket");
+    if let Some(peer_addr) = peer_addr {
+        span.record(
+            "network.peer.address",
+            tracing::field::display(peer_addr.ip()),
+        );
+        span.record("network.peer.port", peer_addr.port());
+    }
+}
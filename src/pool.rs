@@ -0,0 +1,109 @@
+use crate::client::connect;
+use crate::Client;
+use crate::ClientConfig;
+use crate::ClientExt;
+use crate::ConnectionState;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+type ClientFn<E> = Box<dyn Fn(Client<E>) -> E + Send + Sync>;
+
+/// Clients connected ahead of time, so that the TCP, TLS and WebSocket handshakes are already done when a client is needed,
+/// e.g. for a gateway opening connections in bursts of requests.
+///
+/// Warm clients are handed out as long as their connection is younger than [`ClientPool::ttl`], older ones are closed
+/// instead, since servers and proxies tend to drop connections which stay idle for long.
+pub struct ClientPool<E: ClientExt> {
+    client_fn: ClientFn<E>,
+    config: ClientConfig,
+    ttl: Duration,
+    warm: Mutex<VecDeque<Client<E>>>,
+}
+
+impl<E: ClientExt> std::fmt::Debug for ClientPool<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientPool")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E: ClientExt + 'static> ClientPool<E> {
+    /// Pool of clients created with `client_fn` and `config`, whose connections stay fresh for a minute.
+    pub fn new(
+        client_fn: impl Fn(Client<E>) -> E + Send + Sync + 'static,
+        config: ClientConfig,
+    ) -> Self {
+        Self {
+            client_fn: Box::new(client_fn),
+            config,
+            ttl: Duration::from_secs(60),
+            warm: Default::default(),
+        }
+    }
+
+    /// Age after which the connection of a warm client is closed instead of being handed out.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Connects `n` more clients, waiting until they're connected. Clients which fail to connect are left out.
+    pub async fn warm(&self, n: usize) {
+        let clients = futures::future::join_all((0..n).map(|_| self.connect_warm())).await;
+        self.lock().extend(clients.into_iter().flatten());
+    }
+
+    /// Takes a warm client, or connects a new one in the background, like [`connect`], if none is left.
+    pub async fn get(&self) -> Client<E> {
+        loop {
+            let client = match self.lock().pop_front() {
+                Some(client) => client,
+                None => break,
+            };
+            match client.state() {
+                ConnectionState::Connected { since } if since.elapsed() < self.ttl => {
+                    return client
+                }
+                _ => {
+                    tracing::debug!("closing stale warm client");
+                    client.close(None);
+                }
+            }
+        }
+        self.connect().await
+    }
+
+    /// Number of warm clients, including the ones which went stale since.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    async fn connect(&self) -> Client<E> {
+        let (client, _) = connect(|client| (self.client_fn)(client), self.config.clone()).await;
+        client
+    }
+
+    /// Connects a client, returns it once connected, `None` if connecting failed.
+    async fn connect_warm(&self) -> Option<Client<E>> {
+        let client = self.connect().await;
+        let mut states = client.state_changes();
+        loop {
+            let state = states.borrow_and_update().clone();
+            match state {
+                ConnectionState::Connected { .. } => return Some(client),
+                ConnectionState::Closed { .. } => return None,
+                _ => states.changed().await.ok()?,
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Client<E>>> {
+        self.warm.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
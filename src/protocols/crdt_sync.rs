@@ -0,0 +1,381 @@
+//! Collaborative documents merged with [Automerge](https://automerge.org) CRDTs, e.g. to power collaborative editors.
+//!
+//! The server keeps a [`CrdtRoom`] per room, holding the merged document and the sync state of every member.
+//! Sessions joining the room are passed to [`CrdtRoom::join`], and the messages they send to [`CrdtRoom::receive`],
+//! which merges their changes and returns the messages to send to each member, so that changes made by any member
+//! reach all the others. Clients keep a [`CrdtReplica`] of the document, which they change with [`CrdtReplica::change`].
+//!
+//! Unlike with [`json_sync`](super::json_sync), concurrent changes never conflict, Automerge merges them.
+//!
+//! Members also share a presence state, e.g. their cursor or selection, which isn't part of the document.
+//! The server relays it to the other members of the room, and sends the presence of the current members
+//! to sessions joining it. An empty presence state means the member left.
+//!
+//! Messages are sent as Binary messages, encoded as `[tag: u8][room length: u16][room]` followed by:
+//! - sync message (tag 0): `[data]`, an Automerge sync message,
+//! - presence (tag 1): `[peer length: u16][peer][state]`,
+//!
+//! with integers in big endian.
+
+use crate::ErrorEnvelope;
+use crate::Message;
+use automerge::sync;
+use automerge::sync::SyncDoc;
+use automerge::AutoCommit;
+use automerge::AutomergeError;
+use bytes::Buf;
+use bytes::BufMut;
+use bytes::Bytes;
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+
+/// Message of the synchronization protocol, see the [module](self) documentation for its encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CrdtMessage {
+    /// Automerge sync message, exchanged until both ends have the same document.
+    Sync { room: String, data: Bytes },
+    /// Presence state of `peer`, which the server sets from the session that sent it.
+    Presence {
+        room: String,
+        peer: String,
+        state: Bytes,
+    },
+}
+
+impl CrdtMessage {
+    pub fn room(&self) -> &str {
+        match self {
+            Self::Sync { room, .. } | Self::Presence { room, .. } => room,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        let tag = match self {
+            Self::Sync { .. } => 0,
+            Self::Presence { .. } => 1,
+        };
+        bytes.put_u8(tag);
+        put_string(&mut bytes, self.room());
+        match self {
+            Self::Sync { data, .. } => bytes.put_slice(data),
+            Self::Presence { peer, state, .. } => {
+                put_string(&mut bytes, peer);
+                bytes.put_slice(state);
+            }
+        }
+        bytes.freeze()
+    }
+
+    /// Decodes a message, e.g. the payload of a Binary message received by a session.
+    pub fn decode(bytes: impl Into<Bytes>) -> Result<Self, CrdtError> {
+        let mut bytes: Bytes = bytes.into();
+        if bytes.remaining() < 1 {
+            return Err(CrdtError::Malformed(String::from("empty message")));
+        }
+        let tag = bytes.get_u8();
+        let room = get_string(&mut bytes)?;
+        match tag {
+            0 => Ok(Self::Sync { room, data: bytes }),
+            1 => Ok(Self::Presence {
+                room,
+                peer: get_string(&mut bytes)?,
+                state: bytes,
+            }),
+            _ => Err(CrdtError::Malformed(format!("unknown tag {tag}"))),
+        }
+    }
+}
+
+impl From<CrdtMessage> for Message {
+    fn from(message: CrdtMessage) -> Self {
+        Message::Binary(message.encode())
+    }
+}
+
+fn put_string(bytes: &mut BytesMut, string: &str) {
+    bytes.put_u16(string.len() as u16);
+    bytes.put_slice(string.as_bytes());
+}
+
+fn get_string(bytes: &mut Bytes) -> Result<String, CrdtError> {
+    if bytes.remaining() < 2 {
+        return Err(CrdtError::Malformed(String::from("truncated message")));
+    }
+    let len = bytes.get_u16() as usize;
+    if bytes.remaining() < len {
+        return Err(CrdtError::Malformed(String::from("truncated message")));
+    }
+    String::from_utf8(bytes.split_to(len).to_vec())
+        .map_err(|_| CrdtError::Malformed(String::from("invalid UTF-8")))
+}
+
+/// Reason a synchronization message was rejected.
+#[derive(Debug)]
+pub enum CrdtError {
+    /// The message isn't a valid [`CrdtMessage`], or belongs to another room.
+    Malformed(String),
+    /// The changes of a sync message couldn't be merged into the document.
+    Merge(AutomergeError),
+}
+
+impl std::fmt::Display for CrdtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(reason) => write!(f, "malformed sync message: {reason}"),
+            Self::Merge(err) => write!(f, "failed to merge changes: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CrdtError {}
+
+/// Reports the rejected message to the client.
+impl From<CrdtError> for ErrorEnvelope {
+    fn from(error: CrdtError) -> Self {
+        let code = match error {
+            CrdtError::Malformed(_) => "invalid_sync_message",
+            CrdtError::Merge(_) => "merge_failed",
+        };
+        ErrorEnvelope::new(code, error.to_string())
+    }
+}
+
+fn sync_data(room: &str, data: &[u8]) -> Result<sync::Message, CrdtError> {
+    sync::Message::decode(data)
+        .map_err(|err| CrdtError::Malformed(format!("invalid sync message for room {room}: {err}")))
+}
+
+#[derive(Debug, Default)]
+struct Member {
+    sync: sync::State,
+    presence: Bytes,
+}
+
+/// Document shared by the members of a room, identified by `P`, see the [module](self) documentation.
+#[derive(Debug)]
+pub struct CrdtRoom<P> {
+    room: String,
+    document: AutoCommit,
+    members: HashMap<P, Member>,
+}
+
+impl<P: Hash + Eq + Clone + Display> CrdtRoom<P> {
+    pub fn new(room: impl Into<String>, document: AutoCommit) -> Self {
+        Self {
+            room: room.into(),
+            document,
+            members: HashMap::new(),
+        }
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    pub fn document(&self) -> &AutoCommit {
+        &self.document
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &P> {
+        self.members.keys()
+    }
+
+    /// Adds a member, returns the messages to send to it: the start of the synchronization, and the presence
+    /// of the other members.
+    pub fn join(&mut self, peer: P) -> Vec<CrdtMessage> {
+        let mut member = Member::default();
+        let mut messages = Vec::new();
+        if let Some(message) = self.document.sync().generate_sync_message(&mut member.sync) {
+            messages.push(CrdtMessage::Sync {
+                room: self.room.clone(),
+                data: message.encode().into(),
+            });
+        }
+        for (other, Member { presence, .. }) in &self.members {
+            if !presence.is_empty() {
+                messages.push(self.presence(other, presence.clone()));
+            }
+        }
+        self.members.insert(peer, member);
+        messages
+    }
+
+    /// Removes a member, returns the messages to send to the others, clearing its presence.
+    pub fn leave(&mut self, peer: &P) -> Vec<(P, CrdtMessage)> {
+        match self.members.remove(peer) {
+            Some(member) if !member.presence.is_empty() => {
+                self.broadcast(peer, self.presence(peer, Bytes::new()))
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Handles a message of a member, joining the room if it didn't already.
+    /// Returns the messages to send to each member, the sender included.
+    pub fn receive(
+        &mut self,
+        peer: P,
+        message: CrdtMessage,
+    ) -> Result<Vec<(P, CrdtMessage)>, CrdtError> {
+        if message.room() != self.room {
+            return Err(CrdtError::Malformed(format!(
+                "message of room {}, expected {}",
+                message.room(),
+                self.room
+            )));
+        }
+        let member = self.members.entry(peer.clone()).or_default();
+        match message {
+            CrdtMessage::Sync { data, .. } => {
+                let message = sync_data(&self.room, &data)?;
+                self.document
+                    .sync()
+                    .receive_sync_message(&mut member.sync, message)
+                    .map_err(CrdtError::Merge)?;
+                Ok(self.synchronize())
+            }
+            CrdtMessage::Presence { state, .. } => {
+                member.presence = state.clone();
+                Ok(self.broadcast(&peer, self.presence(&peer, state)))
+            }
+        }
+    }
+
+    /// Changes the document on the server, returns the result of `change` and the messages to send to each member.
+    pub fn change<T>(
+        &mut self,
+        change: impl FnOnce(&mut AutoCommit) -> T,
+    ) -> (T, Vec<(P, CrdtMessage)>) {
+        let output = change(&mut self.document);
+        (output, self.synchronize())
+    }
+
+    /// Sync messages for the members which are missing changes.
+    fn synchronize(&mut self) -> Vec<(P, CrdtMessage)> {
+        let mut messages = Vec::new();
+        for (peer, member) in &mut self.members {
+            if let Some(message) = self.document.sync().generate_sync_message(&mut member.sync) {
+                let message = CrdtMessage::Sync {
+                    room: self.room.clone(),
+                    data: message.encode().into(),
+                };
+                messages.push((peer.clone(), message));
+            }
+        }
+        messages
+    }
+
+    fn presence(&self, peer: &P, state: Bytes) -> CrdtMessage {
+        CrdtMessage::Presence {
+            room: self.room.clone(),
+            peer: peer.to_string(),
+            state,
+        }
+    }
+
+    /// Addresses the message to every member but `sender`.
+    fn broadcast(&self, sender: &P, message: CrdtMessage) -> Vec<(P, CrdtMessage)> {
+        self.members
+            .keys()
+            .filter(|peer| *peer != sender)
+            .map(|peer| (peer.clone(), message.clone()))
+            .collect()
+    }
+}
+
+/// Client side copy of a [`CrdtRoom`] document.
+#[derive(Debug)]
+pub struct CrdtReplica {
+    room: String,
+    document: AutoCommit,
+    sync: sync::State,
+    peers: HashMap<String, Bytes>,
+}
+
+impl CrdtReplica {
+    pub fn new(room: impl Into<String>) -> Self {
+        Self::with_document(room, AutoCommit::new())
+    }
+
+    /// Starts from a local copy of the document, e.g. one edited offline, which is merged with the server's.
+    pub fn with_document(room: impl Into<String>, document: AutoCommit) -> Self {
+        Self {
+            room: room.into(),
+            document,
+            sync: sync::State::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    pub fn document(&self) -> &AutoCommit {
+        &self.document
+    }
+
+    /// Presence state of the other members of the room.
+    pub fn peers(&self) -> &HashMap<String, Bytes> {
+        &self.peers
+    }
+
+    /// Handles a message of the server, returns the message to send back, if any.
+    pub fn receive(&mut self, message: CrdtMessage) -> Result<Option<CrdtMessage>, CrdtError> {
+        match message {
+            CrdtMessage::Sync { data, .. } => {
+                let message = sync_data(&self.room, &data)?;
+                self.document
+                    .sync()
+                    .receive_sync_message(&mut self.sync, message)
+                    .map_err(CrdtError::Merge)?;
+                Ok(self.sync_message())
+            }
+            CrdtMessage::Presence { peer, state, .. } => {
+                if state.is_empty() {
+                    self.peers.remove(&peer);
+                } else {
+                    self.peers.insert(peer, state);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Changes the document, returns the result of `change` and the message to send to the server, if any.
+    pub fn change<T>(
+        &mut self,
+        change: impl FnOnce(&mut AutoCommit) -> T,
+    ) -> (T, Option<CrdtMessage>) {
+        let output = change(&mut self.document);
+        (output, self.sync_message())
+    }
+
+    /// Message setting our presence state, empty to clear it.
+    pub fn presence(&self, state: impl Into<Bytes>) -> CrdtMessage {
+        CrdtMessage::Presence {
+            room: self.room.clone(),
+            // Set by the server.
+            peer: String::new(),
+            state: state.into(),
+        }
+    }
+
+    /// Restarts the synchronization, e.g. after reconnecting, returns the message to send to the server.
+    pub fn resync(&mut self) -> Option<CrdtMessage> {
+        self.sync = sync::State::new();
+        self.peers.clear();
+        self.sync_message()
+    }
+
+    fn sync_message(&mut self) -> Option<CrdtMessage> {
+        let message = self.document.sync().generate_sync_message(&mut self.sync)?;
+        Some(CrdtMessage::Sync {
+            room: self.room.clone(),
+            data: message.encode().into(),
+        })
+    }
+}
@@ -0,0 +1,434 @@
+//! Shared JSON documents kept in sync with JSON Patch ([RFC 6902](https://www.rfc-editor.org/rfc/rfc6902)) deltas.
+//!
+//! The server keeps a [`SharedDocument`] per room. It sends [`SharedDocument::snapshot`] to sessions joining the room,
+//! and broadcasts a patch to the room whenever the document changes, either through [`SharedDocument::update`]
+//! or through a patch sent by a client and applied with [`SharedDocument::apply`]. Clients mirror the document
+//! by passing what they receive to [`SharedDocument::receive`].
+//!
+//! Every change increments the version of the document. Patches sent by clients carry the version they were
+//! made against, and are rejected with [`PatchError::Conflict`] if the document changed in the meantime.
+//!
+//! Messages are sent as JSON text messages:
+//! - `{"type":"snapshot","room":"...","version":1,"document":{...}}`,
+//! - `{"type":"patch","room":"...","version":1,"patch":[...]}`.
+
+use crate::ErrorEnvelope;
+use crate::Message;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+/// Message of the synchronization protocol, see the [module](self) documentation for its shape.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncMessage {
+    /// Full state of the document at `version`.
+    Snapshot {
+        room: String,
+        version: u64,
+        document: Value,
+    },
+    /// JSON Patch, resulting in `version` when sent by the server, or made against `version` when sent by a client.
+    Patch {
+        room: String,
+        version: u64,
+        patch: Value,
+    },
+}
+
+impl SyncMessage {
+    pub fn parse(text: &str) -> Result<Self, PatchError> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|err| PatchError::Malformed(err.to_string()))?;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| PatchError::Malformed(format!("missing `{name}`")))
+        };
+        let room = field("room")?
+            .as_str()
+            .ok_or_else(|| PatchError::Malformed(String::from("`room` must be a string")))?
+            .to_string();
+        let version = field("version")?
+            .as_u64()
+            .ok_or_else(|| PatchError::Malformed(String::from("`version` must be an integer")))?;
+        match field("type")?.as_str() {
+            Some("snapshot") => Ok(Self::Snapshot {
+                room,
+                version,
+                document: field("document")?.clone(),
+            }),
+            Some("patch") => Ok(Self::Patch {
+                room,
+                version,
+                patch: field("patch")?.clone(),
+            }),
+            _ => Err(PatchError::Malformed(String::from("unknown `type`"))),
+        }
+    }
+
+    pub fn room(&self) -> &str {
+        match self {
+            Self::Snapshot { room, .. } | Self::Patch { room, .. } => room,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let value = match self {
+            Self::Snapshot {
+                room,
+                version,
+                document,
+            } => {
+                json!({"type": "snapshot", "room": room, "version": version, "document": document})
+            }
+            Self::Patch {
+                room,
+                version,
+                patch,
+            } => json!({"type": "patch", "room": room, "version": version, "patch": patch}),
+        };
+        value.to_string()
+    }
+}
+
+impl From<SyncMessage> for Message {
+    fn from(message: SyncMessage) -> Self {
+        Message::Text(message.to_json())
+    }
+}
+
+/// Reason a patch or a synchronization message was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch was made against an older version of the document, the current one being `version`.
+    Conflict { version: u64 },
+    /// The message or the patch isn't valid JSON Patch.
+    Malformed(String),
+    /// An operation of the patch couldn't be applied, e.g. a `test` operation failed, the document was left unchanged.
+    Failed(String),
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Conflict { version } => {
+                write!(f, "the document changed, current version is {version}")
+            }
+            Self::Malformed(reason) => write!(f, "malformed patch: {reason}"),
+            Self::Failed(reason) => write!(f, "patch failed: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Reports the rejected patch to the client.
+impl From<PatchError> for ErrorEnvelope {
+    fn from(error: PatchError) -> Self {
+        let code = match error {
+            PatchError::Conflict { .. } => "version_conflict",
+            PatchError::Malformed(_) => "invalid_patch",
+            PatchError::Failed(_) => "patch_failed",
+        };
+        ErrorEnvelope::new(code, error.to_string())
+    }
+}
+
+/// JSON document shared by the members of a room, see the [module](self) documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedDocument {
+    room: String,
+    version: u64,
+    document: Value,
+}
+
+impl SharedDocument {
+    pub fn new(room: impl Into<String>, document: Value) -> Self {
+        Self {
+            room: room.into(),
+            version: 0,
+            document,
+        }
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn document(&self) -> &Value {
+        &self.document
+    }
+
+    /// Full state of the document, to send to sessions joining the room.
+    pub fn snapshot(&self) -> SyncMessage {
+        SyncMessage::Snapshot {
+            room: self.room.clone(),
+            version: self.version,
+            document: self.document.clone(),
+        }
+    }
+
+    /// Applies a patch sent by a client, made against `version`, and returns the patch to broadcast to the room.
+    pub fn apply(&mut self, version: u64, patch: Value) -> Result<SyncMessage, PatchError> {
+        if version != self.version {
+            return Err(PatchError::Conflict {
+                version: self.version,
+            });
+        }
+        apply_patch(&mut self.document, &patch)?;
+        Ok(self.changed(patch))
+    }
+
+    /// Changes the document, and returns the patch to broadcast to the room, `None` if nothing changed.
+    pub fn update(&mut self, update: impl FnOnce(&mut Value)) -> Option<SyncMessage> {
+        let before = self.document.clone();
+        update(&mut self.document);
+        let patch = diff(&before, &self.document);
+        if patch
+            .as_array()
+            .is_some_and(|operations| operations.is_empty())
+        {
+            return None;
+        }
+        Some(self.changed(patch))
+    }
+
+    fn changed(&mut self, patch: Value) -> SyncMessage {
+        self.version += 1;
+        SyncMessage::Patch {
+            room: self.room.clone(),
+            version: self.version,
+            patch,
+        }
+    }
+
+    /// Updates a mirror of the document with a message received from the server.
+    ///
+    /// Fails with [`PatchError::Conflict`] if a patch was missed, in which case a new snapshot should be requested.
+    pub fn receive(&mut self, message: SyncMessage) -> Result<(), PatchError> {
+        match message {
+            SyncMessage::Snapshot {
+                version, document, ..
+            } => {
+                self.version = version;
+                self.document = document;
+                Ok(())
+            }
+            SyncMessage::Patch { version, patch, .. } => {
+                if version != self.version + 1 {
+                    return Err(PatchError::Conflict {
+                        version: self.version,
+                    });
+                }
+                apply_patch(&mut self.document, &patch)?;
+                self.version = version;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Applies a JSON Patch to the document, leaving it unchanged if any operation fails.
+pub fn apply_patch(document: &mut Value, patch: &Value) -> Result<(), PatchError> {
+    let operations = patch
+        .as_array()
+        .ok_or_else(|| PatchError::Malformed(String::from("a patch must be an array")))?;
+    let mut patched = document.clone();
+    for operation in operations {
+        apply_operation(&mut patched, operation)?;
+    }
+    *document = patched;
+    Ok(())
+}
+
+fn apply_operation(document: &mut Value, operation: &Value) -> Result<(), PatchError> {
+    let field = |name: &str| {
+        operation
+            .get(name)
+            .ok_or_else(|| PatchError::Malformed(format!("operation without `{name}`")))
+    };
+    let pointer = |name: &str| {
+        field(name)?
+            .as_str()
+            .ok_or_else(|| PatchError::Malformed(format!("`{name}` must be a string")))
+            .and_then(parse_pointer)
+    };
+    let path = pointer("path")?;
+    match field("op")?.as_str() {
+        Some("add") => add(document, &path, field("value")?.clone()),
+        Some("remove") => remove(document, &path).map(drop),
+        Some("replace") => {
+            remove(document, &path)?;
+            add(document, &path, field("value")?.clone())
+        }
+        Some("move") => {
+            let from = pointer("from")?;
+            if path.len() > from.len() && path[..from.len()] == from[..] {
+                return Err(PatchError::Failed(String::from(
+                    "can't move a value into one of its children",
+                )));
+            }
+            let value = remove(document, &from)?;
+            add(document, &path, value)
+        }
+        Some("copy") => {
+            let value = get(document, &pointer("from")?)?.clone();
+            add(document, &path, value)
+        }
+        Some("test") if get(document, &path)? == field("value")? => Ok(()),
+        Some("test") => Err(PatchError::Failed(format!(
+            "test failed at {}",
+            field("path")?
+        ))),
+        _ => Err(PatchError::Malformed(String::from("unknown `op`"))),
+    }
+}
+
+/// Splits a JSON Pointer ([RFC 6901](https://www.rfc-editor.org/rfc/rfc6901)) into its unescaped tokens.
+fn parse_pointer(pointer: &str) -> Result<Vec<String>, PatchError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let tokens = pointer
+        .strip_prefix('/')
+        .ok_or_else(|| PatchError::Malformed(format!("invalid pointer {pointer:?}")))?;
+    Ok(tokens
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+fn not_found(path: &[String]) -> PatchError {
+    PatchError::Failed(format!("no value at /{}", path.join("/")))
+}
+
+fn array_index(token: &str, len: usize, path: &[String]) -> Result<usize, PatchError> {
+    match token.parse::<usize>() {
+        Ok(index) if index < len && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(not_found(path)),
+    }
+}
+
+fn get<'v>(document: &'v Value, path: &[String]) -> Result<&'v Value, PatchError> {
+    let mut value = document;
+    for token in path {
+        value = match value {
+            Value::Object(map) => map.get(token),
+            Value::Array(array) => array_index(token, array.len(), path)
+                .ok()
+                .map(|index| &array[index]),
+            _ => None,
+        }
+        .ok_or_else(|| not_found(path))?;
+    }
+    Ok(value)
+}
+
+fn get_mut<'v>(document: &'v mut Value, path: &[String]) -> Result<&'v mut Value, PatchError> {
+    let mut value = document;
+    for token in path {
+        value = match value {
+            Value::Object(map) => map.get_mut(token),
+            Value::Array(array) => match array_index(token, array.len(), path) {
+                Ok(index) => array.get_mut(index),
+                Err(_) => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| not_found(path))?;
+    }
+    Ok(value)
+}
+
+fn add(document: &mut Value, path: &[String], value: Value) -> Result<(), PatchError> {
+    let (last, parent) = match path.split_last() {
+        Some(split) => split,
+        None => {
+            *document = value;
+            return Ok(());
+        }
+    };
+    match get_mut(document, parent)? {
+        Value::Object(map) => {
+            map.insert(last.clone(), value);
+            Ok(())
+        }
+        Value::Array(array) if last == "-" => {
+            array.push(value);
+            Ok(())
+        }
+        Value::Array(array) => {
+            // Inserting right after the last element is allowed.
+            let index = array_index(last, array.len() + 1, path)?;
+            array.insert(index, value);
+            Ok(())
+        }
+        _ => Err(not_found(path)),
+    }
+}
+
+fn remove(document: &mut Value, path: &[String]) -> Result<Value, PatchError> {
+    let (last, parent) = match path.split_last() {
+        Some(split) => split,
+        None => return Ok(std::mem::take(document)),
+    };
+    match get_mut(document, parent)? {
+        Value::Object(map) => map.remove(last).ok_or_else(|| not_found(path)),
+        Value::Array(array) => {
+            let index = array_index(last, array.len(), path)?;
+            Ok(array.remove(index))
+        }
+        _ => Err(not_found(path)),
+    }
+}
+
+/// Computes a JSON Patch turning `from` into `to`.
+///
+/// Objects are compared member by member, other values, including arrays, are replaced as a whole when they differ.
+pub fn diff(from: &Value, to: &Value) -> Value {
+    let mut operations = Vec::new();
+    diff_into(from, to, &mut String::new(), &mut operations);
+    Value::Array(operations)
+}
+
+fn diff_into(from: &Value, to: &Value, path: &mut String, operations: &mut Vec<Value>) {
+    match (from, to) {
+        (Value::Object(from), Value::Object(to)) => diff_objects(from, to, path, operations),
+        (from, to) if from == to => {}
+        (_, to) => operations.push(json!({"op": "replace", "path": path, "value": to})),
+    }
+}
+
+fn diff_objects(
+    from: &Map<String, Value>,
+    to: &Map<String, Value>,
+    path: &mut String,
+    operations: &mut Vec<Value>,
+) {
+    let len = path.len();
+    for (key, value) in from {
+        path.push('/');
+        path.push_str(&escape_token(key));
+        match to.get(key) {
+            Some(new) => diff_into(value, new, path, operations),
+            None => operations.push(json!({"op": "remove", "path": path})),
+        }
+        path.truncate(len);
+    }
+    for (key, value) in to {
+        if !from.contains_key(key) {
+            path.push('/');
+            path.push_str(&escape_token(key));
+            operations.push(json!({"op": "add", "path": path, "value": value}));
+            path.truncate(len);
+        }
+    }
+}
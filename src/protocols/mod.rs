@@ -0,0 +1,9 @@
+//! Small application protocols built on top of sessions and clients.
+
+#[cfg(feature = "crdt")]
+pub mod crdt_sync;
+#[cfg(feature = "json")]
+pub mod json_sync;
+#[cfg(feature = "json")]
+pub mod router;
+pub mod transfer;
@@ -0,0 +1,182 @@
+//! Dispatch of JSON text messages to handlers, by the value of a discriminating field.
+//!
+//! Clients receiving several kinds of messages, e.g. `{"type":"chat",...}` and `{"type":"presence",...}`,
+//! register a handler per kind on a [`Router`], and pass the text messages they receive to [`Router::dispatch`]:
+//!
+//! ```
+//! use ezsockets::protocols::router::Router;
+//!
+//! enum Event {
+//!     Chat(String),
+//!     Other(String),
+//! }
+//!
+//! let router = Router::new("type")
+//!     .route("chat", |message| Event::Chat(message["text"].as_str().unwrap_or_default().to_string()))
+//!     .fallback(|message| Event::Other(message.to_string()));
+//! assert!(matches!(router.dispatch(r#"{"type":"chat","text":"hi"}"#), Ok(Event::Chat(text)) if text == "hi"));
+//! ```
+//!
+//! Messages can also be decoded into an enum implementing [`WsMessage`], registered with [`Router::messages`].
+
+use crate::Message;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type Handler<T> = Box<dyn Fn(Value) -> Result<T, RouteError> + Send + Sync>;
+
+/// Enum of the messages of an application, tagged with the discriminating field, e.g. `{"type":"chat",...}`.
+///
+/// Usually derived with `#[derive(WsMessage)]`, behind the `derive` feature, which tags every variant with its name
+/// in snake case, and sends its fields as the other fields of the JSON object:
+///
+/// ```ignore
+/// #[derive(ezsockets::WsMessage)]
+/// #[ws(tag = "type")] // The default.
+/// enum Event {
+///     Chat { text: String },
+///     #[ws(rename = "online")]
+///     Presence { user: u64, online: bool },
+///     Ping,
+/// }
+/// ```
+///
+/// Fields are converted with `serde_json`, so they must implement `Serialize` and `Deserialize`,
+/// and `Option` fields may be missing.
+pub trait WsMessage: Sized {
+    /// Name of the discriminating field.
+    const TAG: &'static str;
+    /// Values of the discriminating field, one per variant.
+    const KINDS: &'static [&'static str];
+
+    /// Value of the discriminating field of this message.
+    fn kind(&self) -> &'static str;
+
+    fn to_json(&self) -> Result<Value, serde_json::Error>;
+
+    fn from_json(message: Value) -> Result<Self, RouteError>;
+
+    /// Encodes the message as a Text message.
+    fn to_message(&self) -> Result<Message, serde_json::Error> {
+        Ok(Message::Text(self.to_json()?.to_string()))
+    }
+
+    /// Decodes a Text message.
+    fn parse(text: &str) -> Result<Self, RouteError> {
+        let message: Value =
+            serde_json::from_str(text).map_err(|err| RouteError::Malformed(err.to_string()))?;
+        Self::from_json(message)
+    }
+}
+
+/// Handlers of JSON messages, returning a `T`, registered by the value of the discriminating field.
+pub struct Router<T> {
+    field: String,
+    routes: HashMap<String, Handler<T>>,
+    fallback: Option<Handler<T>>,
+}
+
+impl<T> std::fmt::Debug for Router<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router")
+            .field("field", &self.field)
+            .field("routes", &self.routes.keys().collect::<Vec<_>>())
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl<T> Router<T> {
+    /// Routes messages by the value of `field`, which must be a string.
+    pub fn new(field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            routes: HashMap::new(),
+            fallback: None,
+        }
+    }
+
+    /// Calls `handler` with the messages whose field is `kind`, replacing the handler previously registered for it.
+    pub fn route(
+        mut self,
+        kind: impl Into<String>,
+        handler: impl Fn(Value) -> T + Send + Sync + 'static,
+    ) -> Self {
+        self.routes
+            .insert(kind.into(), Box::new(move |message| Ok(handler(message))));
+        self
+    }
+
+    /// Calls `handler` with the messages of every kind of `M`, decoded, see [`WsMessage`].
+    /// The router must route by the same field as `M`.
+    pub fn messages<M: WsMessage + 'static>(
+        mut self,
+        handler: impl Fn(M) -> T + Send + Sync + 'static,
+    ) -> Self
+    where
+        T: 'static,
+    {
+        debug_assert_eq!(
+            self.field,
+            M::TAG,
+            "the router and the messages must use the same tag"
+        );
+        let handler = Arc::new(handler);
+        for kind in M::KINDS {
+            let handler = handler.clone();
+            self.routes.insert(
+                kind.to_string(),
+                Box::new(move |message| M::from_json(message).map(&*handler)),
+            );
+        }
+        self
+    }
+
+    /// Calls `handler` with the JSON messages no other handler is registered for, including those without the field.
+    pub fn fallback(mut self, handler: impl Fn(Value) -> T + Send + Sync + 'static) -> Self {
+        self.fallback = Some(Box::new(move |message| Ok(handler(message))));
+        self
+    }
+
+    /// Parses `text` and calls the matching handler with it.
+    pub fn dispatch(&self, text: &str) -> Result<T, RouteError> {
+        let message: Value =
+            serde_json::from_str(text).map_err(|err| RouteError::Malformed(err.to_string()))?;
+        self.dispatch_value(message)
+    }
+
+    /// Same as [`Router::dispatch`], with a message already parsed.
+    pub fn dispatch_value(&self, message: Value) -> Result<T, RouteError> {
+        let kind = message.get(&self.field).and_then(Value::as_str);
+        if let Some(handler) = kind.and_then(|kind| self.routes.get(kind)) {
+            return handler(message);
+        }
+        match &self.fallback {
+            Some(fallback) => fallback(message),
+            None => Err(RouteError::Unrouted(kind.map(String::from))),
+        }
+    }
+}
+
+/// Reason a message couldn't be dispatched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// The message isn't valid JSON, or couldn't be decoded as a [`WsMessage`].
+    Malformed(String),
+    /// No handler is registered for the value of the field, `None` if the message doesn't have the field,
+    /// and the router has no fallback.
+    Unrouted(Option<String>),
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(reason) => write!(f, "malformed message: {reason}"),
+            Self::Unrouted(Some(kind)) => write!(f, "no handler for messages of kind {kind}"),
+            Self::Unrouted(None) => write!(f, "message without a kind"),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
@@ -0,0 +1,374 @@
+//! Resumable transfer of large payloads, e.g. files, as a sequence of checksummed chunks.
+//!
+//! The sending side drives an [`Upload`], the receiving side a [`Download`]. Both are independent of the
+//! connection, so they survive reconnections: after reconnecting, the sender sends [`Upload::resume`]
+//! and the receiver answers with the offset to continue from. Chunks are sent as Binary messages, while
+//! at most [`Upload::window`] bytes wait to be acknowledged, which bounds the memory used by the transfer.
+//!
+//! Every chunk carries a CRC-32 checksum of its data. The receiver drops a chunk whose data doesn't match
+//! it, or which doesn't start where the previous one ended, and asks for the data to be sent again.
+//!
+//! All messages are encoded as `[tag: u8][id length: u16][id]` followed by:
+//! - chunk (tag 0): `[offset: u64][checksum: u32][last: u8][data]`,
+//! - acknowledgement (tag 1): `[offset: u64]`,
+//! - resume request (tag 2): nothing,
+//! - resend request (tag 3): `[offset: u64]`,
+//!
+//! with integers in big endian.
+
+use crate::Message;
+use bytes::Buf;
+use bytes::BufMut;
+use bytes::Bytes;
+use bytes::BytesMut;
+use std::ops::Range;
+
+/// Size of the chunks of an [`Upload`], unless set with [`Upload::chunk_size`].
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Message of the transfer protocol, see the [module](self) documentation for its encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferMessage {
+    /// Part of the payload starting at `offset`, `last` being set on the final chunk.
+    Chunk {
+        id: String,
+        offset: u64,
+        checksum: u32,
+        last: bool,
+        data: Bytes,
+    },
+    /// The receiver got everything up to `offset`.
+    Ack { id: String, offset: u64 },
+    /// Asks the receiver where to continue the transfer from, e.g. after reconnecting.
+    Resume { id: String },
+    /// Asks the sender to continue the transfer from `offset`.
+    Resend { id: String, offset: u64 },
+}
+
+impl TransferMessage {
+    /// Identifier of the transfer the message belongs to.
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Chunk { id, .. }
+            | Self::Ack { id, .. }
+            | Self::Resume { id }
+            | Self::Resend { id, .. } => id,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        let tag = match self {
+            Self::Chunk { .. } => 0,
+            Self::Ack { .. } => 1,
+            Self::Resume { .. } => 2,
+            Self::Resend { .. } => 3,
+        };
+        bytes.put_u8(tag);
+        let id = self.id().as_bytes();
+        bytes.put_u16(id.len() as u16);
+        bytes.put_slice(id);
+        match self {
+            Self::Chunk {
+                offset,
+                checksum,
+                last,
+                data,
+                ..
+            } => {
+                bytes.put_u64(*offset);
+                bytes.put_u32(*checksum);
+                bytes.put_u8(u8::from(*last));
+                bytes.put_slice(data);
+            }
+            Self::Ack { offset, .. } | Self::Resend { offset, .. } => bytes.put_u64(*offset),
+            Self::Resume { .. } => {}
+        }
+        bytes.freeze()
+    }
+
+    /// Decodes a message, e.g. the payload of a Binary message received by a session.
+    pub fn decode(bytes: impl Into<Bytes>) -> Result<Self, InvalidTransferMessage> {
+        let mut bytes: Bytes = bytes.into();
+        if bytes.remaining() < 3 {
+            return Err(InvalidTransferMessage);
+        }
+        let tag = bytes.get_u8();
+        let len = bytes.get_u16() as usize;
+        if bytes.remaining() < len {
+            return Err(InvalidTransferMessage);
+        }
+        let id =
+            String::from_utf8(bytes.split_to(len).to_vec()).map_err(|_| InvalidTransferMessage)?;
+        let message = match tag {
+            0 if bytes.remaining() >= 13 => Self::Chunk {
+                id,
+                offset: bytes.get_u64(),
+                checksum: bytes.get_u32(),
+                last: bytes.get_u8() != 0,
+                data: bytes,
+            },
+            1 if bytes.remaining() == 8 => Self::Ack {
+                id,
+                offset: bytes.get_u64(),
+            },
+            2 if bytes.is_empty() => Self::Resume { id },
+            3 if bytes.remaining() == 8 => Self::Resend {
+                id,
+                offset: bytes.get_u64(),
+            },
+            _ => return Err(InvalidTransferMessage),
+        };
+        Ok(message)
+    }
+}
+
+impl From<TransferMessage> for Message {
+    fn from(message: TransferMessage) -> Self {
+        Message::Binary(message.encode())
+    }
+}
+
+/// Error returned when decoding bytes which aren't a [`TransferMessage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTransferMessage;
+
+impl std::fmt::Display for InvalidTransferMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid transfer message")
+    }
+}
+
+impl std::error::Error for InvalidTransferMessage {}
+
+/// CRC-32 (IEEE) checksum of `data`, as carried by chunks.
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Sending side of a transfer of `total` bytes.
+///
+/// Read the data of [`Upload::next_chunk`] and send the message returned by [`Upload::chunk`] until it returns
+/// `None`, then wait for messages from the receiver and pass them to [`Upload::handle`], until [`Upload::is_complete`].
+#[derive(Debug, Clone)]
+pub struct Upload {
+    id: String,
+    total: u64,
+    chunk_size: usize,
+    window: Option<u64>,
+    /// Offset of the next chunk to send.
+    next: u64,
+    /// Whether the last chunk was sent since the transfer started or was resumed.
+    sent_last: bool,
+    acknowledged: u64,
+}
+
+impl Upload {
+    pub fn new(id: impl Into<String>, total: u64) -> Self {
+        Self {
+            id: id.into(),
+            total,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            window: None,
+            next: 0,
+            sent_last: false,
+            acknowledged: 0,
+        }
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Maximum number of bytes sent but not acknowledged yet, 4 chunks by default.
+    pub fn window(mut self, window: u64) -> Self {
+        self.window = Some(window.max(1));
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Number of bytes the receiver acknowledged.
+    pub fn acknowledged(&self) -> u64 {
+        self.acknowledged
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.sent_last && self.acknowledged >= self.total
+    }
+
+    /// Range of the payload to send next, `None` if everything was sent or too much is waiting to be acknowledged.
+    pub fn next_chunk(&self) -> Option<Range<u64>> {
+        let window = self.window.unwrap_or(4 * self.chunk_size as u64);
+        if self.sent_last || self.next.saturating_sub(self.acknowledged) >= window {
+            return None;
+        }
+        Some(self.next..self.total.min(self.next + self.chunk_size as u64))
+    }
+
+    /// Builds the chunk message out of the data read for the range returned by [`Upload::next_chunk`].
+    pub fn chunk(&mut self, data: impl Into<Bytes>) -> TransferMessage {
+        let data = data.into();
+        let offset = self.next;
+        self.next += data.len() as u64;
+        // An empty payload is still sent, as a single empty chunk.
+        self.sent_last = self.next >= self.total;
+        TransferMessage::Chunk {
+            id: self.id.clone(),
+            offset,
+            checksum: checksum(&data),
+            last: self.sent_last,
+            data,
+        }
+    }
+
+    /// Message asking the receiver where to continue from, to send after reconnecting.
+    ///
+    /// Chunks which weren't acknowledged may have been lost, they're sent again once the receiver answers.
+    pub fn resume(&mut self) -> TransferMessage {
+        self.next = self.acknowledged;
+        self.sent_last = false;
+        TransferMessage::Resume {
+            id: self.id.clone(),
+        }
+    }
+
+    /// Handles a message of the receiver, returns `false` if it doesn't belong to this transfer.
+    pub fn handle(&mut self, message: &TransferMessage) -> bool {
+        if message.id() != self.id {
+            return false;
+        }
+        match *message {
+            TransferMessage::Ack { offset, .. } => {
+                self.acknowledged = self.acknowledged.max(offset.min(self.total));
+            }
+            TransferMessage::Resend { offset, .. } => {
+                let offset = offset.min(self.total);
+                self.acknowledged = offset;
+                self.next = offset;
+                self.sent_last = false;
+            }
+            TransferMessage::Chunk { .. } | TransferMessage::Resume { .. } => {}
+        }
+        true
+    }
+}
+
+/// Outcome of a message handled by a [`Download`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Received {
+    /// Data accepted by the receiver, to append to the payload received so far.
+    pub data: Option<Bytes>,
+    /// Message to send back to the sender.
+    pub reply: Option<TransferMessage>,
+}
+
+/// Receiving side of a transfer.
+#[derive(Debug, Clone)]
+pub struct Download {
+    id: String,
+    received: u64,
+    complete: bool,
+    /// Whether a resend was requested, chunks are dropped silently until the requested one arrives.
+    resending: bool,
+}
+
+impl Download {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self::resume_at(id, 0)
+    }
+
+    /// Continues a transfer of which `offset` bytes were already received, e.g. the size of a partially written file.
+    pub fn resume_at(id: impl Into<String>, offset: u64) -> Self {
+        Self {
+            id: id.into(),
+            received: offset,
+            complete: false,
+            resending: false,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Number of bytes received so far.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Whether the last chunk was received.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Handles a message of the sender, returns `None` if it doesn't belong to this transfer.
+    pub fn handle(&mut self, message: TransferMessage) -> Option<Received> {
+        if message.id() != self.id {
+            return None;
+        }
+        let received = match message {
+            TransferMessage::Chunk {
+                offset,
+                checksum: expected,
+                last,
+                data,
+                ..
+            } => {
+                if offset < self.received || (self.complete && offset == self.received) {
+                    // Sent again after a reconnection, acknowledge it again since the acknowledgement may have been lost.
+                    Received {
+                        data: None,
+                        reply: Some(self.ack()),
+                    }
+                } else if offset == self.received && checksum(&data) == expected {
+                    self.resending = false;
+                    self.received += data.len() as u64;
+                    self.complete = last;
+                    Received {
+                        data: Some(data),
+                        reply: Some(self.ack()),
+                    }
+                } else if self.resending && offset != self.received {
+                    Received::default()
+                } else {
+                    self.resending = true;
+                    Received {
+                        data: None,
+                        reply: Some(self.resend()),
+                    }
+                }
+            }
+            TransferMessage::Resume { .. } => Received {
+                data: None,
+                reply: Some(self.resend()),
+            },
+            TransferMessage::Ack { .. } | TransferMessage::Resend { .. } => Received::default(),
+        };
+        Some(received)
+    }
+
+    fn ack(&self) -> TransferMessage {
+        TransferMessage::Ack {
+            id: self.id.clone(),
+            offset: self.received,
+        }
+    }
+
+    fn resend(&self) -> TransferMessage {
+        TransferMessage::Resend {
+            id: self.id.clone(),
+            offset: self.received,
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use crate::Message;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::sync::Semaphore;
+use tokio::sync::TryAcquireError;
+
+/// Queue of outbound data messages waiting to be written by the sink actor.
+///
+/// Only Text and Binary messages go through the queue, control frames are written ahead of them,
+/// except Close frames, which are written once the queue is drained.
+pub trait SendQueue: Send + 'static {
+    /// Adds a message to the queue, possibly dropping or replacing messages already queued.
+    fn push(&mut self, message: Message);
+
+    /// Adds a message replacing the queued message with the same conflation `key`, if any,
+    /// see [`Sink::send_conflated`](crate::Sink::send_conflated).
+    ///
+    /// Queues which don't support conflation queue the message as usual.
+    fn push_conflated(&mut self, key: Arc<str>, message: Message) {
+        let _ = key;
+        self.push(message);
+    }
+
+    /// Removes the next message to write.
+    fn pop(&mut self) -> Option<Message>;
+
+    /// Number of queued messages.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+type QueueFn = Arc<dyn Fn() -> Box<dyn SendQueue> + Send + Sync>;
+
+/// Creates the [`SendQueue`] of every socket, see [`SocketConfig::send_queue`](crate::SocketConfig::send_queue).
+#[derive(Clone)]
+pub struct SendQueueFactory {
+    create: QueueFn,
+}
+
+impl std::fmt::Debug for SendQueueFactory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendQueueFactory").finish_non_exhaustive()
+    }
+}
+
+impl SendQueueFactory {
+    pub fn new<Q: SendQueue>(create: impl Fn() -> Q + Send + Sync + 'static) -> Self {
+        Self {
+            create: Arc::new(move || Box::new(create())),
+        }
+    }
+
+    pub(crate) fn create(&self) -> Box<dyn SendQueue> {
+        (self.create)()
+    }
+}
+
+/// Unbounded first-in first-out queue, used by default.
+#[derive(Debug, Default)]
+pub struct FifoQueue {
+    messages: VecDeque<Message>,
+}
+
+impl SendQueue for FifoQueue {
+    fn push(&mut self, message: Message) {
+        self.messages.push_back(message);
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+/// Bounded queue which drops the oldest message to make room for a new one once full.
+#[derive(Debug)]
+pub struct RingQueue {
+    messages: VecDeque<Message>,
+    capacity: usize,
+}
+
+impl RingQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+}
+
+impl SendQueue for RingQueue {
+    fn push(&mut self, message: Message) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.messages.len() >= self.capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+/// First-in first-out queue conflating messages sent with [`Sink::send_conflated`](crate::Sink::send_conflated).
+///
+/// A message sent with a conflation key replaces the queued message with the same key, taking its place in the queue,
+/// so that under backpressure only the newest value of each key is written, e.g. the latest quote of each instrument.
+#[derive(Debug, Default)]
+pub struct ConflatingQueue {
+    messages: VecDeque<(Option<Arc<str>>, Message)>,
+    /// Position of the queued message of each key, counted from the first message ever queued.
+    positions: HashMap<Arc<str>, u64>,
+    /// Number of messages popped so far, the position of the front of the queue.
+    popped: u64,
+}
+
+impl ConflatingQueue {
+    /// Replaces the queued message with the same `key`, or queues the message with the key made by `owned`,
+    /// so that the key is only allocated the first time it's queued.
+    fn conflate(&mut self, key: &str, owned: impl FnOnce() -> Arc<str>, message: Message) {
+        if let Some(position) = self.positions.get(key) {
+            self.messages[(position - self.popped) as usize].1 = message;
+            return;
+        }
+        let key = owned();
+        let position = self.popped + self.messages.len() as u64;
+        self.positions.insert(key.clone(), position);
+        self.messages.push_back((Some(key), message));
+    }
+}
+
+impl SendQueue for ConflatingQueue {
+    fn push(&mut self, message: Message) {
+        self.messages.push_back((None, message));
+    }
+
+    fn push_conflated(&mut self, key: Arc<str>, message: Message) {
+        self.conflate(&key.clone(), || key, message);
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        let (key, message) = self.messages.pop_front()?;
+        self.popped += 1;
+        if let Some(key) = key {
+            self.positions.remove(&key);
+        }
+        Some(message)
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+type KeyFn = Arc<dyn Fn(&Message) -> Option<String> + Send + Sync>;
+
+/// Queue keeping only the newest message per key, e.g. the latest position update of each entity.
+///
+/// Same as [`ConflatingQueue`], but the conflation key is derived from the message by `key`.
+/// Messages for which `key` returns `None` are always queued.
+pub struct LatestQueue {
+    key: KeyFn,
+    queue: ConflatingQueue,
+}
+
+impl std::fmt::Debug for LatestQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LatestQueue")
+            .field("queue", &self.queue)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LatestQueue {
+    pub fn new(key: impl Fn(&Message) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            key: Arc::new(key),
+            queue: ConflatingQueue::default(),
+        }
+    }
+}
+
+impl SendQueue for LatestQueue {
+    fn push(&mut self, message: Message) {
+        match (self.key)(&message) {
+            Some(key) => self
+                .queue
+                .conflate(&key, || Arc::from(key.as_str()), message),
+            None => self.queue.push(message),
+        }
+    }
+
+    fn push_conflated(&mut self, key: Arc<str>, message: Message) {
+        self.queue.push_conflated(key, message);
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        self.queue.pop()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+type PriorityFn = Arc<dyn Fn(&Message) -> u8 + Send + Sync>;
+
+#[derive(Debug)]
+struct Prioritized {
+    priority: u8,
+    sequence: u64,
+    message: Message,
+}
+
+impl PartialEq for Prioritized {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Prioritized {}
+
+impl PartialOrd for Prioritized {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Prioritized {
+    // Higher priority first, then first in first out.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+/// Queue writing messages with a higher `priority` first, and messages of the same priority in order.
+pub struct PriorityQueue {
+    priority: PriorityFn,
+    messages: BinaryHeap<Prioritized>,
+    sequence: u64,
+}
+
+impl std::fmt::Debug for PriorityQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PriorityQueue")
+            .field("messages", &self.messages)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PriorityQueue {
+    pub fn new(priority: impl Fn(&Message) -> u8 + Send + Sync + 'static) -> Self {
+        Self {
+            priority: Arc::new(priority),
+            messages: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+}
+
+impl SendQueue for PriorityQueue {
+    fn push(&mut self, message: Message) {
+        self.sequence += 1;
+        self.messages.push(Prioritized {
+            priority: (self.priority)(&message),
+            sequence: self.sequence,
+            message,
+        });
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        self.messages.pop().map(|entry| entry.message)
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+/// Queue shared between the `Sink` handles and the sink actor.
+pub(crate) struct Outbox {
+    queue: Mutex<Box<dyn SendQueue>>,
+    notify: Notify,
+    /// Free slots of a bounded queue, see [`SocketConfig::send_capacity`](crate::SocketConfig::send_capacity).
+    capacity: Option<Semaphore>,
+    /// Whether queued messages are held while a fragmented message is written, see [`Sink::send_fragment`](crate::Sink::send_fragment).
+    held: AtomicBool,
+    /// Set once the sink stops accepting messages.
+    closed: AtomicBool,
+}
+
+impl std::fmt::Debug for Outbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Outbox").finish_non_exhaustive()
+    }
+}
+
+impl Outbox {
+    pub(crate) fn new(queue: Box<dyn SendQueue>, capacity: Option<usize>) -> Self {
+        Self {
+            queue: Mutex::new(queue),
+            notify: Notify::new(),
+            capacity: capacity.map(Semaphore::new),
+            held: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Waits for a free slot in a bounded queue, returns `false` if the outbox was closed meanwhile.
+    /// The slot is taken by the next pushed message.
+    pub(crate) async fn reserve(&self) -> bool {
+        if self.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        match &self.capacity {
+            Some(capacity) => match capacity.acquire().await {
+                Ok(permit) => {
+                    permit.forget();
+                    true
+                }
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+
+    /// Takes a free slot in a bounded queue without waiting, see `reserve`.
+    pub(crate) fn try_reserve(&self) -> Result<(), TryAcquireError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(TryAcquireError::Closed);
+        }
+        match &self.capacity {
+            Some(capacity) => capacity.try_acquire().map(|permit| permit.forget()),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops accepting messages, waking the senders waiting in `reserve`, e.g. once the sink actor stopped.
+    pub(crate) fn close(&self) {
+        {
+            // Taking the lock orders the close with the pushes in progress.
+            let _queue = self.lock();
+            self.closed.store(true, Ordering::Release);
+        }
+        if let Some(capacity) = &self.capacity {
+            capacity.close();
+        }
+    }
+
+    fn release(&self, slots: usize) {
+        if let Some(capacity) = &self.capacity {
+            if slots > 0 {
+                capacity.add_permits(slots);
+            }
+        }
+    }
+
+    /// Queues the message and wakes the sink actor, returns how many queued messages were dropped or replaced by it,
+    /// or the message back if the outbox is closed.
+    pub(crate) fn push(&self, message: Message, key: Option<Arc<str>>) -> Result<usize, Message> {
+        let dropped = {
+            let mut queue = self.lock();
+            if self.closed.load(Ordering::Acquire) {
+                drop(queue);
+                self.release(1);
+                return Err(message);
+            }
+            let before = queue.len();
+            match key {
+                Some(key) => queue.push_conflated(key, message),
+                None => queue.push(message),
+            }
+            (before + 1).saturating_sub(queue.len())
+        };
+        self.release(dropped);
+        self.notify.notify_one();
+        Ok(dropped)
+    }
+
+    pub(crate) fn pop(&self) -> Option<Message> {
+        let message = {
+            let mut queue = self.lock();
+            if self.is_held() {
+                return None;
+            }
+            queue.pop()
+        };
+        if message.is_some() {
+            self.release(1);
+        }
+        message
+    }
+
+    /// Takes the queued messages, and holds the ones queued afterwards until `resume` is called.
+    pub(crate) fn hold(&self) -> Vec<Message> {
+        let messages: Vec<_> = {
+            let mut queue = self.lock();
+            self.held.store(true, Ordering::Release);
+            std::iter::from_fn(|| queue.pop()).collect()
+        };
+        self.release(messages.len());
+        messages
+    }
+
+    pub(crate) fn resume(&self) {
+        self.held.store(false, Ordering::Release);
+        self.notify.notify_one();
+    }
+
+    pub(crate) fn is_held(&self) -> bool {
+        self.held.load(Ordering::Acquire)
+    }
+
+    // A panic inside a custom queue must not make every later send panic as well.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Box<dyn SendQueue>> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Waits until a message is pushed.
+    pub(crate) async fn notified(&self) {
+        self.notify.notified().await;
+    }
+}
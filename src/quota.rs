@@ -0,0 +1,107 @@
+use crate::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Messages and bytes received from an identity within a quota window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+/// Storage of quota usage, shared by every session of an identity, and possibly by multiple servers.
+#[async_trait]
+pub trait QuotaStore: Send + Sync {
+    /// Adds `usage` to the usage of `identity` within the window `window`, returns the updated usage.
+    /// Windows are numbered from the UNIX epoch, usage of previous windows can be discarded.
+    async fn add(
+        &self,
+        identity: &str,
+        window: u64,
+        usage: QuotaUsage,
+    ) -> Result<QuotaUsage, Error>;
+}
+
+/// In-memory [`QuotaStore`], shared by the sessions of a single process.
+#[derive(Debug, Default)]
+pub struct MemoryQuotaStore {
+    usage: Mutex<HashMap<String, (u64, QuotaUsage)>>,
+}
+
+#[async_trait]
+impl QuotaStore for MemoryQuotaStore {
+    async fn add(
+        &self,
+        identity: &str,
+        window: u64,
+        usage: QuotaUsage,
+    ) -> Result<QuotaUsage, Error> {
+        let mut entries = self.usage.lock().unwrap();
+        let entry = entries
+            .entry(identity.to_string())
+            .or_insert((window, QuotaUsage::default()));
+        if entry.0 != window {
+            *entry = (window, QuotaUsage::default());
+        }
+        entry.1.messages += usage.messages;
+        entry.1.bytes += usage.bytes;
+        Ok(entry.1)
+    }
+}
+
+/// What happens when a session exceeds its quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaAction {
+    /// Stops reading messages of the session until the next window starts,
+    /// while the session keeps sending messages and handling calls.
+    Throttle,
+    /// Keeps processing messages, calling [`SessionExt::quota_exceeded`](crate::SessionExt::quota_exceeded) for each message over the quota.
+    Notify,
+    /// Closes the session with [`CloseCode::Policy`](crate::CloseCode::Policy).
+    Disconnect,
+}
+
+/// Message and byte budget of an identity per time window, e.g. per day.
+#[derive(Clone)]
+pub struct Quota {
+    /// Identity the usage is accounted to, usually a user or a tenant shared by multiple sessions.
+    pub identity: String,
+    pub max_messages: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub window: Duration,
+    pub action: QuotaAction,
+    pub store: Arc<dyn QuotaStore>,
+}
+
+impl std::fmt::Debug for Quota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Quota")
+            .field("identity", &self.identity)
+            .field("max_messages", &self.max_messages)
+            .field("max_bytes", &self.max_bytes)
+            .field("window", &self.window)
+            .field("action", &self.action)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Quota {
+    /// Number of the current window, and the time left until the next one starts.
+    pub(crate) fn current_window(&self) -> (u64, Duration) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let window = self.window.as_millis().max(1);
+        let elapsed = now.as_millis() % window;
+        let remaining = Duration::from_millis((window - elapsed) as u64);
+        ((now.as_millis() / window) as u64, remaining)
+    }
+
+    pub(crate) fn is_exceeded(&self, usage: &QuotaUsage) -> bool {
+        self.max_messages.is_some_and(|max| usage.messages > max)
+            || self.max_bytes.is_some_and(|max| usage.bytes > max)
+    }
+}
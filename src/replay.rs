@@ -0,0 +1,142 @@
+//! Recording of the frames received by a client, and replay of a recording in place of a connection,
+//! see [`ClientConfig::record`](crate::ClientConfig::record) and [`ClientConfig::replay`](crate::ClientConfig::replay).
+//!
+//! Recordings are text files with one received frame per line: the time it was received at in microseconds
+//! since the recording started, its kind (`text`, `binary`, `ping`, `pong` or `close`), then its base64 payload.
+//! Close frames carry their code before the base64 reason, if any.
+
+use crate::middleware::Middleware;
+use crate::middleware::SocketMiddleware;
+use crate::CloseCode;
+use crate::CloseFrame;
+use crate::Error;
+use crate::RawMessage;
+use crate::Socket;
+use crate::SocketConfig;
+use futures::StreamExt;
+use std::fs::File;
+use std::io::LineWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+struct Recorder {
+    start: Instant,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl SocketMiddleware for Recorder {
+    fn inbound(&self, message: RawMessage) -> Option<RawMessage> {
+        let at = self.start.elapsed().as_micros();
+        let line = match &message {
+            RawMessage::Text(text) => format!("{at} text {}", base64::encode(text)),
+            RawMessage::Binary(bytes) => format!("{at} binary {}", base64::encode(bytes)),
+            RawMessage::Ping(bytes) => format!("{at} ping {}", base64::encode(bytes)),
+            RawMessage::Pong(bytes) => format!("{at} pong {}", base64::encode(bytes)),
+            RawMessage::Close(Some(frame)) => format!(
+                "{at} close {} {}",
+                u16::from(frame.code.clone()),
+                base64::encode(&frame.reason)
+            ),
+            RawMessage::Close(None) => format!("{at} close"),
+            // Transports deliver reassembled messages.
+            RawMessage::Fragment(_) => return Some(message),
+        };
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = writeln!(file, "{line}") {
+            tracing::warn!("failed to record frame: {err}");
+        }
+        Some(message)
+    }
+}
+
+/// Creates the recording at `path`, replacing any previous one, returns the middleware appending the received frames to it.
+pub(crate) fn record(path: &Path) -> Result<Middleware, Error> {
+    let file = File::create(path)
+        .map_err(|err| format!("failed to create recording {}: {err}", path.display()))?;
+    Ok(Middleware::new(Recorder {
+        start: Instant::now(),
+        file: Mutex::new(LineWriter::new(file)),
+    }))
+}
+
+/// Socket playing back the recording at `path`, with the original timing, then closing normally.
+///
+/// Outbound messages are discarded, apart from Pings which are answered so that the connection doesn't time out.
+pub(crate) async fn replay(path: PathBuf, config: SocketConfig) -> Result<Socket, Error> {
+    let recording = tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || std::fs::read_to_string(path)
+    })
+    .await?
+    .map_err(|err| format!("failed to read recording {}: {err}", path.display()))?;
+    let mut frames = recording
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            parse(line).ok_or_else(|| format!("invalid frame at {}:{}", path.display(), i + 1))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if !matches!(frames.last(), Some((_, RawMessage::Close(_)))) {
+        let at = frames.last().map_or(Duration::ZERO, |(at, _)| *at);
+        let frame = CloseFrame {
+            code: CloseCode::Normal,
+            reason: String::from("end of the recording"),
+        };
+        frames.push((at, RawMessage::Close(Some(frame))));
+    }
+
+    let start = Instant::now();
+    let recorded = futures::stream::iter(frames).then(move |(at, message)| async move {
+        tokio::time::sleep_until(start + at).await;
+        message
+    });
+    let (pongs, answers) = mpsc::unbounded_channel();
+    let answers = futures::stream::unfold(answers, |mut answers| async move {
+        answers.recv().await.map(|message| (message, answers))
+    });
+    let stream = futures::stream::select(recorded, answers).map(Ok::<_, Error>);
+    let sink = futures::sink::unfold(pongs, |pongs, message: RawMessage| async move {
+        if let RawMessage::Ping(bytes) = message {
+            let _ = pongs.send(RawMessage::Pong(bytes));
+        }
+        Ok::<_, Error>(pongs)
+    });
+    Ok(Socket::spawn(Box::pin(sink), stream.boxed(), config))
+}
+
+/// Parses a line of a recording.
+fn parse(line: &str) -> Option<(Duration, RawMessage)> {
+    let mut fields = line.split(' ');
+    let at = Duration::from_micros(fields.next()?.parse().ok()?);
+    let kind = fields.next()?;
+    if kind == "close" {
+        let frame = match fields.next() {
+            Some(code) => Some(CloseFrame {
+                code: CloseCode::try_from(code.parse::<u16>().ok()?).ok()?,
+                reason: String::from_utf8(decode(fields.next())?).ok()?,
+            }),
+            None => None,
+        };
+        return Some((at, RawMessage::Close(frame)));
+    }
+    let payload = decode(fields.next())?;
+    let message = match kind {
+        "text" => RawMessage::Text(String::from_utf8(payload).ok()?),
+        "binary" => RawMessage::Binary(payload.into()),
+        "ping" => RawMessage::Ping(payload),
+        "pong" => RawMessage::Pong(payload),
+        _ => return None,
+    };
+    Some((at, message))
+}
+
+/// Decodes a base64 payload, empty payloads may be omitted.
+fn decode(payload: Option<&str>) -> Option<Vec<u8>> {
+    base64::decode(payload.unwrap_or_default()).ok()
+}
@@ -0,0 +1,167 @@
+use crate::ErrorEnvelope;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Reason for rejecting a session from joining a room.
+#[derive(Debug, Clone)]
+pub enum RejectReason {
+    /// The room reached its capacity, see [`Server::set_room_capacity`](crate::Server::set_room_capacity).
+    Full,
+    /// The session isn't allowed to join, or publish to, the room.
+    Denied(String),
+    /// The session tried to publish to a room it isn't a member of.
+    NotMember,
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(f, "room is full"),
+            Self::Denied(reason) => write!(f, "denied: {reason}"),
+            Self::NotMember => write!(f, "not a member of the room"),
+        }
+    }
+}
+
+/// Reports the rejection to the client, e.g. from [`ServerExt::join_rejected`](crate::ServerExt::join_rejected).
+impl From<RejectReason> for ErrorEnvelope {
+    fn from(reason: RejectReason) -> Self {
+        let code = match reason {
+            RejectReason::Full => "room_full",
+            RejectReason::Denied(_) => "denied",
+            RejectReason::NotMember => "not_member",
+        };
+        ErrorEnvelope::new(code, reason.to_string())
+    }
+}
+
+#[derive(Debug)]
+struct Room<ID> {
+    members: HashSet<ID>,
+    empty_since: Option<Instant>,
+}
+
+/// Membership of sessions in named rooms, owned by the server actor.
+///
+/// Rooms are created by the first join, and kept after becoming empty until [`Rooms::destroy_empty`] is called.
+/// Creations and rooms becoming empty are queued, to be retrieved with [`Rooms::take_events`].
+#[derive(Debug)]
+pub(crate) struct Rooms<ID> {
+    rooms: HashMap<String, Room<ID>>,
+    capacities: HashMap<String, usize>,
+    default_capacity: Option<usize>,
+    created: Vec<String>,
+    emptied: Vec<String>,
+}
+
+impl<ID: Hash + Eq + Clone> Rooms<ID> {
+    pub(crate) fn new(default_capacity: Option<usize>) -> Self {
+        Self {
+            rooms: HashMap::new(),
+            capacities: HashMap::new(),
+            default_capacity,
+            created: Vec::new(),
+            emptied: Vec::new(),
+        }
+    }
+
+    pub(crate) fn join(&mut self, room: String, id: ID) {
+        let entry = self.rooms.entry(room).or_insert_with_key(|room| {
+            self.created.push(room.clone());
+            Room {
+                members: HashSet::new(),
+                empty_since: None,
+            }
+        });
+        entry.members.insert(id);
+        entry.empty_since = None;
+    }
+
+    /// Overrides the default capacity of the room, `None` restores the default.
+    pub(crate) fn set_capacity(&mut self, room: String, capacity: Option<usize>) {
+        match capacity {
+            Some(capacity) => self.capacities.insert(room, capacity),
+            None => self.capacities.remove(&room),
+        };
+    }
+
+    pub(crate) fn contains(&self, room: &str, id: &ID) -> bool {
+        self.rooms
+            .get(room)
+            .is_some_and(|entry| entry.members.contains(id))
+    }
+
+    /// Whether the room reached its capacity.
+    pub(crate) fn is_full(&self, room: &str) -> bool {
+        let capacity = match self.capacities.get(room).copied().or(self.default_capacity) {
+            Some(capacity) => capacity,
+            None => return false,
+        };
+        let members = self.rooms.get(room).map_or(0, |entry| entry.members.len());
+        members >= capacity
+    }
+
+    /// Removes the session from the room, returns `false` if it wasn't a member.
+    pub(crate) fn leave(&mut self, room: &str, id: &ID) -> bool {
+        let entry = match self.rooms.get_mut(room) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let removed = entry.members.remove(id);
+        if removed && entry.members.is_empty() {
+            entry.empty_since = Some(Instant::now());
+            self.emptied.push(room.to_string());
+        }
+        removed
+    }
+
+    /// Moves the session from one room to another, returns `false` if it wasn't a member of `from`.
+    pub(crate) fn move_session(&mut self, id: ID, from: &str, to: String) -> bool {
+        if !self.leave(from, &id) {
+            return false;
+        }
+        self.join(to, id);
+        true
+    }
+
+    /// Removes the session from every room it's a member of.
+    pub(crate) fn remove(&mut self, id: &ID) {
+        for (room, entry) in self.rooms.iter_mut() {
+            if entry.members.remove(id) && entry.members.is_empty() {
+                entry.empty_since = Some(Instant::now());
+                self.emptied.push(room.clone());
+            }
+        }
+    }
+
+    pub(crate) fn members(&self, room: &str) -> impl Iterator<Item = &ID> {
+        self.rooms
+            .get(room)
+            .into_iter()
+            .flat_map(|entry| entry.members.iter())
+    }
+
+    /// Returns the rooms created and the rooms which became empty since the last call.
+    pub(crate) fn take_events(&mut self) -> (Vec<String>, Vec<String>) {
+        (
+            std::mem::take(&mut self.created),
+            std::mem::take(&mut self.emptied),
+        )
+    }
+
+    /// Destroys the rooms which have been empty for at least `ttl`.
+    pub(crate) fn destroy_empty(&mut self, ttl: Duration) {
+        self.rooms.retain(|room, entry| {
+            let expired = entry
+                .empty_since
+                .is_some_and(|empty_since| empty_since.elapsed() >= ttl);
+            if expired {
+                tracing::debug!(%room, "destroying empty room");
+            }
+            !expired
+        });
+    }
+}
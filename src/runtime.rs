@@ -0,0 +1,34 @@
+use std::future::Future;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+/// Spawns the future on `runtime`, or on the current runtime if not set.
+/// The task stays in the current `tracing` span, so its events are attributed to the session or client which spawned it.
+///
+/// With the `task-names` feature and `--cfg tokio_unstable`, the task is named `name`,
+/// which makes it identifiable in tokio-console.
+pub(crate) fn spawn<F>(name: &str, runtime: Option<&Handle>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let future = future.in_current_span();
+    #[cfg(all(tokio_unstable, feature = "task-names"))]
+    {
+        let builder = tokio::task::Builder::new().name(name);
+        match runtime {
+            Some(runtime) => builder.spawn_on(future, runtime),
+            None => builder.spawn(future),
+        }
+        .expect("failed to spawn task")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "task-names")))]
+    {
+        let _ = name;
+        match runtime {
+            Some(runtime) => runtime.spawn(future),
+            None => tokio::spawn(future),
+        }
+    }
+}
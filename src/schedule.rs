@@ -0,0 +1,169 @@
+use crate::Message;
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use tokio::time::Instant;
+
+pub(crate) type BroadcastFactory = Box<dyn FnMut() -> Message + Send>;
+
+/// When a broadcast scheduled with [`Server::schedule_broadcast`](crate::Server::schedule_broadcast) runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    period: Duration,
+    delay: Option<Duration>,
+    aligned: bool,
+    times: Option<usize>,
+}
+
+impl Schedule {
+    /// Runs every `period`, the first time one period from now.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn every(period: Duration) -> Self {
+        assert!(
+            !period.is_zero(),
+            "the period of a schedule must be non-zero"
+        );
+        Self {
+            period,
+            delay: None,
+            aligned: false,
+            times: None,
+        }
+    }
+
+    /// Runs for the first time after `delay` instead of one period from now.
+    pub fn starting_in(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Runs at multiples of the period since the Unix epoch, like cron, e.g. at the start of every minute
+    /// with a period of one minute. Takes precedence over [`Schedule::starting_in`].
+    pub fn aligned(mut self) -> Self {
+        self.aligned = true;
+        self
+    }
+
+    /// Stops after running `times` times.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn first_run(&self, now: Instant) -> Instant {
+        if self.aligned {
+            let since_epoch = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            let elapsed = since_epoch.as_nanos() % self.period.as_nanos();
+            return now + (self.period - Duration::from_nanos(elapsed as u64));
+        }
+        now + self.delay.unwrap_or(self.period)
+    }
+}
+
+/// Handle of a broadcast scheduled with [`Server::schedule_broadcast`](crate::Server::schedule_broadcast).
+/// The broadcast keeps running once the handle is dropped, until it's cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct ScheduledBroadcast {
+    finished: Arc<AtomicBool>,
+}
+
+impl ScheduledBroadcast {
+    /// Stops the broadcast, the run in progress, if any, completes.
+    pub fn cancel(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the broadcast was cancelled or ran as many times as its schedule allows.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) struct ScheduledTask {
+    room: String,
+    period: Duration,
+    remaining: Option<usize>,
+    next: Instant,
+    factory: BroadcastFactory,
+    handle: ScheduledBroadcast,
+}
+
+impl ScheduledTask {
+    pub(crate) fn new(
+        room: String,
+        schedule: Schedule,
+        factory: BroadcastFactory,
+        handle: ScheduledBroadcast,
+    ) -> Self {
+        Self {
+            room,
+            period: schedule.period,
+            remaining: schedule.times,
+            next: schedule.first_run(Instant::now()),
+            factory,
+            handle,
+        }
+    }
+}
+
+/// Broadcasts scheduled on the server, run by the server actor.
+#[derive(Default)]
+pub(crate) struct Schedules {
+    tasks: Vec<ScheduledTask>,
+}
+
+impl Schedules {
+    pub(crate) fn add(&mut self, task: ScheduledTask) {
+        if task.remaining == Some(0) {
+            task.handle.cancel();
+            return;
+        }
+        self.tasks.push(task);
+    }
+
+    /// Waits until a broadcast is due, forever if there's none.
+    pub(crate) fn next_run(&self) -> impl Future<Output = ()> + Send + 'static {
+        // Factories aren't Sync, the future can't borrow the schedules.
+        let next = self.tasks.iter().map(|task| task.next).min();
+        async move {
+            match next {
+                Some(next) => tokio::time::sleep_until(next).await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Calls `broadcast` with the room and the message factory of every broadcast which is due.
+    pub(crate) fn run_due(&mut self, mut broadcast: impl FnMut(&str, &mut BroadcastFactory)) {
+        let now = Instant::now();
+        self.tasks.retain_mut(|task| {
+            if task.handle.is_finished() {
+                return false;
+            }
+            if task.next > now {
+                return true;
+            }
+            broadcast(&task.room, &mut task.factory);
+            if let Some(remaining) = &mut task.remaining {
+                *remaining -= 1;
+                if *remaining == 0 {
+                    task.handle.cancel();
+                    return false;
+                }
+            }
+            // Runs missed while the server actor was busy are skipped.
+            while task.next <= now {
+                task.next += task.period;
+            }
+            true
+        });
+    }
+}
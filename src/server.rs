@@ -1,13 +1,56 @@
+use crate::backfill::Backfills;
+use crate::rooms::Rooms;
+use crate::schedule::ScheduledTask;
+use crate::schedule::Schedules;
+use crate::session::ShutdownRequested;
+use crate::throttle::AcceptLimiter;
+use crate::throttle::HandshakePermit;
+use crate::time::random_duration;
+use crate::time::tick;
+use crate::AcceptLimit;
+use crate::Backfill;
+use crate::CloseCode;
 use crate::CloseFrame;
 use crate::Error;
+use crate::HandlerTimings;
+use crate::Message;
+use crate::RejectReason;
+use crate::Schedule;
+use crate::ScheduledBroadcast;
 use crate::Session;
+use crate::SessionConfig;
 use crate::SessionExt;
+use crate::SessionUsage;
 use crate::Socket;
+use crate::SocketConfig;
 use async_trait::async_trait;
 use futures::Future;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tracing::Instrument;
+
+type SessionID<E> = <<E as ServerExt>::Session as SessionExt>::ID;
+type SessionParams<E> = <<E as ServerExt>::Session as SessionExt>::Params;
+type SessionFilter<E> = Box<dyn Fn(&SessionID<E>) -> bool + Send>;
+type BroadcastFn<E> = Box<dyn Fn(&SessionInfo<SessionID<E>>) -> Option<Message> + Send>;
+
+/// Recipient of a broadcast, see [`Server::broadcast_with`].
+#[derive(Debug)]
+pub struct SessionInfo<'a, ID> {
+    pub id: &'a ID,
+    /// Address of the connected client, if known by the server back-end.
+    pub peer_addr: Option<SocketAddr>,
+}
 
 struct NewConnection<E: ServerExt> {
     socket: Socket,
@@ -21,14 +64,126 @@ struct Disconnected<E: ServerExt> {
     result: Result<Option<CloseFrame>, Error>,
 }
 
+enum RegistryCommand<E: ServerExt> {
+    SendMany {
+        ids: Vec<SessionID<E>>,
+        message: Arc<Message>,
+    },
+    DisconnectMany {
+        filter: SessionFilter<E>,
+        frame: Option<CloseFrame>,
+    },
+    HandlerTimings {
+        respond_to: oneshot::Sender<HandlerTimings>,
+    },
+    Usage {
+        respond_to: oneshot::Sender<HashMap<SessionID<E>, SessionUsage>>,
+    },
+    Join {
+        room: String,
+        id: SessionID<E>,
+    },
+    JoinWithBackfill {
+        room: String,
+        id: SessionID<E>,
+        backfill: Backfill,
+    },
+    BackfillDone {
+        room: String,
+        id: SessionID<E>,
+        generation: u64,
+        /// Keys of the backfilled messages.
+        keys: HashSet<String>,
+    },
+    Leave {
+        room: String,
+        id: SessionID<E>,
+    },
+    Move {
+        id: SessionID<E>,
+        from: String,
+        to: String,
+    },
+    Broadcast {
+        room: String,
+        message: Message,
+    },
+    BroadcastWith {
+        room: String,
+        transform: BroadcastFn<E>,
+    },
+    Publish {
+        room: String,
+        from: SessionID<E>,
+        message: Message,
+    },
+    SendAll {
+        message: Message,
+    },
+    RoomCapacity {
+        room: String,
+        capacity: Option<usize>,
+    },
+    Schedule(ScheduledTask),
+}
+
 struct ServerActor<E: ServerExt> {
     connections: mpsc::UnboundedReceiver<NewConnection<E>>,
     disconnections: mpsc::UnboundedReceiver<Disconnected<E>>,
     calls: mpsc::UnboundedReceiver<E::Params>,
+    registry: mpsc::UnboundedReceiver<RegistryCommand<E>>,
+    sessions: HashMap<SessionID<E>, Session<SessionID<E>, SessionParams<E>>>,
+    rooms: Rooms<SessionID<E>>,
+    backfills: Backfills<SessionID<E>>,
+    room_sweep: Option<tokio::time::Interval>,
+    schedules: Schedules,
+    /// Handler timings of the sessions which disconnected, so that they aren't lost with the sessions.
+    disconnected_timings: HandlerTimings,
     server: Server<E>,
     extension: E,
 }
 
+/// Waits until the session is closed and reports it to the server, recycling the connection once it reaches `max_age`.
+async fn watch_session<E: ServerExt>(
+    server: Server<E>,
+    session: Session<SessionID<E>, SessionParams<E>>,
+    max_age: Option<Duration>,
+) {
+    let closed = session.closed();
+    tokio::pin!(closed);
+    let result = match max_age {
+        Some(max_age) => tokio::select! {
+            result = &mut closed => result,
+            _ = recycle_after(&server.config, &session, max_age) => {
+                tracing::info!(id = %session.id, "recycling connection after {}s", max_age.as_secs());
+                session.send(Message::Close(Some(CloseFrame {
+                    code: CloseCode::Restart,
+                    reason: String::from("connection reached its maximum age"),
+                })));
+                closed.await
+            }
+        },
+        None => closed.await,
+    };
+    server.disconnected(session.id.clone(), result).await;
+}
+
+/// Waits until the session reaches `max_age`, sending [`ServerConfig::goaway`] ahead of it.
+async fn recycle_after<ID: std::fmt::Display + Clone, P: std::fmt::Debug>(
+    config: &ServerConfig,
+    session: &Session<ID, P>,
+    max_age: Duration,
+) {
+    match &config.goaway {
+        Some(goaway) => {
+            tokio::time::sleep(max_age.saturating_sub(goaway.lead_time)).await;
+            session.send(goaway.message.clone());
+            tokio::time::sleep(goaway.lead_time.min(max_age)).await;
+        }
+        None => tokio::time::sleep(max_age).await,
+    }
+}
+
 impl<E: ServerExt> ServerActor<E>
 where
     E: Send + 'static,
@@ -36,46 +191,312 @@ where
 {
     async fn run(&mut self) -> Result<(), Error> {
         tracing::info!("starting server");
+        self.room_sweep = self
+            .server
+            .config
+            .empty_room_ttl
+            .filter(|ttl| !ttl.is_zero())
+            .map(tokio::time::interval);
         loop {
             tokio::select! {
                 Some(NewConnection{socket, address, args, respond_to}) = self.connections.recv() => {
                     let session = self.extension.accept(socket, address, args).await?;
                     let session_id = session.id.clone();
                     tracing::info!("connection from {address} accepted");
-                    respond_to.send(session_id.clone()).unwrap();
+                    // The caller may have stopped waiting for the ID, the session is registered anyway.
+                    let _ = respond_to.send(session_id.clone());
+                    self.sessions.insert(session_id.clone(), session.clone());
 
-                    tokio::spawn({
-                        let server = self.server.clone();
-                        async move {
-                            let result = session.closed().await;
-                            server.disconnected(session_id, result).await;
-                        }
+                    let max_age = self.server.config.max_connection_age.map(|max_age| {
+                        max_age + random_duration(self.server.config.max_connection_age_jitter)
                     });
+                    crate::runtime::spawn(
+                        "ezsockets::session_closed",
+                        self.server.config.runtime.as_ref(),
+                        watch_session(self.server.clone(), session, max_age),
+                    );
                 }
                 Some(Disconnected{id, result}) = self.disconnections.recv() => {
+                    if let Some(session) = self.sessions.remove(&id) {
+                        self.disconnected_timings.merge(&session.handler_timings());
+                    }
+                    self.rooms.remove(&id);
+                    self.backfills.cancel_all(&id);
                     self.extension.disconnected(id.clone()).await?;
+                    self.handle_room_events().await?;
                     match result {
                         Ok(Some(CloseFrame { code, reason })) => {
                             tracing::info!(%id, ?code, %reason, "connection closed")
                         }
                         Ok(None) => tracing::info!(%id, "connection closed"),
+                        Err(err) if err.is::<ShutdownRequested>() => {
+                            tracing::error!(%id, "{err}");
+                            for session in self.sessions.values() {
+                                session.close(Some(ShutdownRequested::close_frame()));
+                            }
+                            return Err(err);
+                        }
                         Err(err) => tracing::warn!(%id, "connection closed due to: {err}"),
                     };
                 }
                 Some(params) = self.calls.recv() => {
                     self.extension.call(params).await?
                 }
+                Some(command) = self.registry.recv() => {
+                    self.handle_registry_command(command).await?;
+                    self.handle_room_events().await?;
+                }
+                _ = tick(&mut self.room_sweep) => {
+                    if let Some(ttl) = self.server.config.empty_room_ttl {
+                        self.rooms.destroy_empty(ttl);
+                    }
+                }
+                _ = self.schedules.next_run() => {
+                    let (rooms, sessions, backfills) = (&self.rooms, &self.sessions, &mut self.backfills);
+                    self.schedules.run_due(|room, factory| {
+                        let mut members = rooms
+                            .members(room)
+                            .filter_map(|id| Some((id, sessions.get(id)?)))
+                            .peekable();
+                        // The message isn't even built for empty rooms.
+                        if members.peek().is_none() {
+                            return;
+                        }
+                        let message = factory();
+                        for (id, session) in members {
+                            if let Some(message) = backfills.hold(room, id, message.clone()) {
+                                session.send(message);
+                            }
+                        }
+                    });
+                }
                 else => break
             }
         }
         Ok(())
     }
+
+    async fn handle_room_events(&mut self) -> Result<(), Error> {
+        let (created, emptied) = self.rooms.take_events();
+        for room in created {
+            self.extension.room_created(room).await?;
+        }
+        for room in emptied {
+            self.extension.room_empty(room).await?;
+        }
+        if self.room_sweep.is_none() {
+            self.rooms.destroy_empty(Duration::ZERO);
+        }
+        Ok(())
+    }
+
+    /// Runs the admission control of the room, returns `false` if the session was rejected.
+    async fn admit_to_room(&mut self, id: &SessionID<E>, room: &str) -> Result<bool, Error> {
+        if self.rooms.contains(room, id) {
+            return Ok(true);
+        }
+        let result = match self.extension.can_join(id, room).await {
+            Ok(()) if self.rooms.is_full(room) => Err(RejectReason::Full),
+            result => result,
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(reason) => {
+                tracing::info!(%id, %room, %reason, "session rejected from joining the room");
+                if let Some(session) = self.sessions.get(id).cloned() {
+                    self.extension
+                        .join_rejected(&session, room.to_string(), reason)
+                        .await?;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Sends the backfill to the new member of the room, its live broadcasts are held until it completes.
+    fn start_backfill(&mut self, room: String, id: SessionID<E>, backfill: Backfill) {
+        let session = self.sessions[&id].clone();
+        let server = self.server.clone();
+        let Backfill { mut source, key } = backfill;
+        let generation = self.backfills.next_generation();
+        let task = crate::runtime::spawn(
+            "ezsockets::backfill",
+            self.server.config.runtime.as_ref(),
+            {
+                let (room, id, key) = (room.clone(), id.clone(), key.clone());
+                async move {
+                    let mut keys = HashSet::new();
+                    while let Some(message) = source.next().await {
+                        if let Some(key) = key.as_ref().and_then(|key| key(&message)) {
+                            keys.insert(key);
+                        }
+                        session.send(message);
+                    }
+                    server.registry(RegistryCommand::BackfillDone {
+                        room,
+                        id,
+                        generation,
+                        keys,
+                    });
+                }
+            },
+        );
+        self.backfills.start(room, id, generation, key, task);
+    }
+
+    async fn handle_registry_command(&mut self, command: RegistryCommand<E>) -> Result<(), Error> {
+        match command {
+            RegistryCommand::SendMany { ids, message } => {
+                for id in ids {
+                    match self.sessions.get(&id) {
+                        Some(session) => session.send_shared(message.clone()),
+                        None => tracing::debug!(%id, "skipping unknown session"),
+                    }
+                }
+            }
+            RegistryCommand::DisconnectMany { filter, frame } => {
+                for (id, session) in self.sessions.iter() {
+                    if filter(id) {
+                        tracing::info!(%id, "disconnecting session");
+                        session.send(Message::Close(frame.clone()));
+                    }
+                }
+            }
+            RegistryCommand::HandlerTimings { respond_to } => {
+                let mut timings = self.disconnected_timings.clone();
+                for session in self.sessions.values() {
+                    timings.merge(&session.handler_timings());
+                }
+                let _ = respond_to.send(timings);
+            }
+            RegistryCommand::Usage { respond_to } => {
+                let usage = self
+                    .sessions
+                    .iter()
+                    .map(|(id, session)| (id.clone(), session.usage()))
+                    .collect();
+                let _ = respond_to.send(usage);
+            }
+            RegistryCommand::Join { room, id } => {
+                if !self.sessions.contains_key(&id) {
+                    tracing::debug!(%id, "skipping unknown session");
+                } else if self.admit_to_room(&id, &room).await? {
+                    self.rooms.join(room, id);
+                }
+            }
+            RegistryCommand::JoinWithBackfill { room, id, backfill } => {
+                if !self.sessions.contains_key(&id) {
+                    tracing::debug!(%id, "skipping unknown session");
+                } else if self.rooms.contains(&room, &id) {
+                    tracing::debug!(%id, %room, "session is already a member of the room, skipping backfill");
+                } else if self.admit_to_room(&id, &room).await? {
+                    self.rooms.join(room.clone(), id.clone());
+                    self.start_backfill(room, id, backfill);
+                }
+            }
+            RegistryCommand::BackfillDone {
+                room,
+                id,
+                generation,
+                keys,
+            } => {
+                let messages = self.backfills.finish(&room, &id, generation, keys);
+                if let Some(session) = self.sessions.get(&id) {
+                    for message in messages {
+                        session.send(message);
+                    }
+                }
+            }
+            RegistryCommand::Leave { room, id } => {
+                self.backfills.cancel(&room, &id);
+                if !self.rooms.leave(&room, &id) {
+                    tracing::debug!(%id, %room, "session is not a member of the room");
+                }
+            }
+            RegistryCommand::Move { id, from, to } => {
+                if !self.rooms.contains(&from, &id) {
+                    tracing::debug!(%id, room = %from, "session is not a member of the room");
+                } else if self.admit_to_room(&id, &to).await? {
+                    self.backfills.cancel(&from, &id);
+                    self.rooms.move_session(id, &from, to);
+                }
+            }
+            RegistryCommand::Broadcast { room, message } => {
+                for id in self.rooms.members(&room) {
+                    if let Some(session) = self.sessions.get(id) {
+                        if let Some(message) = self.backfills.hold(&room, id, message.clone()) {
+                            session.send(message);
+                        }
+                    }
+                }
+            }
+            RegistryCommand::BroadcastWith { room, transform } => {
+                for id in self.rooms.members(&room) {
+                    if let Some(session) = self.sessions.get(id) {
+                        let info = SessionInfo {
+                            id,
+                            peer_addr: session.peer_addr(),
+                        };
+                        if let Some(message) = transform(&info) {
+                            if let Some(message) = self.backfills.hold(&room, id, message) {
+                                session.send(message);
+                            }
+                        }
+                    }
+                }
+            }
+            RegistryCommand::Publish {
+                room,
+                from,
+                message,
+            } => {
+                let result = if self.rooms.contains(&room, &from) {
+                    self.extension.can_publish(&from, &room).await
+                } else {
+                    Err(RejectReason::NotMember)
+                };
+                match result {
+                    Ok(()) => {
+                        for id in self.rooms.members(&room).filter(|id| **id != from) {
+                            if let Some(session) = self.sessions.get(id) {
+                                if let Some(message) =
+                                    self.backfills.hold(&room, id, message.clone())
+                                {
+                                    session.send(message);
+                                }
+                            }
+                        }
+                    }
+                    Err(reason) => {
+                        tracing::info!(id = %from, %room, %reason, "session rejected from publishing to the room");
+                        if let Some(session) = self.sessions.get(&from).cloned() {
+                            self.extension
+                                .publish_rejected(&session, room, reason)
+                                .await?;
+                        }
+                    }
+                }
+            }
+            RegistryCommand::SendAll { message } => {
+                for session in self.sessions.values() {
+                    session.send(message.clone());
+                }
+            }
+            RegistryCommand::RoomCapacity { room, capacity } => {
+                self.rooms.set_capacity(room, capacity);
+            }
+            RegistryCommand::Schedule(task) => self.schedules.add(task),
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 pub trait ServerExt: Send {
     type Session: SessionExt;
     type Params: Send + std::fmt::Debug;
+    /// Application state shared by the server extension and its sessions, see [`Server::create_with_state`].
+    type State: Send + Sync + 'static;
 
     async fn accept(
         &mut self,
@@ -88,6 +509,151 @@ pub trait ServerExt: Send {
     >;
     async fn disconnected(&mut self, id: <Self::Session as SessionExt>::ID) -> Result<(), Error>;
     async fn call(&mut self, params: Self::Params) -> Result<(), Error>;
+
+    /// Admission control for sessions joining rooms, either through [`Server::join`] or [`Server::move_session`].
+    /// Room capacity is checked only once the session is allowed to join. Allows every session by default.
+    async fn can_join(
+        &mut self,
+        id: &<Self::Session as SessionExt>::ID,
+        room: &str,
+    ) -> Result<(), RejectReason> {
+        let _ = (id, room);
+        Ok(())
+    }
+
+    /// Called when a session was rejected from joining a room, to let the client know about it.
+    /// Replies to the session with the [`ErrorEnvelope`](crate::ErrorEnvelope) of the reason by default.
+    async fn join_rejected(
+        &mut self,
+        session: &Session<<Self::Session as SessionExt>::ID, <Self::Session as SessionExt>::Params>,
+        room: String,
+        reason: RejectReason,
+    ) -> Result<(), Error> {
+        let _ = room;
+        // The session may be closing already, there's no one left to tell then.
+        let _ = session.reply_error(reason.into());
+        Ok(())
+    }
+
+    /// Write permission of rooms, checked by [`Server::publish`] once the session is known to be a member of the room,
+    /// while [`ServerExt::can_join`] acts as the read permission. Allows every member by default.
+    async fn can_publish(
+        &mut self,
+        id: &<Self::Session as SessionExt>::ID,
+        room: &str,
+    ) -> Result<(), RejectReason> {
+        let _ = (id, room);
+        Ok(())
+    }
+
+    /// Called when a session was rejected from publishing to a room, to let the client know about it.
+    /// Replies to the session with the [`ErrorEnvelope`](crate::ErrorEnvelope) of the reason by default.
+    async fn publish_rejected(
+        &mut self,
+        session: &Session<<Self::Session as SessionExt>::ID, <Self::Session as SessionExt>::Params>,
+        room: String,
+        reason: RejectReason,
+    ) -> Result<(), Error> {
+        let _ = room;
+        // The session may be closing already, there's no one left to tell then.
+        let _ = session.reply_error(reason.into());
+        Ok(())
+    }
+
+    /// Called when a room is created by the first session joining it.
+    async fn room_created(&mut self, room: String) -> Result<(), Error> {
+        let _ = room;
+        Ok(())
+    }
+
+    /// Called when the last session leaves a room. The room is then destroyed, either immediately
+    /// or once it stays empty for [`ServerConfig::empty_room_ttl`].
+    async fn room_empty(&mut self, room: String) -> Result<(), Error> {
+        let _ = room;
+        Ok(())
+    }
+}
+
+/// Notice sent to clients some time before their connection is recycled or drained,
+/// so that they can open their replacement connection and migrate their subscriptions without gaps.
+#[derive(Debug, Clone)]
+pub struct GoAway {
+    /// Message sent as the notice.
+    pub message: Message,
+    /// Time between the notice and the connection being closed.
+    pub lead_time: Duration,
+}
+
+impl GoAway {
+    /// Notice sent `lead_time` ahead, with a `{"type":"goaway"}` text message.
+    pub fn new(lead_time: Duration) -> Self {
+        Self {
+            message: Message::Text(String::from(r#"{"type":"goaway"}"#)),
+            lead_time,
+        }
+    }
+
+    /// Replaces the message sent as the notice.
+    pub fn message(mut self, message: Message) -> Self {
+        self.message = message;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// Configuration applied by the server back-ends to every accepted socket.
+    pub socket: SocketConfig,
+    /// Configuration of the sessions created with [`Session::create`] from the sockets handed over to [`Server::accept`].
+    pub session: SessionConfig,
+    /// Limits on the rate of new connections, enforced by the server back-ends through [`Server::admit`].
+    pub accept_limit: AcceptLimit,
+    /// Time after which empty rooms are destroyed, they're destroyed as soon as they become empty if not set.
+    /// Keeping them for a while lets sessions rejoin shortly after a room empties without it being recreated.
+    pub empty_room_ttl: Option<Duration>,
+    /// Maximum number of sessions in a room, unless overridden with [`Server::set_room_capacity`].
+    pub room_capacity: Option<usize>,
+    /// Time after which connections are closed with [`CloseCode::Restart`], so that clients reconnect
+    /// and get rebalanced by load balancers in front of the server.
+    pub max_connection_age: Option<Duration>,
+    /// Maximum random delay added to [`ServerConfig::max_connection_age`], so that connections accepted
+    /// at the same time aren't all recycled at once.
+    pub max_connection_age_jitter: Duration,
+    /// Notice sent ahead of closing connections because of [`ServerConfig::max_connection_age`] or [`Server::drain`].
+    pub goaway: Option<GoAway>,
+    /// Runtime the server actor is spawned on, defaults to the current runtime.
+    pub runtime: Option<tokio::runtime::Handle>,
+}
+
+impl ServerConfig {
+    /// Makes socket and session actors yield to other tasks after processing `yield_after` messages,
+    /// trading some throughput of busy connections for fairness between connections.
+    pub fn fairness(mut self, yield_after: usize) -> Self {
+        self.socket.yield_after = Some(yield_after);
+        self.session.yield_after = Some(yield_after);
+        self
+    }
+
+    /// Recycles connections after `max_age`, with up to 10% of jitter, see [`ServerConfig::max_connection_age`].
+    pub fn max_connection_age(mut self, max_age: Duration) -> Self {
+        self.max_connection_age = Some(max_age);
+        self.max_connection_age_jitter = max_age / 10;
+        self
+    }
+
+    /// Sends `goaway` to clients before recycling or draining their connection, see [`ServerConfig::goaway`].
+    pub fn goaway(mut self, goaway: GoAway) -> Self {
+        self.goaway = Some(goaway);
+        self
+    }
+
+    /// Spawns the server, session and socket actors on `runtime` instead of the current runtime.
+    pub fn runtime(mut self, runtime: tokio::runtime::Handle) -> Self {
+        self.socket.runtime = Some(runtime.clone());
+        self.session.runtime = Some(runtime.clone());
+        self.runtime = Some(runtime);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -95,6 +661,14 @@ pub struct Server<E: ServerExt> {
     connections: mpsc::UnboundedSender<NewConnection<E>>,
     disconnections: mpsc::UnboundedSender<Disconnected<E>>,
     calls: mpsc::UnboundedSender<E::Params>,
+    registry: mpsc::UnboundedSender<RegistryCommand<E>>,
+    config: Arc<ServerConfig>,
+    state: Arc<E::State>,
+    accept_limiter: Arc<Mutex<AcceptLimiter>>,
+    handshakes: Arc<AtomicUsize>,
+    /// Connections waiting in [`Server::admit`], see [`AcceptLimit::max_queued`].
+    queued: Arc<AtomicUsize>,
+    overloads: Arc<AtomicU64>,
 }
 
 impl<E: ServerExt> From<Server<E>> for mpsc::UnboundedSender<E::Params> {
@@ -104,41 +678,185 @@ impl<E: ServerExt> From<Server<E>> for mpsc::UnboundedSender<E::Params> {
 }
 
 impl<E: ServerExt + 'static> Server<E> {
-    pub fn create(
+    pub fn create(create: impl FnOnce(Self) -> E) -> (Self, impl Future<Output = Result<(), Error>>)
+    where
+        E::State: Default,
+    {
+        Self::create_with_config(create, ServerConfig::default())
+    }
+
+    pub fn create_with_config(
         create: impl FnOnce(Self) -> E,
+        config: ServerConfig,
+    ) -> (Self, impl Future<Output = Result<(), Error>>)
+    where
+        E::State: Default,
+    {
+        Self::create_with_state(create, config, Arc::default())
+    }
+
+    /// Creates the server with the application `state` of its extension, available through [`Server::state`]
+    /// from the handle given to `create`, so that the extension can hand it to its sessions when accepting them,
+    /// similarly to axum's `State`.
+    pub fn create_with_state(
+        create: impl FnOnce(Self) -> E,
+        config: ServerConfig,
+        state: Arc<E::State>,
     ) -> (Self, impl Future<Output = Result<(), Error>>) {
         let (connection_sender, connection_receiver) = mpsc::unbounded_channel();
         let (disconnection_sender, disconnection_receiver) = mpsc::unbounded_channel();
         let (call_sender, call_receiver) = mpsc::unbounded_channel();
+        let (registry_sender, registry_receiver) = mpsc::unbounded_channel();
         let handle = Self {
             connections: connection_sender,
             calls: call_sender,
             disconnections: disconnection_sender,
+            registry: registry_sender,
+            accept_limiter: Arc::new(Mutex::new(AcceptLimiter::new(config.accept_limit.clone()))),
+            handshakes: Arc::new(AtomicUsize::new(0)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            overloads: Arc::new(AtomicU64::new(0)),
+            config: Arc::new(config),
+            state,
         };
         let extension = create(handle.clone());
         let mut actor = ServerActor {
             connections: connection_receiver,
             disconnections: disconnection_receiver,
             calls: call_receiver,
+            registry: registry_receiver,
+            sessions: HashMap::new(),
+            rooms: Rooms::new(handle.config.room_capacity),
+            backfills: Backfills::default(),
+            room_sweep: None,
+            schedules: Schedules::default(),
+            disconnected_timings: HandlerTimings::default(),
             extension,
             server: handle.clone(),
         };
-        let future = tokio::spawn(async move {
-            actor.run().await?;
-            Ok::<_, Error>(())
-        });
-        let future = async move { future.await.unwrap() };
+        let future = crate::runtime::spawn(
+            "ezsockets::server",
+            handle.config.runtime.as_ref(),
+            async move {
+                actor.run().await?;
+                Ok::<_, Error>(())
+            }
+            .instrument(tracing::info_span!("server")),
+        );
+        let future = async move { future.await? };
         (handle, future)
     }
+
+    /// Asks every connected client to reconnect, by closing its session with [`CloseCode::Restart`].
+    /// Clients created with [`connect`](crate::connect) reconnect after a randomized delay.
+    ///
+    /// With [`ServerConfig::goaway`] set, the notice is sent first, and sessions are closed once its lead time elapses.
+    pub fn drain(&self) {
+        let frame = CloseFrame {
+            code: CloseCode::Restart,
+            reason: String::from("server is draining"),
+        };
+        let goaway = match &self.config.goaway {
+            Some(goaway) => goaway,
+            None => return self.disconnect_many(|_| true, Some(frame)),
+        };
+        self.registry(RegistryCommand::SendAll {
+            message: goaway.message.clone(),
+        });
+        let server = self.clone();
+        let lead_time = goaway.lead_time;
+        crate::runtime::spawn(
+            "ezsockets::drain",
+            self.config.runtime.as_ref(),
+            async move {
+                tokio::time::sleep(lead_time).await;
+                server.disconnect_many(|_| true, Some(frame));
+            },
+        );
+    }
 }
 
 impl<E: ServerExt> Server<E> {
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Application state the server was created with, see [`Server::create_with_state`].
+    pub fn state(&self) -> &Arc<E::State> {
+        &self.state
+    }
+
+    /// Checks a new connection from `address` against [`ServerConfig::accept_limit`], returns `false` if it should be rejected.
+    ///
+    /// Called by the server back-ends before the WebSocket handshake. With [`AcceptLimit::queue`] set,
+    /// waits until the connection fits in the limit instead of rejecting it, unless [`AcceptLimit::max_queued`]
+    /// connections are already waiting.
+    pub async fn admit(&self, address: SocketAddr) -> bool {
+        // Held while waiting, counted the same way as handshakes in progress.
+        let mut queued = None;
+        loop {
+            let (result, queue) = {
+                let mut limiter = self.accept_limiter.lock().unwrap();
+                (limiter.check(address.ip()), limiter.queue())
+            };
+            if queue && queued.is_none() && result.is_err() {
+                let max_queued = self.config.accept_limit.max_queued;
+                queued = HandshakePermit::acquire(&self.queued, Some(max_queued));
+                if queued.is_none() {
+                    tracing::warn!(
+                        "connection from {address} rejected, too many connections queued"
+                    );
+                    self.overloaded("accept_queue");
+                    return false;
+                }
+            }
+            match result {
+                Ok(()) => return true,
+                Err(wait) if queue => tokio::time::sleep(wait).await,
+                Err(_) => {
+                    tracing::warn!("connection from {address} rejected due to accept limit");
+                    self.overloaded("accept_limit");
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Takes a slot for the WebSocket handshake with `address`, returns `None` if [`AcceptLimit::max_handshakes`]
+    /// handshakes are already in progress, in which case the back-ends respond with `503 Service Unavailable`.
+    ///
+    /// The slot is released when the permit is dropped, once the handshake completed or failed.
+    pub fn handshake_permit(&self, address: SocketAddr) -> Option<HandshakePermit> {
+        let permit =
+            HandshakePermit::acquire(&self.handshakes, self.config.accept_limit.max_handshakes);
+        if permit.is_none() {
+            tracing::warn!("connection from {address} rejected, too many handshakes in progress");
+            self.overloaded("handshakes");
+        }
+        permit
+    }
+
+    /// Number of connections rejected so far because of [`ServerConfig::accept_limit`].
+    pub fn overloads(&self) -> u64 {
+        self.overloads.load(Ordering::Relaxed)
+    }
+
+    fn overloaded(&self, reason: &'static str) {
+        self.overloads.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "otel")]
+        crate::otel::record_overload(reason);
+        #[cfg(not(feature = "otel"))]
+        let _ = reason;
+    }
+
+    /// Hands the connection over to the server, returns the ID of its session, or an error if the server stopped.
     pub async fn accept(
         &self,
-        socket: Socket,
+        mut socket: Socket,
         address: SocketAddr,
         args: <E::Session as SessionExt>::Args,
-    ) -> <E::Session as SessionExt>::ID {
+    ) -> Result<<E::Session as SessionExt>::ID, Error> {
+        socket.session_config = Some(self.config.session.clone());
         let (sender, receiver) = oneshot::channel();
         self.connections
             .send(NewConnection {
@@ -147,9 +865,24 @@ impl<E: ServerExt> Server<E> {
                 args,
                 respond_to: sender,
             })
-            .map_err(|_| ())
-            .unwrap();
-        receiver.await.unwrap()
+            .map_err(|_| "server actor stopped")?;
+        Ok(receiver.await.map_err(|_| "server actor stopped")?)
+    }
+
+    /// Time spent inside handler callbacks, aggregated over every session since the server started,
+    /// `None` if the server stopped.
+    pub async fn handler_timings(&self) -> Option<HandlerTimings> {
+        let (sender, receiver) = oneshot::channel();
+        self.registry(RegistryCommand::HandlerTimings { respond_to: sender });
+        receiver.await.ok()
+    }
+
+    /// Approximate resource usage of every connected session, to spot the ones worth throttling or disconnecting,
+    /// `None` if the server stopped.
+    pub async fn usage(&self) -> Option<HashMap<SessionID<E>, SessionUsage>> {
+        let (sender, receiver) = oneshot::channel();
+        self.registry(RegistryCommand::Usage { respond_to: sender });
+        receiver.await.ok()
     }
 
     pub(crate) async fn disconnected(
@@ -157,27 +890,179 @@ impl<E: ServerExt> Server<E> {
         id: <E::Session as SessionExt>::ID,
         result: Result<Option<CloseFrame>, Error>,
     ) {
-        self.disconnections
+        if self
+            .disconnections
             .send(Disconnected { id, result })
-            .map_err(|_| ())
-            .unwrap();
+            .is_err()
+        {
+            tracing::debug!("server actor stopped, not reporting the disconnection");
+        }
     }
 
+    /// Calls the server actor, the call is dropped if the server actor already stopped.
     pub fn call(&self, params: E::Params) {
-        self.calls.send(params).map_err(|_| ()).unwrap();
+        if self.calls.send(params).is_err() {
+            tracing::warn!("server actor stopped, dropping call");
+        }
+    }
+
+    /// Sends the same message to every connected session listed in `ids`.
+    /// Sessions that already disconnected are skipped.
+    ///
+    /// The payload is shared by the sessions rather than copied by the server for each of them.
+    pub fn send_many(&self, ids: impl IntoIterator<Item = SessionID<E>>, message: Message) {
+        let ids = ids.into_iter().collect();
+        let message = Arc::new(message);
+        self.registry(RegistryCommand::SendMany { ids, message });
+    }
+
+    /// Closes every connected session for which `filter` returns `true`, using `frame` as the close frame.
+    pub fn disconnect_many(
+        &self,
+        filter: impl Fn(&SessionID<E>) -> bool + Send + 'static,
+        frame: Option<CloseFrame>,
+    ) {
+        self.registry(RegistryCommand::DisconnectMany {
+            filter: Box::new(filter),
+            frame,
+        });
+    }
+
+    /// Adds the session to the room, creating the room if it doesn't exist yet.
+    /// Sessions leave all their rooms once disconnected.
+    ///
+    /// The join is subject to [`ServerExt::can_join`] and the capacity of the room,
+    /// rejections are reported through [`ServerExt::join_rejected`].
+    pub fn join(&self, room: impl Into<String>, id: SessionID<E>) {
+        self.registry(RegistryCommand::Join {
+            room: room.into(),
+            id,
+        });
+    }
+
+    /// Adds the session to the room like [`Server::join`], first sending it the messages of `backfill`, e.g. the history of the room.
+    ///
+    /// Broadcasts to the room are held until the backfill completes and sent right after it, so the session gets
+    /// the backfill followed by the live messages without gaps. See [`Backfill::dedup_by`] to skip the live messages
+    /// the backfill already sent. Does nothing if the session is already a member of the room.
+    pub fn join_with_backfill(
+        &self,
+        room: impl Into<String>,
+        id: SessionID<E>,
+        backfill: Backfill,
+    ) {
+        self.registry(RegistryCommand::JoinWithBackfill {
+            room: room.into(),
+            id,
+            backfill,
+        });
+    }
+
+    /// Removes the session from the room, rooms without members are removed.
+    pub fn leave(&self, room: impl Into<String>, id: SessionID<E>) {
+        self.registry(RegistryCommand::Leave {
+            room: room.into(),
+            id,
+        });
+    }
+
+    /// Atomically moves the session from one room to another.
+    ///
+    /// Every [`broadcast`](Self::broadcast) issued before the move is delivered to the session only if it targets `from`,
+    /// and every broadcast issued after the move only if it targets `to`, so the session neither misses nor
+    /// receives twice the broadcasts in flight. Does nothing if the session isn't a member of `from`.
+    pub fn move_session(&self, id: SessionID<E>, from: impl Into<String>, to: impl Into<String>) {
+        self.registry(RegistryCommand::Move {
+            id,
+            from: from.into(),
+            to: to.into(),
+        });
+    }
+
+    /// Overrides [`ServerConfig::room_capacity`] for the room, `None` restores the default.
+    /// Applies to subsequent joins only, current members are never removed.
+    pub fn set_room_capacity(&self, room: impl Into<String>, capacity: Option<usize>) {
+        self.registry(RegistryCommand::RoomCapacity {
+            room: room.into(),
+            capacity,
+        });
+    }
+
+    /// Sends the message to every member of the room.
+    pub fn broadcast(&self, room: impl Into<String>, message: Message) {
+        self.registry(RegistryCommand::Broadcast {
+            room: room.into(),
+            message,
+        });
+    }
+
+    /// Sends a message published by the session `from` to every other member of the room.
+    ///
+    /// The session must be a member of the room and be allowed by [`ServerExt::can_publish`],
+    /// rejections are reported through [`ServerExt::publish_rejected`].
+    pub fn publish(&self, room: impl Into<String>, from: SessionID<E>, message: Message) {
+        self.registry(RegistryCommand::Publish {
+            room: room.into(),
+            from,
+            message,
+        });
+    }
+
+    /// Sends the message returned by `transform` to every member of the room, skipping members for which it returns `None`.
+    ///
+    /// `transform` runs inside the server actor, which allows customizing the message per recipient,
+    /// e.g. translating it or redacting it according to the recipient's permissions, without sending it to each session separately.
+    pub fn broadcast_with(
+        &self,
+        room: impl Into<String>,
+        transform: impl Fn(&SessionInfo<SessionID<E>>) -> Option<Message> + Send + 'static,
+    ) {
+        self.registry(RegistryCommand::BroadcastWith {
+            room: room.into(),
+            transform: Box::new(transform),
+        });
+    }
+
+    /// Broadcasts the message built by `factory` to the room on every run of the schedule,
+    /// e.g. periodic announcements or statistics, until cancelled through the returned handle.
+    ///
+    /// `factory` runs inside the server actor, and only when the room has members.
+    pub fn schedule_broadcast(
+        &self,
+        room: impl Into<String>,
+        schedule: Schedule,
+        factory: impl FnMut() -> Message + Send + 'static,
+    ) -> ScheduledBroadcast {
+        let handle = ScheduledBroadcast::default();
+        let task = ScheduledTask::new(room.into(), schedule, Box::new(factory), handle.clone());
+        self.registry(RegistryCommand::Schedule(task));
+        handle
+    }
+
+    /// Sends the command to the server actor, the command is dropped if the server actor already stopped.
+    fn registry(&self, command: RegistryCommand<E>) {
+        if self.registry.send(command).is_err() {
+            tracing::warn!("server actor stopped, dropping command");
+        }
     }
 
     /// Calls a method on the session, allowing the Session to respond with oneshot::Sender.
     /// This is just for easier construction of the Params which happen to contain oneshot::Sender in it.
+    ///
+    /// Fails if the server actor stopped, or dropped the sender without responding.
     pub async fn call_with<R: std::fmt::Debug>(
         &self,
         f: impl FnOnce(oneshot::Sender<R>) -> E::Params,
-    ) -> R {
+    ) -> Result<R, Error> {
         let (sender, receiver) = oneshot::channel();
         let params = f(sender);
 
-        self.calls.send(params).unwrap();
-        receiver.await.unwrap()
+        self.calls
+            .send(params)
+            .map_err(|_| "server actor stopped")?;
+        Ok(receiver
+            .await
+            .map_err(|_| "server dropped the call without responding")?)
     }
 }
 
@@ -187,6 +1072,13 @@ impl<E: ServerExt> std::clone::Clone for Server<E> {
             connections: self.connections.clone(),
             disconnections: self.disconnections.clone(),
             calls: self.calls.clone(),
+            registry: self.registry.clone(),
+            config: self.config.clone(),
+            state: self.state.clone(),
+            accept_limiter: self.accept_limiter.clone(),
+            handshakes: self.handshakes.clone(),
+            queued: self.queued.clone(),
+            overloads: self.overloads.clone(),
         }
     }
 }
@@ -1,35 +1,261 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
+use crate::socket::Budget;
+use crate::stats::payload_len;
+use crate::stats::HandlerTimingsRecorder;
+use crate::stats::HistogramRecorder;
+use crate::stats::UsageRecorder;
+use crate::transfer::Target;
+use crate::CloseCode;
 use crate::CloseFrame;
+use crate::CongestionState;
+use crate::DataKind;
 use crate::Error;
+use crate::ErrorEnvelope;
+use crate::Fragment;
+use crate::HandlerTimings;
+use crate::Idempotency;
+use crate::IdempotentRequest;
 use crate::Message;
+use crate::MessageMeta;
+use crate::Quota;
+use crate::QuotaAction;
+use crate::QuotaUsage;
+use crate::ReducedEncodings;
+use crate::SendError;
+use crate::SendTimeoutError;
+use crate::SessionUsage;
+use crate::Sink;
 use crate::Socket;
+use crate::SocketStats;
+use crate::TlsInfo;
+use crate::TraceContext;
+use crate::Transfer;
+use crate::TrySendError;
 use async_trait::async_trait;
+use bytes::Bytes;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tokio::sync::Mutex;
+use tracing::Instrument;
 
 #[async_trait]
 pub trait SessionExt: Send {
-    type ID: Send + Sync + Clone + std::fmt::Debug + std::fmt::Display;
+    /// Identifier of the session, `Hash + Eq` since the server looks sessions up by ID, e.g. in [`Server::send_many`](crate::Server::send_many).
+    type ID: Send + Sync + Clone + std::hash::Hash + Eq + std::fmt::Debug + std::fmt::Display;
     /// Arguments passed for creating a new session on server.
     type Args: std::fmt::Debug + Send;
     type Params: std::fmt::Debug + Send;
 
     fn id(&self) -> &Self::ID;
     async fn text(&mut self, text: String) -> Result<(), Error>;
-    async fn binary(&mut self, bytes: Vec<u8>) -> Result<(), Error>;
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Error>;
+
+    /// Same as `text`, with metadata of the received message. Defaults to calling `text`.
+    async fn text_with_meta(&mut self, text: String, meta: MessageMeta) -> Result<(), Error> {
+        let _ = meta;
+        self.text(text).await
+    }
+
+    /// Same as `binary`, with metadata of the received message. Defaults to calling `binary`.
+    async fn binary_with_meta(&mut self, bytes: Bytes, meta: MessageMeta) -> Result<(), Error> {
+        let _ = meta;
+        self.binary(bytes).await
+    }
+
+    /// Checks the first Text or Binary message when [`SessionConfig::hello`] is set, e.g. that it carries valid credentials.
+    /// Returning `false` closes the session with [`Hello::close_frame`], otherwise the message is handled as usual.
+    /// Accepts any message by default.
+    async fn hello(&mut self, message: &Message) -> Result<bool, Error> {
+        let _ = message;
+        Ok(true)
+    }
+
+    /// Called for both Text and Binary messages instead of `text` and `binary` when [`SessionConfig::unify_data`] is set,
+    /// for sessions which handle both kinds alike, e.g. relays. Defaults to calling `text` or `binary`.
+    async fn data(&mut self, data: Bytes, kind: DataKind) -> Result<(), Error> {
+        match kind {
+            DataKind::Text => self.text(String::from_utf8(data.into())?).await,
+            DataKind::Binary => self.binary(data).await,
+        }
+    }
     async fn call(&mut self, params: Self::Params) -> Result<(), Error>;
+
+    /// Called whenever the congestion state of the connection changes, allowing to adapt the size of outgoing payloads,
+    /// see also [`SessionConfig::reduced_encodings`].
+    async fn on_congestion_change(&mut self, state: CongestionState) -> Result<(), Error> {
+        let _ = state;
+        Ok(())
+    }
+
+    /// Called for every message received over the quota, when the quota action is [`QuotaAction::Notify`].
+    async fn quota_exceeded(&mut self, usage: QuotaUsage) -> Result<(), Error> {
+        let _ = usage;
+        Ok(())
+    }
+
+    /// Called for every Ping frame received when [`SocketConfig::expose_control_frames`](crate::SocketConfig::expose_control_frames) is set,
+    /// after it was answered with a Pong.
+    async fn ping(&mut self, payload: Bytes) -> Result<(), Error> {
+        let _ = payload;
+        Ok(())
+    }
+
+    /// Called for every Pong frame received when [`SocketConfig::expose_control_frames`](crate::SocketConfig::expose_control_frames) is set,
+    /// e.g. to read a timestamp the peer put in its payload.
+    async fn pong(&mut self, payload: Bytes) -> Result<(), Error> {
+        let _ = payload;
+        Ok(())
+    }
+
+    /// Called whenever a handler returns an error, deciding whether the session survives it, e.g. to ignore transient failures
+    /// of a downstream service. Defaults to closing the session with [`CloseCode::Error`].
+    async fn on_handler_error(&mut self, error: &Error) -> ErrorAction {
+        let _ = error;
+        ErrorAction::CloseSession(Some(CloseFrame {
+            code: CloseCode::Error,
+            reason: String::from("internal error"),
+        }))
+    }
+}
+
+/// What to do with the error of a session handler, see [`SessionExt::on_handler_error`].
+#[derive(Debug, Clone)]
+pub enum ErrorAction {
+    /// Logs the error and keeps the session running.
+    Ignore,
+    /// Closes the session with the frame, the session ends with the error.
+    CloseSession(Option<CloseFrame>),
+    /// Closes the session with [`CloseCode::Away`], then stops the server, closing every other session as well.
+    /// The server future resolves with the error.
+    ShutdownServer,
+}
+
+/// Error a session ends with when [`SessionExt::on_handler_error`] asks for the server to shut down.
+#[derive(Debug)]
+pub(crate) struct ShutdownRequested(pub(crate) Error);
+
+impl ShutdownRequested {
+    /// Frame the sessions are closed with when the server shuts down.
+    pub(crate) fn close_frame() -> CloseFrame {
+        CloseFrame {
+            code: CloseCode::Away,
+            reason: String::from("server is shutting down"),
+        }
+    }
+}
+
+impl std::fmt::Display for ShutdownRequested {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "server shutdown requested by a session handler: {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ShutdownRequested {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
+/// Reports session handlers which don't complete within `timeout`, this covers every [`SessionExt`] handler the session actor runs.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    /// Time after which a running handler is reported as stuck.
+    pub timeout: Duration,
+    /// Whether to cancel the stuck handler and close the session with [`CloseCode::Error`].
+    pub force_close: bool,
+}
+
+/// Requires clients to send a hello message soon after connecting, see [`SessionConfig::hello`].
+#[derive(Debug, Clone)]
+pub struct Hello {
+    /// Time after connecting within which the first Text or Binary message must be received.
+    pub timeout: Duration,
+    /// Frame the session is closed with if no hello is received in time, or if [`SessionExt::hello`] rejects it.
+    pub close_frame: CloseFrame,
+}
+
+impl Hello {
+    /// Requires a hello within `timeout`, closing the session with [`CloseCode::Policy`] otherwise.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            close_frame: CloseFrame::policy("no hello received"),
+        }
+    }
+
+    pub fn close_frame(mut self, close_frame: CloseFrame) -> Self {
+        self.close_frame = close_frame;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SessionConfig {
+    pub watchdog: Option<Watchdog>,
+    /// Number of events the session actor processes before yielding to other tasks.
+    pub yield_after: Option<usize>,
+    /// Message and byte budget of the identity behind the session, see [`Quota`].
+    pub quota: Option<Quota>,
+    /// Handles the requests retried by the client only once, see [`Idempotency`].
+    pub idempotency: Option<Idempotency>,
+    /// Runtime the session actor is spawned on, defaults to the current runtime.
+    pub runtime: Option<tokio::runtime::Handle>,
+    /// Closes sessions whose client doesn't send a valid hello message soon after connecting, see [`Hello`].
+    pub hello: Option<Hello>,
+    /// Delivers Text and Binary messages to [`SessionExt::data`] instead of `text` and `binary`.
+    pub unify_data: bool,
+    /// Reduced encodings swapped in for the messages sent while the connection is stalled.
+    pub reduced_encodings: Option<ReducedEncodings>,
 }
 
 type CloseReceiver = oneshot::Receiver<Result<Option<CloseFrame>, Error>>;
 
+#[derive(Debug)]
+pub(crate) enum Outgoing {
+    Message(Message),
+    Adaptive {
+        full: Message,
+        reduced: Option<Message>,
+    },
+    Conflated {
+        key: Arc<str>,
+        message: Message,
+    },
+    /// Message shared with other sessions, only copied by the last session to send it.
+    Shared(Arc<Message>),
+    /// Message a slot of the send queue was already reserved for, see [`Session::try_send`].
+    Reserved(Message),
+    /// Notifies the sender, if any, once the fragment is written.
+    Fragment(Fragment, Option<oneshot::Sender<()>>),
+    Sync(oneshot::Sender<()>),
+}
+
 #[derive(Debug)]
 pub struct Session<I: std::fmt::Display + Clone, P: std::fmt::Debug> {
     pub id: I,
-    socket: mpsc::UnboundedSender<Message>,
+    socket: mpsc::UnboundedSender<Outgoing>,
     calls: mpsc::UnboundedSender<P>,
     closed: Arc<Mutex<Option<CloseReceiver>>>,
+    local_addr: Option<SocketAddr>,
+    peer_addr: Option<SocketAddr>,
+    tls: Option<TlsInfo>,
+    trace_context: Option<TraceContext>,
+    congestion: watch::Receiver<CongestionState>,
+    sink: Sink,
+    timings: Arc<HandlerTimingsRecorder>,
+    usage: Arc<UsageRecorder>,
 }
 
 impl<I: std::fmt::Display + Clone, P: std::fmt::Debug> std::clone::Clone for Session<I, P> {
@@ -39,33 +265,90 @@ impl<I: std::fmt::Display + Clone, P: std::fmt::Debug> std::clone::Clone for Ses
             socket: self.socket.clone(),
             calls: self.calls.clone(),
             closed: self.closed.clone(),
+            local_addr: self.local_addr,
+            peer_addr: self.peer_addr,
+            tls: self.tls.clone(),
+            trace_context: self.trace_context.clone(),
+            congestion: self.congestion.clone(),
+            sink: self.sink.clone(),
+            timings: self.timings.clone(),
+            usage: self.usage.clone(),
         }
     }
 }
 
-impl<I: std::fmt::Display + Clone + Send, P: std::fmt::Debug + Send> Session<I, P> {
+impl<I: std::fmt::Display + Clone + Send + Sync, P: std::fmt::Debug + Send> Session<I, P> {
+    /// Spawns the session actor, configured with [`ServerConfig::session`](crate::ServerConfig::session)
+    /// if the socket was handed over to [`Server::accept`](crate::Server::accept), with the defaults otherwise.
     pub fn create<S: SessionExt<ID = I, Params = P> + 'static>(
+        session_fn: impl FnOnce(Session<I, P>) -> S,
+        session_id: I,
+        mut socket: Socket,
+    ) -> Self {
+        let config = socket.session_config.take().unwrap_or_default();
+        Self::create_with_config(session_fn, session_id, socket, config)
+    }
+
+    pub fn create_with_config<S: SessionExt<ID = I, Params = P> + 'static>(
         session_fn: impl FnOnce(Session<I, P>) -> S,
         session_id: I,
         socket: Socket,
+        config: SessionConfig,
     ) -> Self {
         let (socket_sender, socket_receiver) = mpsc::unbounded_channel();
         let (call_sender, call_receiver) = mpsc::unbounded_channel();
         let (closed_sender, closed_receiver) = oneshot::channel();
         let handle = Self {
-            id: session_id.clone(),
+            id: session_id,
             socket: socket_sender,
             calls: call_sender,
             closed: Arc::new(Mutex::new(Some(closed_receiver))),
+            local_addr: socket.local_addr(),
+            peer_addr: socket.peer_addr(),
+            tls: socket.tls().cloned(),
+            trace_context: socket.trace_context().cloned(),
+            congestion: socket.sink.congestion_changes(),
+            sink: socket.sink.clone(),
+            timings: Default::default(),
+            usage: Default::default(),
         };
         let session = session_fn(handle.clone());
-        let mut actor =
-            SessionActor::new(session, session_id, socket_receiver, call_receiver, socket);
+        let runtime = config.runtime.clone();
+        let mut actor = SessionActor::new(
+            session,
+            &handle,
+            socket_receiver,
+            call_receiver,
+            socket,
+            config,
+        );
 
-        tokio::spawn(async move {
-            let result = actor.run().await;
-            closed_sender.send(result).unwrap();
-        });
+        let span = tracing::info_span!(
+            "session",
+            id = %handle.id,
+            trace_id = tracing::field::Empty,
+            parent_id = tracing::field::Empty,
+            otel.kind = tracing::field::Empty,
+            network.protocol.name = tracing::field::Empty,
+            network.peer.address = tracing::field::Empty,
+            network.peer.port = tracing::field::Empty,
+        );
+        #[cfg(feature = "otel")]
+        crate::otel::record_connection(&span, "server", handle.peer_addr);
+        if let Some(trace_context) = &handle.trace_context {
+            span.record("trace_id", trace_context.trace_id.as_str());
+            span.record("parent_id", trace_context.parent_id.as_str());
+        }
+        crate::runtime::spawn(
+            "ezsockets::session",
+            runtime.as_ref(),
+            async move {
+                let result = actor.run().await;
+                // Every handle to the session may have been dropped already.
+                let _ = closed_sender.send(result);
+            }
+            .instrument(span),
+        );
 
         handle
     }
@@ -84,91 +367,366 @@ impl<I: std::fmt::Display + Clone, P: std::fmt::Debug> Session<I, P> {
         closed.await.unwrap()
     }
 
+    /// Local address of the connection, if known by the server back-end.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Address of the connected client, if known by the server back-end.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Parameters of the TLS handshake with the client, if the server back-end terminated TLS.
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
+
+    /// Trace context propagated by the client through the `traceparent` header of the upgrade request, if any.
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.trace_context.as_ref()
+    }
+
+    /// Time spent inside each of the handler callbacks of this session.
+    pub fn handler_timings(&self) -> HandlerTimings {
+        self.timings.snapshot()
+    }
+
+    /// Approximate resource usage of this session, see [`Server::usage`](crate::Server::usage) for all sessions at once.
+    pub fn usage(&self) -> SessionUsage {
+        self.usage
+            .snapshot(self.sink.buffered(), &self.timings.snapshot())
+    }
+
+    /// Current congestion state of the connection.
+    pub fn congestion(&self) -> CongestionState {
+        *self.congestion.borrow()
+    }
+
+    /// Receiver notified on every congestion state change of the connection.
+    pub fn congestion_changes(&self) -> watch::Receiver<CongestionState> {
+        self.congestion.clone()
+    }
+
+    /// Traffic of the connection, see [`Sink::stats`].
+    pub fn stats(&self) -> SocketStats {
+        self.sink.stats()
+    }
+
+    /// Round-trip time of the connection, see [`Sink::latency`].
+    pub fn latency(&self) -> Option<Duration> {
+        self.sink.latency()
+    }
+
+    /// Receiver notified on every round-trip time measurement of the connection.
+    pub fn latency_changes(&self) -> watch::Receiver<Option<Duration>> {
+        self.sink.latency_changes()
+    }
+
     /// Checks if the Session is still alive, if so you can proceed sending calls or messages.
     pub fn alive(&self) -> bool {
         !self.socket.is_closed() && !self.calls.is_closed()
     }
 
-    /// Sends a Text message to the server
-    pub fn text(&self, text: String) {
+    /// Sends a Text message to the client, returning it back if the session is already closed.
+    pub fn text(&self, text: String) -> Result<(), SendError<Message>> {
+        self.send_outgoing(Outgoing::Message(Message::Text(text)))
+    }
+
+    /// Sends a Binary message to the client, returning it back if the session is already closed.
+    pub fn binary(&self, bytes: impl Into<Bytes>) -> Result<(), SendError<Message>> {
+        self.send_outgoing(Outgoing::Message(Message::Binary(bytes.into())))
+    }
+
+    /// Sends the message without waiting, failing with [`TrySendError::Full`] if the send queue of the connection has no room for it,
+    /// see [`Sink::try_send`].
+    pub fn try_send(&self, message: Message) -> Result<(), TrySendError<Message>> {
+        let outgoing = match message {
+            message @ (Message::Close(_) | Message::Ping(_) | Message::Pong(_)) => {
+                Outgoing::Message(message)
+            }
+            message => match self.sink.try_reserve() {
+                Ok(()) => Outgoing::Reserved(message),
+                Err(err) => return Err(err.with(message)),
+            },
+        };
+        self.send_outgoing(outgoing)
+            .map_err(|SendError(message)| TrySendError::Closed(message))
+    }
+
+    /// Sends the message and waits until it's written to the connection, see [`Sink::send_timeout`].
+    pub async fn send_timeout(
+        &self,
+        message: Message,
+        timeout: Duration,
+    ) -> Result<(), SendTimeoutError<Message>> {
+        self.send_outgoing(Outgoing::Message(message))
+            .map_err(|SendError(message)| SendTimeoutError::Closed(Some(message)))?;
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.socket.send(Outgoing::Sync(sender));
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(SendTimeoutError::Closed(None)),
+            Err(_) => Err(SendTimeoutError::Timeout),
+        }
+    }
+
+    /// Replies with an application error, serialized as described by [`ErrorEnvelope`].
+    pub fn reply_error(&self, error: ErrorEnvelope) -> Result<(), SendError<Message>> {
+        self.send_outgoing(Outgoing::Message(error.into()))
+    }
+
+    /// Sends `full`, unless the connection is stalled at the moment of sending, in which case `reduced` is sent instead.
+    /// If `reduced` is `None`, the message is dropped while the connection is stalled.
+    ///
+    /// Unlike [`SessionConfig::reduced_encodings`], the reduced variant is given with the message.
+    pub fn send_adaptive(
+        &self,
+        full: Message,
+        reduced: Option<Message>,
+    ) -> Result<(), SendError<Message>> {
+        self.send_outgoing(Outgoing::Adaptive { full, reduced })
+    }
+
+    /// Sends the message, replacing the queued message with the same conflation `key` if it wasn't written yet,
+    /// see [`Sink::send_conflated`].
+    pub fn send_conflated(
+        &self,
+        key: impl Into<Arc<str>>,
+        message: Message,
+    ) -> Result<(), SendError<Message>> {
+        self.send_outgoing(Outgoing::Conflated {
+            key: key.into(),
+            message,
+        })
+    }
+
+    /// Sends a frame of a message too large to be held in memory at once, see [`Sink::send_fragment`].
+    pub fn send_fragment(&self, fragment: Fragment) -> Result<(), SendError<Fragment>> {
         self.socket
-            .send(Message::Text(text))
-            .unwrap_or_else(|_| panic!("Session::text {PANIC_MESSAGE_UNHANDLED_CLOSE}"));
+            .send(Outgoing::Fragment(fragment, None))
+            .map_err(|mpsc::error::SendError(outgoing)| match outgoing {
+                Outgoing::Fragment(fragment, _) => SendError(fragment),
+                _ => unreachable!("a fragment is returned as it was sent"),
+            })
     }
 
-    /// Sends a Binary message to the server
-    pub fn binary(&self, bytes: Vec<u8>) {
+    /// Starts sending a message of the given kind fragment by fragment, reporting its progress, see [`Transfer`].
+    pub fn transfer(&self, kind: DataKind) -> Transfer {
+        Transfer::new(Target::Session(self.socket.clone()), kind)
+    }
+
+    /// Closes the connection with the given close frame, does nothing if the session is already closed.
+    pub fn close(&self, frame: Option<CloseFrame>) {
+        self.send(Message::Close(frame));
+    }
+
+    fn send_outgoing(&self, outgoing: Outgoing) -> Result<(), SendError<Message>> {
         self.socket
-            .send(Message::Binary(bytes))
-            .unwrap_or_else(|_| panic!("Session::binary {PANIC_MESSAGE_UNHANDLED_CLOSE}"));
+            .send(outgoing)
+            .map_err(|mpsc::error::SendError(outgoing)| match outgoing {
+                Outgoing::Message(message)
+                | Outgoing::Reserved(message)
+                | Outgoing::Adaptive { full: message, .. }
+                | Outgoing::Conflated { message, .. } => SendError(message),
+                Outgoing::Shared(_) | Outgoing::Fragment(..) | Outgoing::Sync(_) => {
+                    unreachable!("only messages are sent through send_outgoing")
+                }
+            })
+    }
+
+    /// Gracefully closes the connection and waits until the peer answered the Close frame and the session actor has stopped.
+    pub async fn shutdown(&self) {
+        self.send(Message::Close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: String::from("session shutdown"),
+        })));
+        self.socket.closed().await;
+    }
+
+    /// Sends a message to the client, ignoring the result if the Session is already closed
+    pub(crate) fn send(&self, message: Message) {
+        let _ = self.socket.send(Outgoing::Message(message));
+    }
+
+    /// Sends a message shared with other sessions, ignoring the result if the Session is already closed
+    pub(crate) fn send_shared(&self, message: Arc<Message>) {
+        let _ = self.socket.send(Outgoing::Shared(message));
+    }
+
+    /// Waits until all messages sent before this call have been written to the connection.
+    /// Returns immediately if the Session is already closed.
+    pub async fn sync(&self) {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.socket.send(Outgoing::Sync(sender));
+        let _ = receiver.await;
     }
 
-    /// Calls a method on the session
-    pub fn call(&self, params: P) {
+    /// Calls a method on the session, returns the params back if the Session is already closed.
+    pub fn call(&self, params: P) -> Result<(), SendError<P>> {
         self.calls
             .send(params)
-            .unwrap_or_else(|_| panic!("Session::call {PANIC_MESSAGE_UNHANDLED_CLOSE}"));
+            .map_err(|mpsc::error::SendError(params)| SendError(params))
     }
 
     /// Calls a method on the session, allowing the Session to respond with oneshot::Sender.
     /// This is just for easier construction of the Params which happen to contain oneshot::Sender in it.
+    ///
+    /// Fails if the Session is already closed, or dropped the sender without responding.
     pub async fn call_with<R: std::fmt::Debug>(
         &self,
         f: impl FnOnce(oneshot::Sender<R>) -> P,
-    ) -> R {
+    ) -> Result<R, Error> {
         let (sender, receiver) = oneshot::channel();
         let params = f(sender);
 
-        self.calls.send(params).unwrap();
-        receiver.await.unwrap()
+        self.calls.send(params).map_err(|_| "session closed")?;
+        Ok(receiver
+            .await
+            .map_err(|_| "session dropped the call without responding")?)
     }
 }
 
 pub(crate) struct SessionActor<E: SessionExt> {
     pub extension: E,
     id: E::ID,
-    socket_receiver: mpsc::UnboundedReceiver<Message>,
+    socket_receiver: mpsc::UnboundedReceiver<Outgoing>,
     call_receiver: mpsc::UnboundedReceiver<E::Params>,
+    congestion: watch::Receiver<CongestionState>,
     socket: Socket,
+    timings: Arc<HandlerTimingsRecorder>,
+    usage: Arc<UsageRecorder>,
+    budget: Budget,
+    /// Time by which the hello must be received, cleared once it is.
+    hello_deadline: Option<tokio::time::Instant>,
+    /// Keys of the requests received by this session which weren't responded to yet, see [`SessionConfig::idempotency`].
+    requests: HashSet<String>,
+    /// Message over the quota, held back while the stream isn't read, see [`QuotaAction::Throttle`].
+    throttled: Option<Throttled>,
+    config: SessionConfig,
+}
+
+/// Message received over the quota, handled once the next quota window starts.
+struct Throttled {
+    until: tokio::time::Instant,
+    message: Message,
+    meta: MessageMeta,
+}
+
+/// Outcome of accounting a received message to the quota.
+enum QuotaCheck {
+    Allowed,
+    /// Over the quota until the next window starts.
+    Throttled(tokio::time::Instant),
+    /// Closed with the frame, for exceeding the quota or because a handler timed out.
+    Closed(Option<CloseFrame>),
 }
 
 impl<E: SessionExt> SessionActor<E> {
     pub(crate) fn new(
         extension: E,
-        id: E::ID,
-        socket_receiver: mpsc::UnboundedReceiver<Message>,
+        handle: &Session<E::ID, E::Params>,
+        socket_receiver: mpsc::UnboundedReceiver<Outgoing>,
         call_receiver: mpsc::UnboundedReceiver<E::Params>,
         socket: Socket,
+        config: SessionConfig,
     ) -> Self {
         Self {
-            id,
+            id: handle.id.clone(),
             extension,
             socket_receiver,
             call_receiver,
+            congestion: socket.sink.congestion_changes(),
             socket,
+            timings: handle.timings.clone(),
+            usage: handle.usage.clone(),
+            budget: Budget::new(config.yield_after),
+            hello_deadline: config
+                .hello
+                .as_ref()
+                .map(|hello| tokio::time::Instant::now() + hello.timeout),
+            requests: HashSet::new(),
+            throttled: None,
+            config,
         }
     }
 
     pub(crate) async fn run(&mut self) -> Result<Option<CloseFrame>, Error> {
         loop {
+            self.budget.consume().await;
             tokio::select! {
-                Some(message) = self.socket_receiver.recv() => {
-                    self.socket.send(message.clone()).await;
-                    if let Message::Close(frame) = message {
-                        return Ok(frame)
+                Some(outgoing) = self.socket_receiver.recv() => {
+                    match outgoing {
+                        Outgoing::Message(message) => {
+                            if let ControlFlow::Break(frame) = self.send_message(message).await {
+                                return Ok(frame);
+                            }
+                        }
+                        Outgoing::Shared(message) => {
+                            // The last session to send it takes the message instead of copying it.
+                            let message = Arc::try_unwrap(message).unwrap_or_else(|message| (*message).clone());
+                            if let ControlFlow::Break(frame) = self.send_message(message).await {
+                                return Ok(frame);
+                            }
+                        }
+                        Outgoing::Adaptive { full, reduced } => {
+                            let message = if *self.congestion.borrow() == CongestionState::Stalled {
+                                reduced
+                            } else {
+                                Some(full)
+                            };
+                            if let Some(message) = message {
+                                self.send(message).await;
+                            }
+                        }
+                        Outgoing::Reserved(message) => {
+                            self.complete_request(&message).await;
+                            self.usage.sent(&message);
+                            let _ = self.socket.sink.push(message, None);
+                        }
+                        Outgoing::Conflated { key, message } => {
+                            self.usage.sent(&message);
+                            let _ = self.socket.sink.send_conflated(key, message).await;
+                        }
+                        Outgoing::Fragment(fragment, written) => {
+                            self.usage.sent_fragment(&fragment);
+                            let _ = self.socket.sink.send_fragment_with(fragment, written);
+                        }
+                        Outgoing::Sync(respond_to) => self.socket.sink.sync_with(respond_to),
                     }
                 }
                 Some(params) = self.call_receiver.recv() => {
-                    self.extension.call(params).await?;
+                    let handler = self.extension.call(params);
+                    match supervise(&self.config, &self.id, "call", &self.timings.call, handler).await {
+                        Some(result) => self.check(result).await?,
+                        None => return Ok(self.abort().await),
+                    }
+                }
+                _ = deadline(self.hello_deadline) => {
+                    tracing::info!(id = %self.id, "no hello received in time, closing session");
+                    return Ok(self.reject_hello().await);
                 }
-                message = self.socket.recv() => {
+                Ok(()) = self.congestion.changed() => {
+                    let state = *self.congestion.borrow();
+                    let handler = self.extension.on_congestion_change(state);
+                    match watch(&self.config, &self.id, "on_congestion_change", handler).await {
+                        Some(result) => self.check(result).await?,
+                        None => return Ok(self.abort().await),
+                    }
+                }
+                _ = deadline(self.throttled.as_ref().map(|throttled| throttled.until)) => {
+                    let throttled = self.throttled.take().unwrap();
+                    if let ControlFlow::Break(frame) = self.received(throttled.message, throttled.meta).await? {
+                        return Ok(frame);
+                    }
+                }
+                message = self.socket.recv_with_meta(), if self.throttled.is_none() => {
                     match message {
-                        Some(Ok(message)) => match message {
-                            Message::Text(text) => self.extension.text(text).await?,
-                            Message::Binary(bytes) => self.extension.binary(bytes).await?,
-                            Message::Close(frame) => {
-                                return Ok(frame.map(CloseFrame::from))
-                            },
+                        Some(Ok((message, meta))) => {
+                            self.usage.received(&message);
+                            if let ControlFlow::Break(frame) = self.received(message, meta).await? {
+                                return Ok(frame);
+                            }
                         }
                         Some(Err(error)) => {
                             tracing::error!(id = %self.id, "connection error: {error}");
@@ -181,6 +739,333 @@ impl<E: SessionExt> SessionActor<E> {
         }
         Ok(None)
     }
+
+    /// Handles a received message, breaks with the close frame if the session should end.
+    async fn received(
+        &mut self,
+        message: Message,
+        meta: MessageMeta,
+    ) -> Result<ControlFlow<Option<CloseFrame>>, Error> {
+        match self.enforce_quota(&message).await? {
+            QuotaCheck::Allowed => {}
+            QuotaCheck::Throttled(until) => {
+                self.throttled = Some(Throttled {
+                    until,
+                    message,
+                    meta,
+                });
+                return Ok(ControlFlow::Continue(()));
+            }
+            QuotaCheck::Closed(frame) => return Ok(ControlFlow::Break(frame)),
+        }
+        if self.hello_deadline.is_some() && matches!(message, Message::Text(_) | Message::Binary(_))
+        {
+            let accepted = match watch(
+                &self.config,
+                &self.id,
+                "hello",
+                self.extension.hello(&message),
+            )
+            .await
+            {
+                Some(accepted) => accepted?,
+                None => return Ok(ControlFlow::Break(self.abort().await)),
+            };
+            if !accepted {
+                tracing::info!(id = %self.id, "hello rejected, closing session");
+                return Ok(ControlFlow::Break(self.reject_hello().await));
+            }
+            self.hello_deadline = None;
+        }
+        if self.is_retried_request(&message).await {
+            return Ok(ControlFlow::Continue(()));
+        }
+        match message {
+            Message::Text(text) if self.config.unify_data => {
+                let handler = self.extension.data(Bytes::from(text), DataKind::Text);
+                match supervise(&self.config, &self.id, "text", &self.timings.text, handler).await {
+                    Some(result) => self.check(result).await?,
+                    None => return Ok(ControlFlow::Break(self.abort().await)),
+                }
+            }
+            Message::Binary(bytes) if self.config.unify_data => {
+                let handler = self.extension.data(bytes, DataKind::Binary);
+                match supervise(
+                    &self.config,
+                    &self.id,
+                    "binary",
+                    &self.timings.binary,
+                    handler,
+                )
+                .await
+                {
+                    Some(result) => self.check(result).await?,
+                    None => return Ok(ControlFlow::Break(self.abort().await)),
+                }
+            }
+            Message::Text(text) => {
+                let handler = self.extension.text_with_meta(text, meta);
+                match supervise(&self.config, &self.id, "text", &self.timings.text, handler).await {
+                    Some(result) => self.check(result).await?,
+                    None => return Ok(ControlFlow::Break(self.abort().await)),
+                }
+            }
+            Message::Binary(bytes) => {
+                let handler = self.extension.binary_with_meta(bytes, meta);
+                match supervise(
+                    &self.config,
+                    &self.id,
+                    "binary",
+                    &self.timings.binary,
+                    handler,
+                )
+                .await
+                {
+                    Some(result) => self.check(result).await?,
+                    None => return Ok(ControlFlow::Break(self.abort().await)),
+                }
+            }
+            Message::Ping(payload) => {
+                let handler = self.extension.ping(payload);
+                match watch(&self.config, &self.id, "ping", handler).await {
+                    Some(result) => self.check(result).await?,
+                    None => return Ok(ControlFlow::Break(self.abort().await)),
+                }
+            }
+            Message::Pong(payload) => {
+                let handler = self.extension.pong(payload);
+                match watch(&self.config, &self.id, "pong", handler).await {
+                    Some(result) => self.check(result).await?,
+                    None => return Ok(ControlFlow::Break(self.abort().await)),
+                }
+            }
+            Message::Close(frame) => return Ok(ControlFlow::Break(frame)),
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Accounts the received message to the quota, and applies [`Quota::action`] if it's exceeded.
+    async fn enforce_quota(&mut self, message: &Message) -> Result<QuotaCheck, Error> {
+        let quota = match (&self.config.quota, message) {
+            (Some(quota), Message::Text(_) | Message::Binary(_)) => quota.clone(),
+            _ => return Ok(QuotaCheck::Allowed),
+        };
+        let usage = QuotaUsage {
+            messages: 1,
+            bytes: payload_len(message),
+        };
+        let (window, remaining) = quota.current_window();
+        let total = match quota.store.add(&quota.identity, window, usage).await {
+            Ok(total) => total,
+            Err(err) => {
+                tracing::warn!(id = %self.id, "failed to account quota usage: {err}");
+                return Ok(QuotaCheck::Allowed);
+            }
+        };
+        if !quota.is_exceeded(&total) {
+            return Ok(QuotaCheck::Allowed);
+        }
+        tracing::info!(id = %self.id, identity = %quota.identity, ?total, "quota exceeded");
+        match quota.action {
+            QuotaAction::Throttle => Ok(QuotaCheck::Throttled(
+                tokio::time::Instant::now() + remaining,
+            )),
+            QuotaAction::Notify => {
+                let handler = self.extension.quota_exceeded(total);
+                match watch(&self.config, &self.id, "quota_exceeded", handler).await {
+                    Some(result) => result.map(|()| QuotaCheck::Allowed),
+                    None => Ok(QuotaCheck::Closed(self.abort().await)),
+                }
+            }
+            QuotaAction::Disconnect => {
+                let frame = CloseFrame::policy("quota exceeded");
+                self.send(Message::Close(Some(frame.clone()))).await;
+                Ok(QuotaCheck::Closed(Some(frame)))
+            }
+        }
+    }
+
+    /// Records the request carried by the received message, returns `true` if it's a retry of a known request,
+    /// in which case the recorded response is sent again if there's one.
+    async fn is_retried_request(&mut self, message: &Message) -> bool {
+        let idempotency = match (&self.config.idempotency, message) {
+            (Some(idempotency), Message::Text(_) | Message::Binary(_)) => idempotency.clone(),
+            _ => return false,
+        };
+        let key = match idempotency.key.get(message) {
+            Some(key) => key,
+            None => return false,
+        };
+        match idempotency.store.begin(&idempotency.identity, &key).await {
+            Ok(IdempotentRequest::New) => {
+                self.requests.insert(key);
+                false
+            }
+            Ok(IdempotentRequest::Pending) => {
+                tracing::debug!(id = %self.id, key, "dropping retry of a pending request");
+                true
+            }
+            Ok(IdempotentRequest::Completed(response)) => {
+                tracing::debug!(id = %self.id, key, "responding again to a retried request");
+                self.send(response).await;
+                true
+            }
+            Err(err) => {
+                tracing::warn!(id = %self.id, "failed to record request: {err}");
+                false
+            }
+        }
+    }
+
+    /// Records the message as the response to a request received by this session, if it carries its key.
+    async fn complete_request(&mut self, message: &Message) {
+        let idempotency = match &self.config.idempotency {
+            Some(idempotency) => idempotency,
+            None => return,
+        };
+        let key = match idempotency.key.get(message) {
+            Some(key) if self.requests.remove(&key) => key,
+            _ => return,
+        };
+        let result = idempotency
+            .store
+            .complete(&idempotency.identity, &key, message.clone())
+            .await;
+        if let Err(err) = result {
+            tracing::warn!(id = %self.id, "failed to record response: {err}");
+        }
+    }
+
+    /// Sends a message of the session, breaks with the close frame once the connection is closed if it's a Close message.
+    async fn send_message(&mut self, message: Message) -> ControlFlow<Option<CloseFrame>> {
+        self.complete_request(&message).await;
+        let message = match self.reduce(message) {
+            Some(message) => message,
+            None => return ControlFlow::Continue(()),
+        };
+        self.send(message.clone()).await;
+        if let Message::Close(frame) = message {
+            self.socket.stream.closed().await;
+            return ControlFlow::Break(frame);
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Swaps in the reduced encoding of the message while the connection is stalled, `None` if it's dropped.
+    fn reduce(&self, message: Message) -> Option<Message> {
+        match &self.config.reduced_encodings {
+            Some(encodings)
+                if matches!(message, Message::Text(_) | Message::Binary(_))
+                    && *self.congestion.borrow() == CongestionState::Stalled =>
+            {
+                encodings.reduce(message)
+            }
+            _ => Some(message),
+        }
+    }
+
+    async fn send(&mut self, message: Message) {
+        self.usage.sent(&message);
+        let _ = self.socket.send(message).await;
+    }
+
+    /// Closes the session because its hello is missing or was rejected.
+    async fn reject_hello(&mut self) -> Option<CloseFrame> {
+        let frame = self
+            .config
+            .hello
+            .as_ref()
+            .map(|hello| hello.close_frame.clone());
+        self.send(Message::Close(frame.clone())).await;
+        frame
+    }
+
+    /// Applies [`SessionExt::on_handler_error`] to the result of a handler, returns the error if the session should end.
+    async fn check(&mut self, result: Result<(), Error>) -> Result<(), Error> {
+        let error = match result {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        match self.extension.on_handler_error(&error).await {
+            ErrorAction::Ignore => {
+                tracing::warn!(id = %self.id, "ignoring session handler error: {error}");
+                Ok(())
+            }
+            ErrorAction::CloseSession(frame) => {
+                self.send(Message::Close(frame)).await;
+                Err(error)
+            }
+            ErrorAction::ShutdownServer => {
+                self.send(Message::Close(Some(ShutdownRequested::close_frame())))
+                    .await;
+                Err(ShutdownRequested(error).into())
+            }
+        }
+    }
+
+    /// Closes the session after the watchdog cancelled a stuck handler.
+    async fn abort(&mut self) -> Option<CloseFrame> {
+        let frame = CloseFrame {
+            code: CloseCode::Error,
+            reason: String::from("session handler timed out"),
+        };
+        self.send(Message::Close(Some(frame.clone()))).await;
+        Some(frame)
+    }
 }
 
-const PANIC_MESSAGE_UNHANDLED_CLOSE: &str = "should not be called after Session close. Try handling Server::disconnect or Session::drop, also you can check whether the Session is alive using Session::alive";
+/// Waits until `deadline`, forever if not set.
+async fn deadline(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Runs a handler, recording its execution time and reporting it to the watchdog if it doesn't complete in time.
+/// Returns `None` if the handler was cancelled by the watchdog.
+async fn supervise(
+    config: &SessionConfig,
+    id: &impl std::fmt::Display,
+    handler: &'static str,
+    timings: &HistogramRecorder,
+    future: impl Future<Output = Result<(), Error>>,
+) -> Option<Result<(), Error>> {
+    let started_at = Instant::now();
+    let result = watch(config, id, handler, future).await;
+    timings.record(started_at.elapsed());
+    result
+}
+
+/// Runs a handler, reporting it to the watchdog if it doesn't complete in time.
+/// Returns `None` if the handler was cancelled by the watchdog.
+async fn watch<T>(
+    config: &SessionConfig,
+    id: &impl std::fmt::Display,
+    handler: &'static str,
+    future: impl Future<Output = T>,
+) -> Option<T> {
+    let watchdog = match &config.watchdog {
+        Some(watchdog) => watchdog,
+        None => return Some(future.await),
+    };
+    let started_at = Instant::now();
+    tokio::pin!(future);
+    loop {
+        match tokio::time::timeout(watchdog.timeout, &mut future).await {
+            Ok(result) => return Some(result),
+            Err(_) => {
+                tracing::warn!(
+                    %id,
+                    handler,
+                    elapsed = ?started_at.elapsed(),
+                    force_close = watchdog.force_close,
+                    "session handler is not making progress"
+                );
+                if watchdog.force_close {
+                    return None;
+                }
+            }
+        }
+    }
+}
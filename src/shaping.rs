@@ -0,0 +1,176 @@
+use crate::time::random_duration;
+use crate::Error;
+use crate::RawMessage;
+use futures::stream::BoxStream;
+use futures::Sink;
+use futures::SinkExt;
+use futures::Stream;
+use futures::StreamExt;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Network conditions emulated on a connection, e.g. to test how an application copes with a mobile network,
+/// see [`SocketConfig::network_conditions`](crate::SocketConfig::network_conditions).
+///
+/// Conditions apply to both directions, on top of the actual ones. Messages are still delivered in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkConditions {
+    /// Delay added to every message.
+    pub latency: Duration,
+    /// Maximum random delay added to the latency of every message.
+    pub jitter: Duration,
+    /// Maximum throughput in bytes per second, messages queue up once exceeded. Unlimited if not set.
+    pub bandwidth: Option<u64>,
+    /// Periodic pauses of the connection, see [`BurstPattern`].
+    pub bursts: Option<BurstPattern>,
+}
+
+/// Connection alternately delivering messages for `active`, then holding them for `paused`,
+/// so that they arrive in bursts, e.g. like a train going through tunnels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BurstPattern {
+    pub active: Duration,
+    pub paused: Duration,
+}
+
+impl BurstPattern {
+    /// First time at or after `at` the connection is active, for a pattern started at `start`.
+    fn resume(&self, start: Instant, at: Instant) -> Instant {
+        let period = (self.active + self.paused).as_nanos();
+        if period == 0 {
+            return at;
+        }
+        let elapsed = (at - start).as_nanos() % period;
+        if elapsed < self.active.as_nanos() {
+            return at;
+        }
+        at + Duration::from_nanos((period - elapsed) as u64)
+    }
+}
+
+/// One direction of the emulated connection.
+struct Link {
+    conditions: NetworkConditions,
+    start: Instant,
+    /// When the link is done transmitting the messages sent so far.
+    idle_at: Instant,
+    /// Delivery time of the last message, later ones can't be delivered before it.
+    delivered_at: Instant,
+}
+
+impl Link {
+    fn new(conditions: NetworkConditions) -> Self {
+        let now = Instant::now();
+        Self {
+            conditions,
+            start: now,
+            idle_at: now,
+            delivered_at: now,
+        }
+    }
+
+    /// Time at which a message sent now is delivered.
+    fn delivery(&mut self, message: &RawMessage) -> Instant {
+        let mut sent = Instant::now().max(self.idle_at);
+        if let Some(bandwidth) = self.conditions.bandwidth {
+            sent += Duration::from_secs_f64(message.len() as f64 / bandwidth.max(1) as f64);
+        }
+        self.idle_at = sent;
+        let mut delivery = sent + self.conditions.latency + random_duration(self.conditions.jitter);
+        if let Some(bursts) = &self.conditions.bursts {
+            delivery = bursts.resume(self.start, delivery);
+        }
+        self.delivered_at = delivery.max(self.delivered_at);
+        self.delivered_at
+    }
+}
+
+pub(crate) type ShapedSink = Pin<Box<dyn Sink<RawMessage, Error = Error> + Send>>;
+pub(crate) type ShapedStream = BoxStream<'static, Result<RawMessage, Error>>;
+
+/// Delays the messages going through `sink` and `stream` according to `conditions`.
+///
+/// Messages are delayed by tasks of their own, so the socket actors aren't slowed down, and errors
+/// of the underlying sink end the connection instead of being reported to the sink actor.
+pub(crate) fn shape<SI, ST>(
+    sink: SI,
+    stream: ST,
+    conditions: NetworkConditions,
+    runtime: Option<&Handle>,
+) -> (ShapedSink, ShapedStream)
+where
+    SI: Sink<RawMessage, Error = Error> + Unpin + Send + 'static,
+    ST: Stream<Item = Result<RawMessage, Error>> + Unpin + Send + 'static,
+{
+    let (outbound, delayed) = mpsc::unbounded_channel();
+    crate::runtime::spawn("ezsockets::shaping", runtime, write_delayed(sink, delayed));
+    let link = Link::new(conditions.clone());
+    let shaped_sink = futures::sink::unfold(
+        (outbound, link),
+        |(outbound, mut link), message: RawMessage| async move {
+            let delivery = link.delivery(&message);
+            outbound
+                .send((delivery, message))
+                .map_err(|_| Error::from("the connection is closed"))?;
+            Ok::<_, Error>((outbound, link))
+        },
+    );
+
+    let (inbound, delayed) = mpsc::unbounded_channel();
+    crate::runtime::spawn(
+        "ezsockets::shaping",
+        runtime,
+        read_delayed(stream, inbound, Link::new(conditions)),
+    );
+    let shaped_stream = futures::stream::unfold(delayed, |mut delayed| async move {
+        let (delivery, result) = delayed.recv().await?;
+        tokio::time::sleep_until(delivery).await;
+        Some((result, delayed))
+    });
+
+    (Box::pin(shaped_sink), shaped_stream.boxed())
+}
+
+async fn write_delayed<SI>(
+    mut sink: SI,
+    mut delayed: mpsc::UnboundedReceiver<(Instant, RawMessage)>,
+) where
+    SI: Sink<RawMessage, Error = Error> + Unpin,
+{
+    while let Some((delivery, message)) = delayed.recv().await {
+        tokio::time::sleep_until(delivery).await;
+        if let Err(err) = sink.send(message).await {
+            tracing::debug!("failed to write shaped message: {err}");
+            return;
+        }
+    }
+}
+
+async fn read_delayed<ST>(
+    mut stream: ST,
+    inbound: mpsc::UnboundedSender<(Instant, Result<RawMessage, Error>)>,
+    mut link: Link,
+) where
+    ST: Stream<Item = Result<RawMessage, Error>> + Unpin,
+{
+    loop {
+        let result = tokio::select! {
+            result = stream.next() => match result {
+                Some(result) => result,
+                None => return,
+            },
+            // Stops reading once the socket is dropped.
+            _ = inbound.closed() => return,
+        };
+        let delivery = match &result {
+            Ok(message) => link.delivery(message),
+            Err(_) => link.delivered_at,
+        };
+        if inbound.send((delivery, result)).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::client::connect;
+use crate::Client;
+use crate::ClientConfig;
+use crate::ClientExt;
+use crate::Error;
+use crate::Message;
+use crate::SendError;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+type Filter = Box<dyn Fn(&Message) -> bool + Send + Sync>;
+
+struct Consumer {
+    filter: Filter,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+enum DispatcherParams {
+    Subscribe { id: u64, consumer: Consumer },
+    Unsubscribe { id: u64 },
+}
+
+impl std::fmt::Debug for DispatcherParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Subscribe { id, .. } => f.debug_struct("Subscribe").field("id", id).finish(),
+            Self::Unsubscribe { id } => f.debug_struct("Unsubscribe").field("id", id).finish(),
+        }
+    }
+}
+
+struct Dispatcher {
+    consumers: HashMap<u64, Consumer>,
+}
+
+impl Dispatcher {
+    fn dispatch(&mut self, message: Message) {
+        self.consumers.retain(|id, consumer| {
+            if !(consumer.filter)(&message) {
+                return true;
+            }
+            let alive = consumer.sender.send(message.clone()).is_ok();
+            if !alive {
+                tracing::debug!(id, "removing closed consumer");
+            }
+            alive
+        });
+    }
+}
+
+#[async_trait]
+impl ClientExt for Dispatcher {
+    type Params = DispatcherParams;
+
+    async fn text(&mut self, text: String) -> Result<(), Error> {
+        self.dispatch(Message::Text(text));
+        Ok(())
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Error> {
+        self.dispatch(Message::Binary(bytes));
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        match params {
+            DispatcherParams::Subscribe { id, consumer } => {
+                self.consumers.insert(id, consumer);
+            }
+            DispatcherParams::Unsubscribe { id } => {
+                self.consumers.remove(&id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Single physical connection shared between many logical consumers.
+///
+/// Each consumer receives only the inbound messages matching its filter.
+/// The connection is closed once the `SharedClient` and all of its consumers are dropped.
+#[derive(Clone)]
+pub struct SharedClient {
+    client: Client<Dispatcher>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for SharedClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedClient").finish_non_exhaustive()
+    }
+}
+
+impl SharedClient {
+    pub async fn connect(config: ClientConfig) -> (Self, impl Future<Output = Result<(), Error>>) {
+        let (client, future) = connect(
+            |_| Dispatcher {
+                consumers: HashMap::new(),
+            },
+            config,
+        )
+        .await;
+        let shared = Self {
+            client,
+            next_id: Default::default(),
+        };
+        (shared, future)
+    }
+
+    /// Registers a new consumer, which receives every inbound message for which `filter` returns `true`.
+    pub fn consumer(
+        &self,
+        filter: impl Fn(&Message) -> bool + Send + Sync + 'static,
+    ) -> SharedConsumer {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.client.call(DispatcherParams::Subscribe {
+            id,
+            consumer: Consumer {
+                filter: Box::new(filter),
+                sender,
+            },
+        });
+        SharedConsumer {
+            id,
+            client: self.client.clone(),
+            receiver,
+        }
+    }
+}
+
+/// Consumer of a [`SharedClient`], unsubscribes when dropped.
+pub struct SharedConsumer {
+    id: u64,
+    client: Client<Dispatcher>,
+    receiver: mpsc::UnboundedReceiver<Message>,
+}
+
+impl std::fmt::Debug for SharedConsumer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedConsumer")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SharedConsumer {
+    /// Receives the next message matching the filter of this consumer.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.receiver.recv().await
+    }
+
+    /// Sends a Text message through the shared connection.
+    pub fn text(&self, text: String) -> Result<(), SendError<Message>> {
+        self.client.text(text)
+    }
+
+    /// Sends a Binary message through the shared connection.
+    pub fn binary(&self, bytes: impl Into<Bytes>) -> Result<(), SendError<Message>> {
+        self.client.binary(bytes)
+    }
+}
+
+impl Drop for SharedConsumer {
+    fn drop(&mut self) {
+        self.client
+            .call_if_alive(DispatcherParams::Unsubscribe { id: self.id });
+    }
+}
@@ -1,29 +1,180 @@
+use crate::congestion::Congestion;
+use crate::dedup::DeduplicationWindow;
+use crate::filter::Filter;
+use crate::filter::MessageFilter;
+use crate::inbox;
+use crate::inbox::Inbox;
+use crate::inbox::InboxSender;
+use crate::inbox::OverflowPolicy;
+use crate::inbox::Rejected;
+use crate::middleware;
+use crate::middleware::Middleware;
+use crate::queue::FifoQueue;
+use crate::queue::Outbox;
+use crate::queue::SendQueueFactory;
+use crate::shaping;
+use crate::shaping::NetworkConditions;
+use crate::stats::SocketStatsRecorder;
+use crate::transfer::Target;
+use crate::transfer::Transfer;
+use crate::wheel::TimerWheel;
+use crate::CongestionState;
+use crate::Deduplication;
 use crate::Error;
+use crate::SocketStats;
+use crate::TraceContext;
+use bytes::Bytes;
 use futures::{SinkExt, StreamExt, TryStreamExt};
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
 use std::time::Instant;
 use std::{
     marker::PhantomData,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::runtime::Handle;
 use tokio::sync::mpsc;
-use tokio::sync::Mutex;
+use tokio::sync::oneshot;
+use tokio::sync::watch;
+use tokio::sync::TryAcquireError;
 
 #[derive(Debug, Clone)]
-pub struct Config {
+pub struct SocketConfig {
     pub heartbeat: Duration,
     pub timeout: Duration,
+    /// Drops inbound messages which were already received recently, see [`Deduplication`].
+    pub deduplication: Option<Deduplication>,
+    /// Number of queued outbound messages above which the connection is considered [`CongestionState::Buffering`].
+    pub congestion_threshold: usize,
+    /// Time after which a pending write marks the connection as [`CongestionState::Stalled`].
+    pub stall_timeout: Duration,
+    /// Time after which a received message is considered stale, see [`MessageMeta::deadline`].
+    pub message_ttl: Option<Duration>,
+    /// Answers or drops inbound messages before they reach the handler, see [`MessageFilter`].
+    pub filter: Option<MessageFilter>,
+    /// Number of messages the sink and stream actors process before yielding to other tasks,
+    /// so a single busy connection can't starve others running on the same worker thread.
+    pub yield_after: Option<usize>,
+    /// Runtime the socket actors are spawned on, defaults to the current runtime.
+    pub runtime: Option<Handle>,
+    /// Queue of outbound messages, an unbounded [`FifoQueue`] if not set.
+    pub send_queue: Option<SendQueueFactory>,
+    /// Maximum number of outbound messages waiting to be written, once reached [`Sink::send`] waits for room
+    /// instead of letting the queue grow, so that producers are slowed down to the pace of the peer.
+    /// Unbounded if not set.
+    pub send_capacity: Option<usize>,
+    /// Answers inbound Ping frames with a Pong carrying the same payload, enabled by default.
+    ///
+    /// The tungstenite and axum transports already reply to Pings on their own, disable it when the transport
+    /// does as well, or to answer Pings manually, e.g. with a [`MessageFilter`].
+    pub auto_pong: bool,
+    /// Maximum size in bytes of an inbound Text or Binary message, the connection is closed with [`CloseCode::Size`]
+    /// once exceeded. Also passed to the tungstenite transports, so oversized messages are rejected before being buffered.
+    /// Limited only by the transport if not set.
+    pub max_message_size: Option<usize>,
+    /// Time to wait for the peer to answer our Close frame before dropping the connection.
+    ///
+    /// Following RFC 6455, the connection keeps being read after sending a Close frame until the peer sends
+    /// one back, and a Close frame received from the peer is answered before the connection is dropped.
+    pub close_timeout: Duration,
+    /// Interceptors of inbound and outbound messages, see [`SocketMiddleware`](crate::SocketMiddleware).
+    pub middleware: Vec<Middleware>,
+    /// Emulates the given network conditions on the connection, e.g. in tests or staging environments.
+    pub network_conditions: Option<NetworkConditions>,
+    /// Maximum number of inbound messages waiting to be received from the [`Stream`], handled according to
+    /// [`SocketConfig::recv_overflow`] once reached. Unbounded if not set.
+    pub recv_capacity: Option<usize>,
+    /// What happens to inbound messages received once [`SocketConfig::recv_capacity`] is reached.
+    pub recv_overflow: OverflowPolicy,
+    /// Drives the heartbeat of the socket from a timer wheel shared with other sockets, instead of a task
+    /// of its own, see [`TimerWheel`]. Sockets accepted by a server share the wheel set in the socket config of the server.
+    pub heartbeat_wheel: Option<TimerWheel>,
+    /// Delivers inbound Ping and Pong frames as [`Message::Ping`] and [`Message::Pong`], e.g. for protocols carrying
+    /// data in their payloads. They are still answered and used for the heartbeat as usual. Disabled by default.
+    pub expose_control_frames: bool,
+    /// Closes the connection once no Text or Binary message was received for a while, see [`IdleTimeout`]. Disabled if not set.
+    pub idle_timeout: Option<IdleTimeout>,
+    /// Whether the transport can write [`RawMessage::Fragment`]s, fragmented messages are otherwise reassembled
+    /// and written at once. Enabled by default, the axum back-end disables it as axum can't write fragments.
+    pub write_fragments: bool,
 }
 
-impl Default for Config {
+/// Closes connections which stay idle, see [`SocketConfig::idle_timeout`].
+///
+/// Only Text and Binary messages count as activity, control frames keep flowing on idle connections
+/// because of the heartbeat, which already closes the connections of unresponsive peers.
+#[derive(Debug, Clone)]
+pub struct IdleTimeout {
+    /// Time without any message received after which the connection is closed.
+    pub timeout: Duration,
+    pub close_frame: CloseFrame,
+}
+
+impl IdleTimeout {
+    /// Closes the connection after `timeout` without any message, with [`CloseCode::Away`].
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            close_frame: CloseFrame {
+                code: CloseCode::Away,
+                reason: String::from("connection idle"),
+            },
+        }
+    }
+
+    pub fn close_frame(mut self, close_frame: CloseFrame) -> Self {
+        self.close_frame = close_frame;
+        self
+    }
+}
+
+impl Default for SocketConfig {
     fn default() -> Self {
         Self {
             heartbeat: Duration::from_secs(5),
             timeout: Duration::from_secs(10),
+            deduplication: None,
+            congestion_threshold: 64,
+            stall_timeout: Duration::from_secs(1),
+            message_ttl: None,
+            filter: None,
+            yield_after: None,
+            runtime: None,
+            send_queue: None,
+            send_capacity: None,
+            auto_pong: true,
+            max_message_size: None,
+            close_timeout: Duration::from_secs(5),
+            middleware: Vec::new(),
+            network_conditions: None,
+            recv_capacity: None,
+            recv_overflow: OverflowPolicy::Block,
+            heartbeat_wheel: None,
+            expose_control_frames: false,
+            idle_timeout: None,
+            write_fragments: true,
         }
     }
 }
 
+impl SocketConfig {
+    /// Configuration of the tungstenite connection, applying the limits of this config.
+    #[cfg(feature = "tokio-tungstenite")]
+    pub(crate) fn websocket_config(
+        &self,
+    ) -> Option<tokio_tungstenite::tungstenite::protocol::WebSocketConfig> {
+        self.max_message_size.map(|max_message_size| {
+            tokio_tungstenite::tungstenite::protocol::WebSocketConfig {
+                max_message_size: Some(max_message_size),
+                ..Default::default()
+            }
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum CloseCode {
     /// Indicates a normal closure, meaning that the purpose for
@@ -84,6 +235,8 @@ pub enum CloseCode {
     /// to a different IP (when multiple targets exist), or reconnect to the same IP
     /// when a user has performed an action.
     Again,
+    /// Any other code, e.g. registered by a library (3000-3999) or private to the application (4000-4999).
+    Other(u16),
 }
 
 impl From<CloseCode> for u16 {
@@ -103,6 +256,7 @@ impl From<CloseCode> for u16 {
             Error => 1011,
             Restart => 1012,
             Again => 1013,
+            Other(code) => code,
         }
     }
 }
@@ -127,6 +281,7 @@ impl TryFrom<u16> for CloseCode {
             1011 => Error,
             1012 => Restart,
             1013 => Again,
+            3000..=4999 => Other(code),
             code => {
                 return Err(code);
             }
@@ -134,26 +289,314 @@ impl TryFrom<u16> for CloseCode {
     }
 }
 
+impl CloseCode {
+    /// Whether the code is reserved for reporting a closure locally, or otherwise not allowed, and must not be sent in a Close frame.
+    pub fn is_reserved(&self) -> bool {
+        match self {
+            Self::Status | Self::Abnormal => true,
+            Self::Other(code) => !(3000..=4999).contains(code),
+            _ => false,
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal closure",
+            Self::Away => "going away",
+            Self::Protocol => "protocol error",
+            Self::Unsupported => "unsupported data",
+            Self::Status => "no status received",
+            Self::Abnormal => "abnormal closure",
+            Self::Invalid => "invalid payload data",
+            Self::Policy => "policy violation",
+            Self::Size => "message too big",
+            Self::Extension => "mandatory extension",
+            Self::Error => "internal error",
+            Self::Restart => "service restart",
+            Self::Again => "try again later",
+            Self::Other(3000..=3999) => "registered",
+            Self::Other(4000..=4999) => "private use",
+            Self::Other(_) => "unknown",
+        }
+    }
+}
+
+/// Formats the code as its number followed by its description, e.g. `1008 (policy violation)`.
+impl std::fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", u16::from(self.clone()), self.description())
+    }
+}
+
+/// Maximum length in bytes of a close reason, so that the Close frame fits in a control frame.
+pub const MAX_CLOSE_REASON_LEN: usize = 123;
+
 #[derive(Debug, Clone)]
 pub struct CloseFrame {
     pub code: CloseCode,
     pub reason: String,
 }
 
+impl CloseFrame {
+    /// Closure after the purpose of the connection was fulfilled, without a reason.
+    pub fn normal() -> Self {
+        Self {
+            code: CloseCode::Normal,
+            reason: String::new(),
+        }
+    }
+
+    /// Closure because the peer violated a policy of the application, e.g. by sending a forbidden message.
+    pub fn policy(reason: impl Into<String>) -> Self {
+        Self {
+            code: CloseCode::Policy,
+            reason: reason.into(),
+        }
+    }
+
+    /// Closure because the endpoint is going away, e.g. shutting down.
+    pub fn going_away() -> Self {
+        Self {
+            code: CloseCode::Away,
+            reason: String::new(),
+        }
+    }
+
+    /// Builds a close frame checked to be valid on the wire.
+    pub fn builder(code: CloseCode) -> CloseFrameBuilder {
+        CloseFrameBuilder {
+            code,
+            reason: String::new(),
+        }
+    }
+
+    /// Checks that the frame can be sent, i.e. that its code isn't reserved and its reason
+    /// is at most [`MAX_CLOSE_REASON_LEN`] bytes long.
+    ///
+    /// Invalid frames passed to the socket are still sent, without their code if it's reserved,
+    /// and with their reason truncated if it's too long.
+    pub fn validate(&self) -> Result<(), InvalidCloseFrame> {
+        if self.code.is_reserved() {
+            return Err(InvalidCloseFrame::ReservedCode(self.code.clone()));
+        }
+        if self.reason.len() > MAX_CLOSE_REASON_LEN {
+            return Err(InvalidCloseFrame::ReasonTooLong(self.reason.len()));
+        }
+        Ok(())
+    }
+
+    /// Makes the frame valid on the wire, see [`CloseFrame::validate`].
+    fn into_sendable(mut self) -> Option<Self> {
+        match self.validate() {
+            Ok(()) => Some(self),
+            Err(InvalidCloseFrame::ReservedCode(code)) => {
+                tracing::warn!("close code {code} can't be sent, closing without a code");
+                None
+            }
+            Err(err @ InvalidCloseFrame::ReasonTooLong(_)) => {
+                tracing::warn!("{err}, truncating it");
+                let mut len = MAX_CLOSE_REASON_LEN;
+                while !self.reason.is_char_boundary(len) {
+                    len -= 1;
+                }
+                self.reason.truncate(len);
+                Some(self)
+            }
+        }
+    }
+}
+
+/// Formats the frame as its code followed by its reason, if any.
+impl std::fmt::Display for CloseFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.reason.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{}: {}", self.code, self.reason)
+        }
+    }
+}
+
+/// Builder of a [`CloseFrame`], see [`CloseFrame::builder`].
+#[derive(Debug, Clone)]
+pub struct CloseFrameBuilder {
+    code: CloseCode,
+    reason: String,
+}
+
+impl CloseFrameBuilder {
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = reason.into();
+        self
+    }
+
+    /// Returns the frame, or an error if its code is reserved or its reason is longer than [`MAX_CLOSE_REASON_LEN`].
+    pub fn build(self) -> Result<CloseFrame, InvalidCloseFrame> {
+        let frame = CloseFrame {
+            code: self.code,
+            reason: self.reason,
+        };
+        frame.validate()?;
+        Ok(frame)
+    }
+}
+
+/// Reason a [`CloseFrameBuilder`] failed to build a frame.
+#[derive(Debug, Clone)]
+pub enum InvalidCloseFrame {
+    /// The code is only meant to be reported locally, see [`CloseCode::is_reserved`].
+    ReservedCode(CloseCode),
+    /// The reason, of the given length in bytes, doesn't fit in a Close frame.
+    ReasonTooLong(usize),
+}
+
+impl std::fmt::Display for InvalidCloseFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReservedCode(code) => write!(f, "close code {code} can't be sent"),
+            Self::ReasonTooLong(len) => write!(
+                f,
+                "close reason is {len} bytes long, at most {MAX_CLOSE_REASON_LEN} are allowed"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidCloseFrame {}
+
+/// Error returned when sending through a connection which already closed, holding the message that wasn't sent.
+#[derive(Debug, Clone)]
+pub struct SendError<T>(pub T);
+
+impl<T> SendError<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the connection is closed, the message wasn't sent")
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for SendError<T> {}
+
+/// Error returned by [`Sink::try_send`] when the message can't be queued right away, holding the message back.
+#[derive(Debug, Clone)]
+pub enum TrySendError<T> {
+    /// The send queue has no room for the message, see [`SocketConfig::send_capacity`].
+    Full(T),
+    /// The connection is closed.
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Full(message) | Self::Closed(message) => message,
+        }
+    }
+}
+
+impl TrySendError<()> {
+    /// Same error, holding `message` back.
+    pub(crate) fn with<T>(self, message: T) -> TrySendError<T> {
+        match self {
+            Self::Full(()) => TrySendError::Full(message),
+            Self::Closed(()) => TrySendError::Closed(message),
+        }
+    }
+}
+
+impl<T> std::fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full(_) => write!(f, "the send queue is full, the message wasn't sent"),
+            Self::Closed(_) => write!(f, "the connection is closed, the message wasn't sent"),
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for TrySendError<T> {}
+
+/// Error returned by [`Sink::send_timeout`] when the message couldn't be written in time.
+#[derive(Debug, Clone)]
+pub enum SendTimeoutError<T> {
+    /// The connection is closed, holding the message back unless it was already queued.
+    Closed(Option<T>),
+    /// The message wasn't written within the timeout. It was dropped if the queue had no room for it,
+    /// otherwise it stays queued and is written once the peer catches up.
+    Timeout,
+}
+
+impl<T> std::fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed(_) => write!(f, "the connection is closed, the message wasn't sent"),
+            Self::Timeout => write!(f, "timed out waiting for the message to be written"),
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for SendTimeoutError<T> {}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     Text(String),
-    Binary(Vec<u8>),
+    /// Binary payload, cloning it is cheap, so the same message can be broadcast without copying the data.
+    Binary(Bytes),
     Close(Option<CloseFrame>),
+    /// Ping frame, received only with [`SocketConfig::expose_control_frames`], the socket answers it on its own.
+    Ping(Bytes),
+    /// Pong frame, received only with [`SocketConfig::expose_control_frames`].
+    Pong(Bytes),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Binary(bytes.into())
+    }
+}
+
+impl From<Bytes> for Message {
+    fn from(bytes: Bytes) -> Self {
+        Self::Binary(bytes)
+    }
+}
+
+/// Kind of a data message, see [`SessionExt::data`](crate::SessionExt::data).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    Text,
+    Binary,
+}
+
+/// Frame of a message sent in several parts, see [`Sink::send_fragment`].
+#[derive(Debug, Clone)]
+pub enum Fragment {
+    /// Starts a message of the given kind.
+    First(DataKind, Bytes),
+    Continuation(Bytes),
+    /// Ends the message.
+    Final(Bytes),
 }
 
 #[derive(Debug, Clone)]
 pub enum RawMessage {
     Text(String),
-    Binary(Vec<u8>),
+    Binary(Bytes),
     Ping(Vec<u8>),
     Pong(Vec<u8>),
     Close(Option<CloseFrame>),
+    /// Only sent, transports deliver fragmented messages once reassembled.
+    Fragment(Fragment),
 }
 
 impl From<Message> for RawMessage {
@@ -161,31 +604,417 @@ impl From<Message> for RawMessage {
         match message {
             Message::Text(text) => Self::Text(text),
             Message::Binary(bytes) => Self::Binary(bytes),
-            Message::Close(frame) => Self::Close(frame.map(CloseFrame::from)),
+            Message::Ping(bytes) => Self::Ping(bytes.to_vec()),
+            Message::Pong(bytes) => Self::Pong(bytes.to_vec()),
+            Message::Close(frame) => Self::Close(frame),
+        }
+    }
+}
+
+/// Error converting a [`RawMessage`] into a [`Message`], holding back the fragment, which isn't a message on its own.
+#[derive(Debug, Clone)]
+pub struct UnexpectedFragment(pub Fragment);
+
+impl std::fmt::Display for UnexpectedFragment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a fragment isn't a complete message")
+    }
+}
+
+impl std::error::Error for UnexpectedFragment {}
+
+impl TryFrom<RawMessage> for Message {
+    type Error = UnexpectedFragment;
+
+    fn try_from(message: RawMessage) -> Result<Self, Self::Error> {
+        match message {
+            RawMessage::Text(text) => Ok(Self::Text(text)),
+            RawMessage::Binary(bytes) => Ok(Self::Binary(bytes)),
+            RawMessage::Ping(bytes) => Ok(Self::Ping(bytes.into())),
+            RawMessage::Pong(bytes) => Ok(Self::Pong(bytes.into())),
+            RawMessage::Close(frame) => Ok(Self::Close(frame)),
+            RawMessage::Fragment(fragment) => Err(UnexpectedFragment(fragment)),
+        }
+    }
+}
+
+/// Kind of frame of a [`RawMessage`], see [`RawMessage::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Text,
+    Binary,
+    Ping,
+    Pong,
+    Close,
+    Fragment,
+}
+
+impl RawMessage {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            Self::Text(_) => MessageKind::Text,
+            Self::Binary(_) => MessageKind::Binary,
+            Self::Ping(_) => MessageKind::Ping,
+            Self::Pong(_) => MessageKind::Pong,
+            Self::Close(_) => MessageKind::Close,
+            Self::Fragment(_) => MessageKind::Fragment,
+        }
+    }
+
+    /// Size of the payload in bytes, including the code of a Close frame.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Text(text) => text.len(),
+            Self::Binary(bytes) => bytes.len(),
+            Self::Ping(bytes) | Self::Pong(bytes) => bytes.len(),
+            Self::Close(frame) => frame.as_ref().map_or(0, |frame| 2 + frame.reason.len()),
+            Self::Fragment(
+                Fragment::First(_, bytes) | Fragment::Continuation(bytes) | Fragment::Final(bytes),
+            ) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the message is a Ping, Pong or Close frame.
+    pub fn is_control(&self) -> bool {
+        matches!(self, Self::Ping(_) | Self::Pong(_) | Self::Close(_))
+    }
+}
+
+/// Metadata of a received message.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageMeta {
+    /// When the message was read from the connection.
+    pub received_at: Instant,
+    /// When the message becomes stale, set if [`SocketConfig::message_ttl`] is configured.
+    pub deadline: Option<Instant>,
+}
+
+impl MessageMeta {
+    /// Whether the deadline of the message has already passed.
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Yields to the scheduler after processing a configured number of messages.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    yield_after: Option<usize>,
+    processed: usize,
+}
+
+impl Budget {
+    pub(crate) fn new(yield_after: Option<usize>) -> Self {
+        Self {
+            yield_after,
+            processed: 0,
+        }
+    }
+
+    pub(crate) async fn consume(&mut self) {
+        if let Some(yield_after) = self.yield_after {
+            self.processed += 1;
+            if self.processed >= yield_after {
+                self.processed = 0;
+                tokio::task::yield_now().await;
+            }
         }
     }
 }
 
+#[derive(Debug)]
+enum SinkCommand {
+    /// Close frame, written after the queued data messages.
+    Message(RawMessage),
+    /// Frame of a fragmented message, written after the messages queued before its first fragment.
+    Fragment {
+        queued: Vec<Message>,
+        fragment: Fragment,
+        /// Notified once the fragment is written.
+        written: Option<oneshot::Sender<()>>,
+    },
+    /// Responds once all previously queued messages have been written and flushed.
+    Sync(oneshot::Sender<()>),
+    /// Writes the queued messages and the Close frame, then stops the sink actor, see [`Sink::close_graceful`].
+    Shutdown {
+        frame: Option<CloseFrame>,
+        written: oneshot::Sender<()>,
+    },
+}
+
 #[derive(Debug)]
 struct SinkActor<M, S>
 where
     M: From<RawMessage>,
     S: SinkExt<M, Error = Error> + Unpin,
 {
-    receiver: mpsc::UnboundedReceiver<RawMessage>,
+    receiver: mpsc::UnboundedReceiver<SinkCommand>,
+    /// Pings and Pongs, written ahead of everything else so that keepalives don't wait behind a backlog.
+    control: mpsc::UnboundedReceiver<RawMessage>,
+    outbox: Arc<Outbox>,
     sink: S,
+    congestion: Arc<Congestion>,
+    stall_timeout: Duration,
+    budget: Budget,
+    /// Sync requests received while a fragmented message is being written, answered once it's complete.
+    syncs: Vec<oneshot::Sender<()>>,
+    /// Set once a Close frame was written, starting the close handshake.
+    closing: watch::Sender<bool>,
+    /// When the first message written since the last flush was written.
+    unflushed_since: Option<Instant>,
+    middleware: Vec<Middleware>,
+    stats: Arc<SocketStatsRecorder>,
+    /// Fragmented message being reassembled, for transports which can't write fragments.
+    reassembly: Option<Reassembly>,
     phantom: PhantomData<M>,
 }
 
+/// Fragmented message reassembled before being written, see [`SocketConfig::write_fragments`].
+#[derive(Debug, Default)]
+struct Reassembly {
+    kind: Option<DataKind>,
+    payload: Vec<u8>,
+}
+
+impl Reassembly {
+    /// Adds the fragment, returns the complete message once the final fragment is added.
+    fn push(&mut self, fragment: Fragment) -> Option<RawMessage> {
+        let bytes = match fragment {
+            Fragment::First(kind, bytes) => {
+                self.kind = Some(kind);
+                self.payload.clear();
+                bytes
+            }
+            Fragment::Continuation(bytes) => bytes,
+            Fragment::Final(bytes) => {
+                self.payload.extend_from_slice(&bytes);
+                let payload = std::mem::take(&mut self.payload);
+                return match self.kind.take() {
+                    Some(DataKind::Text) => match String::from_utf8(payload) {
+                        Ok(text) => Some(RawMessage::Text(text)),
+                        Err(_) => {
+                            tracing::warn!(
+                                "dropping fragmented text message, it isn't valid UTF-8"
+                            );
+                            None
+                        }
+                    },
+                    Some(DataKind::Binary) => Some(RawMessage::Binary(payload.into())),
+                    None => None,
+                };
+            }
+        };
+        self.payload.extend_from_slice(&bytes);
+        None
+    }
+}
+
+/// Maximum time written messages wait to be flushed while more messages keep being written.
+const MAX_FLUSH_DELAY: Duration = Duration::from_millis(1);
+
+/// Marks the connection as [`CongestionState::Stalled`] if the write or flush takes longer than `stall_timeout`.
+async fn watch_stall(
+    congestion: &Congestion,
+    stall_timeout: Duration,
+    write: impl Future<Output = Result<(), Error>>,
+) -> Result<(), Error> {
+    tokio::pin!(write);
+    match tokio::time::timeout(stall_timeout, &mut write).await {
+        Ok(result) => result,
+        Err(_) => {
+            congestion.stalled();
+            write.await
+        }
+    }
+}
+
 impl<M, S> SinkActor<M, S>
 where
     M: From<RawMessage>,
     S: SinkExt<M, Error = Error> + Unpin,
 {
     async fn run(&mut self) -> Result<(), Error> {
-        while let Some(message) = self.receiver.recv().await {
-            tracing::trace!("sending message: {:?}", message);
-            self.sink.send(M::from(message)).await?;
+        loop {
+            self.write_control().await?;
+            // Commands are handled ahead of the queued messages.
+            match self.receiver.try_recv() {
+                Ok(command) => {
+                    self.handle(command).await?;
+                    continue;
+                }
+                Err(mpsc::error::TryRecvError::Disconnected) => break,
+                Err(mpsc::error::TryRecvError::Empty) => {}
+            }
+            if let Some(message) = self.outbox.pop() {
+                self.write(message.into()).await?;
+                continue;
+            }
+            // Messages written in a burst are flushed together, once there's nothing left to write.
+            self.flush().await?;
+            tokio::select! {
+                command = self.receiver.recv() => match command {
+                    Some(command) => self.handle(command).await?,
+                    None => break,
+                },
+                Some(message) = self.control.recv() => self.write(message).await?,
+                _ = self.outbox.notified() => {}
+            }
+        }
+        // Every Sink was dropped, write what's left in the queue.
+        self.drain().await?;
+        self.flush().await
+    }
+
+    async fn handle(&mut self, command: SinkCommand) -> Result<(), Error> {
+        match command {
+            SinkCommand::Message(message) => {
+                if matches!(message, RawMessage::Close(_)) {
+                    self.drain().await?;
+                }
+                self.write(message).await
+            }
+            SinkCommand::Fragment {
+                queued,
+                fragment,
+                written,
+            } => {
+                for message in queued {
+                    self.write_control().await?;
+                    self.write(message.into()).await?;
+                }
+                let is_final = matches!(fragment, Fragment::Final(_));
+                self.write(RawMessage::Fragment(fragment)).await?;
+                if let Some(written) = written {
+                    self.flush().await?;
+                    let _ = written.send(());
+                }
+                if is_final {
+                    self.outbox.resume();
+                    if !self.syncs.is_empty() {
+                        self.sync().await?;
+                    }
+                }
+                Ok(())
+            }
+            SinkCommand::Sync(respond_to) => {
+                self.syncs.push(respond_to);
+                if !self.outbox.is_held() {
+                    self.sync().await?;
+                }
+                Ok(())
+            }
+            SinkCommand::Shutdown { frame, written } => {
+                self.drain().await?;
+                self.write(RawMessage::Close(frame)).await?;
+                // Nothing may be written after the Close frame, commands sent meanwhile are dropped.
+                self.control.close();
+                while self.control.try_recv().is_ok() {
+                    self.congestion.written();
+                }
+                self.receiver.close();
+                while let Ok(command) = self.receiver.try_recv() {
+                    if matches!(
+                        command,
+                        SinkCommand::Message(_) | SinkCommand::Fragment { .. }
+                    ) {
+                        self.congestion.written();
+                    }
+                }
+                let _ = written.send(());
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes and flushes the queued messages, then answers the pending sync requests.
+    async fn sync(&mut self) -> Result<(), Error> {
+        self.drain().await?;
+        self.flush().await?;
+        for respond_to in self.syncs.drain(..) {
+            let _ = respond_to.send(());
+        }
+        Ok(())
+    }
+
+    /// Writes every queued message, still letting Pings and Pongs through first.
+    async fn drain(&mut self) -> Result<(), Error> {
+        loop {
+            self.write_control().await?;
+            match self.outbox.pop() {
+                Some(message) => self.write(message.into()).await?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Writes the pending Pings and Pongs.
+    async fn write_control(&mut self) -> Result<(), Error> {
+        while let Ok(message) = self.control.try_recv() {
+            self.write(message).await?;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, message: RawMessage) -> Result<(), Error> {
+        if *self.closing.borrow() {
+            // Nothing may follow the Close frame, e.g. a heartbeat Ping while waiting for the peer's answer.
+            tracing::trace!("dropping message written after the Close frame: {message:?}");
+            self.congestion.written();
+            return Ok(());
+        }
+        self.budget.consume().await;
+        let message = match (message, &mut self.reassembly) {
+            (RawMessage::Fragment(fragment), Some(reassembly)) => match reassembly.push(fragment) {
+                Some(message) => message,
+                None => {
+                    self.congestion.written();
+                    return Ok(());
+                }
+            },
+            (message, _) => message,
+        };
+        let message = match middleware::outbound(&self.middleware, message) {
+            Some(message) => message,
+            None => {
+                tracing::trace!("message dropped by middleware");
+                self.congestion.written();
+                return Ok(());
+            }
+        };
+        let message = match message {
+            RawMessage::Close(frame) => {
+                RawMessage::Close(frame.and_then(CloseFrame::into_sendable))
+            }
+            message => message,
+        };
+        tracing::trace!("sending message: {:?}", message);
+        #[cfg(feature = "otel")]
+        crate::otel::record_message(&message, crate::otel::Direction::Transmit);
+        let is_close = matches!(message, RawMessage::Close(_));
+        self.stats.sent(&message);
+        let feed = self.sink.feed(M::from(message));
+        watch_stall(&self.congestion, self.stall_timeout, feed).await?;
+        self.congestion.written();
+        let unflushed_since = *self.unflushed_since.get_or_insert_with(Instant::now);
+        if is_close || unflushed_since.elapsed() >= MAX_FLUSH_DELAY {
+            self.flush().await?;
+        }
+        if is_close {
+            let _ = self.closing.send(true);
+        }
+        Ok(())
+    }
+
+    /// Flushes the messages written since the last flush, if any.
+    async fn flush(&mut self) -> Result<(), Error> {
+        if self.unflushed_since.take().is_some() {
+            let flush = self.sink.flush();
+            watch_stall(&self.congestion, self.stall_timeout, flush).await?;
         }
         Ok(())
     }
@@ -193,35 +1022,330 @@ where
 
 #[derive(Debug, Clone)]
 pub struct Sink {
-    sender: mpsc::UnboundedSender<RawMessage>,
+    sender: mpsc::UnboundedSender<SinkCommand>,
+    control: mpsc::UnboundedSender<RawMessage>,
+    outbox: Arc<Outbox>,
+    congestion: Arc<Congestion>,
+    /// Round-trip time measured by the last Pong answering one of our heartbeat Pings.
+    latency: Arc<watch::Sender<Option<Duration>>>,
+    stats: Arc<SocketStatsRecorder>,
 }
 
 impl Sink {
-    fn new<M, S>(sink: S) -> (tokio::task::JoinHandle<Result<(), Error>>, Self)
+    fn new<M, S>(
+        sink: S,
+        closing: watch::Sender<bool>,
+        config: &SocketConfig,
+    ) -> (tokio::task::JoinHandle<Result<(), Error>>, Self)
     where
         M: From<RawMessage> + Send + 'static,
         S: SinkExt<M, Error = Error> + Unpin + Send + 'static,
     {
         let (sender, receiver) = mpsc::unbounded_channel();
+        let (control_sender, control) = mpsc::unbounded_channel();
+        let congestion = Arc::new(Congestion::new(config.congestion_threshold));
+        let queue = match &config.send_queue {
+            Some(factory) => factory.create(),
+            None => Box::<FifoQueue>::default(),
+        };
+        let outbox = Arc::new(Outbox::new(queue, config.send_capacity));
+        let stats = Arc::new(SocketStatsRecorder::default());
         let mut actor = SinkActor {
             receiver,
+            control,
+            outbox: outbox.clone(),
             sink,
+            congestion: congestion.clone(),
+            stall_timeout: config.stall_timeout,
+            budget: Budget::new(config.yield_after),
+            syncs: Vec::new(),
+            closing,
+            unflushed_since: None,
+            middleware: config.middleware.clone(),
+            stats: stats.clone(),
+            reassembly: (!config.write_fragments).then(Reassembly::default),
             phantom: Default::default(),
         };
-        let future = tokio::spawn(async move { actor.run().await });
-        (future, Self { sender })
+        let future =
+            crate::runtime::spawn("ezsockets::sink", config.runtime.as_ref(), async move {
+                let result = actor.run().await;
+                actor.outbox.close();
+                result
+            });
+        (
+            future,
+            Self {
+                sender,
+                control: control_sender,
+                outbox,
+                congestion,
+                latency: Arc::new(watch::channel(None).0),
+                stats,
+            },
+        )
+    }
+
+    /// Current congestion state of the connection.
+    pub fn congestion(&self) -> CongestionState {
+        *self.congestion.subscribe().borrow()
+    }
+
+    /// Number of queued messages which weren't written to the connection yet.
+    pub fn buffered(&self) -> usize {
+        self.congestion.buffered()
+    }
+
+    /// Receiver notified on every congestion state change.
+    pub fn congestion_changes(&self) -> watch::Receiver<CongestionState> {
+        self.congestion.subscribe()
+    }
+
+    /// Counters of the messages received and written to the connection.
+    pub fn stats(&self) -> SocketStats {
+        self.stats.snapshot()
+    }
+
+    /// Round-trip time of the connection, measured with the heartbeat Pings, `None` until the first Pong is received.
+    pub fn latency(&self) -> Option<Duration> {
+        *self.latency.borrow()
+    }
+
+    /// Receiver notified on every round-trip time measurement, once per [`SocketConfig::heartbeat`].
+    pub fn latency_changes(&self) -> watch::Receiver<Option<Duration>> {
+        self.latency.subscribe()
     }
 
     pub fn is_closed(&self) -> bool {
         self.sender.is_closed()
     }
 
-    pub async fn send(&self, message: Message) {
-        self.sender.send(message.into()).unwrap();
+    /// Queues the message, waiting for room first if [`SocketConfig::send_capacity`] is reached.
+    /// Returns the message back if the sink already stopped, e.g. because the connection died.
+    pub async fn send(&self, message: Message) -> Result<(), SendError<Message>> {
+        match message {
+            message @ (Message::Close(_) | Message::Ping(_) | Message::Pong(_)) => self
+                .send_control(message.clone().into())
+                .map_err(|_| SendError(message)),
+            message => self.send_data(message, None).await,
+        }
+    }
+
+    /// Queues the message without waiting, failing with [`TrySendError::Full`] if [`SocketConfig::send_capacity`] is reached,
+    /// e.g. to send from synchronous code or a `Drop` implementation. Control frames are never refused for lack of room.
+    pub fn try_send(&self, message: Message) -> Result<(), TrySendError<Message>> {
+        match message {
+            message @ (Message::Close(_) | Message::Ping(_) | Message::Pong(_)) => self
+                .send_control(message.clone().into())
+                .map_err(|_| TrySendError::Closed(message)),
+            message => {
+                if let Err(err) = self.try_reserve() {
+                    return Err(err.with(message));
+                }
+                self.push(message, None)
+                    .map_err(|SendError(message)| TrySendError::Closed(message))
+            }
+        }
+    }
+
+    /// Sends the message and waits until it's written to the connection, along with the messages queued before it,
+    /// failing with [`SendTimeoutError::Timeout`] if that takes longer than `timeout`, e.g. because the peer stalled.
+    pub async fn send_timeout(
+        &self,
+        message: Message,
+        timeout: Duration,
+    ) -> Result<(), SendTimeoutError<Message>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        match tokio::time::timeout_at(deadline, self.send(message)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(SendError(message))) => return Err(SendTimeoutError::Closed(Some(message))),
+            Err(_) => return Err(SendTimeoutError::Timeout),
+        }
+        let (sender, receiver) = oneshot::channel();
+        self.sync_with(sender);
+        match tokio::time::timeout_at(deadline, receiver).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(SendTimeoutError::Closed(None)),
+            Err(_) => Err(SendTimeoutError::Timeout),
+        }
+    }
+
+    /// Sends the message, replacing the queued message with the same conflation `key` if it wasn't written yet.
+    ///
+    /// Requires a queue supporting conflation, like [`ConflatingQueue`](crate::ConflatingQueue),
+    /// see [`SocketConfig::send_queue`]. Control frames are never conflated.
+    pub async fn send_conflated(
+        &self,
+        key: impl Into<Arc<str>>,
+        message: Message,
+    ) -> Result<(), SendError<Message>> {
+        match message {
+            Message::Close(_) | Message::Ping(_) | Message::Pong(_) => self.send(message).await,
+            message => self.send_data(message, Some(key.into())).await,
+        }
+    }
+
+    /// Queues the message, returning it back if the sink actor already stopped, e.g. because of a write error.
+    /// Text and Binary messages go through the [`SendQueue`](crate::SendQueue), control frames don't.
+    pub(crate) async fn send_raw(&self, message: RawMessage) -> Result<(), SendError<RawMessage>> {
+        let message = match message {
+            RawMessage::Text(text) => Message::Text(text),
+            RawMessage::Binary(bytes) => Message::Binary(bytes),
+            RawMessage::Fragment(fragment) => {
+                return self
+                    .send_fragment(fragment)
+                    .map_err(|SendError(fragment)| SendError(RawMessage::Fragment(fragment)))
+            }
+            message => return self.send_control(message),
+        };
+        self.send_data(message, None)
+            .await
+            .map_err(|SendError(message)| SendError(message.into()))
+    }
+
+    async fn send_data(
+        &self,
+        message: Message,
+        key: Option<Arc<str>>,
+    ) -> Result<(), SendError<Message>> {
+        if !self.outbox.reserve().await || self.sender.is_closed() {
+            tracing::debug!("dropping message, the sink is closed");
+            return Err(SendError(message));
+        }
+        self.push(message, key)
+    }
+
+    /// Takes a slot of the send queue without waiting, for a message queued with `push`.
+    pub(crate) fn try_reserve(&self) -> Result<(), TrySendError<()>> {
+        match self.outbox.try_reserve() {
+            Ok(()) if !self.sender.is_closed() => Ok(()),
+            Err(TryAcquireError::NoPermits) => Err(TrySendError::Full(())),
+            _ => {
+                tracing::debug!("dropping message, the sink is closed");
+                Err(TrySendError::Closed(()))
+            }
+        }
+    }
+
+    /// Queues a message a slot was reserved for.
+    pub(crate) fn push(
+        &self,
+        message: Message,
+        key: Option<Arc<str>>,
+    ) -> Result<(), SendError<Message>> {
+        self.congestion.queued();
+        let dropped = match self.outbox.push(message, key) {
+            Ok(dropped) => dropped,
+            Err(message) => {
+                tracing::debug!("dropping message, the sink is closed");
+                self.congestion.written();
+                return Err(SendError(message));
+            }
+        };
+        if dropped > 0 {
+            tracing::trace!(dropped, "send queue dropped messages");
+            self.congestion.discarded(dropped);
+        }
+        Ok(())
     }
 
-    pub(crate) async fn send_raw(&self, message: RawMessage) {
-        self.sender.send(message).unwrap();
+    /// Sends Pings and Pongs through their own lane, ahead of the queued messages, and Close frames after them.
+    fn send_control(&self, message: RawMessage) -> Result<(), SendError<RawMessage>> {
+        self.congestion.queued();
+        let result = match message {
+            RawMessage::Ping(_) | RawMessage::Pong(_) => self
+                .control
+                .send(message)
+                .map_err(|mpsc::error::SendError(message)| message),
+            message => self.sender.send(SinkCommand::Message(message)).map_err(
+                |mpsc::error::SendError(command)| match command {
+                    SinkCommand::Message(message) => message,
+                    _ => unreachable!("a command is returned as it was sent"),
+                },
+            ),
+        };
+        if let Err(message) = result {
+            tracing::debug!("dropping message, the sink is closed");
+            self.congestion.written();
+            return Err(SendError(message));
+        }
+        Ok(())
+    }
+
+    /// Sends a frame of a message too large to be held in memory at once, e.g. a file read chunk by chunk.
+    ///
+    /// A message starts with [`Fragment::First`] and ends with [`Fragment::Final`]. Fragments are written ahead of
+    /// the messages queued after the first one, which wait until the final fragment is sent, so the fragments
+    /// of a message should be sent without delay, from a single task.
+    ///
+    /// The peer receives the reassembled message. On transports which can't write fragments, see
+    /// [`SocketConfig::write_fragments`], the message is reassembled by the socket and written once the final fragment is sent.
+    pub fn send_fragment(&self, fragment: Fragment) -> Result<(), SendError<Fragment>> {
+        self.send_fragment_with(fragment, None)
+    }
+
+    /// Same as `send_fragment`, notifying `written` once the fragment is written.
+    pub(crate) fn send_fragment_with(
+        &self,
+        fragment: Fragment,
+        written: Option<oneshot::Sender<()>>,
+    ) -> Result<(), SendError<Fragment>> {
+        let queued = match fragment {
+            Fragment::First(..) => self.outbox.hold(),
+            _ => Vec::new(),
+        };
+        self.congestion.queued();
+        let command = SinkCommand::Fragment {
+            queued,
+            fragment,
+            written,
+        };
+        if let Err(mpsc::error::SendError(SinkCommand::Fragment { fragment, .. })) =
+            self.sender.send(command)
+        {
+            tracing::debug!("dropping fragment, the sink is closed");
+            self.congestion.written();
+            return Err(SendError(fragment));
+        }
+        Ok(())
+    }
+
+    /// Starts sending a message of the given kind fragment by fragment, reporting its progress, see [`Transfer`].
+    pub fn transfer(&self, kind: DataKind) -> Transfer {
+        Transfer::new(Target::Sink(self.clone()), kind)
+    }
+
+    /// Adapts the sink to [`futures::Sink`], to use it with combinators, e.g. [`StreamExt::forward`].
+    pub fn into_futures_sink(self) -> impl futures::Sink<Message, Error = SendError<Message>> {
+        futures::sink::unfold(self, |sink, message| async move {
+            sink.send(message).await?;
+            Ok(sink)
+        })
+    }
+
+    /// Waits until all previously queued messages have been written to the underlying sink,
+    /// returns immediately if the sink is already closed.
+    pub async fn sync(&self) {
+        let (sender, receiver) = oneshot::channel();
+        self.sync_with(sender);
+        let _ = receiver.await;
+    }
+
+    /// Same as `sync`, but responds through `respond_to` instead of waiting.
+    pub(crate) fn sync_with(&self, respond_to: oneshot::Sender<()>) {
+        let _ = self.sender.send(SinkCommand::Sync(respond_to));
+    }
+
+    /// Closes the connection without losing the messages sent before: stops accepting new messages, writes
+    /// the queued ones, then the Close frame, and stops the sink. Waits until the Close frame is written,
+    /// returns immediately if the sink is already closed.
+    ///
+    /// Messages sent after this call are returned back, control frames included. The connection keeps being
+    /// read until the peer answers the Close frame, see [`SocketConfig::close_timeout`].
+    pub async fn close_graceful(&self, frame: Option<CloseFrame>) {
+        self.outbox.close();
+        let (written, receiver) = oneshot::channel();
+        let _ = self.sender.send(SinkCommand::Shutdown { frame, written });
+        let _ = receiver.await;
     }
 }
 
@@ -231,9 +1355,24 @@ where
     M: Into<RawMessage>,
     S: StreamExt<Item = Result<M, Error>> + Unpin,
 {
-    sender: mpsc::UnboundedSender<Result<Message, Error>>,
+    sender: Arc<InboxSender>,
     stream: S,
+    sink: Sink,
     last_alive: Arc<Mutex<Instant>>,
+    filter: Option<MessageFilter>,
+    deduplication: Option<DeduplicationWindow>,
+    message_ttl: Option<Duration>,
+    auto_pong: bool,
+    expose_control_frames: bool,
+    max_message_size: Option<usize>,
+    /// Whether we sent a Close frame, in which case the connection is read until the peer answers it.
+    closing: watch::Receiver<bool>,
+    close_timeout: Duration,
+    middleware: Vec<Middleware>,
+    budget: Budget,
+    idle_timeout: Option<IdleTimeout>,
+    /// When the last Text or Binary message was received.
+    last_message: Instant,
 }
 
 impl<M, S> StreamActor<M, S>
@@ -242,128 +1381,656 @@ where
     S: StreamExt<Item = Result<M, Error>> + Unpin,
 {
     async fn run(&mut self) -> Result<(), Error> {
-        while let Some(result) = self.stream.next().await {
-            let result = result.map(M::into);
+        loop {
+            let result = match &self.idle_timeout {
+                Some(idle_timeout) => {
+                    let idle_at = self.last_message + idle_timeout.timeout;
+                    match tokio::time::timeout_at(idle_at.into(), self.stream.next()).await {
+                        Ok(result) => result,
+                        Err(_) => return self.close_idle().await,
+                    }
+                }
+                None => self.stream.next().await,
+            };
+            let result = match result {
+                Some(result) => result.map(M::into),
+                None => break,
+            };
             tracing::trace!("received message: {:?}", result);
+            if let Ok(RawMessage::Text(_) | RawMessage::Binary(_)) = &result {
+                self.last_message = Instant::now();
+            }
+            if let Ok(message) = &result {
+                self.sink.stats.received(message);
+            }
+            #[cfg(feature = "otel")]
+            if let Ok(message) = &result {
+                crate::otel::record_message(message, crate::otel::Direction::Receive);
+            }
+            let result = match result.map(|message| middleware::inbound(&self.middleware, message))
+            {
+                Ok(Some(message)) => Ok(message),
+                Ok(None) => {
+                    tracing::trace!("message dropped by middleware");
+                    continue;
+                }
+                Err(err) => Err(err),
+            };
+            let received_at = Instant::now();
+            let meta = MessageMeta {
+                received_at,
+                deadline: self.message_ttl.map(|ttl| received_at + ttl),
+            };
+            if let Some(size) = self.oversized(&result) {
+                return self.close_oversized(size, meta).await;
+            }
+            if let (Ok(message), Some(filter)) = (&result, &self.filter) {
+                if !matches!(message, RawMessage::Pong(_) | RawMessage::Close(_)) {
+                    match filter.apply(message) {
+                        Filter::Deliver => {}
+                        Filter::Drop => {
+                            tracing::trace!("message dropped by filter");
+                            continue;
+                        }
+                        Filter::Fastpath(reply) => {
+                            tracing::trace!("message answered by filter");
+                            let _ = self.sink.send(reply).await;
+                            continue;
+                        }
+                    }
+                }
+            }
 
             let message = match result {
                 Ok(message) => Ok(match message {
                     RawMessage::Text(text) => Message::Text(text),
                     RawMessage::Binary(bytes) => Message::Binary(bytes),
-                    RawMessage::Ping(_bytes) => continue,
+                    RawMessage::Ping(bytes) => {
+                        let payload = Bytes::from(bytes);
+                        if self.auto_pong {
+                            let _ = self.sink.send_raw(RawMessage::Pong(payload.to_vec())).await;
+                        }
+                        if !self.expose_control_frames {
+                            continue;
+                        }
+                        Message::Ping(payload)
+                    }
                     RawMessage::Pong(bytes) => {
-                        *self.last_alive.lock().await = Instant::now();
-                        let bytes: [u8; 16] = bytes.try_into().unwrap(); // TODO: handle invalid byte frame
-                        let timestamp = u128::from_be_bytes(bytes);
-                        let timestamp = Duration::from_millis(timestamp as u64); // TODO: handle overflow
-                        let latency = SystemTime::now()
-                            .duration_since(UNIX_EPOCH + timestamp)
-                            .unwrap();
-                        tracing::trace!("latency: {}ms", latency.as_millis());
+                        *self
+                            .last_alive
+                            .lock()
+                            .unwrap_or_else(|err| err.into_inner()) = Instant::now();
+                        self.record_latency(&bytes);
+                        if !self.expose_control_frames {
+                            continue;
+                        }
+                        Message::Pong(bytes.into())
+                    }
+                    RawMessage::Close(frame) => {
+                        if !*self.closing.borrow() {
+                            // Answer the peer's Close frame before the connection is dropped.
+                            let _ = self.sink.send_raw(RawMessage::Close(frame.clone())).await;
+                            let _ =
+                                tokio::time::timeout(self.close_timeout, self.sink.sync()).await;
+                        }
+                        let _ = self.sender.force(Ok((Message::Close(frame), meta)));
+                        return Ok(());
+                    }
+                    RawMessage::Fragment(_) => {
+                        tracing::debug!("dropping fragment, expected a reassembled message");
                         continue;
                     }
-                    RawMessage::Close(_) => return Ok(()),
                 }),
-                Err(err) => Err(err), // maybe early return here?
+                // Errors are handed to the application, the stream ends by itself if the connection is gone.
+                Err(err) => Err(err),
             };
-            self.sender.send(message).unwrap();
+            if let (Ok(message @ (Message::Text(_) | Message::Binary(_))), Some(deduplication)) =
+                (&message, &mut self.deduplication)
+            {
+                if deduplication.is_duplicate(message) {
+                    tracing::trace!("dropping duplicated message");
+                    continue;
+                }
+            }
+            match self
+                .sender
+                .send(message.map(|message| (message, meta)))
+                .await
+            {
+                Ok(()) => {}
+                Err(Rejected::Closed) if !*self.closing.borrow() => {
+                    tracing::debug!("stream dropped, stopping reading from the connection");
+                    return Ok(());
+                }
+                Err(Rejected::Closed) => {}
+                Err(Rejected::Full) => {
+                    tracing::info!("closing connection, the inbound queue is full");
+                    return self
+                        .close_with(CloseFrame::policy("inbound queue full"), meta)
+                        .await;
+                }
+            }
+            self.budget.consume().await;
         }
         Ok(())
     }
+
+    /// Updates the latency from a Pong answering one of our heartbeat Pings, which carry the time they were sent.
+    fn record_latency(&self, payload: &[u8]) {
+        // Pongs not answering our own Pings may carry arbitrary payloads.
+        let bytes: [u8; 16] = match payload.try_into() {
+            Ok(bytes) => bytes,
+            Err(_) => return,
+        };
+        let timestamp = match u64::try_from(u128::from_be_bytes(bytes)) {
+            Ok(timestamp) => Duration::from_micros(timestamp),
+            Err(_) => return,
+        };
+        if let Ok(latency) = SystemTime::now().duration_since(UNIX_EPOCH + timestamp) {
+            tracing::trace!("latency: {}us", latency.as_micros());
+            self.sink.latency.send_replace(Some(latency));
+        }
+    }
+
+    /// Size of the message if it exceeds `max_message_size`, including messages rejected by tungstenite itself.
+    fn oversized(&self, result: &Result<RawMessage, Error>) -> Option<usize> {
+        let max_message_size = self.max_message_size?;
+        let size = match result {
+            Ok(RawMessage::Text(text)) => text.len(),
+            Ok(RawMessage::Binary(bytes)) => bytes.len(),
+            #[cfg(feature = "tokio-tungstenite")]
+            Err(err) => {
+                use tokio_tungstenite::tungstenite;
+                // axum wraps the tungstenite error into its own.
+                let err: &(dyn std::error::Error + 'static) = err.as_ref();
+                let size = std::iter::successors(Some(err), |err| err.source()).find_map(|err| {
+                    match err.downcast_ref::<tungstenite::Error>() {
+                        Some(tungstenite::Error::Capacity(
+                            tungstenite::error::CapacityError::MessageTooLong { size, .. },
+                        )) => Some(*size),
+                        _ => None,
+                    }
+                });
+                size?
+            }
+            _ => return None,
+        };
+        (size > max_message_size).then_some(size)
+    }
+
+    /// Closes the connection with [`CloseCode::Size`], the stream ends with the Close frame that was sent.
+    async fn close_oversized(&mut self, size: usize, meta: MessageMeta) -> Result<(), Error> {
+        tracing::info!("closing connection, received a message of {size} bytes");
+        let frame = CloseFrame {
+            code: CloseCode::Size,
+            reason: String::from("message too big"),
+        };
+        self.close_with(frame, meta).await
+    }
+
+    /// Closes the connection with the frame of [`SocketConfig::idle_timeout`], the stream ends with it.
+    async fn close_idle(&mut self) -> Result<(), Error> {
+        tracing::info!("closing idle connection");
+        let frame = match &self.idle_timeout {
+            Some(idle_timeout) => idle_timeout.close_frame.clone(),
+            None => CloseFrame::going_away(),
+        };
+        let meta = MessageMeta {
+            received_at: Instant::now(),
+            deadline: None,
+        };
+        self.close_with(frame, meta).await
+    }
+
+    /// Closes the connection with `frame`, the stream ends with it once the queued messages are taken.
+    async fn close_with(&mut self, frame: CloseFrame, meta: MessageMeta) -> Result<(), Error> {
+        let _ = self
+            .sink
+            .send_raw(RawMessage::Close(Some(frame.clone())))
+            .await;
+        // The sink is stopped as soon as the stream actor returns.
+        let _ = tokio::time::timeout(self.close_timeout, self.sink.sync()).await;
+        let _ = self.sender.force(Ok((Message::Close(Some(frame)), meta)));
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct Stream {
-    receiver: mpsc::UnboundedReceiver<Result<Message, Error>>,
+    receiver: Inbox,
+    /// Outbox of the sink of the same connection, identifying it in [`Socket::reunite`].
+    outbox: Weak<Outbox>,
 }
 
 impl Stream {
     fn new<M, S>(
         stream: S,
+        sink: Sink,
         last_alive: Arc<Mutex<Instant>>,
-    ) -> (tokio::task::JoinHandle<Result<(), Error>>, Self)
+        closing: watch::Receiver<bool>,
+        config: &SocketConfig,
+    ) -> (
+        tokio::task::JoinHandle<Result<(), Error>>,
+        Arc<InboxSender>,
+        Self,
+    )
     where
         M: Into<RawMessage> + std::fmt::Debug + Send + 'static,
         S: StreamExt<Item = Result<M, Error>> + Unpin + Send + 'static,
     {
-        let (sender, receiver) = mpsc::unbounded_channel();
+        let (sender, receiver) = inbox::channel(config.recv_capacity, config.recv_overflow);
+        let sender = Arc::new(sender);
+        let outbox = Arc::downgrade(&sink.outbox);
         let mut actor = StreamActor {
-            sender,
+            sender: sender.clone(),
             stream,
+            sink,
             last_alive,
+            filter: config.filter.clone(),
+            deduplication: config.deduplication.clone().map(DeduplicationWindow::new),
+            message_ttl: config.message_ttl,
+            auto_pong: config.auto_pong,
+            expose_control_frames: config.expose_control_frames,
+            max_message_size: config.max_message_size,
+            closing,
+            close_timeout: config.close_timeout,
+            middleware: config.middleware.clone(),
+            budget: Budget::new(config.yield_after),
+            idle_timeout: config.idle_timeout.clone(),
+            last_message: Instant::now(),
         };
-        let future = tokio::spawn(async move { actor.run().await });
-        (future, Self { receiver })
+        let future =
+            crate::runtime::spawn("ezsockets::stream", config.runtime.as_ref(), async move {
+                actor.run().await
+            });
+        (future, sender, Self { receiver, outbox })
     }
 
     pub async fn recv(&mut self) -> Option<Result<Message, Error>> {
+        self.recv_with_meta()
+            .await
+            .map(|result| result.map(|(message, _)| message))
+    }
+
+    pub async fn recv_with_meta(&mut self) -> Option<Result<(Message, MessageMeta), Error>> {
         self.receiver.recv().await
     }
+
+    /// Waits until the peer answered the Close frame sent to it, or the connection ended,
+    /// which [`SocketConfig::close_timeout`] bounds. Messages received meanwhile are discarded.
+    #[cfg(any(feature = "client", feature = "server"))]
+    pub(crate) async fn closed(&mut self) {
+        while let Some(result) = self.recv().await {
+            if let Ok(Message::Close(_)) = result {
+                return;
+            }
+            tracing::trace!("discarding message received while closing: {result:?}");
+        }
+    }
+}
+
+/// Yields the same messages as [`Stream::recv`], control frames being handled by the socket itself.
+impl futures::Stream for Stream {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|item| item.map(|result| result.map(|(message, _)| message)))
+    }
+}
+
+/// Completes once `timeout` elapsed since a Close frame was written, never if none is.
+async fn close_timed_out(mut closed_by_us: watch::Receiver<bool>, timeout: Duration) {
+    while !*closed_by_us.borrow_and_update() {
+        if closed_by_us.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+    tokio::time::sleep(timeout).await;
+}
+
+/// Pings the peer periodically, and closes the connection once it stops answering.
+pub(crate) struct Heartbeat {
+    sink: Sink,
+    /// When the peer last answered one of our Pings.
+    last_alive: Arc<Mutex<Instant>>,
+    pub(crate) period: Duration,
+    timeout: Duration,
+}
+
+impl Heartbeat {
+    /// Sends a Ping, or a Close frame if the peer didn't answer the previous ones in time.
+    /// Returns whether the heartbeat goes on.
+    pub(crate) fn beat(&self) -> bool {
+        let last_alive = *self
+            .last_alive
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if last_alive.elapsed() > self.timeout {
+            tracing::info!("closing connection due to timeout");
+            let _ = self.sink.send_control(RawMessage::Close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: String::from("client didn't respond to Ping frame"),
+            })));
+            return false;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let bytes = timestamp.as_micros().to_be_bytes();
+        self.sink
+            .send_control(RawMessage::Ping(bytes.to_vec()))
+            .is_ok()
+    }
+}
+
+/// Error returned by [`Socket::reunite`] for halves of different sockets, holding them back.
+#[derive(Debug)]
+pub struct ReuniteError(pub Sink, pub Stream);
+
+impl std::fmt::Display for ReuniteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tried to reunite halves of different sockets")
+    }
+}
+
+impl std::error::Error for ReuniteError {}
+
+/// Parameters negotiated by the TLS handshake of a connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// Server name indicated by the client (SNI), `None` if it didn't send one.
+    pub server_name: Option<String>,
+    /// Application protocol negotiated with ALPN, `None` if none was, or if the TLS back-end doesn't report it.
+    pub alpn_protocol: Option<Vec<u8>>,
 }
 
 #[derive(Debug)]
 pub struct Socket {
     pub sink: Sink,
     pub stream: Stream,
+    local_addr: Option<SocketAddr>,
+    peer_addr: Option<SocketAddr>,
+    tls: Option<TlsInfo>,
+    trace_context: Option<TraceContext>,
+    /// Set by [`Server::accept`](crate::Server::accept) from [`ServerConfig::session`](crate::ServerConfig::session),
+    /// used by [`Session::create`](crate::Session::create).
+    #[cfg(feature = "server")]
+    pub(crate) session_config: Option<crate::SessionConfig>,
+    /// Set by the tungstenite back-end, see [`Socket::raw_fd`].
+    #[cfg(all(feature = "handoff", target_os = "linux"))]
+    pub(crate) raw_fd: Option<std::os::fd::RawFd>,
+}
+
+/// Yields the messages received by [`Socket::stream`].
+impl futures::Stream for Socket {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        std::pin::Pin::new(&mut self.stream).poll_next(cx)
+    }
 }
 
 impl Socket {
-    pub fn new<M, E: std::error::Error, S>(socket: S, config: Config) -> Self
+    pub fn new<M, E, S>(socket: S, config: SocketConfig) -> Self
     where
         M: Into<RawMessage> + From<RawMessage> + std::fmt::Debug + Send + 'static,
-        E: Into<Error>,
+        E: Into<Error> + std::error::Error,
         S: SinkExt<M, Error = E> + Unpin + StreamExt<Item = Result<M, E>> + Unpin + Send + 'static,
+    {
+        let (sink, stream) = socket.sink_err_into().err_into().split();
+        match config.network_conditions.clone() {
+            Some(conditions) => {
+                let sink =
+                    sink.with(|message: RawMessage| std::future::ready(Ok(M::from(message))));
+                let stream = stream.map_ok(M::into);
+                let (sink, stream) =
+                    shaping::shape(sink, stream, conditions, config.runtime.as_ref());
+                Self::spawn(sink, stream, config)
+            }
+            None => Self::spawn(sink, stream, config),
+        }
+    }
+
+    /// Spawns the actors of the socket.
+    pub(crate) fn spawn<M, SI, ST>(sink: SI, stream: ST, config: SocketConfig) -> Self
+    where
+        M: Into<RawMessage> + From<RawMessage> + std::fmt::Debug + Send + 'static,
+        SI: SinkExt<M, Error = Error> + Unpin + Send + 'static,
+        ST: StreamExt<Item = Result<M, Error>> + Unpin + Send + 'static,
     {
         let last_alive = Instant::now();
         let last_alive = Arc::new(Mutex::new(last_alive));
-        let (sink, stream) = socket.sink_err_into().err_into().split();
-        let ((sink_future, sink), (stream_future, stream)) =
-            (Sink::new(sink), Stream::new(stream, last_alive.clone()));
-        let heartbeat_future = tokio::spawn({
-            let sink = sink.clone();
-            async move {
-                let mut interval = tokio::time::interval(config.heartbeat);
-
-                loop {
-                    interval.tick().await;
-                    if last_alive.lock().await.elapsed() > config.timeout {
-                        tracing::info!("closing connection due to timeout");
-                        sink.send_raw(RawMessage::Close(Some(CloseFrame {
-                            code: CloseCode::Normal,
-                            reason: String::from("client didn't respond to Ping frame"),
-                        })))
-                        .await;
-                        return;
+        let (closing, closed_by_us) = watch::channel(false);
+        let (mut sink_future, sink) = Sink::new(sink, closing, &config);
+        let (mut stream_future, inbox, stream) = Stream::new(
+            stream,
+            sink.clone(),
+            last_alive.clone(),
+            closed_by_us.clone(),
+            &config,
+        );
+        let runtime = config.runtime.clone();
+        let close_timeout = config.close_timeout;
+        let heartbeat = Heartbeat {
+            sink: sink.clone(),
+            last_alive,
+            period: config.heartbeat,
+            timeout: config.timeout,
+        };
+        let (heartbeat_future, heartbeat_registration) = match &config.heartbeat_wheel {
+            Some(wheel) => (None, Some(wheel.register(heartbeat, runtime.as_ref()))),
+            None => {
+                let future =
+                    crate::runtime::spawn("ezsockets::heartbeat", runtime.as_ref(), async move {
+                        let mut interval = tokio::time::interval(heartbeat.period);
+                        loop {
+                            interval.tick().await;
+                            if !heartbeat.beat() {
+                                return;
+                            }
+                        }
+                    });
+                (Some(future), None)
+            }
+        };
+
+        crate::runtime::spawn("ezsockets::socket", runtime.as_ref(), async move {
+            let close_timed_out = close_timed_out(closed_by_us, close_timeout);
+            tokio::pin!(close_timed_out);
+            let mut sink_stopped = false;
+            let result = loop {
+                tokio::select! {
+                    result = &mut stream_future => break result.unwrap_or_else(|err| Err(err.into())),
+                    // The sink stops without error once every Sink is dropped, or after `close_graceful`.
+                    result = &mut sink_future, if !sink_stopped => {
+                        sink_stopped = true;
+                        if let Err(err) = result.unwrap_or_else(|err| Err(err.into())) {
+                            // Nothing can be written to the connection anymore, stop reading from it as well.
+                            stream_future.abort();
+                            break Err(err);
+                        }
+                    }
+                    _ = &mut close_timed_out => {
+                        tracing::debug!("peer didn't answer the Close frame in time, dropping the connection");
+                        stream_future.abort();
+                        break Ok(());
                     }
-                    let timestamp = SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap();
-                    let timestamp = timestamp.as_millis();
-                    let bytes = timestamp.to_be_bytes();
-                    sink.send_raw(RawMessage::Ping(bytes.to_vec())).await;
                 }
+            };
+            sink_future.abort();
+            if let Some(heartbeat_future) = heartbeat_future {
+                heartbeat_future.abort();
+            }
+            drop(heartbeat_registration);
+            // Reported to the owner of the Stream, which ends right after.
+            if let Err(err) = result {
+                tracing::debug!("socket failed: {err}");
+                let _ = inbox.force(Err(err));
             }
         });
 
-        tokio::spawn(async move {
-            let result = stream_future.await.unwrap();
-            sink_future.abort();
-            heartbeat_future.abort();
-            result
-        });
+        Self {
+            sink,
+            stream,
+            local_addr: None,
+            peer_addr: None,
+            tls: None,
+            trace_context: None,
+            #[cfg(feature = "server")]
+            session_config: None,
+            #[cfg(all(feature = "handoff", target_os = "linux"))]
+            raw_fd: None,
+        }
+    }
+
+    /// Splits the socket into its owned halves, e.g. to send and receive from different tasks.
+    /// See [`Socket::reunite`] to put them back together.
+    pub fn split(self) -> (Sink, Stream) {
+        (self.sink, self.stream)
+    }
+
+    /// Puts back together the halves of a socket, returning them back if they weren't split from the same socket.
+    ///
+    /// The addresses, TLS parameters and trace context of the original socket aren't kept, see [`Socket::with_addresses`].
+    pub fn reunite(sink: Sink, stream: Stream) -> Result<Self, ReuniteError> {
+        if !std::ptr::eq(stream.outbox.as_ptr(), Arc::as_ptr(&sink.outbox)) {
+            return Err(ReuniteError(sink, stream));
+        }
+        Ok(Self {
+            sink,
+            stream,
+            local_addr: None,
+            peer_addr: None,
+            tls: None,
+            trace_context: None,
+            #[cfg(feature = "server")]
+            session_config: None,
+            #[cfg(all(feature = "handoff", target_os = "linux"))]
+            raw_fd: None,
+        })
+    }
 
-        Self { sink, stream }
+    /// Attaches the addresses of the underlying transport, so they can be later retrieved from the Session or Client.
+    pub fn with_addresses(
+        mut self,
+        local_addr: Option<SocketAddr>,
+        peer_addr: Option<SocketAddr>,
+    ) -> Self {
+        self.local_addr = local_addr;
+        self.peer_addr = peer_addr;
+        self
     }
 
-    pub async fn send(&self, message: Message) {
-        self.sink.send(message).await;
+    /// Local address of the underlying transport, if known.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
     }
 
-    pub async fn send_raw(&self, message: RawMessage) {
-        self.sink.send_raw(message).await;
+    /// Remote address of the underlying transport, if known.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Attaches the parameters of the TLS handshake, so they can be later retrieved from the Session or Client.
+    pub fn with_tls(mut self, tls: Option<TlsInfo>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Parameters of the TLS handshake, `None` if the connection isn't encrypted or the back-end terminated TLS elsewhere.
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
+
+    /// Attaches the trace context extracted from the upgrade request, so it can be later retrieved from the Session.
+    pub fn with_trace_context(mut self, trace_context: Option<TraceContext>) -> Self {
+        self.trace_context = trace_context;
+        self
+    }
+
+    /// Trace context of the upgrade request, if it carried a valid `traceparent` header.
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.trace_context.as_ref()
+    }
+
+    /// File descriptor of the underlying TCP connection, if known, see [`handoff`](crate::handoff).
+    ///
+    /// Only set by the tungstenite back-end for connections without TLS, whose state can't be handed off.
+    #[cfg(all(feature = "handoff", target_os = "linux"))]
+    pub fn raw_fd(&self) -> Option<std::os::fd::RawFd> {
+        self.raw_fd
+    }
+
+    /// See [`Sink::stats`].
+    pub fn stats(&self) -> SocketStats {
+        self.sink.stats()
+    }
+
+    /// See [`Sink::latency`].
+    pub fn latency(&self) -> Option<Duration> {
+        self.sink.latency()
+    }
+
+    /// See [`Sink::latency_changes`].
+    pub fn latency_changes(&self) -> watch::Receiver<Option<Duration>> {
+        self.sink.latency_changes()
+    }
+
+    pub async fn send(&self, message: Message) -> Result<(), SendError<Message>> {
+        self.sink.send(message).await
+    }
+
+    pub async fn send_raw(&self, message: RawMessage) -> Result<(), SendError<RawMessage>> {
+        self.sink.send_raw(message).await
+    }
+
+    /// See [`Sink::try_send`].
+    pub fn try_send(&self, message: Message) -> Result<(), TrySendError<Message>> {
+        self.sink.try_send(message)
+    }
+
+    /// See [`Sink::send_timeout`].
+    pub async fn send_timeout(
+        &self,
+        message: Message,
+        timeout: Duration,
+    ) -> Result<(), SendTimeoutError<Message>> {
+        self.sink.send_timeout(message, timeout).await
+    }
+
+    /// See [`Sink::send_fragment`].
+    pub fn send_fragment(&self, fragment: Fragment) -> Result<(), SendError<Fragment>> {
+        self.sink.send_fragment(fragment)
+    }
+
+    /// See [`Sink::transfer`].
+    pub fn transfer(&self, kind: DataKind) -> Transfer {
+        self.sink.transfer(kind)
     }
 
     pub async fn recv(&mut self) -> Option<Result<Message, Error>> {
         self.stream.recv().await
     }
+
+    pub async fn recv_with_meta(&mut self) -> Option<Result<(Message, MessageMeta), Error>> {
+        self.stream.recv_with_meta().await
+    }
+
+    /// Waits until all previously sent messages have been written to the underlying sink.
+    pub async fn sync(&self) {
+        self.sink.sync().await;
+    }
+
+    /// See [`Sink::close_graceful`].
+    pub async fn close_graceful(&self, frame: Option<CloseFrame>) {
+        self.sink.close_graceful(frame).await;
+    }
 }
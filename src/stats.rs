@@ -0,0 +1,284 @@
+// recorders are only used by the client and server actors
+#![cfg_attr(not(any(feature = "client", feature = "server")), allow(dead_code))]
+
+use crate::Fragment;
+#[cfg(feature = "server")]
+use crate::Message;
+use crate::RawMessage;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Upper bounds of histogram buckets, the last bucket holds everything above the last bound.
+const BUCKETS: [Duration; 7] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+/// Snapshot of a duration histogram.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Number of recorded durations.
+    pub count: u64,
+    /// Sum of all recorded durations.
+    pub sum: Duration,
+    /// Pairs of bucket upper bound and number of durations in that bucket, `None` is the bucket above the last bound.
+    pub buckets: Vec<(Option<Duration>, u64)>,
+}
+
+impl Histogram {
+    /// Adds durations recorded by `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        self.count += other.count;
+        self.sum += other.sum;
+        if self.buckets.is_empty() {
+            self.buckets = other.buckets.clone();
+            return;
+        }
+        for ((_, count), (_, other)) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *count += other;
+        }
+    }
+
+    /// Mean of recorded durations, `None` if nothing was recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            (self.sum.as_nanos() / u128::from(self.count)) as u64,
+        ))
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HistogramRecorder {
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+}
+
+impl HistogramRecorder {
+    pub(crate) fn record(&self, duration: Duration) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| duration <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Histogram {
+        let bounds = BUCKETS.iter().copied().map(Some).chain([None]);
+        Histogram {
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+            buckets: bounds
+                .zip(self.buckets.iter())
+                .map(|(bound, count)| (bound, count.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+}
+
+/// Time spent inside each of the handler callbacks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandlerTimings {
+    pub text: Histogram,
+    pub binary: Histogram,
+    pub call: Histogram,
+}
+
+impl HandlerTimings {
+    /// Adds timings recorded by `other` to these timings.
+    pub fn merge(&mut self, other: &HandlerTimings) {
+        self.text.merge(&other.text);
+        self.binary.merge(&other.binary);
+        self.call.merge(&other.call);
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HandlerTimingsRecorder {
+    pub(crate) text: HistogramRecorder,
+    pub(crate) binary: HistogramRecorder,
+    pub(crate) call: HistogramRecorder,
+}
+
+impl HandlerTimingsRecorder {
+    pub(crate) fn snapshot(&self) -> HandlerTimings {
+        HandlerTimings {
+            text: self.text.snapshot(),
+            binary: self.binary.snapshot(),
+            call: self.call.snapshot(),
+        }
+    }
+}
+
+/// Approximate resource usage of a session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionUsage {
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    /// Outbound messages queued, but not yet written to the connection.
+    pub buffered: usize,
+    /// Cumulative time spent inside the handler callbacks.
+    pub handler_time: Duration,
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, Default)]
+pub(crate) struct UsageRecorder {
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+#[cfg(feature = "server")]
+impl UsageRecorder {
+    pub(crate) fn received(&self, message: &Message) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(payload_len(message), Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, message: &Message) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent
+            .fetch_add(payload_len(message), Ordering::Relaxed);
+    }
+
+    /// Accounts the fragment's payload, and a sent message once the last fragment is sent.
+    pub(crate) fn sent_fragment(&self, fragment: &Fragment) {
+        let bytes = match fragment {
+            Fragment::First(_, bytes) | Fragment::Continuation(bytes) => bytes,
+            Fragment::Final(bytes) => {
+                self.messages_sent.fetch_add(1, Ordering::Relaxed);
+                bytes
+            }
+        };
+        self.bytes_sent
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, buffered: usize, timings: &HandlerTimings) -> SessionUsage {
+        SessionUsage {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            buffered,
+            handler_time: timings.text.sum + timings.binary.sum + timings.call.sum,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+pub(crate) fn payload_len(message: &Message) -> u64 {
+    match message {
+        Message::Text(text) => text.len() as u64,
+        Message::Binary(bytes) => bytes.len() as u64,
+        Message::Ping(_) | Message::Pong(_) | Message::Close(_) => 0,
+    }
+}
+
+/// Traffic of a socket, see [`Socket::stats`](crate::Socket::stats).
+///
+/// Only Text and Binary messages are accounted, control frames aren't.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketStats {
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    /// When the last message was received from or written to the connection, `None` if there was none yet.
+    pub last_activity: Option<Instant>,
+}
+
+/// Counters maintained by the socket actors.
+#[derive(Debug)]
+pub(crate) struct SocketStatsRecorder {
+    created_at: Instant,
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Microseconds between `created_at` and the last activity, plus one, zero if there was none yet.
+    last_activity: AtomicU64,
+}
+
+impl Default for SocketStatsRecorder {
+    fn default() -> Self {
+        Self {
+            created_at: Instant::now(),
+            messages_received: Default::default(),
+            bytes_received: Default::default(),
+            messages_sent: Default::default(),
+            bytes_sent: Default::default(),
+            last_activity: Default::default(),
+        }
+    }
+}
+
+impl SocketStatsRecorder {
+    pub(crate) fn received(&self, message: &RawMessage) {
+        if let Some((bytes, complete)) = data_len(message) {
+            self.messages_received
+                .fetch_add(complete as u64, Ordering::Relaxed);
+            self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+            self.touch();
+        }
+    }
+
+    pub(crate) fn sent(&self, message: &RawMessage) {
+        if let Some((bytes, complete)) = data_len(message) {
+            self.messages_sent
+                .fetch_add(complete as u64, Ordering::Relaxed);
+            self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+            self.touch();
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.created_at.elapsed().as_micros() as u64;
+        self.last_activity.store(elapsed + 1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> SocketStats {
+        let last_activity = match self.last_activity.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(self.created_at + Duration::from_micros(micros - 1)),
+        };
+        SocketStats {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            last_activity,
+        }
+    }
+}
+
+/// Payload length of a Text or Binary frame, and whether it completes a message, `None` for control frames.
+fn data_len(message: &RawMessage) -> Option<(u64, bool)> {
+    match message {
+        RawMessage::Text(text) => Some((text.len() as u64, true)),
+        RawMessage::Binary(bytes) => Some((bytes.len() as u64, true)),
+        RawMessage::Fragment(Fragment::First(_, bytes) | Fragment::Continuation(bytes)) => {
+            Some((bytes.len() as u64, false))
+        }
+        RawMessage::Fragment(Fragment::Final(bytes)) => Some((bytes.len() as u64, true)),
+        RawMessage::Ping(_) | RawMessage::Pong(_) | RawMessage::Close(_) => None,
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+const WINDOW: Duration = Duration::from_secs(1);
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_QUEUED: usize = 128;
+
+/// Limits on the rate of new connections, see [`Server::admit`](crate::Server::admit).
+#[derive(Debug, Clone)]
+pub struct AcceptLimit {
+    /// Maximum number of connections accepted per second, over all peers.
+    pub per_second: Option<u32>,
+    /// Maximum number of connections accepted per second from a single IP address.
+    pub per_ip_per_second: Option<u32>,
+    /// Whether connections over the limit wait for the next second instead of being rejected immediately.
+    pub queue: bool,
+    /// Maximum number of connections waiting with [`AcceptLimit::queue`], further connections over the limit are rejected.
+    /// Defaults to 128.
+    pub max_queued: usize,
+    /// Maximum number of WebSocket handshakes in progress at once, see [`Server::handshake_permit`](crate::Server::handshake_permit).
+    ///
+    /// Connections arriving while the back-end is saturated are rejected with `503 Service Unavailable`
+    /// rather than waiting in the listen backlog for a handshake slot.
+    pub max_handshakes: Option<usize>,
+    /// Value of the `Retry-After` header of `503 Service Unavailable` responses to rejected connections,
+    /// rounded up to whole seconds. The header is omitted if not set.
+    pub retry_after: Option<Duration>,
+    /// Time given to a connection to complete its TLS and WebSocket handshakes with the tungstenite back-end,
+    /// after which it's closed and its handshake slot released. Defaults to 10 seconds.
+    pub handshake_timeout: Duration,
+}
+
+impl Default for AcceptLimit {
+    fn default() -> Self {
+        Self {
+            per_second: None,
+            per_ip_per_second: None,
+            queue: false,
+            max_queued: DEFAULT_MAX_QUEUED,
+            max_handshakes: None,
+            retry_after: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+impl AcceptLimit {
+    /// Value of the `Retry-After` header, see [`AcceptLimit::retry_after`].
+    pub fn retry_after_header(&self) -> Option<String> {
+        self.retry_after.map(|retry_after| {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            seconds.to_string()
+        })
+    }
+}
+
+/// Slot of a WebSocket handshake in progress, released when dropped, see [`Server::handshake_permit`](crate::Server::handshake_permit).
+#[derive(Debug)]
+pub struct HandshakePermit {
+    pending: Arc<AtomicUsize>,
+}
+
+impl HandshakePermit {
+    /// Takes a slot, unless `max` handshakes are already in progress.
+    pub(crate) fn acquire(pending: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Self> {
+        pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| match max {
+                Some(max) if count >= max => None,
+                _ => Some(count + 1),
+            })
+            .ok()?;
+        Some(Self {
+            pending: pending.clone(),
+        })
+    }
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AcceptLimiter {
+    limit: AcceptLimit,
+    window_start: Instant,
+    total: u32,
+    per_ip: HashMap<IpAddr, u32>,
+}
+
+impl AcceptLimiter {
+    pub(crate) fn new(limit: AcceptLimit) -> Self {
+        Self {
+            limit,
+            window_start: Instant::now(),
+            total: 0,
+            per_ip: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn queue(&self) -> bool {
+        self.limit.queue
+    }
+
+    /// Counts a connection from `ip` if it fits in the current window,
+    /// otherwise returns how long until the next window starts.
+    pub(crate) fn check(&mut self, ip: IpAddr) -> Result<(), Duration> {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= WINDOW {
+            self.window_start = Instant::now();
+            self.total = 0;
+            self.per_ip.clear();
+        }
+        let from_ip = self.per_ip.get(&ip).copied().unwrap_or(0);
+        let exceeded = self
+            .limit
+            .per_second
+            .is_some_and(|limit| self.total >= limit)
+            || self
+                .limit
+                .per_ip_per_second
+                .is_some_and(|limit| from_ip >= limit);
+        if exceeded {
+            return Err(WINDOW.saturating_sub(self.window_start.elapsed()));
+        }
+        self.total += 1;
+        self.per_ip.insert(ip, from_ip + 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+/// Returns a random duration between zero and `max`.
+pub(crate) fn random_duration(max: Duration) -> Duration {
+    use std::hash::BuildHasher;
+    use std::hash::Hasher;
+
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+/// Waits for the next tick of the interval, or forever if there's no interval.
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
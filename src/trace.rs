@@ -0,0 +1,71 @@
+/// Name of the W3C Trace Context header.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// W3C Trace Context, as carried by the `traceparent` header of the upgrade request.
+///
+/// Attached to the spans of the session accepted from that request, so that its events
+/// can be correlated with the rest of a distributed trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 lowercase hex digits identifying the whole trace.
+    pub trace_id: String,
+    /// 16 lowercase hex digits identifying the caller's span.
+    pub parent_id: String,
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Parses a `traceparent` header value, returns `None` if it's malformed.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+        // Later versions may append fields, version 00 must not.
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let is_hex = |part: &str, len: usize| {
+            part.len() == len
+                && part
+                    .bytes()
+                    .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        let is_zero = |part: &str| part.bytes().all(|byte| byte == b'0');
+        if !is_hex(version, 2) || version == "ff" {
+            return None;
+        }
+        if !is_hex(trace_id, 32)
+            || is_zero(trace_id)
+            || !is_hex(parent_id, 16)
+            || is_zero(parent_id)
+        {
+            return None;
+        }
+        if !is_hex(flags, 2) {
+            return None;
+        }
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        })
+    }
+
+    /// Whether the caller recorded its span, and suggests recording ours as well.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+}
+
+/// Formats the context as a `traceparent` header value.
+impl std::fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
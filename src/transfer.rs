@@ -0,0 +1,145 @@
+use crate::socket::SendError;
+use crate::DataKind;
+use crate::Fragment;
+use crate::Sink;
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+#[cfg(feature = "server")]
+use crate::session::Outgoing;
+#[cfg(feature = "server")]
+use tokio::sync::mpsc;
+
+/// Where the fragments of a [`Transfer`] are sent.
+#[derive(Debug)]
+pub(crate) enum Target {
+    Sink(Sink),
+    /// Through the session actor, so the fragments are written after the messages sent before them.
+    #[cfg(feature = "server")]
+    Session(mpsc::UnboundedSender<Outgoing>),
+}
+
+impl Target {
+    fn send(
+        &self,
+        fragment: Fragment,
+        written: oneshot::Sender<()>,
+    ) -> Result<(), SendError<Fragment>> {
+        match self {
+            Self::Sink(sink) => sink.send_fragment_with(fragment, Some(written)),
+            #[cfg(feature = "server")]
+            Self::Session(sender) => sender
+                .send(Outgoing::Fragment(fragment, Some(written)))
+                .map_err(|mpsc::error::SendError(outgoing)| match outgoing {
+                    Outgoing::Fragment(fragment, _) => SendError(fragment),
+                    _ => unreachable!("a fragment is returned as it was sent"),
+                }),
+        }
+    }
+}
+
+type ProgressFn = Box<dyn Fn(u64, Option<u64>) + Send + Sync>;
+
+/// Message sent fragment by fragment, e.g. a file read chunk by chunk, see [`Sink::send_fragment`].
+///
+/// Each chunk is sent once the previous one was written, so a fast producer doesn't buffer the whole payload,
+/// and the progress reported to [`Transfer::on_progress`] is the number of bytes actually written.
+///
+/// Dropping a transfer before [`Transfer::finish`] ends the message with the chunks sent so far.
+pub struct Transfer {
+    target: Target,
+    kind: DataKind,
+    started: bool,
+    finished: bool,
+    sent: u64,
+    total: Option<u64>,
+    on_progress: Option<ProgressFn>,
+}
+
+impl std::fmt::Debug for Transfer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transfer")
+            .field("kind", &self.kind)
+            .field("sent", &self.sent)
+            .field("total", &self.total)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Transfer {
+    pub(crate) fn new(target: Target, kind: DataKind) -> Self {
+        Self {
+            target,
+            kind,
+            started: false,
+            finished: false,
+            sent: 0,
+            total: None,
+            on_progress: None,
+        }
+    }
+
+    /// Size of the whole message, passed along to the progress callback.
+    pub fn total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    /// Called with the bytes written so far and the total, if known, after every chunk.
+    pub fn on_progress(
+        mut self,
+        on_progress: impl Fn(u64, Option<u64>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Number of bytes written so far.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Sends the next chunk of the message and waits until it's written.
+    /// Returns the chunk back if the connection is closed.
+    pub async fn send(&mut self, chunk: impl Into<Bytes>) -> Result<(), SendError<Bytes>> {
+        let chunk = chunk.into();
+        let fragment = match self.started {
+            false => Fragment::First(self.kind, chunk.clone()),
+            true => Fragment::Continuation(chunk.clone()),
+        };
+        self.started = true;
+        self.write(fragment, chunk).await
+    }
+
+    /// Sends the last chunk of the message and waits until it's written.
+    pub async fn finish(mut self, chunk: impl Into<Bytes>) -> Result<(), SendError<Bytes>> {
+        let chunk = chunk.into();
+        if !self.started {
+            self.send(Bytes::new()).await?;
+        }
+        self.finished = true;
+        self.write(Fragment::Final(chunk.clone()), chunk).await
+    }
+
+    async fn write(&mut self, fragment: Fragment, chunk: Bytes) -> Result<(), SendError<Bytes>> {
+        let (written, on_written) = oneshot::channel();
+        if self.target.send(fragment, written).is_err() || on_written.await.is_err() {
+            self.finished = true;
+            return Err(SendError(chunk));
+        }
+        self.sent += chunk.len() as u64;
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(self.sent, self.total);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if self.started && !self.finished {
+            let (written, _) = oneshot::channel();
+            let _ = self.target.send(Fragment::Final(Bytes::new()), written);
+        }
+    }
+}
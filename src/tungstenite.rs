@@ -1,9 +1,14 @@
 use crate::socket::RawMessage;
 use crate::CloseCode;
 use crate::CloseFrame;
+use crate::DataKind;
+use crate::Fragment;
 use crate::Message;
 use tokio_tungstenite::tungstenite;
 use tungstenite::protocol::frame::coding::CloseCode as TungsteniteCloseCode;
+use tungstenite::protocol::frame::coding::Data;
+use tungstenite::protocol::frame::coding::OpCode;
+use tungstenite::protocol::frame::Frame;
 
 impl<'t> From<tungstenite::protocol::CloseFrame<'t>> for CloseFrame {
     fn from(frame: tungstenite::protocol::CloseFrame) -> Self {
@@ -39,6 +44,7 @@ impl From<CloseCode> for TungsteniteCloseCode {
             CloseCode::Error => Self::Error,
             CloseCode::Restart => Self::Restart,
             CloseCode::Again => Self::Again,
+            CloseCode::Other(code) => Self::from(code),
         }
     }
 }
@@ -59,7 +65,7 @@ impl From<TungsteniteCloseCode> for CloseCode {
             TungsteniteCloseCode::Error => Self::Error,
             TungsteniteCloseCode::Restart => Self::Restart,
             TungsteniteCloseCode::Again => Self::Again,
-            code => unimplemented!("could not handle close code: {code:?}"),
+            code => Self::Other(code.into()),
         }
     }
 }
@@ -68,19 +74,32 @@ impl From<RawMessage> for tungstenite::Message {
     fn from(message: RawMessage) -> Self {
         match message {
             RawMessage::Text(text) => Self::Text(text),
-            RawMessage::Binary(bytes) => Self::Binary(bytes),
+            RawMessage::Binary(bytes) => Self::Binary(bytes.into()),
             RawMessage::Ping(bytes) => Self::Ping(bytes),
             RawMessage::Pong(bytes) => Self::Pong(bytes),
             RawMessage::Close(frame) => Self::Close(frame.map(CloseFrame::into)),
+            RawMessage::Fragment(fragment) => Self::Frame(fragment.into()),
         }
     }
 }
 
+impl From<Fragment> for Frame {
+    fn from(fragment: Fragment) -> Self {
+        let (data, bytes, is_final) = match fragment {
+            Fragment::First(DataKind::Text, bytes) => (Data::Text, bytes, false),
+            Fragment::First(DataKind::Binary, bytes) => (Data::Binary, bytes, false),
+            Fragment::Continuation(bytes) => (Data::Continue, bytes, false),
+            Fragment::Final(bytes) => (Data::Continue, bytes, true),
+        };
+        Frame::message(bytes.into(), OpCode::Data(data), is_final)
+    }
+}
+
 impl From<tungstenite::Message> for RawMessage {
     fn from(message: tungstenite::Message) -> Self {
         match message {
             tungstenite::Message::Text(text) => Self::Text(text),
-            tungstenite::Message::Binary(bytes) => Self::Binary(bytes),
+            tungstenite::Message::Binary(bytes) => Self::Binary(bytes.into()),
             tungstenite::Message::Ping(bytes) => Self::Ping(bytes),
             tungstenite::Message::Pong(bytes) => Self::Pong(bytes),
             tungstenite::Message::Close(frame) => Self::Close(frame.map(CloseFrame::from)),
@@ -92,7 +111,9 @@ impl From<Message> for tungstenite::Message {
     fn from(message: Message) -> Self {
         match message {
             Message::Text(text) => tungstenite::Message::Text(text),
-            Message::Binary(bytes) => tungstenite::Message::Binary(bytes),
+            Message::Binary(bytes) => tungstenite::Message::Binary(bytes.into()),
+            Message::Ping(bytes) => tungstenite::Message::Ping(bytes.to_vec()),
+            Message::Pong(bytes) => tungstenite::Message::Pong(bytes.to_vec()),
             Message::Close(frame) => tungstenite::Message::Close(frame.map(CloseFrame::into)),
         }
     }
@@ -101,52 +122,349 @@ impl From<Message> for tungstenite::Message {
 cfg_if::cfg_if! {
     if #[cfg(feature = "server")] {
         use crate::Server;
+        use crate::ServerConfig;
         use crate::Error;
         use crate::Socket;
-        use crate::socket;
+        use crate::SocketConfig;
         use crate::ServerExt;
         use crate::SessionExt;
+        use crate::TlsInfo;
 
+        use crate::TraceContext;
+        use crate::TRACEPARENT;
+
+        use tokio::io::AsyncRead;
+        use tokio::io::AsyncWrite;
+        use tokio::io::AsyncWriteExt;
+        use crate::HandshakePermit;
+        use std::net::SocketAddr;
+        use std::sync::Arc;
+        use std::time::Duration;
         use tokio::net::TcpListener;
+        use tokio::net::TcpStream;
         use tokio::net::ToSocketAddrs;
+        use tokio_tungstenite::WebSocketStream;
+        use tungstenite::handshake::server::Request;
+        use tungstenite::handshake::server::Response;
         use futures::Future;
 
+        /// Performs the WebSocket handshake, extracting the trace context from the `traceparent` header of the upgrade request.
+        // The callback signature, including its error response, is imposed by tungstenite.
+        #[allow(clippy::result_large_err)]
+        async fn accept_with_trace_context<S>(stream: S, config: &SocketConfig) -> Result<(WebSocketStream<S>, Option<TraceContext>), Error>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            let mut trace_context = None;
+            let callback = |request: &Request, response: Response| {
+                trace_context = request
+                    .headers()
+                    .get(TRACEPARENT)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(TraceContext::parse);
+                Ok(response)
+            };
+            let socket = tokio_tungstenite::accept_hdr_async_with_config(stream, callback, config.websocket_config()).await?;
+            Ok((socket, trace_context))
+        }
+
+        /// Checks the connection against the accept limit, and takes a handshake slot for it.
+        async fn admit<E: ServerExt>(server: &Server<E>, address: SocketAddr) -> Option<HandshakePermit> {
+            if !server.admit(address).await {
+                return None;
+            }
+            server.handshake_permit(address)
+        }
+
+        /// Responds to a rejected connection with `503 Service Unavailable`, without reading its request.
+        ///
+        /// The connection is closed without a response if the peer doesn't accept it within a second.
+        fn reject<E: ServerExt>(server: &Server<E>, mut stream: TcpStream) {
+            let mut response = String::from("HTTP/1.1 503 Service Unavailable\r\n");
+            if let Some(retry_after) = server.config().accept_limit.retry_after_header() {
+                response.push_str(&format!("Retry-After: {retry_after}\r\n"));
+            }
+            response.push_str("Content-Length: 0\r\nConnection: close\r\n\r\n");
+            crate::runtime::spawn(
+                "ezsockets::reject",
+                server.config().runtime.as_ref(),
+                async move {
+                    let write = stream.write_all(response.as_bytes());
+                    let _ = tokio::time::timeout(Duration::from_secs(1), write).await;
+                },
+            );
+        }
+
+        /// Performs the handshakes with an admitted connection, then hands the socket over to the server.
+        ///
+        /// `stream` resolves once the transport is established, e.g. after the TLS handshake, with its TLS parameters. Its slot is released once
+        /// the handshakes complete, fail, or exceed [`AcceptLimit::handshake_timeout`](crate::AcceptLimit::handshake_timeout).
+        /// Failures only affect this connection, they're logged rather than stopping the accept loop.
+        async fn handshake<E, S, GetArgsFut>(
+            server: Server<E>,
+            stream: impl Future<Output = std::io::Result<(S, Option<TlsInfo>)>>,
+            local_address: Option<SocketAddr>,
+            address: SocketAddr,
+            get_args: Arc<impl Fn(&mut Socket) -> GetArgsFut>,
+            permit: HandshakePermit,
+        )
+        where
+            E: ServerExt + 'static,
+            S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+            GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>>
+        {
+            let timeout = server.config().accept_limit.handshake_timeout;
+            let upgraded = tokio::time::timeout(timeout, upgrade(&server, stream, local_address, address, get_args)).await;
+            let (socket, args) = match upgraded {
+                Ok(Some(upgraded)) => upgraded,
+                Ok(None) => return,
+                Err(_) => {
+                    tracing::warn!("handshake with {address} timed out");
+                    return;
+                }
+            };
+            drop(permit);
+            if let Err(err) = server.accept(socket, address, args).await {
+                tracing::warn!("could not hand the connection from {address} over to the server: {err}");
+            }
+        }
+
+        /// Upgrades the connection to a WebSocket and gets the arguments of its session, returns `None` if either failed.
+        async fn upgrade<E, S, GetArgsFut>(
+            server: &Server<E>,
+            stream: impl Future<Output = std::io::Result<(S, Option<TlsInfo>)>>,
+            local_address: Option<SocketAddr>,
+            address: SocketAddr,
+            get_args: Arc<impl Fn(&mut Socket) -> GetArgsFut>,
+        ) -> Option<(Socket, <E::Session as SessionExt>::Args)>
+        where
+            E: ServerExt + 'static,
+            S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+            GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>>
+        {
+            let (stream, tls) = match stream.await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::warn!("TLS handshake with {address} failed: {err}");
+                    return None;
+                }
+            };
+            let (socket, trace_context) = match accept_with_trace_context(stream, &server.config().socket).await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!("WebSocket handshake with {address} failed: {err}");
+                    return None;
+                }
+            };
+            let mut socket = Socket::new(socket, server.config().socket.clone())
+                .with_addresses(local_address, Some(address))
+                .with_tls(tls)
+                .with_trace_context(trace_context);
+            match get_args(&mut socket).await {
+                Ok(args) => Some((socket, args)),
+                Err(err) => {
+                    tracing::warn!("could not get session arguments for {address}: {err}");
+                    None
+                }
+            }
+        }
+
         pub async fn run<E, A, GetArgsFut>(
             server: Server<E>,
             address: A,
-            get_args: impl Fn(&mut Socket) -> GetArgsFut
+            get_args: impl Fn(&mut Socket) -> GetArgsFut + Send + Sync + 'static
         ) -> Result<(), Error>
         where
             E: ServerExt + 'static,
             A: ToSocketAddrs,
-            GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>>
+            GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>> + Send + 'static,
         {
             let listener = TcpListener::bind(address).await?;
+            run_on(server, listener, get_args).await
+        }
+
+        /// Accepts connections from `listener`, performing their handshakes concurrently.
+        ///
+        /// Connections over [`ServerConfig::accept_limit`](crate::ServerConfig::accept_limit) are rejected
+        /// with `503 Service Unavailable`.
+        pub async fn run_on<E, GetArgsFut>(
+            server: Server<E>,
+            listener: TcpListener,
+            get_args: impl Fn(&mut Socket) -> GetArgsFut + Send + Sync + 'static
+        ) -> Result<(), Error>
+        where
+            E: ServerExt + 'static,
+            GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>> + Send + 'static,
+        {
+            let get_args = Arc::new(get_args);
             loop {
                 let (socket, address) = listener.accept().await?;
-                let socket = tokio_tungstenite::accept_async(socket).await?;
-                let mut socket = Socket::new(socket, socket::Config::default());
-                let args = get_args(&mut socket).await?;
-                server.accept(socket, address, args).await;
+                let local_address = socket.local_addr().ok();
+                #[cfg(all(feature = "handoff", target_os = "linux"))]
+                let get_args = {
+                    use std::os::fd::AsRawFd;
+                    let raw_fd = socket.as_raw_fd();
+                    let get_args = get_args.clone();
+                    Arc::new(move |socket: &mut Socket| {
+                        socket.raw_fd = Some(raw_fd);
+                        get_args(socket)
+                    })
+                };
+                let runtime = server.config().runtime.clone();
+                let server = server.clone();
+                let get_args = get_args.clone();
+                // Admitted in the task of the connection, so that connections waiting in the accept queue don't hold back the others.
+                crate::runtime::spawn(
+                    "ezsockets::handshake",
+                    runtime.as_ref(),
+                    async move {
+                        let permit = match admit(&server, address).await {
+                            Some(permit) => permit,
+                            None => return reject(&server, socket),
+                        };
+                        handshake(server, std::future::ready(Ok((socket, None))), local_address, address, get_args, permit).await;
+                    },
+                );
             }
         }
 
-        pub async fn run_on<E, GetArgsFut>(
+        /// Servers sharing the application state `S`, each with its own [`ServerExt`] and listeners, e.g. a gateway
+        /// exposing a public and an admin endpoint handled differently.
+        ///
+        /// Every server of the group is created with the same [`ServerExt::State`], which holds what the endpoints share,
+        /// e.g. the users, rooms, metrics or broker connections of the application. The sessions and rooms managed
+        /// by a [`Server`] are its own, and shared by its listeners when it listens on several ports.
+        pub struct ServerGroup<S> {
+            state: Arc<S>,
+            tasks: Vec<futures::future::BoxFuture<'static, Result<(), Error>>>,
+        }
+
+        impl<S: Send + Sync + 'static> ServerGroup<S> {
+            pub fn new(state: Arc<S>) -> Self {
+                Self {
+                    state,
+                    tasks: Vec::new(),
+                }
+            }
+
+            /// State shared by the servers of the group.
+            pub fn state(&self) -> &Arc<S> {
+                &self.state
+            }
+
+            /// Creates a server of the group with the shared state, see [`Server::create_with_state`].
+            pub fn server<E>(&mut self, create: impl FnOnce(Server<E>) -> E + 'static, config: ServerConfig) -> Server<E>
+            where
+                E: ServerExt<State = S> + 'static,
+            {
+                let (server, future) = Server::create_with_state(create, config, self.state.clone());
+                self.tasks.push(Box::pin(future));
+                server
+            }
+
+            /// Accepts connections to `server` from `listener`, with the session arguments built by `get_args`, see [`run_on`].
+            pub fn listen<E, GetArgsFut>(
+                &mut self,
+                server: &Server<E>,
+                listener: TcpListener,
+                get_args: impl Fn(&mut Socket) -> GetArgsFut + Send + Sync + 'static
+            )
+            where
+                E: ServerExt<State = S> + 'static,
+                GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>> + Send + 'static,
+            {
+                self.tasks.push(Box::pin(run_on(server.clone(), listener, get_args)));
+            }
+
+            /// Runs every server and listener of the group, until one of them fails.
+            pub async fn run(self) -> Result<(), Error> {
+                futures::future::try_join_all(self.tasks).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "server", feature = "rustls"))] {
+        use tokio::sync::watch;
+        use tokio_rustls::rustls;
+        use tokio_rustls::TlsAcceptor;
+
+        /// Same as [`run_on`], but terminates TLS using the most recent configuration received on `tls_config`.
+        ///
+        /// Sending a new `ServerConfig` through the channel applies it to subsequent handshakes only,
+        /// which allows rotating certificates without restarting the server or dropping existing connections.
+        ///
+        /// Connections over the accept limit are closed before the TLS handshake, as answering them
+        /// with `503 Service Unavailable` would require completing it first.
+        pub async fn run_on_tls<E, GetArgsFut>(
             server: Server<E>,
             listener: TcpListener,
-            get_args: impl Fn(&mut Socket) -> GetArgsFut
+            tls_config: watch::Receiver<Arc<rustls::ServerConfig>>,
+            get_args: impl Fn(&mut Socket) -> GetArgsFut + Send + Sync + 'static
         ) -> Result<(), Error>
         where
             E: ServerExt + 'static,
-            GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>>
+            GetArgsFut: Future<Output = Result<<E::Session as SessionExt>::Args, Error>> + Send + 'static,
         {
+            let get_args = Arc::new(get_args);
             loop {
                 let (socket, address) = listener.accept().await?;
-                let socket = tokio_tungstenite::accept_async(socket).await?;
-                let mut socket = Socket::new(socket, socket::Config::default());
-                let args = get_args(&mut socket).await?;
-                server.accept(socket, address, args).await;
+                let local_address = socket.local_addr().ok();
+                let acceptor = TlsAcceptor::from(tls_config.borrow().clone());
+                let runtime = server.config().runtime.clone();
+                let server = server.clone();
+                let get_args = get_args.clone();
+                crate::runtime::spawn(
+                    "ezsockets::handshake",
+                    runtime.as_ref(),
+                    async move {
+                        let permit = match admit(&server, address).await {
+                            Some(permit) => permit,
+                            None => return,
+                        };
+                        let stream = async move {
+                            let stream = acceptor.accept(socket).await?;
+                            let (_, connection) = stream.get_ref();
+                            let tls = TlsInfo {
+                                server_name: connection.sni_hostname().map(String::from),
+                                alpn_protocol: connection.alpn_protocol().map(Vec::from),
+                            };
+                            Ok((stream, Some(tls)))
+                        };
+                        handshake(server, stream, local_address, address, get_args, permit).await;
+                    },
+                );
             }
         }
     }
 }
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "server", feature = "handoff", target_os = "linux"))] {
+        use tungstenite::protocol::Role;
+
+        /// Resumes a WebSocket connection handed off by another process, see [`handoff`](crate::handoff),
+        /// handing it over to the server as a new session created with `args`, without another handshake.
+        pub async fn resume<E>(
+            server: &Server<E>,
+            stream: TcpStream,
+            args: <E::Session as SessionExt>::Args,
+        ) -> Result<<E::Session as SessionExt>::ID, Error>
+        where
+            E: ServerExt + 'static,
+        {
+            use std::os::fd::AsRawFd;
+
+            let address = stream.peer_addr()?;
+            let local_address = stream.local_addr().ok();
+            let raw_fd = stream.as_raw_fd();
+            let config = &server.config().socket;
+            let stream = WebSocketStream::from_raw_socket(stream, Role::Server, config.websocket_config()).await;
+            let mut socket = Socket::new(stream, config.clone()).with_addresses(local_address, Some(address));
+            socket.raw_fd = Some(raw_fd);
+            server.accept(socket, address, args).await
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::socket::Heartbeat;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::time::MissedTickBehavior;
+
+/// Hashed timer wheel driving the heartbeats of many sockets from a single task, see [`SocketConfig::heartbeat_wheel`](crate::SocketConfig::heartbeat_wheel).
+///
+/// With hundreds of thousands of connections, a timer per socket gets costly even while they're idle.
+/// The wheel instead ticks every `resolution`, only visiting the sockets whose heartbeat is due in the current slot,
+/// so heartbeats are rounded up to the resolution. Sharding connections, e.g. one server per core, works best with a wheel per shard.
+///
+/// The wheel stops ticking while no socket uses it.
+#[derive(Clone)]
+pub struct TimerWheel {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for TimerWheel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimerWheel")
+            .field("resolution", &self.shared.resolution)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Shared {
+    resolution: Duration,
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Number of ticks until `delay` elapsed, at least one.
+    fn ticks(&self, delay: Duration) -> usize {
+        let ticks = delay.as_nanos().div_ceil(self.resolution.as_nanos());
+        usize::try_from(ticks).unwrap_or(usize::MAX).max(1)
+    }
+}
+
+struct State {
+    slots: Vec<Vec<Entry>>,
+    /// Slot visited on the next tick.
+    cursor: usize,
+    len: usize,
+    /// Whether the task ticking the wheel is running.
+    running: bool,
+}
+
+impl State {
+    fn insert(&mut self, ticks: usize, mut entry: Entry) {
+        let slots = self.slots.len();
+        entry.rounds = (ticks - 1) / slots;
+        let slot = (self.cursor + ticks - 1) % slots;
+        self.slots[slot].push(entry);
+    }
+}
+
+struct Entry {
+    /// Number of full turns of the wheel left before the entry is due.
+    rounds: usize,
+    heartbeat: Heartbeat,
+    stopped: Arc<AtomicBool>,
+}
+
+/// Heartbeat of a socket registered on a [`TimerWheel`], removed from the wheel once dropped.
+pub(crate) struct Registration {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Default for TimerWheel {
+    /// Wheel ticking every 100 milliseconds, with 512 slots.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(100), 512)
+    }
+}
+
+impl TimerWheel {
+    /// Wheel ticking every `resolution`, with `slots` slots. Timers further than `resolution * slots`
+    /// away stay in their slot for several turns of the wheel.
+    pub fn new(resolution: Duration, slots: usize) -> Self {
+        let resolution = resolution.max(Duration::from_millis(1));
+        Self {
+            shared: Arc::new(Shared {
+                resolution,
+                state: Mutex::new(State {
+                    slots: (0..slots.max(1)).map(|_| Vec::new()).collect(),
+                    cursor: 0,
+                    len: 0,
+                    running: false,
+                }),
+            }),
+        }
+    }
+
+    /// Sends the first beat right away, then schedules the next ones.
+    pub(crate) fn register(&self, heartbeat: Heartbeat, runtime: Option<&Handle>) -> Registration {
+        let stopped = Arc::new(AtomicBool::new(false));
+        if heartbeat.beat() {
+            let ticks = self.shared.ticks(heartbeat.period);
+            let mut state = self.shared.lock();
+            state.insert(
+                ticks,
+                Entry {
+                    rounds: 0,
+                    heartbeat,
+                    stopped: stopped.clone(),
+                },
+            );
+            state.len += 1;
+            if !state.running {
+                state.running = true;
+                crate::runtime::spawn("ezsockets::wheel", runtime, run(self.shared.clone()));
+            }
+        }
+        Registration { stopped }
+    }
+}
+
+async fn run(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(shared.resolution);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let mut state = shared.lock();
+        let slot = state.cursor;
+        state.cursor = (slot + 1) % state.slots.len();
+        for mut entry in std::mem::take(&mut state.slots[slot]) {
+            if entry.stopped.load(Ordering::Relaxed) {
+                state.len -= 1;
+            } else if entry.rounds > 0 {
+                entry.rounds -= 1;
+                state.slots[slot].push(entry);
+            } else if entry.heartbeat.beat() {
+                let ticks = shared.ticks(entry.heartbeat.period);
+                state.insert(ticks, entry);
+            } else {
+                state.len -= 1;
+            }
+        }
+        if state.len == 0 {
+            state.running = false;
+            return;
+        }
+    }
+}
@@ -24,13 +24,14 @@ where
     E: ServerExt + 'static,
     <E::Session as SessionExt>::Args: Default,
 {
-    ezsocket.on_upgrade(server, Default::default())
+    ezsocket.on_upgrade(server, Default::default()).await
 }
 
 async fn run<E>(create_fn: impl FnOnce(Server<E>) -> E) -> (Server<E>, SocketAddr)
 where
     E: ServerExt + 'static,
     <E::Session as SessionExt>::Args: Default,
+    E::State: Default,
 {
     let (server, _) = Server::create(create_fn);
     let app = Router::new()
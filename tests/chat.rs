@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use ezsockets::Bytes;
 use ezsockets::Error;
 use ezsockets::Server;
 use ezsockets::Socket;
@@ -45,6 +46,7 @@ impl ChatServer {
 impl ezsockets::ServerExt for ChatServer {
     type Params = Message;
     type Session = SessionActor;
+    type State = ();
 
     async fn accept(
         &mut self,
@@ -103,7 +105,7 @@ impl ezsockets::ServerExt for ChatServer {
                         .join(",")
                 );
                 for session in sessions {
-                    session.text(text.clone());
+                    let _ = session.text(text.clone());
                 }
             }
             Message::Join {
@@ -134,7 +136,7 @@ impl ezsockets::ServerExt for ChatServer {
 
                 respond_to.send(()).unwrap();
                 for session in sessions {
-                    session.text(format!("User with ID: {id} just joined {room} room"));
+                    let _ = session.text(format!("User with ID: {id} just joined {room} room"));
                 }
             }
         };
@@ -173,7 +175,8 @@ impl ezsockets::SessionExt for SessionActor {
                         room,
                         respond_to,
                     })
-                    .await;
+                    .await
+                    .unwrap();
             } else {
                 tracing::error!("unrecognized command: {text}");
             }
@@ -187,7 +190,7 @@ impl ezsockets::SessionExt for SessionActor {
         Ok(())
     }
 
-    async fn binary(&mut self, _bytes: Vec<u8>) -> Result<(), Error> {
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
         unimplemented!()
     }
 
@@ -232,14 +235,14 @@ impl ezsockets::ClientExt for ChatClient {
         Ok(())
     }
 
-    async fn binary(&mut self, bytes: Vec<u8>) -> Result<(), ezsockets::Error> {
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), ezsockets::Error> {
         tracing::info!("received bytes: {bytes:?}");
         Ok(())
     }
 
     async fn call(&mut self, params: Self::Params) -> Result<(), ezsockets::Error> {
         match params {
-            ChatClientMessage::Send(message) => self.handle.text(message),
+            ChatClientMessage::Send(message) => self.handle.text(message)?,
             ChatClientMessage::Subscribe(respond_to) => {
                 respond_to.send(self.messages.subscribe()).unwrap()
             }
@@ -249,18 +252,18 @@ impl ezsockets::ClientExt for ChatClient {
 }
 
 pub async fn test(alice: Client<ChatClient>, bob: Client<ChatClient>) {
-    let mut bob_messages = bob.call_with(ChatClientMessage::Subscribe).await;
-    let mut alice_messages = alice.call_with(ChatClientMessage::Subscribe).await;
+    let mut bob_messages = bob.call_with(ChatClientMessage::Subscribe).await.unwrap();
+    let mut alice_messages = alice.call_with(ChatClientMessage::Subscribe).await.unwrap();
     alice.call(ChatClientMessage::Send("Hi Bob!".to_string()));
     alice.call(ChatClientMessage::Send("Cya Bob!".to_string()));
     assert_eq!(bob_messages.recv().await.unwrap(), "Hi Bob!".to_string());
     assert_eq!(bob_messages.recv().await.unwrap(), "Cya Bob!".to_string());
-    alice.call(ChatClientMessage::Send(format!("/join abc")));
+    alice.call(ChatClientMessage::Send("/join abc".to_string()));
 
     alice.call(ChatClientMessage::Send("Is there anyone?".to_string())); // no
 
     tokio::time::sleep(Duration::from_millis(100)).await; // sorry for this hack, but i can't find a better solution right now
-    bob.call(ChatClientMessage::Send(format!("/join abc")));
+    bob.call(ChatClientMessage::Send("/join abc".to_string()));
 
     assert_eq!(
         alice_messages.recv().await.unwrap(),
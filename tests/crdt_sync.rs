@@ -0,0 +1,111 @@
+use automerge::transaction::Transactable;
+use automerge::AutoCommit;
+use automerge::ReadDoc;
+use automerge::ScalarValue;
+use automerge::Value;
+use automerge::ROOT;
+use ezsockets::protocols::crdt_sync::CrdtMessage;
+use ezsockets::protocols::crdt_sync::CrdtReplica;
+use ezsockets::protocols::crdt_sync::CrdtRoom;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+/// Exchanges messages between the room and the replicas until they're all in sync.
+fn route(
+    room: &mut CrdtRoom<u32>,
+    replicas: &mut HashMap<u32, CrdtReplica>,
+    to_room: Vec<(u32, CrdtMessage)>,
+    to_replicas: Vec<(u32, CrdtMessage)>,
+) {
+    let mut to_room = VecDeque::from(to_room);
+    let mut to_replicas = VecDeque::from(to_replicas);
+    while !to_room.is_empty() || !to_replicas.is_empty() {
+        while let Some((peer, message)) = to_replicas.pop_front() {
+            let message = CrdtMessage::decode(message.encode()).unwrap();
+            if let Some(reply) = replicas.get_mut(&peer).unwrap().receive(message).unwrap() {
+                to_room.push_back((peer, reply));
+            }
+        }
+        while let Some((peer, message)) = to_room.pop_front() {
+            let message = CrdtMessage::decode(message.encode()).unwrap();
+            to_replicas.extend(room.receive(peer, message).unwrap());
+        }
+    }
+}
+
+fn int(document: &AutoCommit, key: &str) -> Option<i64> {
+    match document.get(ROOT, key).unwrap() {
+        Some((Value::Scalar(value), _)) => match value.as_ref() {
+            ScalarValue::Int(value) => Some(*value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[test]
+fn test_concurrent_changes_are_merged() {
+    let mut document = AutoCommit::new();
+    document.put(ROOT, "title", "draft").unwrap();
+    let mut room = CrdtRoom::new("doc", document);
+    let mut replicas = HashMap::new();
+    for peer in [1, 2] {
+        replicas.insert(peer, CrdtReplica::new("doc"));
+        let messages = room.join(peer);
+        let messages = messages
+            .into_iter()
+            .map(|message| (peer, message))
+            .collect();
+        route(&mut room, &mut replicas, Vec::new(), messages);
+    }
+    for replica in replicas.values() {
+        assert!(replica.document().get(ROOT, "title").unwrap().is_some());
+    }
+
+    // Both replicas change the document before hearing from each other.
+    let mut to_room = Vec::new();
+    for (peer, key) in [(1, "a"), (2, "b")] {
+        let replica = replicas.get_mut(&peer).unwrap();
+        let (_, message) = replica.change(|document| document.put(ROOT, key, peer as i64).unwrap());
+        to_room.push((peer, message.unwrap()));
+    }
+    route(&mut room, &mut replicas, to_room, Vec::new());
+
+    // A change made by the server reaches every replica as well.
+    let (_, to_replicas) = room.change(|document| document.put(ROOT, "c", 3).unwrap());
+    route(&mut room, &mut replicas, Vec::new(), to_replicas);
+
+    for document in replicas
+        .values()
+        .map(CrdtReplica::document)
+        .chain([room.document()])
+    {
+        assert_eq!(int(document, "a"), Some(1));
+        assert_eq!(int(document, "b"), Some(2));
+        assert_eq!(int(document, "c"), Some(3));
+    }
+}
+
+#[test]
+fn test_presence() {
+    let mut room = CrdtRoom::new("doc", AutoCommit::new());
+    let mut replicas = HashMap::from([(1, CrdtReplica::new("doc"))]);
+    room.join(1);
+    let presence = replicas[&1].presence("cursor at 4");
+    route(&mut room, &mut replicas, vec![(1, presence)], Vec::new());
+
+    // Members joining later get the presence of the others.
+    replicas.insert(2, CrdtReplica::new("doc"));
+    let messages = room
+        .join(2)
+        .into_iter()
+        .map(|message| (2, message))
+        .collect();
+    route(&mut room, &mut replicas, Vec::new(), messages);
+    assert_eq!(replicas[&2].peers()["1"], "cursor at 4");
+    assert!(replicas[&1].peers().is_empty());
+
+    let messages = room.leave(&1);
+    route(&mut room, &mut replicas, Vec::new(), messages);
+    assert!(replicas[&2].peers().is_empty());
+}
@@ -0,0 +1,147 @@
+use ezsockets::protocols::router::RouteError;
+use ezsockets::protocols::router::Router;
+use ezsockets::protocols::router::WsMessage;
+use ezsockets::Bytes;
+use ezsockets::ClientExt;
+use ezsockets::Message;
+use ezsockets::SessionExt;
+use serde_json::json;
+
+#[derive(Debug, PartialEq, ezsockets::WsMessage)]
+enum Event {
+    Chat {
+        text: String,
+        reply_to: Option<u64>,
+    },
+    #[ws(rename = "online")]
+    UserOnline {
+        user: u64,
+    },
+    Ping,
+}
+
+#[derive(Debug, PartialEq, ezsockets::WsMessage)]
+#[ws(tag = "op")]
+enum Command {
+    Join { room: String },
+}
+
+#[test]
+fn test_codec() {
+    let chat = Event::Chat {
+        text: String::from("hello"),
+        reply_to: None,
+    };
+    assert_eq!(Event::KINDS, ["chat", "online", "ping"]);
+    assert_eq!(chat.kind(), "chat");
+    assert_eq!(
+        chat.to_json().unwrap(),
+        json!({"type": "chat", "text": "hello", "reply_to": null})
+    );
+    // Missing Option fields are decoded as None.
+    assert_eq!(Event::parse(r#"{"type":"chat","text":"hello"}"#), Ok(chat));
+    assert_eq!(
+        Event::parse(r#"{"type":"online","user":1}"#),
+        Ok(Event::UserOnline { user: 1 })
+    );
+    assert!(matches!(
+        Event::Ping.to_message(),
+        Ok(Message::Text(text)) if text == r#"{"type":"ping"}"#
+    ));
+
+    assert_eq!(
+        Event::parse(r#"{"type":"typing"}"#),
+        Err(RouteError::Unrouted(Some(String::from("typing"))))
+    );
+    assert!(matches!(
+        Event::parse(r#"{"type":"online","user":"me"}"#),
+        Err(RouteError::Malformed(_))
+    ));
+    assert_eq!(
+        Command::parse(r#"{"op":"join","room":"lobby"}"#),
+        Ok(Command::Join {
+            room: String::from("lobby")
+        })
+    );
+}
+
+#[test]
+fn test_router() {
+    let router = Router::new("type")
+        .messages(|event: Event| event.kind().to_string())
+        .route("typing", |_| String::from("someone is typing"));
+    assert_eq!(
+        router.dispatch(r#"{"type":"online","user":1}"#),
+        Ok(String::from("online"))
+    );
+    assert_eq!(
+        router.dispatch(r#"{"type":"typing"}"#),
+        Ok(String::from("someone is typing"))
+    );
+    assert!(matches!(
+        router.dispatch(r#"{"type":"chat"}"#),
+        Err(RouteError::Malformed(_))
+    ));
+}
+
+#[derive(Default)]
+struct Recorder {
+    id: u16,
+    received: Vec<String>,
+}
+
+#[ezsockets::client_ext(params = String)]
+impl Recorder {
+    async fn text(&mut self, text: String) -> Result<(), ezsockets::Error> {
+        self.received.push(text);
+        Ok(())
+    }
+
+    fn call(&mut self, params: String) -> Result<(), ezsockets::Error> {
+        self.received.push(format!("call {params}"));
+        Ok(())
+    }
+}
+
+struct Session(Recorder);
+
+#[ezsockets::session_ext(id = u16)]
+impl Session {
+    fn id(&self) -> &u16 {
+        &self.0.id
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), ezsockets::Error> {
+        self.0.received.push(format!("{} bytes", bytes.len()));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_handlers() {
+    let mut client = Recorder::default();
+    ClientExt::text(&mut client, String::from("hello"))
+        .await
+        .unwrap();
+    ClientExt::call(&mut client, String::from("ping"))
+        .await
+        .unwrap();
+    // Handlers which aren't defined do nothing.
+    ClientExt::binary(&mut client, Bytes::from_static(&[1]))
+        .await
+        .unwrap();
+    assert_eq!(client.received, ["hello", "call ping"]);
+
+    let mut session = Session(Recorder {
+        id: 7,
+        ..Default::default()
+    });
+    assert_eq!(*SessionExt::id(&session), 7);
+    SessionExt::binary(&mut session, Bytes::from_static(&[1, 2]))
+        .await
+        .unwrap();
+    SessionExt::text(&mut session, String::from("ignored"))
+        .await
+        .unwrap();
+    assert_eq!(session.0.received, ["2 bytes"]);
+}
@@ -0,0 +1,10 @@
+use ezsockets::ErrorEnvelope;
+
+#[test]
+fn test_error_envelope() {
+    let envelope = ErrorEnvelope::new("invalid_request", r#"missing "room""#).correlation_id("42");
+    assert_eq!(
+        envelope.to_json(),
+        r#"{"type":"error","code":"invalid_request","message":"missing \"room\"","correlation_id":"42"}"#
+    );
+}
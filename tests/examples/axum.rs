@@ -0,0 +1,36 @@
+use axum_crate as axum;
+
+use crate::common::collector;
+use crate::common::config;
+use crate::common::Hub;
+use axum::extract::Extension;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use ezsockets::axum::Upgrade;
+use ezsockets::Server;
+use std::net::SocketAddr;
+
+async fn websocket_handler(
+    Extension(server): Extension<Server<Hub>>,
+    ezsocket: Upgrade,
+) -> impl IntoResponse {
+    ezsocket.on_upgrade(server, ()).await
+}
+
+#[tokio::test]
+async fn axum_integration() {
+    let (server, _) = Server::create(Hub::new);
+    let app = Router::new()
+        .route("/websocket", get(websocket_handler))
+        .layer(Extension(server));
+    let address = SocketAddr::from(([127, 0, 0, 1], 0));
+    let future =
+        axum::Server::bind(&address).serve(app.into_make_service_with_connect_info::<SocketAddr>());
+    let address = future.local_addr();
+    tokio::spawn(future);
+
+    let (client, mut texts) = collector(config(address)).await;
+    client.text("hello".to_string()).unwrap();
+    assert_eq!(texts.recv().await.unwrap(), "hello");
+}
@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+use ezsockets::Bytes;
+use ezsockets::Client;
+use ezsockets::ClientConfig;
+use ezsockets::Error;
+use ezsockets::Server;
+use ezsockets::Socket;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use url::Url;
+
+pub type Session = ezsockets::Session<u8, ()>;
+
+/// Server relaying messages between rooms, driven by the text commands of its sessions:
+/// - `/join <room>` joins the room,
+/// - `/publish <room> <text>` sends the text to the other members of the room,
+/// - anything else is echoed back.
+pub struct Hub {
+    handle: Server<Self>,
+    next_id: u8,
+}
+
+impl Hub {
+    pub fn new(handle: Server<Self>) -> Self {
+        Self { handle, next_id: 0 }
+    }
+}
+
+#[async_trait]
+impl ezsockets::ServerExt for Hub {
+    type Session = HubSession;
+    type Params = ();
+    type State = ();
+
+    async fn accept(
+        &mut self,
+        socket: Socket,
+        _address: SocketAddr,
+        _args: (),
+    ) -> Result<Session, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let server = self.handle.clone();
+        Ok(Session::create(
+            |handle| HubSession { handle, server },
+            id,
+            socket,
+        ))
+    }
+
+    async fn disconnected(&mut self, _id: u8) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+pub struct HubSession {
+    handle: Session,
+    server: Server<Hub>,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for HubSession {
+    type ID = u8;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.handle.id
+    }
+
+    async fn text(&mut self, text: String) -> Result<(), Error> {
+        let id = self.handle.id;
+        if let Some(room) = text.strip_prefix("/join ") {
+            self.server.join(room, id);
+        } else if let Some(command) = text.strip_prefix("/publish ") {
+            let (room, text) = command.split_once(' ').ok_or("missing room")?;
+            let message = ezsockets::Message::Text(format!("{id}: {text}"));
+            self.server.publish(room, id, message);
+        } else {
+            self.handle.text(text)?;
+        }
+        Ok(())
+    }
+
+    async fn binary(&mut self, bytes: Bytes) -> Result<(), Error> {
+        self.handle.binary(bytes)?;
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+/// Starts a [`Hub`] on a random local port, served by the tungstenite back-end.
+pub async fn hub() -> (Server<Hub>, SocketAddr) {
+    let (server, _) = Server::create(Hub::new);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(ezsockets::tungstenite::run_on(
+        server.clone(),
+        listener,
+        |_| async move { Ok(()) },
+    ));
+    (server, address)
+}
+
+pub fn config(address: SocketAddr) -> ClientConfig {
+    ClientConfig::new(Url::parse(&format!("ws://{address}/websocket")).unwrap())
+}
+
+/// Client forwarding every text message it receives to a channel.
+pub struct Collector {
+    texts: mpsc::UnboundedSender<String>,
+}
+
+#[async_trait]
+impl ezsockets::ClientExt for Collector {
+    type Params = ();
+
+    async fn text(&mut self, text: String) -> Result<(), Error> {
+        let _ = self.texts.send(text);
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+pub async fn collector(
+    config: ClientConfig,
+) -> (Client<Collector>, mpsc::UnboundedReceiver<String>) {
+    let (texts, receiver) = mpsc::unbounded_channel();
+    let (client, _) = ezsockets::connect(|_| Collector { texts }, config).await;
+    (client, receiver)
+}
@@ -0,0 +1,13 @@
+//! End-to-end examples of the main features, each running a client and a server in-process.
+//!
+//! They double as documentation, so they stick to the public API and the happy path.
+//! There's no typed codec example, since messages are plain Text and Binary frames.
+
+mod common;
+
+#[cfg(feature = "axum")]
+mod axum;
+mod pubsub;
+mod reconnect;
+mod replay;
+mod rooms;
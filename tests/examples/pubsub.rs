@@ -0,0 +1,29 @@
+use crate::common::config;
+use crate::common::hub;
+use ezsockets::Message;
+use ezsockets::SharedClient;
+
+fn has_prefix(message: &Message, prefix: &str) -> bool {
+    matches!(message, Message::Text(text) if text.starts_with(prefix))
+}
+
+#[tokio::test]
+async fn pubsub() {
+    let (_server, address) = hub().await;
+    // A single connection shared by independent consumers, each receiving only its topic.
+    let (client, _) = SharedClient::connect(config(address)).await;
+    let mut quotes = client.consumer(|message| has_prefix(message, "quote:"));
+    let mut news = client.consumer(|message| has_prefix(message, "news:"));
+
+    news.text("news:released".to_string()).unwrap();
+    quotes.text("quote:42".to_string()).unwrap();
+
+    match quotes.recv().await.unwrap() {
+        Message::Text(text) => assert_eq!(text, "quote:42"),
+        message => panic!("unexpected message: {message:?}"),
+    }
+    match news.recv().await.unwrap() {
+        Message::Text(text) => assert_eq!(text, "news:released"),
+        message => panic!("unexpected message: {message:?}"),
+    }
+}
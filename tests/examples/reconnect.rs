@@ -0,0 +1,27 @@
+use crate::common::collector;
+use crate::common::config;
+use crate::common::hub;
+use ezsockets::ConnectionState;
+use std::time::Duration;
+
+#[tokio::test]
+async fn reconnect() {
+    let (server, address) = hub().await;
+    let (client, mut texts) = collector(config(address).restart_jitter(Duration::ZERO)).await;
+    client.text("before".to_string()).unwrap();
+    assert_eq!(texts.recv().await.unwrap(), "before");
+
+    // Draining asks clients to reconnect, as when a server is about to be restarted.
+    let mut states = client.state_changes();
+    server.drain();
+    while !matches!(*states.borrow(), ConnectionState::Reconnecting { .. }) {
+        states.changed().await.unwrap();
+    }
+    while !matches!(*states.borrow(), ConnectionState::Connected { .. }) {
+        states.changed().await.unwrap();
+    }
+    assert_eq!(client.reconnects(), 1);
+
+    client.text("after".to_string()).unwrap();
+    assert_eq!(texts.recv().await.unwrap(), "after");
+}
@@ -0,0 +1,28 @@
+use crate::common::collector;
+use crate::common::config;
+use crate::common::hub;
+use ezsockets::ClientConfig;
+use ezsockets::ConnectionState;
+use url::Url;
+
+#[tokio::test]
+async fn record_replay() {
+    let path = std::env::temp_dir().join(format!("ezsockets-replay-{}", std::process::id()));
+    let (_, address) = hub().await;
+    let (client, mut texts) = collector(config(address).record(&path)).await;
+    for text in ["hello", "world"] {
+        client.text(text.to_string()).unwrap();
+        assert_eq!(texts.recv().await.unwrap(), text);
+    }
+
+    // The replay doesn't connect, the echoed messages are played back from the recording.
+    let url = Url::parse("ws://offline.invalid/websocket").unwrap();
+    let (client, mut texts) = collector(ClientConfig::new(url).replay(&path)).await;
+    assert_eq!(texts.recv().await.unwrap(), "hello");
+    assert_eq!(texts.recv().await.unwrap(), "world");
+    let mut states = client.state_changes();
+    while !matches!(*states.borrow(), ConnectionState::Closed { .. }) {
+        states.changed().await.unwrap();
+    }
+    std::fs::remove_file(&path).unwrap();
+}
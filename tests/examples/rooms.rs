@@ -0,0 +1,22 @@
+use crate::common::collector;
+use crate::common::config;
+use crate::common::hub;
+
+#[tokio::test]
+async fn rooms() {
+    let (_server, address) = hub().await;
+    let (alice, mut alice_texts) = collector(config(address)).await;
+    let (bob, mut bob_texts) = collector(config(address)).await;
+
+    alice.text("/join lobby".to_string()).unwrap();
+    bob.text("/join lobby".to_string()).unwrap();
+    // Echoes act as barriers, once echoed the preceding join has been handled.
+    alice.text("joined".to_string()).unwrap();
+    bob.text("joined".to_string()).unwrap();
+    assert_eq!(alice_texts.recv().await.unwrap(), "joined");
+    assert_eq!(bob_texts.recv().await.unwrap(), "joined");
+
+    alice.text("/publish lobby hello".to_string()).unwrap();
+    let text = bob_texts.recv().await.unwrap();
+    assert!(text.ends_with(": hello"), "{text}");
+}
@@ -0,0 +1,111 @@
+use async_trait::async_trait;
+use ezsockets::Bytes;
+use ezsockets::Error;
+use ezsockets::Server;
+use ezsockets::Socket;
+use futures::SinkExt;
+use futures::StreamExt;
+use std::net::SocketAddr;
+use std::os::fd::AsRawFd;
+use tokio::net::TcpListener;
+use tokio::net::UnixStream;
+use tokio_tungstenite::tungstenite::Message;
+
+type Session = ezsockets::Session<u8, ()>;
+
+struct EchoServer;
+
+#[async_trait]
+impl ezsockets::ServerExt for EchoServer {
+    type Session = EchoSession;
+    type Params = ();
+    type State = ();
+
+    async fn accept(
+        &mut self,
+        socket: Socket,
+        _address: SocketAddr,
+        state: Vec<u8>,
+    ) -> Result<Session, Error> {
+        assert!(socket.raw_fd().is_some());
+        let prefix = String::from_utf8(state)?;
+        Ok(Session::create(
+            |handle| EchoSession { handle, prefix },
+            0,
+            socket,
+        ))
+    }
+
+    async fn disconnected(&mut self, _id: u8) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+struct EchoSession {
+    handle: Session,
+    prefix: String,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for EchoSession {
+    type ID = u8;
+    type Args = Vec<u8>;
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.handle.id
+    }
+
+    async fn text(&mut self, text: String) -> Result<(), Error> {
+        self.handle.text(format!("{}{text}", self.prefix))?;
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_handoff() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let client = tokio::spawn(tokio_tungstenite::connect_async(url));
+    let (stream, _) = listener.accept().await.unwrap();
+    let old = tokio_tungstenite::accept_async(stream).await.unwrap();
+    let (mut client, _) = client.await.unwrap().unwrap();
+
+    // Both ends of the channel would normally live in different processes.
+    let (sender, receiver) = UnixStream::pair().unwrap();
+    ezsockets::handoff::send(&sender, old.get_ref().as_raw_fd(), b"resumed: ")
+        .await
+        .unwrap();
+    drop(old);
+    let handoff = ezsockets::handoff::recv(&receiver).await.unwrap();
+    assert_eq!(handoff.state, b"resumed: ");
+
+    let (server, _) = Server::create(|_| EchoServer);
+    ezsockets::tungstenite::resume(&server, handoff.stream, handoff.state)
+        .await
+        .unwrap();
+    client.send(Message::Text("hello".into())).await.unwrap();
+    loop {
+        match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => {
+                assert_eq!(text, "resumed: hello");
+                break;
+            }
+            _ => continue,
+        }
+    }
+}
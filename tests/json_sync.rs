@@ -0,0 +1,72 @@
+use ezsockets::protocols::json_sync::apply_patch;
+use ezsockets::protocols::json_sync::PatchError;
+use ezsockets::protocols::json_sync::SharedDocument;
+use ezsockets::protocols::json_sync::SyncMessage;
+use ezsockets::Message;
+use serde_json::json;
+
+fn text(message: impl Into<Message>) -> String {
+    match message.into() {
+        Message::Text(text) => text,
+        message => panic!("expected text message, got {message:?}"),
+    }
+}
+
+#[test]
+fn test_shared_document_sync() {
+    let mut server = SharedDocument::new("dashboard", json!({"title": "cpu", "values": [1, 2]}));
+    let mut client = SharedDocument::new("dashboard", json!(null));
+    client
+        .receive(SyncMessage::parse(&text(server.snapshot())).unwrap())
+        .unwrap();
+    assert_eq!(client, server);
+
+    let update = server
+        .update(|document| document["title"] = json!("memory"))
+        .unwrap();
+    client
+        .receive(SyncMessage::parse(&text(update)).unwrap())
+        .unwrap();
+    assert_eq!(client.document(), server.document());
+    assert!(server.update(|_| {}).is_none());
+
+    // A client patch made against the current version is applied, a stale one is rejected.
+    let patch = json!([{"op": "add", "path": "/values/-", "value": 3}]);
+    let applied = server.apply(1, patch.clone()).unwrap();
+    assert_eq!(server.document()["values"], json!([1, 2, 3]));
+    assert_eq!(
+        server.apply(1, patch),
+        Err(PatchError::Conflict { version: 2 })
+    );
+    client.receive(applied).unwrap();
+    assert_eq!(client, server);
+}
+
+#[test]
+fn test_apply_patch() {
+    let mut document = json!({"a/b": {"c": [1, 2]}, "d": "e"});
+    let patch = json!([
+        {"op": "test", "path": "/d", "value": "e"},
+        {"op": "copy", "from": "/a~1b/c", "path": "/f"},
+        {"op": "move", "from": "/d", "path": "/a~1b/d"},
+        {"op": "replace", "path": "/a~1b/c/0", "value": 0},
+        {"op": "remove", "path": "/f/1"},
+        {"op": "add", "path": "/f/0", "value": -1},
+    ]);
+    apply_patch(&mut document, &patch).unwrap();
+    assert_eq!(
+        document,
+        json!({"a/b": {"c": [0, 2], "d": "e"}, "f": [-1, 1]})
+    );
+
+    // A failing operation leaves the document unchanged.
+    let patch = json!([
+        {"op": "remove", "path": "/f"},
+        {"op": "test", "path": "/d", "value": "e"},
+    ]);
+    assert!(matches!(
+        apply_patch(&mut document, &patch),
+        Err(PatchError::Failed(_))
+    ));
+    assert_eq!(document["f"], json!([-1, 1]));
+}
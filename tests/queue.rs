@@ -0,0 +1,80 @@
+use ezsockets::ConflatingQueue;
+use ezsockets::LatestQueue;
+use ezsockets::Message;
+use ezsockets::PriorityQueue;
+use ezsockets::RingQueue;
+use ezsockets::SendQueue;
+
+fn drain(queue: &mut impl SendQueue) -> Vec<String> {
+    std::iter::from_fn(|| queue.pop())
+        .map(|message| match message {
+            Message::Text(text) => text,
+            message => panic!("unexpected message: {message:?}"),
+        })
+        .collect()
+}
+
+fn drain_one(queue: &mut impl SendQueue) -> String {
+    match queue.pop() {
+        Some(Message::Text(text)) => text,
+        message => panic!("unexpected message: {message:?}"),
+    }
+}
+
+fn push(queue: &mut impl SendQueue, texts: &[&str]) {
+    for text in texts {
+        queue.push(Message::Text(text.to_string()));
+    }
+}
+
+#[test]
+fn test_ring_queue() {
+    let mut queue = RingQueue::new(2);
+    push(&mut queue, &["a", "b", "c"]);
+    assert_eq!(drain(&mut queue), ["b", "c"]);
+}
+
+#[test]
+fn test_conflating_queue() {
+    let mut queue = ConflatingQueue::default();
+    queue.push_conflated("EURUSD".into(), Message::Text("1.08".to_string()));
+    push(&mut queue, &["hello"]);
+    queue.push_conflated("EURUSD".into(), Message::Text("1.09".to_string()));
+    queue.push_conflated("GBPUSD".into(), Message::Text("1.27".to_string()));
+    assert_eq!(drain(&mut queue), ["1.09", "hello", "1.27"]);
+}
+
+#[test]
+fn test_conflating_queue_after_pops() {
+    let mut queue = ConflatingQueue::default();
+    for (key, text) in [("a", "a1"), ("b", "b1"), ("c", "c1")] {
+        queue.push_conflated(key.into(), Message::Text(text.to_string()));
+    }
+    assert_eq!(drain_one(&mut queue), "a1");
+    // The key of a written message is queued again, the others still replace their queued message.
+    queue.push_conflated("a".into(), Message::Text("a2".to_string()));
+    queue.push_conflated("c".into(), Message::Text("c2".to_string()));
+    queue.push_conflated("b".into(), Message::Text("b2".to_string()));
+    assert_eq!(drain(&mut queue), ["b2", "c2", "a2"]);
+}
+
+#[test]
+fn test_latest_queue() {
+    // Messages are "<entity>:<position>", only the latest position of each entity is kept.
+    let mut queue = LatestQueue::new(|message| match message {
+        Message::Text(text) => text.split_once(':').map(|(entity, _)| entity.to_string()),
+        _ => None,
+    });
+    push(&mut queue, &["a:1", "b:1", "a:2", "hello", "b:2"]);
+    assert_eq!(drain(&mut queue), ["a:2", "b:2", "hello"]);
+}
+
+#[test]
+fn test_priority_queue() {
+    let mut queue = PriorityQueue::new(|message| match message {
+        Message::Text(text) if text.starts_with('!') => 1,
+        _ => 0,
+    });
+    push(&mut queue, &["a", "!b", "c", "!d"]);
+    assert_eq!(drain(&mut queue), ["!b", "!d", "a", "c"]);
+}
@@ -0,0 +1,513 @@
+mod transport;
+
+use async_trait::async_trait;
+use ezsockets::Bytes;
+use ezsockets::CloseCode;
+use ezsockets::CloseFrame;
+use ezsockets::Error;
+use ezsockets::GoAway;
+use ezsockets::Hello;
+use ezsockets::Message;
+use ezsockets::RawMessage;
+use ezsockets::RejectReason;
+use ezsockets::Server;
+use ezsockets::ServerConfig;
+use ezsockets::Socket;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Session = ezsockets::Session<u8, ()>;
+
+#[derive(Default)]
+struct AppState {
+    greeting: String,
+}
+
+struct RoomServer {
+    next_id: u8,
+    events: mpsc::UnboundedSender<String>,
+    state: Arc<AppState>,
+}
+
+impl RoomServer {
+    fn new(handle: Server<Self>) -> (Self, mpsc::UnboundedReceiver<String>) {
+        let (events, receiver) = mpsc::unbounded();
+        let state = handle.state().clone();
+        (
+            Self {
+                next_id: 0,
+                events,
+                state,
+            },
+            receiver,
+        )
+    }
+}
+
+#[async_trait]
+impl ezsockets::ServerExt for RoomServer {
+    type Session = RoomSession;
+    type Params = ();
+    type State = AppState;
+
+    async fn accept(
+        &mut self,
+        socket: Socket,
+        _address: SocketAddr,
+        _args: (),
+    ) -> Result<Session, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let session = Session::create(|_| RoomSession { id }, id, socket);
+        if !self.state.greeting.is_empty() {
+            session.text(self.state.greeting.clone())?;
+        }
+        Ok(session)
+    }
+
+    async fn disconnected(&mut self, _id: u8) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+
+    async fn can_publish(&mut self, _id: &u8, room: &str) -> Result<(), RejectReason> {
+        match room {
+            "announcements" => Err(RejectReason::Denied("read-only room".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    async fn room_created(&mut self, room: String) -> Result<(), Error> {
+        let _ = self.events.unbounded_send(format!("created {room}"));
+        Ok(())
+    }
+
+    async fn room_empty(&mut self, room: String) -> Result<(), Error> {
+        let _ = self.events.unbounded_send(format!("empty {room}"));
+        Ok(())
+    }
+}
+
+struct RoomSession {
+    id: u8,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for RoomSession {
+    type ID = u8;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.id
+    }
+
+    async fn text(&mut self, _text: String) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+struct Peer {
+    id: u8,
+    to_socket: mpsc::UnboundedSender<RawMessage>,
+    from_socket: mpsc::UnboundedReceiver<RawMessage>,
+}
+
+impl Peer {
+    async fn connect(server: &Server<RoomServer>) -> Self {
+        let (socket, to_socket, from_socket) = transport::socket(Default::default());
+        let address = SocketAddr::from(([127, 0, 0, 1], 0));
+        let id = server.accept(socket, address, ()).await.unwrap();
+        Self {
+            id,
+            to_socket,
+            from_socket,
+        }
+    }
+
+    async fn next_text(&mut self) -> String {
+        loop {
+            match self.from_socket.next().await.unwrap() {
+                RawMessage::Text(text) => return text,
+                RawMessage::Ping(_) => continue,
+                message => panic!("unexpected message: {message:?}"),
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_move_session() {
+    let (server, _) = Server::create(|handle| RoomServer::new(handle).0);
+    let mut alice = Peer::connect(&server).await;
+    let mut bob = Peer::connect(&server).await;
+    server.join("lobby", alice.id);
+    server.join("lobby", bob.id);
+    server.broadcast("lobby", Message::Text("1".to_string()));
+    server.move_session(bob.id, "lobby", "game");
+    server.broadcast("lobby", Message::Text("2".to_string()));
+    server.broadcast("game", Message::Text("3".to_string()));
+
+    assert_eq!(alice.next_text().await, "1");
+    assert_eq!(alice.next_text().await, "2");
+    assert_eq!(bob.next_text().await, "1");
+    assert_eq!(bob.next_text().await, "3");
+}
+
+#[tokio::test]
+async fn test_send_many_disconnect_many() {
+    let (server, _) = Server::create(|handle| RoomServer::new(handle).0);
+    let mut alice = Peer::connect(&server).await;
+    let mut bob = Peer::connect(&server).await;
+    let mut carol = Peer::connect(&server).await;
+    // Unknown IDs are ignored.
+    server.send_many([alice.id, carol.id, 42], Message::Text("hi".to_string()));
+    server.send_many([bob.id], Message::Text("bob only".to_string()));
+    assert_eq!(alice.next_text().await, "hi");
+    assert_eq!(carol.next_text().await, "hi");
+    assert_eq!(bob.next_text().await, "bob only");
+
+    let bob_id = bob.id;
+    server.disconnect_many(move |id| *id == bob_id, Some(CloseFrame::policy("kicked")));
+    loop {
+        match bob.from_socket.next().await.unwrap() {
+            RawMessage::Close(Some(frame)) => break assert_eq!(frame.reason, "kicked"),
+            RawMessage::Ping(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+    server.send_many(
+        [alice.id, carol.id],
+        Message::Text("still here".to_string()),
+    );
+    assert_eq!(alice.next_text().await, "still here");
+    assert_eq!(carol.next_text().await, "still here");
+}
+
+#[tokio::test]
+async fn test_broadcast_with() {
+    let (server, _) = Server::create(|handle| RoomServer::new(handle).0);
+    let mut alice = Peer::connect(&server).await;
+    let mut bob = Peer::connect(&server).await;
+    server.join("lobby", alice.id);
+    server.join("lobby", bob.id);
+    let moderator = alice.id;
+    server.broadcast_with("lobby", move |recipient| {
+        (*recipient.id == moderator).then(|| Message::Text(format!("hello {}", recipient.id)))
+    });
+    server.broadcast("lobby", Message::Text("everyone".to_string()));
+
+    assert_eq!(alice.next_text().await, format!("hello {}", alice.id));
+    assert_eq!(alice.next_text().await, "everyone");
+    assert_eq!(bob.next_text().await, "everyone");
+}
+
+#[tokio::test]
+async fn test_room_lifecycle() {
+    let mut events = None;
+    let (server, _) = Server::create(|handle| {
+        let (server, receiver) = RoomServer::new(handle);
+        events = Some(receiver);
+        server
+    });
+    let mut events = events.unwrap();
+    let alice = Peer::connect(&server).await;
+    server.join("lobby", alice.id);
+    server.leave("lobby", alice.id);
+    server.join("lobby", alice.id);
+
+    assert_eq!(events.next().await.unwrap(), "created lobby");
+    assert_eq!(events.next().await.unwrap(), "empty lobby");
+    assert_eq!(events.next().await.unwrap(), "created lobby");
+}
+
+#[tokio::test]
+async fn test_room_capacity() {
+    let mut events = None;
+    let (server, _) = Server::create(|handle| {
+        let (server, receiver) = RoomServer::new(handle);
+        events = Some(receiver);
+        server
+    });
+    let mut events = events.unwrap();
+    let alice = Peer::connect(&server).await;
+    let mut bob = Peer::connect(&server).await;
+    server.set_room_capacity("game", Some(1));
+    server.join("lobby", bob.id);
+    server.join("game", alice.id);
+    server.move_session(bob.id, "lobby", "game");
+    server.broadcast("lobby", Message::Text("still in lobby".to_string()));
+
+    assert_eq!(events.next().await.unwrap(), "created lobby");
+    assert_eq!(events.next().await.unwrap(), "created game");
+    assert_eq!(
+        bob.next_text().await,
+        r#"{"type":"error","code":"room_full","message":"room is full"}"#
+    );
+    assert_eq!(bob.next_text().await, "still in lobby");
+}
+
+#[tokio::test]
+async fn test_drain_sends_goaway() {
+    let config = ServerConfig::default().goaway(GoAway::new(Duration::from_millis(50)));
+    let (server, _) = Server::create_with_config(|handle| RoomServer::new(handle).0, config);
+    let mut peer = Peer::connect(&server).await;
+    server.drain();
+    assert_eq!(peer.next_text().await, r#"{"type":"goaway"}"#);
+    loop {
+        match peer.from_socket.next().await.unwrap() {
+            RawMessage::Close(Some(CloseFrame { code, .. })) => {
+                assert_eq!(u16::from(code), u16::from(CloseCode::Restart));
+                break;
+            }
+            RawMessage::Ping(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_session_config() {
+    let mut config = ServerConfig::default();
+    config.session.hello = Some(Hello::new(Duration::from_millis(50)));
+    let (server, _) = Server::create_with_config(|handle| RoomServer::new(handle).0, config);
+    let mut peer = Peer::connect(&server).await;
+    loop {
+        match peer.from_socket.next().await.unwrap() {
+            RawMessage::Close(Some(CloseFrame { code, .. })) => {
+                assert_eq!(u16::from(code), u16::from(CloseCode::Policy));
+                break;
+            }
+            RawMessage::Ping(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_server_state() {
+    let state = Arc::new(AppState {
+        greeting: String::from("hello"),
+    });
+    let (server, _) = Server::create_with_state(
+        |handle| RoomServer::new(handle).0,
+        ServerConfig::default(),
+        state,
+    );
+    assert_eq!(server.state().greeting, "hello");
+    let mut peer = Peer::connect(&server).await;
+    assert_eq!(peer.next_text().await, "hello");
+}
+
+#[tokio::test]
+async fn test_publish_permissions() {
+    let mut events = None;
+    let (server, _) = Server::create(|handle| {
+        let (server, receiver) = RoomServer::new(handle);
+        events = Some(receiver);
+        server
+    });
+    let mut events = events.unwrap();
+    let mut alice = Peer::connect(&server).await;
+    let mut bob = Peer::connect(&server).await;
+    server.join("lobby", alice.id);
+    server.join("lobby", bob.id);
+    server.join("announcements", bob.id);
+    server.publish("announcements", bob.id, Message::Text("denied".to_string()));
+    server.publish("game", bob.id, Message::Text("not a member".to_string()));
+    server.publish("lobby", bob.id, Message::Text("hello".to_string()));
+
+    assert_eq!(alice.next_text().await, "hello");
+    assert_eq!(events.next().await.unwrap(), "created lobby");
+    assert_eq!(events.next().await.unwrap(), "created announcements");
+    assert_eq!(
+        bob.next_text().await,
+        r#"{"type":"error","code":"denied","message":"denied: read-only room"}"#
+    );
+    assert_eq!(
+        bob.next_text().await,
+        r#"{"type":"error","code":"not_member","message":"not a member of the room"}"#
+    );
+}
+
+#[tokio::test]
+async fn test_scheduled_broadcast() {
+    use ezsockets::Schedule;
+
+    let (server, _) = Server::create(|handle| RoomServer::new(handle).0);
+    let mut alice = Peer::connect(&server).await;
+    server.join("lobby", alice.id);
+    let mut runs = 0;
+    let schedule = Schedule::every(Duration::from_millis(10)).times(2);
+    let handle = server.schedule_broadcast("lobby", schedule, move || {
+        runs += 1;
+        Message::Text(runs.to_string())
+    });
+    assert_eq!(alice.next_text().await, "1");
+    assert_eq!(alice.next_text().await, "2");
+    assert!(handle.is_finished());
+
+    let schedule = Schedule::every(Duration::from_millis(10)).starting_in(Duration::ZERO);
+    let handle = server.schedule_broadcast("lobby", schedule, || Message::Text("tick".to_string()));
+    assert_eq!(alice.next_text().await, "tick");
+    handle.cancel();
+    server.broadcast("lobby", Message::Text("end".to_string()));
+    while alice.next_text().await != "end" {}
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    while let Ok(message) = alice.from_socket.try_recv() {
+        assert!(
+            matches!(message, RawMessage::Ping(_)),
+            "broadcast after cancel: {message:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_join_with_backfill() {
+    use ezsockets::Backfill;
+    use futures::channel::oneshot;
+
+    let (server, _) = Server::create(|handle| RoomServer::new(handle).0);
+    let mut alice = Peer::connect(&server).await;
+    let (read_history, history_read) = oneshot::channel::<()>();
+    // The end of the history is read after "live 1" was broadcast.
+    let history = futures::stream::iter(["old 1", "old 2"])
+        .chain(futures::stream::once(async move {
+            history_read.await.unwrap();
+            "live 1"
+        }))
+        .map(|text| Message::Text(text.to_string()));
+    let backfill = Backfill::new(history).dedup_by(|message| match message {
+        Message::Text(text) => Some(text.clone()),
+        _ => None,
+    });
+    server.join_with_backfill("lobby", alice.id, backfill);
+    server.broadcast("lobby", Message::Text("live 1".to_string()));
+    server.broadcast("lobby", Message::Text("live 2".to_string()));
+    read_history.send(()).unwrap();
+
+    for expected in ["old 1", "old 2", "live 1", "live 2"] {
+        assert_eq!(alice.next_text().await, expected);
+    }
+    server.broadcast("lobby", Message::Text("live 3".to_string()));
+    assert_eq!(alice.next_text().await, "live 3");
+}
+
+#[tokio::test]
+async fn test_handler_timings() {
+    let (server, _) = Server::create(|handle| RoomServer::new(handle).0);
+    let alice = Peer::connect(&server).await;
+    let _bob = Peer::connect(&server).await;
+    for message in [
+        RawMessage::Text("hi".to_string()),
+        RawMessage::Text("there".to_string()),
+        RawMessage::Binary(vec![1, 2, 3].into()),
+    ] {
+        alice.to_socket.unbounded_send(message).unwrap();
+    }
+
+    // Aggregated over every session, only Alice's messages were handled.
+    let timings = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            let timings = server.handler_timings().await.unwrap();
+            if timings.text.count + timings.binary.count == 3 {
+                break timings;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(timings.text.count, 2);
+    assert_eq!(timings.binary.count, 1);
+    assert_eq!(timings.call.count, 0);
+    assert_eq!(
+        timings
+            .text
+            .buckets
+            .iter()
+            .map(|(_, count)| count)
+            .sum::<u64>(),
+        2
+    );
+
+    // Kept once Alice disconnects.
+    let alice_id = alice.id;
+    drop(alice);
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while server.usage().await.unwrap().contains_key(&alice_id) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(server.handler_timings().await.unwrap(), timings);
+}
+
+#[tokio::test]
+async fn test_usage() {
+    let (server, _) = Server::create(|handle| RoomServer::new(handle).0);
+    let mut alice = Peer::connect(&server).await;
+    let bob = Peer::connect(&server).await;
+    for message in [
+        RawMessage::Text("hi".to_string()),
+        RawMessage::Binary(vec![1, 2, 3].into()),
+    ] {
+        alice.to_socket.unbounded_send(message).unwrap();
+    }
+    server.send_many([alice.id], Message::Text("welcome".to_string()));
+    assert_eq!(alice.next_text().await, "welcome");
+
+    let usage = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            let usage = server.usage().await.unwrap();
+            if usage[&alice.id].messages_received == 2 {
+                break usage;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let alice_usage = &usage[&alice.id];
+    assert_eq!(alice_usage.bytes_received, 5);
+    assert_eq!((alice_usage.messages_sent, alice_usage.bytes_sent), (1, 7));
+    assert_eq!(alice_usage.buffered, 0);
+    assert_eq!(usage[&bob.id].messages_received, 0);
+    assert_eq!(usage[&bob.id].messages_sent, 0);
+}
+
+#[tokio::test]
+async fn test_stopped_server_queries() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let config = ServerConfig::default().runtime(runtime.handle().clone());
+    let (server, _) = Server::create_with_config(|handle| RoomServer::new(handle).0, config);
+    tokio::task::spawn_blocking(move || drop(runtime))
+        .await
+        .unwrap();
+    assert!(server.handler_timings().await.is_none());
+    assert!(server.usage().await.is_none());
+    let (socket, _to_socket, _from_socket) = transport::socket(Default::default());
+    let address = SocketAddr::from(([127, 0, 0, 1], 0));
+    assert!(server.accept(socket, address, ()).await.is_err());
+    assert!(server
+        .call_with(|_: tokio::sync::oneshot::Sender<()>| ())
+        .await
+        .is_err());
+}
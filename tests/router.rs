@@ -0,0 +1,52 @@
+use ezsockets::protocols::router::RouteError;
+use ezsockets::protocols::router::Router;
+use serde_json::Value;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Chat(String),
+    Presence(bool),
+    Other(Value),
+}
+
+#[test]
+fn test_dispatch() {
+    let router = Router::new("type")
+        .route("chat", |message| {
+            Event::Chat(message["text"].as_str().unwrap().to_string())
+        })
+        .route("presence", |message| {
+            Event::Presence(message["online"].as_bool().unwrap())
+        });
+    assert_eq!(
+        router.dispatch(r#"{"type":"chat","text":"hello"}"#),
+        Ok(Event::Chat(String::from("hello")))
+    );
+    assert_eq!(
+        router.dispatch(r#"{"online":true,"type":"presence"}"#),
+        Ok(Event::Presence(true))
+    );
+    assert_eq!(
+        router.dispatch(r#"{"type":"typing"}"#),
+        Err(RouteError::Unrouted(Some(String::from("typing"))))
+    );
+    assert_eq!(
+        router.dispatch(r#"{"text":"hello"}"#),
+        Err(RouteError::Unrouted(None))
+    );
+    assert!(matches!(
+        router.dispatch("not json"),
+        Err(RouteError::Malformed(_))
+    ));
+
+    // The fallback gets every message no handler is registered for.
+    let router = router.fallback(Event::Other);
+    assert_eq!(
+        router.dispatch(r#"{"type":"typing"}"#),
+        Ok(Event::Other(serde_json::json!({"type": "typing"})))
+    );
+    assert_eq!(
+        router.dispatch("[1]"),
+        Ok(Event::Other(serde_json::json!([1])))
+    );
+}
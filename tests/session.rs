@@ -0,0 +1,661 @@
+mod transport;
+
+use async_trait::async_trait;
+use ezsockets::Bytes;
+use ezsockets::CloseCode;
+use ezsockets::CloseFrame;
+use ezsockets::DataKind;
+use ezsockets::Error;
+use ezsockets::ErrorAction;
+use ezsockets::Hello;
+use ezsockets::Idempotency;
+use ezsockets::IdempotencyKey;
+use ezsockets::MemoryIdempotencyStore;
+use ezsockets::MemoryQuotaStore;
+use ezsockets::Message;
+use ezsockets::Quota;
+use ezsockets::QuotaAction;
+use ezsockets::RawMessage;
+use ezsockets::SessionConfig;
+use ezsockets::SocketConfig;
+use ezsockets::Watchdog;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use std::pin::Pin;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+type Session = ezsockets::Session<u8, ()>;
+
+struct StuckSession {
+    id: u8,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for StuckSession {
+    type ID = u8;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.id
+    }
+
+    async fn text(&mut self, _text: String) -> Result<(), Error> {
+        std::future::pending().await
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn ping(&mut self, _payload: Bytes) -> Result<(), Error> {
+        std::future::pending().await
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+struct IdleSession {
+    id: u8,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for IdleSession {
+    type ID = u8;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.id
+    }
+
+    async fn text(&mut self, _text: String) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+async fn expect_close(from_socket: &mut mpsc::UnboundedReceiver<RawMessage>, expected: CloseCode) {
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Close(Some(CloseFrame { code, .. })) => {
+                assert_eq!(u16::from(code), u16::from(expected));
+                break;
+            }
+            RawMessage::Ping(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_watchdog_closes_stuck_session() {
+    let (socket, to_socket, mut from_socket) = transport::socket(Default::default());
+    let config = SessionConfig {
+        watchdog: Some(Watchdog {
+            timeout: Duration::from_millis(50),
+            force_close: true,
+        }),
+        ..Default::default()
+    };
+    let _session = Session::create_with_config(|_| StuckSession { id: 0 }, 0, socket, config);
+    to_socket
+        .unbounded_send(RawMessage::Text("hello".to_string()))
+        .unwrap();
+    expect_close(&mut from_socket, CloseCode::Error).await;
+}
+
+#[tokio::test]
+async fn test_watchdog_closes_stuck_ping_handler() {
+    let (socket, to_socket, mut from_socket) = transport::socket(SocketConfig {
+        expose_control_frames: true,
+        ..Default::default()
+    });
+    let config = SessionConfig {
+        watchdog: Some(Watchdog {
+            timeout: Duration::from_millis(50),
+            force_close: true,
+        }),
+        ..Default::default()
+    };
+    let _session = Session::create_with_config(|_| StuckSession { id: 0 }, 0, socket, config);
+    to_socket
+        .unbounded_send(RawMessage::Ping(b"ping".to_vec()))
+        .unwrap();
+    loop {
+        match tokio::time::timeout(Duration::from_secs(5), from_socket.next())
+            .await
+            .expect("stuck ping handler wasn't closed by the watchdog")
+            .unwrap()
+        {
+            RawMessage::Close(Some(CloseFrame { code, .. })) => {
+                assert_eq!(u16::from(code), u16::from(CloseCode::Error));
+                break;
+            }
+            RawMessage::Ping(_) | RawMessage::Pong(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_quota_disconnects_session() {
+    let (socket, to_socket, mut from_socket) = transport::socket(Default::default());
+    let config = SessionConfig {
+        quota: Some(Quota {
+            identity: "tenant".to_string(),
+            max_messages: Some(1),
+            max_bytes: None,
+            window: Duration::from_secs(60 * 60 * 24),
+            action: QuotaAction::Disconnect,
+            store: Arc::new(MemoryQuotaStore::default()),
+        }),
+        ..Default::default()
+    };
+    let _session = Session::create_with_config(|_| IdleSession { id: 0 }, 0, socket, config);
+    for text in ["first", "second"] {
+        to_socket
+            .unbounded_send(RawMessage::Text(text.to_string()))
+            .unwrap();
+    }
+    expect_close(&mut from_socket, CloseCode::Policy).await;
+}
+
+struct RelaySession {
+    id: u8,
+    handle: Session,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for RelaySession {
+    type ID = u8;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.id
+    }
+
+    async fn text(&mut self, _text: String) -> Result<(), Error> {
+        unreachable!("data is unified")
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        unreachable!("data is unified")
+    }
+
+    async fn data(&mut self, data: ezsockets::Bytes, kind: DataKind) -> Result<(), Error> {
+        self.handle
+            .binary([format!("{kind:?}:").as_bytes(), &data].concat())?;
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_unify_data() {
+    let (socket, to_socket, mut from_socket) = transport::socket(Default::default());
+    let config = SessionConfig {
+        unify_data: true,
+        ..Default::default()
+    };
+    let _session =
+        Session::create_with_config(|handle| RelaySession { id: 0, handle }, 0, socket, config);
+    to_socket
+        .unbounded_send(RawMessage::Text("a".to_string()))
+        .unwrap();
+    to_socket
+        .unbounded_send(RawMessage::Binary(b"b".to_vec().into()))
+        .unwrap();
+    let mut relayed = Vec::new();
+    while relayed.len() < 2 {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Binary(bytes) => relayed.push(String::from_utf8(bytes.to_vec()).unwrap()),
+            RawMessage::Ping(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+    assert_eq!(relayed, ["Text:a", "Binary:b"]);
+}
+
+#[tokio::test]
+async fn test_hello_timeout() {
+    let (socket, _to_socket, mut from_socket) = transport::socket(Default::default());
+    let config = SessionConfig {
+        hello: Some(Hello::new(Duration::from_millis(50))),
+        ..Default::default()
+    };
+    let _session = Session::create_with_config(|_| IdleSession { id: 0 }, 0, socket, config);
+    expect_close(&mut from_socket, CloseCode::Policy).await;
+}
+
+/// Session answering every text message with `<text> done`, counting them.
+struct OrderSession {
+    id: u8,
+    handle: Session,
+    orders: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for OrderSession {
+    type ID = u8;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.id
+    }
+
+    async fn text(&mut self, text: String) -> Result<(), Error> {
+        self.orders.fetch_add(1, Ordering::Relaxed);
+        self.handle.text(format!("{text} done"))?;
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+}
+
+async fn next_text(from_socket: &mut mpsc::UnboundedReceiver<RawMessage>) -> String {
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Text(text) => return text,
+            RawMessage::Ping(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_quota_throttles_session() {
+    let (socket, to_socket, mut from_socket) = transport::socket(Default::default());
+    let config = SessionConfig {
+        quota: Some(Quota {
+            identity: "tenant".to_string(),
+            max_messages: Some(1),
+            max_bytes: None,
+            window: Duration::from_secs(60 * 60 * 24),
+            action: QuotaAction::Throttle,
+            store: Arc::new(MemoryQuotaStore::default()),
+        }),
+        ..Default::default()
+    };
+    let orders = Arc::new(AtomicUsize::new(0));
+    let session = Session::create_with_config(
+        |handle| OrderSession {
+            id: 0,
+            handle,
+            orders: orders.clone(),
+        },
+        0,
+        socket,
+        config,
+    );
+    for request in ["order-1", "order-2"] {
+        to_socket
+            .unbounded_send(RawMessage::Text(request.to_string()))
+            .unwrap();
+    }
+    assert_eq!(next_text(&mut from_socket).await, "order-1 done");
+
+    // The second order waits for the next window, without holding back what the session sends meanwhile.
+    session.text("still there".to_string()).unwrap();
+    assert_eq!(next_text(&mut from_socket).await, "still there");
+    assert_eq!(orders.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn test_idempotency() {
+    let config = SessionConfig {
+        idempotency: Some(Idempotency {
+            identity: String::from("alice"),
+            key: IdempotencyKey::new(|message| match message {
+                Message::Text(text) => text.split(' ').next().map(String::from),
+                _ => None,
+            }),
+            store: Arc::new(MemoryIdempotencyStore::new(Duration::from_secs(60))),
+        }),
+        ..Default::default()
+    };
+    let orders = Arc::new(AtomicUsize::new(0));
+    let connect = |id| {
+        let (socket, to_socket, from_socket) = transport::socket(Default::default());
+        let session = Session::create_with_config(
+            |handle| OrderSession {
+                id,
+                handle,
+                orders: orders.clone(),
+            },
+            id,
+            socket,
+            config.clone(),
+        );
+        (session, to_socket, from_socket)
+    };
+
+    let (_first, to_socket, mut from_socket) = connect(0);
+    to_socket
+        .unbounded_send(RawMessage::Text("order-1".to_string()))
+        .unwrap();
+    assert_eq!(next_text(&mut from_socket).await, "order-1 done");
+
+    // The client retries the request after reconnecting, e.g. because the connection was lost before the response arrived.
+    let (_second, to_socket, mut from_socket) = connect(1);
+    for request in ["order-1", "order-2"] {
+        to_socket
+            .unbounded_send(RawMessage::Text(request.to_string()))
+            .unwrap();
+    }
+    assert_eq!(next_text(&mut from_socket).await, "order-1 done");
+    assert_eq!(next_text(&mut from_socket).await, "order-2 done");
+    assert_eq!(orders.load(Ordering::Relaxed), 2);
+}
+
+struct FlakySession {
+    id: u8,
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for FlakySession {
+    type ID = u8;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.id
+    }
+
+    async fn text(&mut self, text: String) -> Result<(), Error> {
+        Err(text.into())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), Error> {
+        let () = params;
+        Ok(())
+    }
+
+    async fn on_handler_error(&mut self, error: &Error) -> ErrorAction {
+        if error.to_string() == "transient" {
+            return ErrorAction::Ignore;
+        }
+        ErrorAction::CloseSession(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: error.to_string(),
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_handler_error_action() {
+    let (socket, to_socket, mut from_socket) = transport::socket(Default::default());
+    let _session = Session::create(|_| FlakySession { id: 0 }, 0, socket);
+    for text in ["transient", "fatal"] {
+        to_socket
+            .unbounded_send(RawMessage::Text(text.to_string()))
+            .unwrap();
+    }
+    expect_close(&mut from_socket, CloseCode::Policy).await;
+}
+
+#[tokio::test]
+async fn test_session_shutdown() {
+    let (socket, to_socket, mut from_socket) = transport::socket(Default::default());
+    let session = Session::create(|_| IdleSession { id: 0 }, 0, socket);
+    let shutdown = tokio::spawn({
+        let session = session.clone();
+        async move { session.shutdown().await }
+    });
+    expect_close(&mut from_socket, CloseCode::Normal).await;
+    // Still waiting for the peer to answer the Close frame.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!shutdown.is_finished());
+
+    to_socket
+        .unbounded_send(RawMessage::Close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: String::new(),
+        })))
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(1), shutdown)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_call_closed_session() {
+    let (socket, to_socket, _from_socket) = transport::socket(Default::default());
+    let session = Session::create(|_| IdleSession { id: 0 }, 0, socket);
+    assert!(session.call(()).is_ok());
+    drop(to_socket);
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while session.alive() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(session.call(()).is_err());
+    assert!(session
+        .call_with(|_: tokio::sync::oneshot::Sender<()>| ())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_session_sync() {
+    let (socket, _to_socket, mut from_socket) = transport::socket(Default::default());
+    let session = Session::create(|_| IdleSession { id: 0 }, 0, socket);
+    for i in 0..10 {
+        session.text(i.to_string()).unwrap();
+    }
+    session.sync().await;
+    // Already written once the barrier resolved, nothing left to wait for.
+    let mut texts = Vec::new();
+    while let Ok(message) = from_socket.try_recv() {
+        if let RawMessage::Text(text) = message {
+            texts.push(text);
+        }
+    }
+    assert_eq!(texts, (0..10).map(|i| i.to_string()).collect::<Vec<_>>());
+}
+
+/// Peer which never sends anything, and whose writes wait until it's opened.
+struct Gated {
+    open: Arc<std::sync::atomic::AtomicBool>,
+    waker: Arc<std::sync::Mutex<Option<std::task::Waker>>>,
+    written: mpsc::UnboundedSender<RawMessage>,
+}
+
+impl futures::Stream for Gated {
+    type Item = Result<RawMessage, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Pending
+    }
+}
+
+impl futures::Sink<RawMessage> for Gated {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.open.load(std::sync::atomic::Ordering::Relaxed) {
+            return Poll::Ready(Ok(()));
+        }
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: RawMessage) -> Result<(), Self::Error> {
+        let _ = self.written.unbounded_send(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn test_send_adaptive() {
+    use ezsockets::CongestionState;
+    use ezsockets::Message;
+
+    let open = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let waker = Arc::new(std::sync::Mutex::new(None::<std::task::Waker>));
+    let (written, mut from_socket) = mpsc::unbounded();
+    let peer = Gated {
+        open: open.clone(),
+        waker: waker.clone(),
+        written,
+    };
+    let config = ezsockets::SocketConfig {
+        stall_timeout: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let session = Session::create(
+        |_| IdleSession { id: 0 },
+        0,
+        ezsockets::Socket::new(peer, config),
+    );
+    session.text("first".to_string()).unwrap();
+    let mut states = session.congestion_changes();
+    states
+        .wait_for(|state| *state == CongestionState::Stalled)
+        .await
+        .unwrap();
+
+    // Reduced while stalled, or dropped without a reduced variant.
+    let text = |text: &str| Message::Text(text.to_string());
+    session
+        .send_adaptive(text("full 1"), Some(text("reduced 1")))
+        .unwrap();
+    session.send_adaptive(text("full 2"), None).unwrap();
+    // Lets the session pick the variants before the connection recovers.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    open.store(true, std::sync::atomic::Ordering::Relaxed);
+    if let Some(waker) = waker.lock().unwrap().take() {
+        waker.wake();
+    }
+    states
+        .wait_for(|state| *state == CongestionState::Ready)
+        .await
+        .unwrap();
+    session
+        .send_adaptive(text("full 3"), Some(text("reduced 3")))
+        .unwrap();
+    session.sync().await;
+
+    let mut texts = Vec::new();
+    while let Ok(message) = from_socket.try_recv() {
+        if let RawMessage::Text(text) = message {
+            texts.push(text);
+        }
+    }
+    assert_eq!(texts, ["first", "reduced 1", "full 3"]);
+}
+
+#[tokio::test]
+async fn test_reduced_encodings() {
+    use ezsockets::CongestionState;
+    use ezsockets::Message;
+    use ezsockets::ReducedEncodings;
+
+    let open = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let waker = Arc::new(std::sync::Mutex::new(None::<std::task::Waker>));
+    let (written, mut from_socket) = mpsc::unbounded();
+    let peer = Gated {
+        open: open.clone(),
+        waker: waker.clone(),
+        written,
+    };
+    let socket_config = ezsockets::SocketConfig {
+        stall_timeout: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let text = |text: &str| Message::Text(text.to_string());
+    let encodings = ReducedEncodings::new(|message| match message {
+        Message::Text(text) => text.split_once(':').map(|(kind, _)| kind.to_string()),
+        _ => None,
+    })
+    .register("image", move |_| Some(text("image:thumbnail")))
+    .register("cursor", |_| None);
+    let config = SessionConfig {
+        reduced_encodings: Some(encodings),
+        ..Default::default()
+    };
+    let session = Session::create_with_config(
+        |_| IdleSession { id: 0 },
+        0,
+        ezsockets::Socket::new(peer, socket_config),
+        config,
+    );
+    session.text("first".to_string()).unwrap();
+    let mut states = session.congestion_changes();
+    states
+        .wait_for(|state| *state == CongestionState::Stalled)
+        .await
+        .unwrap();
+
+    // Reduced or dropped while stalled, unless the type has no reduced encoding.
+    session.text("image:full".to_string()).unwrap();
+    session.text("cursor:1,2".to_string()).unwrap();
+    session.text("chat:hello".to_string()).unwrap();
+    // Lets the session pick the encodings before the connection recovers.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    open.store(true, std::sync::atomic::Ordering::Relaxed);
+    if let Some(waker) = waker.lock().unwrap().take() {
+        waker.wake();
+    }
+    states
+        .wait_for(|state| *state == CongestionState::Ready)
+        .await
+        .unwrap();
+    session.text("image:full".to_string()).unwrap();
+    session.sync().await;
+
+    let mut texts = Vec::new();
+    while let Ok(message) = from_socket.try_recv() {
+        if let RawMessage::Text(text) = message {
+            texts.push(text);
+        }
+    }
+    assert_eq!(
+        texts,
+        ["first", "image:thumbnail", "chat:hello", "image:full"]
+    );
+}
@@ -0,0 +1,1130 @@
+mod transport;
+
+use ezsockets::CloseCode;
+use ezsockets::CloseFrame;
+use ezsockets::Deduplication;
+use ezsockets::Filter;
+use ezsockets::Fragment;
+use ezsockets::IdleTimeout;
+use ezsockets::Message;
+use ezsockets::MessageFilter;
+use ezsockets::MessageKind;
+use ezsockets::NetworkConditions;
+use ezsockets::OverflowPolicy;
+use ezsockets::RawMessage;
+use ezsockets::Socket;
+use ezsockets::SocketConfig;
+use ezsockets::TimerWheel;
+use ezsockets::TrySendError;
+use futures::StreamExt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use transport::socket;
+
+fn text(message: Option<Result<Message, ezsockets::Error>>) -> String {
+    match message {
+        Some(Ok(Message::Text(text))) => text,
+        message => panic!("expected text message, got {message:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_deduplication() {
+    let config = SocketConfig {
+        deduplication: Some(Deduplication::new(
+            |message| match message {
+                Message::Text(text) => Some(text.clone()),
+                _ => None,
+            },
+            16,
+            Duration::from_secs(60),
+        )),
+        ..Default::default()
+    };
+    let (mut socket, to_socket, _from_socket) = socket(config);
+    for message in ["a", "a", "b", "a", "c"] {
+        to_socket
+            .unbounded_send(RawMessage::Text(message.to_string()))
+            .unwrap();
+    }
+    assert_eq!(text(socket.recv().await), "a");
+    assert_eq!(text(socket.recv().await), "b");
+    assert_eq!(text(socket.recv().await), "c");
+}
+
+#[tokio::test]
+async fn test_filter() {
+    let config = SocketConfig {
+        filter: Some(MessageFilter::new(|message| match message {
+            RawMessage::Text(text) if text == "ping" => {
+                Filter::Fastpath(Message::Text("pong".to_string()))
+            }
+            RawMessage::Text(text) if text == "noise" => Filter::Drop,
+            _ => Filter::Deliver,
+        })),
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+    for message in ["ping", "noise", "hello"] {
+        to_socket
+            .unbounded_send(RawMessage::Text(message.to_string()))
+            .unwrap();
+    }
+    assert_eq!(text(socket.recv().await), "hello");
+    loop {
+        match from_socket.next().await {
+            Some(RawMessage::Text(text)) => break assert_eq!(text, "pong"),
+            Some(_) => continue,
+            None => panic!("socket closed before the reply was sent"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_unsolicited_pong() {
+    let (mut socket, to_socket, _from_socket) = socket(Default::default());
+    to_socket
+        .unbounded_send(RawMessage::Pong(b"unsolicited".to_vec()))
+        .unwrap();
+    to_socket
+        .unbounded_send(RawMessage::Text("hello".to_string()))
+        .unwrap();
+    assert_eq!(text(socket.recv().await), "hello");
+}
+
+#[tokio::test]
+async fn test_latency() {
+    let (socket, to_socket, mut from_socket) = socket(Default::default());
+    assert_eq!(socket.latency(), None);
+    let mut latency = socket.latency_changes();
+    let payload = match from_socket.next().await.unwrap() {
+        RawMessage::Ping(payload) => payload,
+        message => panic!("expected a Ping, got {message:?}"),
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    to_socket.unbounded_send(RawMessage::Pong(payload)).unwrap();
+    latency.changed().await.unwrap();
+    assert!(socket.latency().unwrap() >= Duration::from_millis(20));
+}
+
+#[tokio::test]
+async fn test_auto_pong() {
+    for auto_pong in [true, false] {
+        let config = SocketConfig {
+            auto_pong,
+            ..Default::default()
+        };
+        let (mut socket, to_socket, mut from_socket) = socket(config);
+        to_socket
+            .unbounded_send(RawMessage::Ping(b"probe".to_vec()))
+            .unwrap();
+        to_socket
+            .unbounded_send(RawMessage::Text("hello".to_string()))
+            .unwrap();
+        assert_eq!(text(socket.recv().await), "hello");
+        socket
+            .send(Message::Text("done".to_string()))
+            .await
+            .unwrap();
+        let mut pong = None;
+        loop {
+            match from_socket.next().await.unwrap() {
+                RawMessage::Pong(bytes) => pong = Some(bytes),
+                RawMessage::Text(text) if text == "done" => break,
+                _ => continue,
+            }
+        }
+        assert_eq!(pong, auto_pong.then(|| b"probe".to_vec()));
+    }
+}
+
+#[tokio::test]
+async fn test_expose_control_frames() {
+    let config = SocketConfig {
+        expose_control_frames: true,
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+    to_socket
+        .unbounded_send(RawMessage::Pong(b"server time".to_vec()))
+        .unwrap();
+    to_socket
+        .unbounded_send(RawMessage::Ping(b"probe".to_vec()))
+        .unwrap();
+    assert!(matches!(
+        socket.recv().await,
+        Some(Ok(Message::Pong(payload))) if payload == b"server time"[..]
+    ));
+    assert!(matches!(
+        socket.recv().await,
+        Some(Ok(Message::Ping(payload))) if payload == b"probe"[..]
+    ));
+    // Exposed Pings are still answered.
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Pong(payload) if payload == b"probe" => break,
+            _ => continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let (mut socket, to_socket, mut from_socket) = socket(Default::default());
+    assert_eq!(socket.stats().last_activity, None);
+    to_socket
+        .unbounded_send(RawMessage::Text("hello".to_string()))
+        .unwrap();
+    to_socket
+        .unbounded_send(RawMessage::Pong(b"ignored".to_vec()))
+        .unwrap();
+    assert_eq!(text(socket.recv().await), "hello");
+    socket
+        .send(Message::Binary(vec![1, 2, 3].into()))
+        .await
+        .unwrap();
+    socket.sync().await;
+    while !matches!(from_socket.next().await.unwrap(), RawMessage::Binary(_)) {}
+
+    let stats = socket.stats();
+    assert_eq!(
+        (stats.messages_received, stats.bytes_received),
+        (1, 5),
+        "control frames aren't accounted"
+    );
+    assert_eq!((stats.messages_sent, stats.bytes_sent), (1, 3));
+    assert!(stats.last_activity.is_some());
+}
+
+#[tokio::test]
+async fn test_heartbeat_wheel() {
+    let config = SocketConfig {
+        heartbeat: Duration::from_millis(20),
+        timeout: Duration::from_millis(100),
+        heartbeat_wheel: Some(TimerWheel::new(Duration::from_millis(5), 4)),
+        ..Default::default()
+    };
+    let (_alive, to_alive, mut from_alive) = socket(config.clone());
+    let (_dead, _to_dead, mut from_dead) = socket(config);
+    for _ in 0..3 {
+        match from_alive.next().await.unwrap() {
+            RawMessage::Ping(payload) => {
+                to_alive.unbounded_send(RawMessage::Pong(payload)).unwrap()
+            }
+            message => panic!("expected a Ping, got {message:?}"),
+        }
+    }
+    // The socket whose peer doesn't answer is closed once the timeout elapses.
+    loop {
+        match from_dead.next().await.unwrap() {
+            RawMessage::Ping(_) => continue,
+            RawMessage::Close(frame) => {
+                assert!(matches!(
+                    frame,
+                    Some(CloseFrame {
+                        code: CloseCode::Normal,
+                        ..
+                    })
+                ));
+                break;
+            }
+            message => panic!("expected a Ping or Close, got {message:?}"),
+        }
+    }
+}
+
+/// Peer which never reads, so that nothing can be written to it.
+struct Stalled;
+
+impl futures::Stream for Stalled {
+    type Item = Result<RawMessage, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Pending
+    }
+}
+
+impl futures::Sink<RawMessage> for Stalled {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Pending
+    }
+
+    fn start_send(self: Pin<&mut Self>, _item: RawMessage) -> Result<(), Self::Error> {
+        unreachable!("never ready")
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Pending
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Pending
+    }
+}
+
+/// Peer which never sends anything, counting the flushes of the messages written to it,
+/// and the Text messages written before the last flush.
+#[derive(Default)]
+struct Counting {
+    flushes: Arc<std::sync::atomic::AtomicUsize>,
+    texts: usize,
+    flushed_texts: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl futures::Stream for Counting {
+    type Item = Result<RawMessage, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Pending
+    }
+}
+
+impl futures::Sink<RawMessage> for Counting {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: RawMessage) -> Result<(), Self::Error> {
+        if let RawMessage::Text(_) = item {
+            self.texts += 1;
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.flushes
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.flushed_texts
+            .store(self.texts, std::sync::atomic::Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Peer sending `limit` Text messages as fast as they're read, counting them.
+struct Flooding {
+    read: Arc<std::sync::atomic::AtomicUsize>,
+    limit: usize,
+}
+
+impl futures::Stream for Flooding {
+    type Item = Result<RawMessage, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let read = self.read.load(std::sync::atomic::Ordering::Relaxed);
+        if read == self.limit {
+            return Poll::Pending;
+        }
+        self.read
+            .store(read + 1, std::sync::atomic::Ordering::Relaxed);
+        Poll::Ready(Some(Ok(RawMessage::Text(read.to_string()))))
+    }
+}
+
+impl futures::Sink<RawMessage> for Flooding {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, _item: RawMessage) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Peer which never sends anything, and doesn't accept writes until it's opened.
+#[derive(Default)]
+struct Gated {
+    open: Arc<std::sync::atomic::AtomicBool>,
+    waker: Arc<std::sync::Mutex<Option<std::task::Waker>>>,
+}
+
+impl Gated {
+    fn opener(&self) -> impl FnOnce() {
+        let open = self.open.clone();
+        let waker = self.waker.clone();
+        move || {
+            open.store(true, std::sync::atomic::Ordering::Relaxed);
+            if let Some(waker) = waker.lock().unwrap().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl futures::Stream for Gated {
+    type Item = Result<RawMessage, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Pending
+    }
+}
+
+impl futures::Sink<RawMessage> for Gated {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.open.load(std::sync::atomic::Ordering::Relaxed) {
+            return Poll::Ready(Ok(()));
+        }
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn start_send(self: Pin<&mut Self>, _item: RawMessage) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn wait_for_congestion(
+    states: &mut tokio::sync::watch::Receiver<ezsockets::CongestionState>,
+    expected: ezsockets::CongestionState,
+) {
+    let wait = states.wait_for(|state| *state == expected);
+    tokio::time::timeout(Duration::from_secs(1), wait)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_congestion() {
+    use ezsockets::CongestionState;
+
+    let config = SocketConfig {
+        congestion_threshold: 2,
+        stall_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let peer = Gated::default();
+    let open = peer.opener();
+    let socket = Socket::new(peer, config);
+    let mut states = socket.sink.congestion_changes();
+    assert_eq!(socket.sink.congestion(), CongestionState::Ready);
+    for i in 0..5 {
+        socket.send(Message::Text(i.to_string())).await.unwrap();
+    }
+    assert_eq!(socket.sink.congestion(), CongestionState::Buffering);
+    wait_for_congestion(&mut states, CongestionState::Stalled).await;
+    assert!(socket.sink.buffered() > 2);
+
+    // Writes make progress again, the outbox drains.
+    open();
+    wait_for_congestion(&mut states, CongestionState::Ready).await;
+    assert_eq!(socket.sink.buffered(), 0);
+}
+
+#[tokio::test]
+async fn test_yield_after() {
+    let read = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let transport = Flooding {
+        read: read.clone(),
+        limit: 10_000,
+    };
+    let config = SocketConfig {
+        yield_after: Some(8),
+        ..Default::default()
+    };
+    let mut socket = Socket::new(transport, config);
+    // The stream actor runs until it yields, on the single threaded test runtime.
+    tokio::task::yield_now().await;
+    let read = read.load(std::sync::atomic::Ordering::Relaxed);
+    assert!(read > 0, "the stream actor didn't run");
+    assert!(read < 10_000, "the stream actor didn't yield");
+    assert_eq!(text(socket.recv().await), "0");
+}
+
+#[tokio::test]
+async fn test_sink_error() {
+    let (mut socket, _to_socket, from_socket) = socket(Default::default());
+    // Writing the first heartbeat Ping fails once the peer is gone.
+    drop(from_socket);
+    assert!(matches!(socket.recv().await, Some(Err(_))));
+    assert!(socket.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn test_write_coalescing() {
+    let peer = Counting::default();
+    let flushes = peer.flushes.clone();
+    let socket = Socket::new(peer, Default::default());
+    for i in 0..100 {
+        socket.send(Message::Text(i.to_string())).await.unwrap();
+    }
+    socket.sync().await;
+    let flushes = flushes.load(std::sync::atomic::Ordering::Relaxed);
+    assert!(flushes < 10, "{flushes} flushes for 100 messages");
+}
+
+#[tokio::test]
+async fn test_sync() {
+    let peer = Counting::default();
+    let flushed_texts = peer.flushed_texts.clone();
+    let socket = Socket::new(peer, Default::default());
+    for round in 1..=3 {
+        for i in 0..10 {
+            socket.send(Message::Text(i.to_string())).await.unwrap();
+        }
+        // Every message sent before the barrier is written and flushed once it resolves.
+        socket.sync().await;
+        assert_eq!(
+            flushed_texts.load(std::sync::atomic::Ordering::Relaxed),
+            round * 10
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_send_capacity() {
+    let config = SocketConfig {
+        send_capacity: Some(2),
+        ..Default::default()
+    };
+    let socket = Socket::new(Stalled, config);
+    // The first message is buffered by the split sink and the second one taken by the sink actor,
+    // which then waits for the peer forever.
+    for message in ["a", "b", "c", "d"] {
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            socket.send(Message::Text(message.to_string())),
+        )
+        .await
+        .expect("queue should have room")
+        .unwrap();
+    }
+    let full = tokio::time::timeout(
+        Duration::from_millis(100),
+        socket.send(Message::Text("e".to_string())),
+    )
+    .await;
+    assert!(full.is_err(), "send should wait while the queue is full");
+}
+
+#[tokio::test]
+async fn test_try_send() {
+    let config = SocketConfig {
+        send_capacity: Some(2),
+        ..Default::default()
+    };
+    let socket = Socket::new(Stalled, config);
+    for message in ["a", "b", "c", "d"] {
+        socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+    assert!(matches!(
+        socket.try_send(Message::Text("e".to_string())),
+        Err(TrySendError::Full(Message::Text(text))) if text == "e"
+    ));
+    // Control frames don't take room in the queue.
+    socket.try_send(Message::Ping("ping".into())).unwrap();
+}
+
+#[test]
+fn test_raw_message_kind() {
+    let close = RawMessage::Close(Some(CloseFrame::normal()));
+    assert_eq!(close.kind(), MessageKind::Close);
+    assert!(close.is_control());
+    assert_eq!(close.len(), 2 + CloseFrame::normal().reason.len());
+    let text = RawMessage::Text("hello".to_string());
+    assert!(!text.is_control());
+    assert_eq!(text.len(), 5);
+    assert!(matches!(Message::try_from(text), Ok(Message::Text(text)) if text == "hello"));
+    let fragment = RawMessage::Fragment(Fragment::Final("end".into()));
+    assert_eq!(fragment.kind(), MessageKind::Fragment);
+    assert!(Message::try_from(fragment).is_err());
+}
+
+#[test]
+fn test_close_frame_builder() {
+    use ezsockets::InvalidCloseFrame;
+
+    let frame = CloseFrame::builder(CloseCode::Policy)
+        .reason("forbidden")
+        .build()
+        .unwrap();
+    assert_eq!(frame.to_string(), "1008 (policy violation): forbidden");
+    assert_eq!(CloseFrame::normal().to_string(), "1000 (normal closure)");
+    assert!(matches!(
+        CloseFrame::builder(CloseCode::Abnormal).build(),
+        Err(InvalidCloseFrame::ReservedCode(_))
+    ));
+    assert!(matches!(
+        CloseFrame::builder(CloseCode::Normal)
+            .reason("x".repeat(124))
+            .build(),
+        Err(InvalidCloseFrame::ReasonTooLong(124))
+    ));
+    assert!(CloseFrame::policy("é".repeat(62)).validate().is_err());
+}
+
+#[tokio::test]
+async fn test_invalid_close_frame() {
+    let (socket, _to_socket, mut from_socket) = socket(Default::default());
+    socket
+        .send(Message::Close(Some(CloseFrame::policy("é".repeat(62)))))
+        .await
+        .unwrap();
+    // The reason is truncated to the last character fitting in a Close frame.
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Close(Some(frame)) => {
+                assert_eq!(frame.reason, "é".repeat(61));
+                break;
+            }
+            _ => continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_send_after_close() {
+    let (socket, _to_socket, from_socket) = socket(Default::default());
+    drop(from_socket);
+    // The sink actor stops on the first failed write, later sends hand the message back.
+    let error = loop {
+        match socket.send(Message::Text("hello".to_string())).await {
+            Ok(()) => tokio::time::sleep(Duration::from_millis(10)).await,
+            Err(error) => break error,
+        }
+    };
+    assert!(matches!(error.into_inner(), Message::Text(text) if text == "hello"));
+}
+
+#[tokio::test]
+async fn test_split_reunite() {
+    let (first, _, _) = socket(Default::default());
+    let (second, _, _) = socket(Default::default());
+    let (first_sink, first_stream) = first.split();
+    let (second_sink, second_stream) = second.split();
+    let ezsockets::ReuniteError(first_sink, second_stream) =
+        Socket::reunite(first_sink, second_stream).unwrap_err();
+    assert!(Socket::reunite(first_sink, first_stream).is_ok());
+    assert!(Socket::reunite(second_sink, second_stream).is_ok());
+}
+
+#[tokio::test]
+async fn test_futures_adapters() {
+    let (socket, to_socket, mut from_socket) = socket(Default::default());
+    let (sink, mut stream) = socket.split();
+    let messages = ["a", "b"].map(|text| Ok(Message::Text(text.to_string())));
+    futures::stream::iter(messages)
+        .forward(sink.into_futures_sink())
+        .await
+        .unwrap();
+    let mut sent = Vec::new();
+    while sent.len() < 2 {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Text(text) => sent.push(text),
+            _ => continue,
+        }
+    }
+    assert_eq!(sent, ["a", "b"]);
+
+    to_socket
+        .unbounded_send(RawMessage::Text("c".to_string()))
+        .unwrap();
+    assert_eq!(text(stream.next().await), "c");
+}
+
+#[tokio::test]
+async fn test_max_message_size() {
+    let config = SocketConfig {
+        max_message_size: Some(4),
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+    for message in ["ok", "too big"] {
+        to_socket
+            .unbounded_send(RawMessage::Text(message.to_string()))
+            .unwrap();
+    }
+    assert_eq!(text(socket.recv().await), "ok");
+    assert!(matches!(
+        socket.recv().await,
+        Some(Ok(Message::Close(Some(CloseFrame {
+            code: CloseCode::Size,
+            ..
+        }))))
+    ));
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Close(frame) => {
+                break assert!(matches!(frame.unwrap().code, CloseCode::Size))
+            }
+            _ => continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_max_message_size_before_filter() {
+    let config = SocketConfig {
+        max_message_size: Some(4),
+        filter: Some(MessageFilter::new(|_| Filter::Drop)),
+        ..Default::default()
+    };
+    let (_socket, to_socket, mut from_socket) = socket(config);
+    to_socket
+        .unbounded_send(RawMessage::Text("too big".to_string()))
+        .unwrap();
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Close(frame) => {
+                break assert!(matches!(frame.unwrap().code, CloseCode::Size))
+            }
+            _ => continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_transfer_progress() {
+    use ezsockets::DataKind;
+    use ezsockets::Fragment;
+    use std::sync::Mutex;
+
+    let (socket, _to_socket, mut from_socket) = socket(Default::default());
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let mut transfer = socket.transfer(DataKind::Binary).total(6).on_progress({
+        let progress = progress.clone();
+        move |sent, total| progress.lock().unwrap().push((sent, total))
+    });
+    transfer.send(b"ab".to_vec()).await.unwrap();
+    transfer.send(b"cd".to_vec()).await.unwrap();
+    transfer.finish(b"ef".to_vec()).await.unwrap();
+    assert_eq!(
+        *progress.lock().unwrap(),
+        [(2, Some(6)), (4, Some(6)), (6, Some(6))]
+    );
+
+    let mut fragments = Vec::new();
+    while fragments.len() < 3 {
+        if let RawMessage::Fragment(fragment) = from_socket.next().await.unwrap() {
+            fragments.push(fragment);
+        }
+    }
+    assert!(matches!(
+        fragments.as_slice(),
+        [
+            Fragment::First(DataKind::Binary, first),
+            Fragment::Continuation(continuation),
+            Fragment::Final(last),
+        ] if first == "ab" && continuation == "cd" && last == "ef"
+    ));
+}
+
+#[tokio::test]
+async fn test_write_fragments_disabled() {
+    use ezsockets::DataKind;
+    use ezsockets::Fragment;
+
+    let config = SocketConfig {
+        write_fragments: false,
+        ..Default::default()
+    };
+    let (socket, _to_socket, mut from_socket) = socket(config);
+    socket
+        .sink
+        .send_fragment(Fragment::First(DataKind::Text, "hello".into()))
+        .unwrap();
+    socket
+        .sink
+        .send_fragment(Fragment::Final(", world".into()))
+        .unwrap();
+
+    // The transport gets the reassembled message instead of the fragments.
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Text(text) => break assert_eq!(text, "hello, world"),
+            RawMessage::Fragment(_) => panic!("fragment written"),
+            _ => continue,
+        }
+    }
+}
+
+/// Waits for the Close frame sent by the socket.
+async fn close_sent(from_socket: &mut futures::channel::mpsc::UnboundedReceiver<RawMessage>) {
+    while !matches!(from_socket.next().await, Some(RawMessage::Close(_)) | None) {}
+}
+
+#[tokio::test]
+async fn test_close_answered() {
+    let (mut socket, to_socket, mut from_socket) = socket(Default::default());
+    to_socket
+        .unbounded_send(RawMessage::Close(Some(CloseFrame::normal())))
+        .unwrap();
+    assert!(matches!(socket.recv().await, Some(Ok(Message::Close(_)))));
+    loop {
+        match from_socket.next().await {
+            Some(RawMessage::Close(frame)) => {
+                break assert!(matches!(frame.unwrap().code, CloseCode::Normal))
+            }
+            Some(_) => continue,
+            None => panic!("connection dropped before answering the Close frame"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_close_graceful() {
+    let (socket, _to_socket, mut from_socket) = socket(Default::default());
+    for text in ["a", "b", "c"] {
+        socket.send(Message::Text(text.to_string())).await.unwrap();
+    }
+    socket.close_graceful(Some(CloseFrame::normal())).await;
+    assert!(socket
+        .send(Message::Text("late".to_string()))
+        .await
+        .is_err());
+    assert!(socket.sink.is_closed());
+
+    let mut written = Vec::new();
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Text(text) => written.push(text),
+            RawMessage::Close(_) => break,
+            _ => continue,
+        }
+    }
+    assert_eq!(written, ["a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn test_control_frames_first() {
+    let (socket, _to_socket, mut from_socket) = socket(Default::default());
+    for text in ["a", "b", "c"] {
+        socket.send(Message::Text(text.to_string())).await.unwrap();
+    }
+    // The Close frame waits for the queued messages, the Ping doesn't.
+    socket.send(Message::Close(None)).await.unwrap();
+    socket
+        .send(Message::Ping("keepalive".into()))
+        .await
+        .unwrap();
+
+    let mut written = Vec::new();
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Text(text) => written.push(text),
+            RawMessage::Ping(payload) if payload == b"keepalive" => {
+                written.push(String::from("ping"))
+            }
+            RawMessage::Close(_) => break,
+            _ => continue,
+        }
+    }
+    assert_eq!(written, ["ping", "a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    let config = SocketConfig {
+        idle_timeout: Some(
+            IdleTimeout::new(Duration::from_millis(100)).close_frame(CloseFrame::policy("idle")),
+        ),
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    // Messages postpone the timeout.
+    to_socket
+        .unbounded_send(RawMessage::Text("hello".to_string()))
+        .unwrap();
+    let started_at = std::time::Instant::now();
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Close(Some(frame)) => {
+                assert_eq!(frame.reason, "idle");
+                break;
+            }
+            RawMessage::Ping(_) => continue,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+    assert!(started_at.elapsed() >= Duration::from_millis(80));
+    assert!(matches!(socket.recv().await, Some(Ok(Message::Text(_)))));
+    assert!(matches!(
+        socket.recv().await,
+        Some(Ok(Message::Close(Some(frame)))) if frame.reason == "idle"
+    ));
+}
+
+#[tokio::test]
+async fn test_close_timeout_unresponsive_peer() {
+    let config = SocketConfig {
+        idle_timeout: Some(IdleTimeout::new(Duration::from_millis(10))),
+        close_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let mut socket = Socket::new(Stalled, config);
+    // The Close frame can't be written, closing gives up after the close timeout.
+    let result = tokio::time::timeout(Duration::from_millis(500), socket.recv()).await;
+    assert!(matches!(result, Ok(Some(Ok(Message::Close(Some(_)))))));
+}
+
+#[tokio::test]
+async fn test_recv_overflow() {
+    let send = |to_socket: &futures::channel::mpsc::UnboundedSender<RawMessage>| {
+        for text in ["a", "b", "c", "d"] {
+            to_socket
+                .unbounded_send(RawMessage::Text(text.to_string()))
+                .unwrap();
+        }
+    };
+
+    for (policy, expected) in [
+        (OverflowPolicy::Block, ["a", "b", "c", "d"].as_slice()),
+        (OverflowPolicy::DropOldest, &["c", "d"]),
+    ] {
+        let config = SocketConfig {
+            recv_capacity: Some(2),
+            recv_overflow: policy,
+            ..Default::default()
+        };
+        let (mut socket, to_socket, _from_socket) = socket(config);
+        send(&to_socket);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        for text in expected {
+            assert_eq!(self::text(socket.recv().await), *text);
+        }
+    }
+
+    let config = SocketConfig {
+        recv_capacity: Some(2),
+        recv_overflow: OverflowPolicy::Close,
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+    send(&to_socket);
+    loop {
+        if let RawMessage::Close(frame) = from_socket.next().await.unwrap() {
+            break assert!(matches!(frame.unwrap().code, CloseCode::Policy));
+        }
+    }
+    assert_eq!(text(socket.recv().await), "a");
+    assert_eq!(text(socket.recv().await), "b");
+    assert!(matches!(socket.recv().await, Some(Ok(Message::Close(_)))));
+}
+
+#[tokio::test]
+async fn test_network_conditions() {
+    let config = SocketConfig {
+        network_conditions: Some(NetworkConditions {
+            latency: Duration::from_millis(50),
+            // 100 bytes take 100ms to be sent.
+            bandwidth: Some(1000),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+    let start = std::time::Instant::now();
+    for _ in 0..2 {
+        socket.send(Message::Text("x".repeat(100))).await.unwrap();
+    }
+    let mut delivered = Vec::new();
+    while delivered.len() < 2 {
+        if let RawMessage::Text(_) = from_socket.next().await.unwrap() {
+            delivered.push(start.elapsed());
+        }
+    }
+    assert!(delivered[0] >= Duration::from_millis(150));
+    assert!(delivered[1] >= Duration::from_millis(250));
+
+    let start = std::time::Instant::now();
+    to_socket
+        .unbounded_send(RawMessage::Text("hello".to_string()))
+        .unwrap();
+    assert_eq!(text(socket.recv().await), "hello");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_close_handshake() {
+    let (mut socket, to_socket, mut from_socket) = socket(Default::default());
+    let close = Message::Close(Some(CloseFrame::normal()));
+    socket.send(close).await.unwrap();
+    close_sent(&mut from_socket).await;
+    // The connection is read until the peer answers.
+    to_socket
+        .unbounded_send(RawMessage::Text("late".to_string()))
+        .unwrap();
+    assert_eq!(text(socket.recv().await), "late");
+    to_socket
+        .unbounded_send(RawMessage::Close(Some(CloseFrame::normal())))
+        .unwrap();
+    assert!(matches!(socket.recv().await, Some(Ok(Message::Close(_)))));
+    while let Some(message) = from_socket.next().await {
+        assert!(
+            !matches!(message, RawMessage::Close(_)),
+            "Close frame sent twice"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_close_timeout() {
+    let config = SocketConfig {
+        close_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let (socket, _to_socket, mut from_socket) = socket(config);
+    let close = Message::Close(Some(CloseFrame::normal()));
+    socket.send(close).await.unwrap();
+    close_sent(&mut from_socket).await;
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while from_socket.next().await.is_some() {}
+    })
+    .await
+    .expect("connection wasn't dropped after the close timeout");
+}
+
+#[tokio::test]
+async fn test_send_timeout() {
+    use ezsockets::SendTimeoutError;
+
+    let (socket, _to_socket, mut from_socket) = socket(Default::default());
+    let message = Message::Text("hello".to_string());
+    socket
+        .send_timeout(message, Duration::from_secs(1))
+        .await
+        .unwrap();
+    // Already written, even if the heartbeat's Ping went first.
+    loop {
+        match from_socket.try_recv().unwrap() {
+            RawMessage::Text(text) => break assert_eq!(text, "hello"),
+            _ => continue,
+        }
+    }
+
+    let socket = Socket::new(Stalled, Default::default());
+    let message = Message::Text("hello".to_string());
+    let result = socket
+        .send_timeout(message, Duration::from_millis(50))
+        .await;
+    assert!(matches!(result, Err(SendTimeoutError::Timeout)));
+}
+
+/// Reverses Binary payloads, standing for a transparent encryption, and drops "noise" Text messages.
+struct Reverse;
+
+impl ezsockets::SocketMiddleware for Reverse {
+    fn inbound(&self, message: RawMessage) -> Option<RawMessage> {
+        match message {
+            RawMessage::Text(text) if text == "noise" => None,
+            message => self.outbound(message),
+        }
+    }
+
+    fn outbound(&self, message: RawMessage) -> Option<RawMessage> {
+        match message {
+            RawMessage::Binary(bytes) => Some(RawMessage::Binary(
+                bytes.iter().rev().copied().collect::<Vec<u8>>().into(),
+            )),
+            message => Some(message),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_middleware() {
+    let config = SocketConfig {
+        middleware: vec![ezsockets::Middleware::new(Reverse)],
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+    socket
+        .send(Message::Binary(vec![1, 2, 3].into()))
+        .await
+        .unwrap();
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Binary(bytes) => break assert_eq!(bytes[..], [3, 2, 1]),
+            _ => continue,
+        }
+    }
+
+    to_socket
+        .unbounded_send(RawMessage::Text("noise".to_string()))
+        .unwrap();
+    to_socket
+        .unbounded_send(RawMessage::Binary(vec![3, 2, 1].into()))
+        .unwrap();
+    assert!(matches!(
+        socket.recv().await,
+        Some(Ok(Message::Binary(bytes))) if bytes == [1, 2, 3][..]
+    ));
+}
+
+/// Appends its tag to Text messages going either way.
+struct Tag(&'static str);
+
+impl ezsockets::SocketMiddleware for Tag {
+    fn inbound(&self, message: RawMessage) -> Option<RawMessage> {
+        self.outbound(message)
+    }
+
+    fn outbound(&self, message: RawMessage) -> Option<RawMessage> {
+        match message {
+            RawMessage::Text(text) => Some(RawMessage::Text(format!("{text} {}", self.0))),
+            message => Some(message),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_middleware_order() {
+    let config = SocketConfig {
+        middleware: vec![
+            ezsockets::Middleware::new(Tag("first")),
+            ezsockets::Middleware::new(Tag("second")),
+        ],
+        ..Default::default()
+    };
+    let (mut socket, to_socket, mut from_socket) = socket(config);
+
+    // The first middleware is the closest to the connection.
+    to_socket
+        .unbounded_send(RawMessage::Text("in".to_string()))
+        .unwrap();
+    assert!(matches!(
+        socket.recv().await,
+        Some(Ok(Message::Text(text))) if text == "in first second"
+    ));
+    socket.send(Message::Text("out".to_string())).await.unwrap();
+    loop {
+        match from_socket.next().await.unwrap() {
+            RawMessage::Text(text) => break assert_eq!(text, "out second first"),
+            _ => continue,
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use ezsockets::Histogram;
+use std::time::Duration;
+
+#[test]
+fn test_histogram_mean() {
+    assert_eq!(Histogram::default().mean(), None);
+    let histogram = Histogram {
+        count: 4,
+        sum: Duration::from_millis(10),
+        buckets: Vec::new(),
+    };
+    assert_eq!(histogram.mean(), Some(Duration::from_micros(2500)));
+    // Counts which don't fit in 32 bits.
+    let histogram = Histogram {
+        count: 1 << 32,
+        sum: Duration::from_secs(1 << 32),
+        buckets: Vec::new(),
+    };
+    assert_eq!(histogram.mean(), Some(Duration::from_secs(1)));
+}
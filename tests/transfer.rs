@@ -0,0 +1,92 @@
+use ezsockets::protocols::transfer::checksum;
+use ezsockets::protocols::transfer::Download;
+use ezsockets::protocols::transfer::TransferMessage;
+use ezsockets::protocols::transfer::Upload;
+
+const PAYLOAD: &[u8] = b"hello resumable world";
+
+/// Sends the chunks allowed by the window, passing them through `link`, which may drop or alter them.
+fn send_window(
+    upload: &mut Upload,
+    link: &mut impl FnMut(TransferMessage) -> Option<TransferMessage>,
+) -> Vec<TransferMessage> {
+    let mut sent = Vec::new();
+    while let Some(range) = upload.next_chunk() {
+        let chunk = upload.chunk(PAYLOAD[range.start as usize..range.end as usize].to_vec());
+        let encoded = chunk.encode();
+        if let Some(chunk) = link(TransferMessage::decode(encoded).unwrap()) {
+            sent.push(chunk);
+        }
+    }
+    sent
+}
+
+fn deliver(
+    download: &mut Download,
+    upload: &mut Upload,
+    output: &mut Vec<u8>,
+    messages: Vec<TransferMessage>,
+) {
+    for message in messages {
+        let received = download.handle(message).unwrap();
+        output.extend(received.data.into_iter().flatten());
+        if let Some(reply) = received.reply {
+            assert!(upload.handle(&reply));
+        }
+    }
+}
+
+#[test]
+fn test_checksum() {
+    assert_eq!(checksum(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_transfer_resumes_after_loss() {
+    let mut upload = Upload::new("file", PAYLOAD.len() as u64)
+        .chunk_size(4)
+        .window(8);
+    let mut download = Download::new("file");
+    let mut output = Vec::new();
+    let mut sent = 0;
+    // The second chunk is corrupted, the fourth one is lost along with the connection.
+    let mut link = |message: TransferMessage| {
+        sent += 1;
+        match (sent, message) {
+            (
+                2,
+                TransferMessage::Chunk {
+                    id,
+                    offset,
+                    checksum,
+                    last,
+                    ..
+                },
+            ) => Some(TransferMessage::Chunk {
+                id,
+                offset,
+                checksum,
+                last,
+                data: "oops".into(),
+            }),
+            (4, _) => None,
+            (_, message) => Some(message),
+        }
+    };
+
+    for _ in 0..4 {
+        let chunks = send_window(&mut upload, &mut link);
+        deliver(&mut download, &mut upload, &mut output, chunks);
+    }
+    assert!(!upload.is_complete());
+
+    // Reconnected.
+    let resume = upload.resume();
+    deliver(&mut download, &mut upload, &mut output, vec![resume]);
+    while !upload.is_complete() {
+        let chunks = send_window(&mut upload, &mut link);
+        deliver(&mut download, &mut upload, &mut output, chunks);
+    }
+    assert!(download.is_complete());
+    assert_eq!(output, PAYLOAD);
+}
@@ -0,0 +1,61 @@
+use ezsockets::RawMessage;
+use ezsockets::Socket;
+use ezsockets::SocketConfig;
+use futures::channel::mpsc;
+use futures::Sink;
+use futures::Stream;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// In-memory transport, acting as the remote end of the Socket.
+pub struct Transport {
+    incoming: mpsc::UnboundedReceiver<RawMessage>,
+    outgoing: mpsc::UnboundedSender<RawMessage>,
+}
+
+impl Stream for Transport {
+    type Item = Result<RawMessage, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.incoming)
+            .poll_next(cx)
+            .map(|m| m.map(Ok))
+    }
+}
+
+impl Sink<RawMessage> for Transport {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: RawMessage) -> Result<(), Self::Error> {
+        self.outgoing
+            .unbounded_send(item)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::BrokenPipe, err))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Creates a Socket, returning the sender of messages to it and the receiver of messages sent by it.
+pub fn socket(
+    config: SocketConfig,
+) -> (
+    Socket,
+    mpsc::UnboundedSender<RawMessage>,
+    mpsc::UnboundedReceiver<RawMessage>,
+) {
+    let (to_socket, incoming) = mpsc::unbounded();
+    let (outgoing, from_socket) = mpsc::unbounded();
+    let socket = Socket::new(Transport { incoming, outgoing }, config);
+    (socket, to_socket, from_socket)
+}
@@ -4,18 +4,49 @@ mod client;
 use chat::ChatClient;
 use chat::ChatServer;
 
+use async_trait::async_trait;
+use ezsockets::tungstenite::ServerGroup;
+use ezsockets::AcceptLimit;
+use ezsockets::Bytes;
+use ezsockets::ClientConfig;
+use ezsockets::ClientPool;
+use ezsockets::ConnectionState;
+use ezsockets::EndpointResolver;
+use ezsockets::ExpectedRefusal;
+use ezsockets::GoAway;
+use ezsockets::IdempotencyKey;
+use ezsockets::Mismatch;
+use ezsockets::ResponseExpectations;
+use ezsockets::ResponseMismatch;
 use ezsockets::Server;
+use ezsockets::ServerConfig;
 use ezsockets::ServerExt;
 use ezsockets::SessionExt;
+use ezsockets::SharedClient;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
+use url::Url;
 
 async fn run<E>(create_fn: impl FnOnce(Server<E>) -> E) -> (Server<E>, SocketAddr)
 where
     E: ServerExt + 'static,
     <E::Session as SessionExt>::Args: Default,
+    E::State: Default,
 {
-    let (server, _) = Server::create(create_fn);
+    run_with_config(create_fn, ServerConfig::default()).await
+}
+
+async fn run_with_config<E>(
+    create_fn: impl FnOnce(Server<E>) -> E,
+    config: ServerConfig,
+) -> (Server<E>, SocketAddr)
+where
+    E: ServerExt + 'static,
+    <E::Session as SessionExt>::Args: Default,
+    E::State: Default,
+{
+    let (server, _) = Server::create_with_config(create_fn, config);
     let address = SocketAddr::from(([127, 0, 0, 1], 0));
 
     tracing::debug!("listening on {}", address);
@@ -44,3 +75,1217 @@ async fn test_tungstenite_chat() {
     let bob = client::connect(ChatClient::new, address).await;
     chat::test(alice, bob).await;
 }
+
+/// State shared by the endpoints of a gateway.
+#[derive(Debug, Default)]
+struct GatewayState {
+    accepted: std::sync::atomic::AtomicUsize,
+}
+
+type GatewaySession = ezsockets::Session<u16, ()>;
+
+/// Public endpoint of a gateway, counting the connections it accepts.
+struct PublicServer {
+    server: Server<Self>,
+}
+
+/// Admin endpoint of a gateway, greeting with the number of connections accepted by the public endpoint.
+struct AdminServer {
+    server: Server<Self>,
+}
+
+#[async_trait]
+impl ServerExt for PublicServer {
+    type Session = SilentSession;
+    type Params = ();
+    type State = GatewayState;
+
+    async fn accept(
+        &mut self,
+        socket: ezsockets::Socket,
+        address: SocketAddr,
+        _args: (),
+    ) -> Result<GatewaySession, ezsockets::Error> {
+        let state = self.server.state();
+        let accepted = state
+            .accepted
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            + 1;
+        let session =
+            GatewaySession::create(|handle| SilentSession { handle }, address.port(), socket);
+        session.text(format!("welcome #{accepted}"))?;
+        Ok(session)
+    }
+
+    async fn disconnected(&mut self, _id: u16) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, (): ()) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ServerExt for AdminServer {
+    type Session = SilentSession;
+    type Params = ();
+    type State = GatewayState;
+
+    async fn accept(
+        &mut self,
+        socket: ezsockets::Socket,
+        address: SocketAddr,
+        _args: (),
+    ) -> Result<GatewaySession, ezsockets::Error> {
+        let state = self.server.state();
+        let accepted = state.accepted.load(std::sync::atomic::Ordering::Relaxed);
+        let session =
+            GatewaySession::create(|handle| SilentSession { handle }, address.port(), socket);
+        session.text(format!("{accepted} accepted"))?;
+        Ok(session)
+    }
+
+    async fn disconnected(&mut self, _id: u16) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, (): ()) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+}
+
+struct SilentSession {
+    handle: GatewaySession,
+}
+
+#[async_trait]
+impl SessionExt for SilentSession {
+    type ID = u16;
+    type Args = ();
+    type Params = ();
+
+    fn id(&self) -> &u16 {
+        &self.handle.id
+    }
+
+    async fn text(&mut self, _text: String) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, (): ()) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_server_group() {
+    use futures::StreamExt;
+
+    let mut group = ServerGroup::new(std::sync::Arc::new(GatewayState::default()));
+    let public = group.server(|server| PublicServer { server }, ServerConfig::default());
+    let admin = group.server(|server| AdminServer { server }, ServerConfig::default());
+    let public_listeners = [
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+    ];
+    let admin_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let public_addresses: Vec<SocketAddr> = public_listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+    let admin_address = admin_listener.local_addr().unwrap();
+    for listener in public_listeners {
+        group.listen(&public, listener, |_| async move { Ok(()) });
+    }
+    group.listen(&admin, admin_listener, |_| async move { Ok(()) });
+    tokio::spawn(group.run());
+
+    let greeting = |address: SocketAddr| async move {
+        let (mut stream, _) = tokio_tungstenite::connect_async(format!("ws://{address}"))
+            .await
+            .unwrap();
+        let message = stream.next().await.unwrap().unwrap();
+        (stream, message.into_text().unwrap())
+    };
+    let (_alice, text) = greeting(public_addresses[0]).await;
+    assert_eq!(text, "welcome #1");
+    let (_bob, text) = greeting(public_addresses[1]).await;
+    assert_eq!(text, "welcome #2");
+    // The admin endpoint sees the state of the public one.
+    let (_admin, text) = greeting(admin_address).await;
+    assert_eq!(text, "2 accepted");
+}
+
+#[tokio::test]
+async fn test_accept_limit() {
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            per_ip_per_second: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, _) = Server::create_with_config(ChatServer::new, config);
+    let alice = SocketAddr::from(([127, 0, 0, 1], 1000));
+    let bob = SocketAddr::from(([127, 0, 0, 2], 1000));
+    assert!(server.admit(alice).await);
+    assert!(!server.admit(alice).await);
+    assert!(server.admit(bob).await);
+}
+
+#[tokio::test]
+async fn test_accept_limit_per_second() {
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            per_second: Some(2),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, _) = Server::create_with_config(ChatServer::new, config);
+    let peers = [1, 2, 3].map(|ip| SocketAddr::from(([127, 0, 0, ip], 1000)));
+    assert!(server.admit(peers[0]).await);
+    assert!(server.admit(peers[1]).await);
+    assert!(!server.admit(peers[2]).await);
+}
+
+#[tokio::test]
+async fn test_accept_limit_queue() {
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            per_second: Some(1),
+            queue: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, _) = Server::create_with_config(ChatServer::new, config);
+    let address = SocketAddr::from(([127, 0, 0, 1], 1000));
+    assert!(server.admit(address).await);
+    // Waits for the next one second window instead of being rejected.
+    let started_at = std::time::Instant::now();
+    assert!(server.admit(address).await);
+    assert!(started_at.elapsed() >= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn test_accept_limit_max_queued() {
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            per_second: Some(1),
+            queue: true,
+            max_queued: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, _) = Server::create_with_config(ChatServer::new, config);
+    let address = SocketAddr::from(([127, 0, 0, 1], 1000));
+    assert!(server.admit(address).await);
+    let queued = tokio::spawn({
+        let server = server.clone();
+        async move { server.admit(address).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // The queue is full, rejected right away.
+    assert!(!server.admit(address).await);
+    assert!(queued.await.unwrap());
+}
+
+#[tokio::test]
+async fn test_accept_queue_does_not_block_other_connections() {
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            per_ip_per_second: Some(1),
+            queue: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (_, address) = run_with_config(ChatServer::new, config).await;
+    let connect_from = |ip: [u8; 4]| async move {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind(SocketAddr::from((ip, 0))).unwrap();
+        let stream = socket.connect(address).await.unwrap();
+        tokio_tungstenite::client_async(format!("ws://{address}"), stream)
+            .await
+            .unwrap()
+    };
+    let _alice = connect_from([127, 0, 0, 1]).await;
+    // Waits for the next window of the accept limit of its address.
+    let queued = tokio::spawn(connect_from([127, 0, 0, 1]));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let _bob = tokio::time::timeout(Duration::from_millis(500), connect_from([127, 0, 0, 2]))
+        .await
+        .unwrap();
+    queued.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_accept_limit_rejects_connection() {
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            per_second: Some(1),
+            retry_after: Some(Duration::from_millis(1500)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (_, address) = run_with_config(ChatServer::new, config).await;
+    let url = format!("ws://{address}");
+    let _accepted = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let response = match tokio_tungstenite::connect_async(&url).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => response,
+        result => panic!("expected the connection to be rejected, got {result:?}"),
+    };
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.headers()["retry-after"], "2");
+}
+
+#[tokio::test]
+async fn test_handshake_timeout() {
+    use tokio::io::AsyncReadExt;
+
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            max_handshakes: Some(1),
+            handshake_timeout: Duration::from_millis(100),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (_, address) = run_with_config(ChatServer::new, config).await;
+    // Takes the only handshake slot without ever sending the upgrade request.
+    let mut idle = tokio::net::TcpStream::connect(address).await.unwrap();
+    let mut buffer = [0; 1];
+    let read = tokio::time::timeout(Duration::from_secs(1), idle.read(&mut buffer)).await;
+    assert_eq!(read.unwrap().unwrap(), 0);
+    tokio_tungstenite::connect_async(format!("ws://{address}"))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_client_state() {
+    let (_, address) = run(ChatServer::new).await;
+    let client = client::connect(ChatClient::new, address).await;
+    let mut states = client.state_changes();
+    while !matches!(*states.borrow(), ConnectionState::Connected { .. }) {
+        states.changed().await.unwrap();
+    }
+    client.close(None);
+    while !matches!(*states.borrow(), ConnectionState::Closed { .. }) {
+        states.changed().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_connect_checked() {
+    let (_, address) = run(ChatServer::new).await;
+    let url = Url::parse(&format!("ws://{address}/websocket")).unwrap();
+    let expectations = ResponseExpectations::new()
+        .status(101)
+        .header("upgrade", "websocket");
+    let (client, _) = ezsockets::connect_checked(
+        ChatClient::new,
+        ClientConfig::new(url.clone()),
+        expectations,
+    )
+    .await;
+    let mut states = client.state_changes();
+    while !matches!(*states.borrow(), ConnectionState::Connected { .. }) {
+        states.changed().await.unwrap();
+    }
+
+    let expectations = ResponseExpectations::new().subprotocol("chat");
+    let (_client, future) =
+        ezsockets::connect_checked(ChatClient::new, ClientConfig::new(url), expectations).await;
+    let err = future.await.unwrap_err();
+    let mismatch = err.downcast_ref::<ResponseMismatch>().unwrap();
+    assert_eq!(
+        mismatch.mismatches,
+        [Mismatch {
+            field: String::from("Sec-WebSocket-Protocol"),
+            expected: String::from("chat"),
+            actual: None,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_connect_checked_refusal() {
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            per_second: Some(0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (_, address) = run_with_config(ChatServer::new, config).await;
+    let url = Url::parse(&format!("ws://{address}/websocket")).unwrap();
+
+    let expectations = ResponseExpectations::new().status(503);
+    let (_client, future) = ezsockets::connect_checked(
+        ChatClient::new,
+        ClientConfig::new(url.clone()),
+        expectations,
+    )
+    .await;
+    let err = future.await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ExpectedRefusal>(),
+        Some(&ExpectedRefusal { status: 503 })
+    );
+
+    let expectations = ResponseExpectations::new().status(403);
+    let (_client, future) =
+        ezsockets::connect_checked(ChatClient::new, ClientConfig::new(url), expectations).await;
+    let err = future.await.unwrap_err();
+    let mismatch = err.downcast_ref::<ResponseMismatch>().unwrap();
+    assert_eq!(
+        mismatch.mismatches,
+        [Mismatch {
+            field: String::from("status"),
+            expected: String::from("403"),
+            actual: Some(String::from("503")),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_client_pool() {
+    let (_, address) = run(ChatServer::new).await;
+    let url = Url::parse(&format!("ws://{address}/websocket")).unwrap();
+    let pool = ClientPool::new(ChatClient::new, ClientConfig::new(url.clone()));
+    pool.warm(2).await;
+    assert_eq!(pool.len(), 2);
+    let client = pool.get().await;
+    assert!(matches!(client.state(), ConnectionState::Connected { .. }));
+    assert_eq!(pool.len(), 1);
+
+    // Stale clients are replaced by new ones.
+    let pool = ClientPool::new(ChatClient::new, ClientConfig::new(url)).ttl(Duration::ZERO);
+    pool.warm(1).await;
+    let _client = pool.get().await;
+    assert!(pool.is_empty());
+}
+
+#[tokio::test]
+async fn test_overload_response() {
+    use tokio::io::AsyncReadExt;
+
+    let config = ServerConfig {
+        accept_limit: AcceptLimit {
+            max_handshakes: Some(0),
+            retry_after: Some(std::time::Duration::from_millis(2500)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, _) = Server::create_with_config(ChatServer::new, config);
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(ezsockets::tungstenite::run_on(
+        server.clone(),
+        listener,
+        |_| async move { Ok(()) },
+    ));
+
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(response.contains("Retry-After: 3\r\n"));
+    assert_eq!(server.overloads(), 1);
+}
+
+#[tokio::test]
+async fn test_send_fragments() {
+    use ezsockets::DataKind;
+    use ezsockets::Fragment;
+    use ezsockets::Socket;
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::WebSocketStream;
+
+    let (server, client) = tokio::io::duplex(1024);
+    let server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+    let mut client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+    let socket = Socket::new(server, Default::default());
+
+    socket
+        .send_fragment(Fragment::First(DataKind::Text, "hello".into()))
+        .unwrap();
+    // Queued until the fragmented message is complete.
+    socket
+        .send(ezsockets::Message::Text("after".to_string()))
+        .await
+        .unwrap();
+    socket
+        .send_fragment(Fragment::Continuation(", ".into()))
+        .unwrap();
+    socket
+        .send_fragment(Fragment::Final("world".into()))
+        .unwrap();
+
+    let mut received = Vec::new();
+    while received.len() < 2 {
+        match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => received.push(text),
+            _ => continue,
+        }
+    }
+    assert_eq!(received, ["hello, world", "after"]);
+}
+
+#[cfg(feature = "axum")]
+#[tokio::test]
+async fn test_send_fragments_axum() {
+    use axum_crate::extract::ws::WebSocketUpgrade;
+    use axum_crate::routing::get;
+    use axum_crate::Router;
+    use ezsockets::DataKind;
+    use ezsockets::Fragment;
+    use ezsockets::Socket;
+    use ezsockets::SocketConfig;
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    // axum can't write fragments, the socket reassembles them.
+    let app = Router::new().route(
+        "/websocket",
+        get(|ws: WebSocketUpgrade| async move {
+            ws.on_upgrade(|socket| async move {
+                let config = SocketConfig {
+                    write_fragments: false,
+                    ..Default::default()
+                };
+                let socket = Socket::new(socket, config);
+                for fragment in [
+                    Fragment::First(DataKind::Text, "hello".into()),
+                    Fragment::Continuation(", ".into()),
+                    Fragment::Final("world".into()),
+                ] {
+                    socket.send_fragment(fragment).unwrap();
+                }
+                socket.sync().await;
+            })
+        }),
+    );
+    let address = SocketAddr::from(([127, 0, 0, 1], 0));
+    let future = axum_crate::Server::bind(&address).serve(app.into_make_service());
+    let address = future.local_addr();
+    tokio::spawn(future);
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{address}/websocket"))
+        .await
+        .unwrap();
+    loop {
+        match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => {
+                assert_eq!(text, "hello, world");
+                break;
+            }
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn test_tls_connection() {
+    use ezsockets::TlsInfo;
+    use std::sync::Arc;
+    use tokio_rustls::rustls;
+
+    let cert = rustls::Certificate(include_bytes!("certs/localhost.der").to_vec());
+    let key = rustls::PrivateKey(include_bytes!("certs/localhost.key.der").to_vec());
+    let mut tls_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .unwrap();
+    tls_config.alpn_protocols = vec![b"chat".to_vec()];
+    let (_tls_config, tls_config_receiver) = tokio::sync::watch::channel(Arc::new(tls_config));
+
+    let (server, _) = Server::create(ChatServer::new);
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let (accepted, mut accepted_tls) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(ezsockets::tungstenite::run_on_tls(
+        server,
+        listener,
+        tls_config_receiver,
+        move |socket| {
+            let _ = accepted.send(socket.tls().cloned());
+            async move { Ok(()) }
+        },
+    ));
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(&rustls::Certificate(
+            include_bytes!("certs/ca.der").to_vec(),
+        ))
+        .unwrap();
+    let mut tls = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"chat".to_vec()];
+    let url = Url::parse(&format!("wss://localhost:{}/websocket", address.port())).unwrap();
+    let config = ClientConfig::new(url).tls(Arc::new(tls));
+    let (client, _) = ezsockets::connect(ChatClient::new, config).await;
+    let mut states = client.state_changes();
+    while !matches!(*states.borrow(), ConnectionState::Connected { .. }) {
+        states.changed().await.unwrap();
+    }
+    assert_eq!(
+        client.peer_addr().map(|peer| peer.port()),
+        Some(address.port())
+    );
+    assert!(client.local_addr().is_some());
+
+    // Both ends report the server name and the protocol negotiated by the TLS handshake.
+    let expected = TlsInfo {
+        server_name: Some("localhost".to_string()),
+        alpn_protocol: Some(b"chat".to_vec()),
+    };
+    assert_eq!(client.tls(), Some(expected.clone()));
+    assert_eq!(accepted_tls.recv().await.unwrap(), Some(expected));
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn test_client_cert_resolver() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use tokio_rustls::rustls;
+
+    /// Provides the test client certificate, counting how many times it was asked for.
+    struct TestClientCert {
+        key: Arc<rustls::sign::CertifiedKey>,
+        resolved: AtomicUsize,
+    }
+
+    impl rustls::client::ResolvesClientCert for TestClientCert {
+        fn resolve(
+            &self,
+            _acceptable_issuers: &[&[u8]],
+            _sigschemes: &[rustls::SignatureScheme],
+        ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+            self.resolved.fetch_add(1, Ordering::Relaxed);
+            Some(self.key.clone())
+        }
+
+        fn has_certs(&self) -> bool {
+            true
+        }
+    }
+
+    let mut client_roots = rustls::RootCertStore::empty();
+    client_roots
+        .add(&rustls::Certificate(
+            include_bytes!("certs/client-ca.der").to_vec(),
+        ))
+        .unwrap();
+    let cert = rustls::Certificate(include_bytes!("certs/localhost.der").to_vec());
+    let key = rustls::PrivateKey(include_bytes!("certs/localhost.key.der").to_vec());
+    let tls_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(rustls::server::AllowAnyAuthenticatedClient::new(
+            client_roots,
+        ))
+        .with_single_cert(vec![cert], key)
+        .unwrap();
+    let (_tls_config, tls_config_receiver) = tokio::sync::watch::channel(Arc::new(tls_config));
+
+    let (server, _) = Server::create(ChatServer::new);
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let (accepted, mut accepted_addresses) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(ezsockets::tungstenite::run_on_tls(
+        server,
+        listener,
+        tls_config_receiver,
+        move |socket| {
+            let _ = accepted.send(socket.peer_addr());
+            async move { Ok(()) }
+        },
+    ));
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(&rustls::Certificate(
+            include_bytes!("certs/ca.der").to_vec(),
+        ))
+        .unwrap();
+    let tls = Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    );
+    let url = Url::parse(&format!("wss://localhost:{}/websocket", address.port())).unwrap();
+
+    // Refused without a client certificate.
+    let config = ClientConfig::new(url.clone()).tls(tls.clone());
+    let (_, future) = ezsockets::connect(ChatClient::new, config).await;
+    assert!(future.await.is_err());
+
+    // Accepted with the certificate of the resolver, the roots of the TLS configuration being kept.
+    let client_key = rustls::PrivateKey(include_bytes!("certs/client.key.der").to_vec());
+    let resolver = Arc::new(TestClientCert {
+        key: Arc::new(rustls::sign::CertifiedKey::new(
+            vec![rustls::Certificate(
+                include_bytes!("certs/client.der").to_vec(),
+            )],
+            rustls::sign::any_supported_type(&client_key).unwrap(),
+        )),
+        resolved: AtomicUsize::new(0),
+    });
+    let config = ClientConfig::new(url)
+        .tls(tls)
+        .client_cert_resolver(resolver.clone());
+    let (client, _) = ezsockets::connect(ChatClient::new, config).await;
+    let mut states = client.state_changes();
+    while !matches!(*states.borrow(), ConnectionState::Connected { .. }) {
+        states.changed().await.unwrap();
+    }
+    assert_eq!(
+        accepted_addresses.recv().await.unwrap(),
+        client.local_addr()
+    );
+    assert_eq!(resolver.resolved.load(Ordering::Relaxed), 1);
+}
+
+/// Advertises a single address, which isn't the one the client connected to.
+#[derive(Debug)]
+struct MovedEndpoint(SocketAddr);
+
+#[async_trait]
+impl EndpointResolver for MovedEndpoint {
+    async fn resolve(&self, _host: &str, _port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        Ok(vec![self.0])
+    }
+}
+
+#[tokio::test]
+async fn test_endpoint_refresh() {
+    let (address, mut frames) = recording_peer(Duration::ZERO).await;
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 2], 0)))
+        .await
+        .unwrap();
+    let moved_address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let _stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        std::future::pending::<()>().await;
+    });
+    let url = Url::parse(&format!("ws://localhost:{}/websocket", address.port())).unwrap();
+    let config = ClientConfig::new(url)
+        .endpoint_refresh(Duration::from_millis(100))
+        .endpoint_resolver(std::sync::Arc::new(MovedEndpoint(moved_address)));
+    let (client, _) = ezsockets::connect(ChatClient::new, config).await;
+    let mut states = client.state_changes();
+    let connected_since = loop {
+        if let ConnectionState::Connected { since } = *states.borrow_and_update() {
+            break since;
+        }
+        states.changed().await.unwrap();
+    };
+
+    // Once quiet, the connection is replaced by one to the advertised address.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            states.changed().await.unwrap();
+            if let ConnectionState::Connected { since } = *states.borrow() {
+                if since > connected_since {
+                    break;
+                }
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(client.peer_addr(), Some(moved_address));
+
+    // The replaced connection is closed gracefully.
+    let reason = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            match frames.recv().await {
+                Some(tokio_tungstenite::tungstenite::Message::Close(frame)) => {
+                    break frame.map(|frame| frame.reason.into_owned())
+                }
+                Some(_) => continue,
+                None => break None,
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(reason.as_deref(), Some("endpoint refresh"));
+    let closed = tokio::time::timeout(Duration::from_secs(1), frames.recv())
+        .await
+        .unwrap();
+    assert!(closed.is_none());
+}
+
+#[tokio::test]
+async fn test_reconnecting_state() {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        stream.close(None).await.unwrap();
+    });
+    let client = client::connect(ChatClient::new, address).await;
+    let mut states = client.state_changes();
+
+    // Reported right away, not after the reconnect interval of 5s.
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while !matches!(
+            *states.borrow_and_update(),
+            ConnectionState::Reconnecting { attempt: 1 }
+        ) {
+            states.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+}
+
+/// Client whose first credentials refresh fails, and which re-authenticates with a message if `reauth` is set.
+struct RefreshingClient {
+    refreshes: u32,
+    reauth: bool,
+}
+
+#[async_trait]
+impl ezsockets::ClientExt for RefreshingClient {
+    type Params = ();
+
+    async fn text(&mut self, _text: String) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+
+    async fn binary(&mut self, _bytes: Bytes) -> Result<(), ezsockets::Error> {
+        Ok(())
+    }
+
+    async fn call(&mut self, params: Self::Params) -> Result<(), ezsockets::Error> {
+        let () = params;
+        Ok(())
+    }
+
+    async fn refresh_credentials(&mut self) -> Result<Option<String>, ezsockets::Error> {
+        self.refreshes += 1;
+        match self.refreshes {
+            1 => Err("token endpoint unavailable".into()),
+            n => Ok(Some(format!("token-{n}"))),
+        }
+    }
+
+    fn reauth_message(&self, token: &str) -> Option<ezsockets::Message> {
+        self.reauth
+            .then(|| ezsockets::Message::Text(format!("auth {token}")))
+    }
+}
+
+#[tokio::test]
+async fn test_credentials_refresh_reauth_message() {
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite;
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let url = Url::parse(&format!("ws://{address}/websocket")).unwrap();
+    let config = ClientConfig::new(url).credentials_refresh(Duration::from_millis(50));
+    let (_client, _) = ezsockets::connect(
+        |_| RefreshingClient {
+            refreshes: 0,
+            reauth: true,
+        },
+        config,
+    )
+    .await;
+
+    // The failed refresh is retried on the next tick, over the same connection.
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+    let text = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let tungstenite::Message::Text(text) = stream.next().await.unwrap().unwrap() {
+                break text;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(text, "auth token-2");
+}
+
+#[tokio::test]
+#[allow(clippy::result_large_err)]
+async fn test_credentials_refresh_reconnects() {
+    use tokio_tungstenite::tungstenite::handshake::server::Request;
+    use tokio_tungstenite::tungstenite::handshake::server::Response;
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let url = Url::parse(&format!("ws://{address}/websocket")).unwrap();
+    let config = ClientConfig::new(url)
+        .bearer("token-0")
+        .credentials_refresh(Duration::from_millis(50));
+    let (_client, _) = ezsockets::connect(
+        |_| RefreshingClient {
+            refreshes: 0,
+            reauth: false,
+        },
+        config,
+    )
+    .await;
+
+    // Without a re-authentication message, the connection is replaced by one using the new token.
+    let mut tokens = Vec::new();
+    let mut streams = Vec::new();
+    for _ in 0..2 {
+        let accept = tokio::time::timeout(Duration::from_secs(5), listener.accept());
+        let (stream, _) = accept.await.unwrap().unwrap();
+        let mut token = None;
+        let callback = |request: &Request, response: Response| {
+            token = request
+                .headers()
+                .get("authorization")
+                .map(|value| value.to_str().unwrap().to_string());
+            Ok(response)
+        };
+        streams.push(
+            tokio_tungstenite::accept_hdr_async(stream, callback)
+                .await
+                .unwrap(),
+        );
+        tokens.push(token.unwrap());
+    }
+    assert_eq!(tokens, ["Bearer token-0", "Bearer token-2"]);
+}
+
+/// Accepts a single connection, reporting the frames received on it until it ends.
+/// The peer starts reading, and so answering Close frames, after `read_delay`.
+async fn recording_peer(
+    read_delay: Duration,
+) -> (
+    SocketAddr,
+    tokio::sync::mpsc::UnboundedReceiver<tokio_tungstenite::tungstenite::Message>,
+) {
+    use futures::StreamExt;
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        tokio::time::sleep(read_delay).await;
+        while let Some(Ok(message)) = stream.next().await {
+            let _ = sender.send(message);
+        }
+    });
+    (address, receiver)
+}
+
+#[tokio::test]
+async fn test_client_shutdown() {
+    let (address, mut frames) = recording_peer(Duration::from_millis(200)).await;
+    let client = client::connect(ChatClient::new, address).await;
+    let mut states = client.state_changes();
+    while !matches!(*states.borrow(), ConnectionState::Connected { .. }) {
+        states.changed().await.unwrap();
+    }
+    let started_at = std::time::Instant::now();
+    tokio::time::timeout(Duration::from_secs(1), client.shutdown())
+        .await
+        .unwrap();
+    // Resolved once the server read and answered the Close frame.
+    assert!(started_at.elapsed() >= Duration::from_millis(200));
+    let close = loop {
+        match frames.recv().await.unwrap() {
+            tokio_tungstenite::tungstenite::Message::Close(frame) => break frame.unwrap(),
+            _ => continue,
+        }
+    };
+    assert_eq!(close.reason, "client shutdown");
+    assert!(matches!(client.state(), ConnectionState::Closed { .. }));
+}
+
+#[tokio::test]
+async fn test_client_close_on_drop() {
+    let (address, mut frames) = recording_peer(Duration::ZERO).await;
+    let client = client::connect(ChatClient::new, address).await;
+    let clone = client.clone();
+    let mut states = client.state_changes();
+    while !matches!(*states.borrow(), ConnectionState::Connected { .. }) {
+        states.changed().await.unwrap();
+    }
+
+    // The connection stays open as long as a clone is alive.
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!matches!(
+        frames.try_recv(),
+        Ok(tokio_tungstenite::tungstenite::Message::Close(_))
+    ));
+
+    drop(clone);
+    let close = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let tokio_tungstenite::tungstenite::Message::Close(frame) =
+                frames.recv().await.unwrap()
+            {
+                break frame;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert!(close.is_some());
+}
+
+#[tokio::test]
+async fn test_max_connection_age() {
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let config = ServerConfig::default()
+        .max_connection_age(Duration::from_millis(200))
+        .goaway(GoAway::new(Duration::from_millis(100)));
+    let (_, address) = run_with_config(ChatServer::new, config).await;
+    let (mut stream, _) = tokio_tungstenite::connect_async(format!("ws://{address}"))
+        .await
+        .unwrap();
+    let started_at = std::time::Instant::now();
+    let mut goaway_at = None;
+    let close = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match stream.next().await.unwrap().unwrap() {
+                Message::Text(text) if text == r#"{"type":"goaway"}"# => {
+                    goaway_at = Some(started_at.elapsed())
+                }
+                Message::Close(frame) => break frame.unwrap(),
+                _ => continue,
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(close.code, CloseCode::Restart);
+    // Sent once the connection reached its maximum age minus the lead time, then closed after the lead time.
+    let goaway_at = goaway_at.expect("no goaway notice received");
+    assert!(goaway_at >= Duration::from_millis(100), "{goaway_at:?}");
+    assert!(started_at.elapsed() >= Duration::from_millis(200));
+}
+
+/// Accepts a single connection, echoing its Text messages and reporting the frames received on it until it ends.
+async fn echo_peer() -> (
+    SocketAddr,
+    tokio::sync::mpsc::UnboundedReceiver<tokio_tungstenite::tungstenite::Message>,
+) {
+    use futures::SinkExt;
+    use futures::StreamExt;
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(Ok(message)) = stream.next().await {
+            if message.is_text() {
+                let _ = stream.send(message.clone()).await;
+            }
+            let _ = sender.send(message);
+        }
+    });
+    (address, receiver)
+}
+
+/// Accepts two connections, dropping the first one as soon as it receives a Text message,
+/// and answering the Text messages of the second one with `<text> done`.
+async fn flaky_peer() -> SocketAddr {
+    use futures::SinkExt;
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(Ok(message)) = stream.next().await {
+            if message.is_text() {
+                break;
+            }
+        }
+        drop(stream);
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(Ok(message)) = stream.next().await {
+            if let Message::Text(text) = message {
+                let _ = stream.send(Message::Text(format!("{text} done"))).await;
+            }
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn test_call_idempotent() {
+    let address = flaky_peer().await;
+    let url = Url::parse(&format!("ws://{address}/websocket")).unwrap();
+    let key = IdempotencyKey::new(|message| match message {
+        ezsockets::Message::Text(text) => text.split(' ').next().map(String::from),
+        _ => None,
+    });
+    let config = ClientConfig::new(url).idempotency_key(key);
+    let (client, _) = ezsockets::connect(ChatClient::new, config).await;
+    let request = ezsockets::Message::Text("order-1".to_string());
+    let response = client.call_idempotent("order-1", request).await.unwrap();
+    assert!(has_prefix(&response, "order-1 done"));
+    assert_eq!(client.reconnects(), 1);
+}
+
+fn has_prefix(message: &ezsockets::Message, prefix: &str) -> bool {
+    matches!(message, ezsockets::Message::Text(text) if text.starts_with(prefix))
+}
+
+async fn next_text(consumer: &mut ezsockets::SharedConsumer) -> String {
+    match tokio::time::timeout(Duration::from_secs(1), consumer.recv())
+        .await
+        .unwrap()
+    {
+        Some(ezsockets::Message::Text(text)) => text,
+        message => panic!("unexpected message: {message:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_shared_client_routing() {
+    use futures::FutureExt;
+
+    let (address, _frames) = echo_peer().await;
+    let config = ClientConfig::new(Url::parse(&format!("ws://{address}")).unwrap());
+    let (client, _) = SharedClient::connect(config).await;
+    let mut quotes = client.consumer(|message| has_prefix(message, "quote:"));
+    let news = client.consumer(|message| has_prefix(message, "news:"));
+    let mut everything = client.consumer(|_| true);
+
+    quotes.text("quote:1".to_string()).unwrap();
+    quotes.text("news:1".to_string()).unwrap();
+    assert_eq!(next_text(&mut quotes).await, "quote:1");
+    assert_eq!(next_text(&mut everything).await, "quote:1");
+    assert_eq!(next_text(&mut everything).await, "news:1");
+
+    // The remaining consumers keep receiving their messages once one of them is dropped.
+    drop(news);
+    quotes.text("news:2".to_string()).unwrap();
+    quotes.text("quote:2".to_string()).unwrap();
+    assert_eq!(next_text(&mut everything).await, "news:2");
+    assert_eq!(next_text(&mut everything).await, "quote:2");
+    assert_eq!(next_text(&mut quotes).await, "quote:2");
+    assert!(quotes.recv().now_or_never().is_none());
+}
+
+#[tokio::test]
+async fn test_shared_client_close() {
+    let (address, mut frames) = echo_peer().await;
+    let config = ClientConfig::new(Url::parse(&format!("ws://{address}")).unwrap());
+    let (client, _) = SharedClient::connect(config).await;
+    let mut first = client.consumer(|_| true);
+    let second = client.consumer(|_| true);
+    first.text("hello".to_string()).unwrap();
+    assert_eq!(next_text(&mut first).await, "hello");
+
+    // The connection stays open as long as a consumer is alive.
+    drop(client);
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    while let Ok(frame) = frames.try_recv() {
+        assert!(!frame.is_close(), "closed while a consumer is alive");
+    }
+
+    drop(second);
+    let close = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let tokio_tungstenite::tungstenite::Message::Close(frame) =
+                frames.recv().await.unwrap()
+            {
+                break frame;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert!(close.is_some());
+}
+
+#[tokio::test]
+async fn test_private_close_code() {
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode as TungsteniteCloseCode;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame as TungsteniteCloseFrame;
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let frame = TungsteniteCloseFrame {
+            code: TungsteniteCloseCode::from(4000),
+            reason: "bye".into(),
+        };
+        stream
+            .send(tokio_tungstenite::tungstenite::Message::Close(Some(frame)))
+            .await
+            .unwrap();
+    });
+    let (stream, _) = tokio_tungstenite::connect_async(format!("ws://{address}"))
+        .await
+        .unwrap();
+    let mut socket = ezsockets::Socket::new(stream, Default::default());
+    match socket.recv().await {
+        Some(Ok(ezsockets::Message::Close(Some(frame)))) => {
+            assert!(matches!(frame.code, ezsockets::CloseCode::Other(4000)));
+            assert_eq!(frame.reason, "bye");
+        }
+        message => panic!("unexpected message: {message:?}"),
+    }
+}